@@ -0,0 +1,164 @@
+// Times lexing, parsing and interpreting a few representative programs
+//
+//     cargo bench --bench interpreter
+//     cargo bench --bench interpreter -- interpret/fib
+//
+// Criterion keeps the last run in target/criterion/ and reports the change
+// against it, so run it once before a refactor and once after
+use std::io;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use lost::{
+    interpreter::{
+        environment::{GlobalNames, Globals},
+        interpreter::Interpreter,
+    },
+    lexer::{lexer::Lexer, token::Token},
+    parser::{parser::Parser, stmt::Stmt},
+    resolver::resolver::Resolver,
+};
+
+const PROGRAMS: [(&str, &str); 8] = [
+    (
+        "fib",
+        "fun fib(n) {
+            if (n < 2) return n;
+            return fib(n - 1) + fib(n - 2);
+        }
+        print fib(15);",
+    ),
+    (
+        "loops",
+        "var sum = 0;
+        for (var i = 0; i < 20000; i = i + 1) {
+            sum = sum + i;
+        }
+        print sum;",
+    ),
+    (
+        "locals",
+        "{
+            var sum = 0;
+            var step = 1;
+            for (var i = 0; i < 20000; i = i + step) {
+                var square = i * i;
+                sum = sum + square;
+            }
+            print sum;
+        }",
+    ),
+    (
+        "branches",
+        "var low = 0;
+        var high = 0;
+        var i = 0;
+        while (i < 20000) {
+            if (i < 10000) {
+                low = low + 1;
+            } else {
+                high = high + 1;
+            }
+            i = i + 1;
+        }
+        print low;",
+    ),
+    (
+        "calls",
+        "fun add(a, b) {
+            return a + b;
+        }
+        var total = 0;
+        for (var i = 0; i < 5000; i = i + 1) {
+            total = add(total, i);
+        }
+        print total;",
+    ),
+    (
+        "strings",
+        "var text = \"\";
+        for (var i = 0; i < 2000; i = i + 1) {
+            text = text + \"lost\";
+        }
+        print text;",
+    ),
+    (
+        "builder",
+        "var builder = string_builder();
+        for (var i = 0; i < 2000; i = i + 1) {
+            append(builder, \"lost\");
+        }
+        print to_string(builder);",
+    ),
+    (
+        "classes",
+        "class Shape {
+            area(width, height) { return width * height; }
+        }
+        class Square < Shape {
+            side(area) { return area / 2; }
+        }
+        var total = 0;
+        for (var i = 0; i < 2000; i = i + 1) {
+            var square = Square();
+            total = total + square.side(square.area(i, i));
+        }
+        print total;",
+    ),
+];
+
+fn lex(source_code: &str) -> Vec<Token> {
+    let mut lexer = Lexer::new(source_code.to_string());
+    lexer.scan();
+    assert!(lexer.errors.is_empty(), "benchmark does not lex");
+    lexer.tokens
+}
+
+fn parse(tokens: Vec<Token>) -> Vec<Stmt> {
+    let mut parser = Parser::new(tokens);
+    parser.parse();
+    assert!(parser.get_errors().is_empty(), "benchmark does not parse");
+    std::mem::take(parser.get_parsed_statements())
+}
+
+fn lexing(criterion: &mut Criterion) {
+    let mut group = criterion.benchmark_group("lex");
+    for (name, source_code) in PROGRAMS {
+        group.bench_function(name, |bencher| bencher.iter(|| lex(source_code)));
+    }
+    group.finish();
+}
+
+fn parsing(criterion: &mut Criterion) {
+    let mut group = criterion.benchmark_group("parse");
+    for (name, source_code) in PROGRAMS {
+        let tokens = lex(source_code);
+        group.bench_function(name, |bencher| {
+            bencher.iter_batched(|| tokens.clone(), parse, BatchSize::SmallInput)
+        });
+    }
+    group.finish();
+}
+
+fn interpreting(criterion: &mut Criterion) {
+    let mut group = criterion.benchmark_group("interpret");
+    for (name, source_code) in PROGRAMS {
+        // Every interpreter numbers its globals like the one the program is resolved for
+        let names = GlobalNames::default();
+        let mut statements = parse(lex(source_code));
+        Resolver::with_globals(names.clone()).resolve(&mut statements);
+        group.bench_function(name, |bencher| {
+            bencher.iter(|| {
+                let mut interpreter = Interpreter::new(Some(Globals::with_names(names.clone())));
+                interpreter.set_output(Box::new(io::sink()));
+                if let Err(error) = interpreter.interpret(&statements) {
+                    error.report();
+                    panic!("benchmark fails");
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, lexing, parsing, interpreting);
+criterion_main!(benches);
@@ -0,0 +1,85 @@
+// Operations on bytes, the same for the interpreter and the VM
+//
+// Bytes are immutable like strings, copies share them. Failures are the
+// message the runtimes raise
+use std::fmt;
+
+use crate::number;
+
+// The encodings `encode` and `decode` know, by the name scripts give
+const ENCODINGS: [&str; 3] = ["utf-8", "ascii", "latin-1"];
+
+// Bytes shown by `print` at most, the rest are `...`
+const SHOWN: usize = 16;
+
+// How `print` shows bytes, in hex
+pub fn format(bytes: &[u8], f: &mut fmt::Formatter) -> fmt::Result {
+    let mut shown: Vec<String> = bytes
+        .iter()
+        .take(SHOWN)
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    if bytes.len() > SHOWN {
+        shown.push("...".to_string());
+    }
+    write!(f, "Bytes <{}>", shown.join(" "))
+}
+
+fn unknown(encoding: &str) -> String {
+    format!(
+        "Unknown encoding `{}`, expected `{}`",
+        encoding,
+        ENCODINGS.join("`, `")
+    )
+}
+
+// The text in the encoding, an error for a character it can't hold
+pub fn encode(text: &str, encoding: &str) -> Result<Vec<u8>, String> {
+    let limit = match encoding {
+        "utf-8" => return Ok(text.as_bytes().to_vec()),
+        "ascii" => 0x7f,
+        "latin-1" => 0xff,
+        _ => return Err(unknown(encoding)),
+    };
+    text.chars()
+        .map(|character| match u32::from(character) {
+            code if code <= limit => Ok(code as u8),
+            _ => Err(format!("Can't encode {:?} as {}", character, encoding)),
+        })
+        .collect()
+}
+
+// The text the bytes hold in the encoding, an error for bytes it can't have
+pub fn decode(bytes: &[u8], encoding: &str) -> Result<String, String> {
+    let invalid = |index: usize| {
+        format!(
+            "Can't decode byte {} at {} as {}",
+            bytes[index], index, encoding
+        )
+    };
+    match encoding {
+        "utf-8" => String::from_utf8(bytes.to_vec())
+            .map_err(|error| invalid(error.utf8_error().valid_up_to())),
+        "ascii" => match bytes.iter().position(|byte| !byte.is_ascii()) {
+            Some(index) => Err(invalid(index)),
+            None => Ok(bytes.iter().map(|&byte| char::from(byte)).collect()),
+        },
+        "latin-1" => Ok(bytes.iter().map(|&byte| char::from(byte)).collect()),
+        _ => Err(unknown(encoding)),
+    }
+}
+
+// The bytes from `start` up to, not including, `end`, which have to be whole
+// numbers with `start` no later than `end`, within the bytes
+pub fn slice(bytes: &[u8], start: f64, end: f64) -> Result<&[u8], String> {
+    let whole = |bound: f64| bound.fract() == 0.0 && bound >= 0.0;
+    if !whole(start) || !whole(end) || start > end || end > bytes.len() as f64 {
+        return Err(format!(
+            "Slice from {} to {} is out of range for {} bytes",
+            number::format(start),
+            number::format(end),
+            bytes.len()
+        ));
+    }
+    Ok(&bytes[start as usize..end as usize])
+}
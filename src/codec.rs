@@ -0,0 +1,118 @@
+// What the `hash` and `codec` modules compute, the same for the interpreter and
+// the VM. Data is the bytes of a string or bytes as they are
+//
+// Failures are the message the runtimes raise, for text that isn't in the
+// encoding it is decoded from
+use md5::Md5;
+use sha2::{Digest, Sha256};
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// The MD5 digest, as hex
+pub fn md5(data: &[u8]) -> String {
+    hex_encode(&Md5::digest(data))
+}
+
+// The SHA-256 digest, as hex
+pub fn sha256(data: &[u8]) -> String {
+    hex_encode(&Sha256::digest(data))
+}
+
+// Standard base64, padded with `=`
+pub fn base64_encode(data: &[u8]) -> String {
+    let mut text = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (index, &byte)| {
+            bits | u32::from(byte) << (16 - 8 * index)
+        });
+        for index in 0..4 {
+            match index <= chunk.len() {
+                true => text.push(BASE64[(bits >> (18 - 6 * index) & 0x3f) as usize] as char),
+                false => text.push('='),
+            }
+        }
+    }
+    text
+}
+
+// Standard base64, with or without the padding
+pub fn base64_decode(text: &str) -> Result<Vec<u8>, String> {
+    let digits = text.trim_end_matches('=');
+    if text.len() - digits.len() > 2 || digits.len() % 4 == 1 {
+        return Err(format!("Invalid base64 length {}", text.len()));
+    }
+    let mut data = Vec::with_capacity(digits.len() * 3 / 4);
+    let (mut bits, mut count) = (0u32, 0);
+    for (index, character) in digits.char_indices() {
+        let Some(value) = BASE64.iter().position(|&digit| digit as char == character) else {
+            return Err(format!("Invalid base64 {:?} at {}", character, index));
+        };
+        bits = bits << 6 | value as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            data.push((bits >> count) as u8);
+        }
+    }
+    Ok(data)
+}
+
+// Two lowercase digits a byte
+pub fn hex_encode(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Two digits a byte, in either case
+pub fn hex_decode(text: &str) -> Result<Vec<u8>, String> {
+    if !text.len().is_multiple_of(2) {
+        return Err(format!("Invalid hex length {}", text.len()));
+    }
+    let digits = text.as_bytes();
+    (0..digits.len())
+        .step_by(2)
+        .map(|index| {
+            byte(&digits[index..index + 2]).ok_or_else(|| format!("Invalid hex at {}", index))
+        })
+        .collect()
+}
+
+// The byte two hex digits make, none for anything else, signs included
+fn byte(pair: &[u8]) -> Option<u8> {
+    let digit = |digit: u8| (digit as char).to_digit(16);
+    Some((digit(pair[0])? * 16 + digit(pair[1])?) as u8)
+}
+
+// Every byte but letters, digits and `-_.~` as `%XX`, like a URL component
+pub fn url_encode(data: &[u8]) -> String {
+    let mut text = String::with_capacity(data.len());
+    for &byte in data {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                text.push(byte as char)
+            }
+            _ => text.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    text
+}
+
+// `%XX` back to the byte, the text it makes has to be UTF-8
+pub fn url_decode(text: &str) -> Result<String, String> {
+    let encoded = text.as_bytes();
+    let mut data = Vec::with_capacity(encoded.len());
+    let mut index = 0;
+    while index < encoded.len() {
+        if encoded[index] != b'%' {
+            data.push(encoded[index]);
+            index += 1;
+            continue;
+        }
+        let escaped = encoded
+            .get(index + 1..index + 3)
+            .and_then(byte)
+            .ok_or_else(|| format!("Invalid escape at {}", index))?;
+        data.push(escaped);
+        index += 3;
+    }
+    String::from_utf8(data).map_err(|_| "The decoded URL isn't UTF-8".to_string())
+}
@@ -0,0 +1,134 @@
+use std::collections::BTreeMap;
+
+use crate::parser::stmt::Stmt;
+
+// Which lines of a program ran, and how many times
+//
+// A line counts when a statement starting on it is executed. Lines with no
+// statement (comments, closing braces) are not part of the report, and
+// blocks only count through the statements in them
+pub struct Coverage {
+    hits: BTreeMap<usize, usize>, // Line, number of statements executed on it
+}
+
+impl Coverage {
+    // Every line a statement of the program starts on, none of them hit yet
+    pub fn new(statements: &[Stmt]) -> Coverage {
+        let mut coverage = Coverage {
+            hits: BTreeMap::new(),
+        };
+        for statement in statements {
+            coverage.add_lines(statement);
+        }
+        coverage
+    }
+
+    fn add_lines(&mut self, stmt: &Stmt) {
+        if let Some(line) = Coverage::counted_line(stmt) {
+            self.hits.entry(line).or_insert(0);
+        }
+
+        match stmt {
+            Stmt::Block(statements) => statements.iter().for_each(|stmt| self.add_lines(stmt)),
+            Stmt::Class(_, _, _, methods, _) => {
+                // Methods are not executed as statements, only their bodies are
+                for method in methods.iter() {
+                    if let Stmt::Function(_, _, body, ..) = method {
+                        body.iter().for_each(|stmt| self.add_lines(stmt));
+                    }
+                }
+            }
+            Stmt::Function(_, _, body, ..) => body.iter().for_each(|stmt| self.add_lines(stmt)),
+            Stmt::ForLoop(initializer, _, _, body) => {
+                if let Some(initializer) = initializer {
+                    self.add_lines(initializer);
+                }
+                self.add_lines(body);
+            }
+            Stmt::IfElse(_, then_branch, else_branch) => {
+                self.add_lines(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.add_lines(else_branch);
+                }
+            }
+            Stmt::WhileLoop(_, body) | Stmt::ForIn(_, _, body) => self.add_lines(body),
+            Stmt::With(_, _, _, body) => body.iter().for_each(|stmt| self.add_lines(stmt)),
+            Stmt::Try(body, catches) => {
+                body.iter().for_each(|stmt| self.add_lines(stmt));
+                for catch in catches.iter() {
+                    catch.body.iter().for_each(|stmt| self.add_lines(stmt));
+                }
+            }
+            Stmt::Expression(_)
+            | Stmt::Import(_, _)
+            | Stmt::Print(_)
+            | Stmt::Return(_, _)
+            | Stmt::Throw(_, _)
+            | Stmt::Yield(_, _, _)
+            | Stmt::Var(_, _, _)
+            | Stmt::Unpack(_, _)
+            | Stmt::Error(_) => {}
+        }
+    }
+
+    pub fn record(&mut self, stmt: &Stmt) {
+        if let Some(line) = Coverage::counted_line(stmt) {
+            *self.hits.entry(line).or_insert(0) += 1;
+        }
+    }
+
+    fn counted_line(stmt: &Stmt) -> Option<usize> {
+        match stmt {
+            Stmt::Block(_) => None,
+            stmt => stmt.line(),
+        }
+    }
+
+    pub fn lines_found(&self) -> usize {
+        self.hits.len()
+    }
+
+    pub fn lines_hit(&self) -> usize {
+        self.hits.values().filter(|hits| **hits > 0).count()
+    }
+
+    pub fn missed_lines(&self) -> Vec<usize> {
+        self.hits
+            .iter()
+            .filter(|(_, hits)| **hits == 0)
+            .map(|(line, _)| *line)
+            .collect()
+    }
+
+    // "Coverage: 9 of 10 lines (90.0%), not run: 7"
+    pub fn summary(&self) -> String {
+        let found = self.lines_found();
+        let hit = self.lines_hit();
+        let percent = match found {
+            0 => 100.0,
+            found => hit as f64 * 100.0 / found as f64,
+        };
+
+        let mut summary = format!("Coverage: {} of {} lines ({:.1}%)", hit, found, percent);
+        let missed = self.missed_lines();
+        if !missed.is_empty() {
+            let missed: Vec<String> = missed.iter().map(|line| line.to_string()).collect();
+            summary += &format!(", not run: {}", missed.join(", "));
+        }
+        summary
+    }
+
+    // The tracefile format of LCOV, read by genhtml and most coverage services
+    pub fn lcov(&self, source_file: &str) -> String {
+        let mut lcov = format!("TN:\nSF:{}\n", source_file);
+        for (line, hits) in self.hits.iter() {
+            lcov += &format!("DA:{},{}\n", line, hits);
+        }
+        lcov += &format!(
+            "LF:{}\nLH:{}\nend_of_record\n",
+            self.lines_found(),
+            self.lines_hit()
+        );
+        lcov
+    }
+}
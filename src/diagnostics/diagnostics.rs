@@ -0,0 +1,227 @@
+use crate::{
+    error::{Error, Severity, Warning},
+    lexer::token::Span,
+};
+
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+// Renders errors and warnings with the line of source they point at
+//
+//     RuntimeError[E0003]: Undefined Variable q
+//      --> script.lox:3:9
+//       |
+//     3 |   print q;
+//       |         ^
+//
+// The caret underlines the span of the diagnostic, up to the end of its first line
+//
+// For tools, every diagnostic can be a JSON object on its own line instead
+//
+//     {"severity":"error","kind":"RuntimeError","code":"E0003","message":"Undefined Variable q","file":"script.lox","line":3,"column":9}
+pub struct Renderer<'a> {
+    name: &'a str,   // Shown in the location, usually the path of the script
+    source: &'a str, // Text the spans of the errors point into
+    style: Style,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Style {
+    Plain,
+    Color, // ANSI colors, for terminals
+    Json,  // One object per line, for editors and CI
+}
+
+impl<'a> Renderer<'a> {
+    pub fn new(name: &'a str, source: &'a str, style: Style) -> Renderer<'a> {
+        Renderer {
+            name,
+            source,
+            style,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        self.name
+    }
+
+    pub fn render(&self, error: &Error) -> String {
+        self.render_diagnostic(
+            Severity::Error,
+            error.name(),
+            error.code(),
+            &error.message(),
+            error.span(),
+        )
+    }
+
+    pub fn render_warning(&self, warning: &Warning) -> String {
+        self.render_diagnostic(
+            Severity::Warning,
+            "Warning",
+            warning.code(),
+            &warning.message,
+            warning.span,
+        )
+    }
+
+    pub fn report(&self, error: &Error) {
+        eprint!("{}", self.render(error));
+    }
+
+    pub fn report_warning(&self, warning: &Warning) {
+        eprint!("{}", self.render_warning(warning));
+    }
+
+    // "2 errors", "1 error, 3 warnings", nothing when there were none
+    // JSON consumers count the objects themselves
+    pub fn report_summary(&self, errors: usize, warnings: usize) {
+        if self.style == Style::Json {
+            return;
+        }
+
+        let counts: Vec<String> = [(errors, "error"), (warnings, "warning")]
+            .into_iter()
+            .filter(|(count, _)| *count > 0)
+            .map(|(count, noun)| match count {
+                1 => format!("1 {}", noun),
+                count => format!("{} {}s", count, noun),
+            })
+            .collect();
+        if !counts.is_empty() {
+            eprintln!("{}", counts.join(", "));
+        }
+    }
+
+    fn render_diagnostic(
+        &self,
+        severity: Severity,
+        name: &str,
+        code: &str,
+        message: &str,
+        span: Span,
+    ) -> String {
+        if self.style == Style::Json {
+            return self.render_json(severity, name, code, message, span);
+        }
+
+        let accent = match severity {
+            Severity::Error => RED,
+            Severity::Warning => YELLOW,
+        };
+        let mut rendered = format!(
+            "{}{}[{}]{}{}: {}{}\n",
+            self.paint(accent),
+            name,
+            code,
+            self.paint(RESET),
+            self.paint(BOLD),
+            message,
+            self.paint(RESET)
+        );
+
+        let gutter = " ".repeat(span.line.to_string().len());
+        rendered.push_str(&format!(
+            "{}{}-->{} {}:{}:{}\n",
+            gutter,
+            self.paint(BLUE),
+            self.paint(RESET),
+            self.name,
+            span.line,
+            span.column
+        ));
+
+        // Spans past the end of the source, like the one of EOF, have no line to show
+        let Some(line) = self.source.lines().nth(span.line.saturating_sub(1)) else {
+            return rendered;
+        };
+        let line = line.trim_start_matches('\u{feff}'); // Columns don't count the byte order mark
+
+        // Keeps the tabs before the span so the caret lines up with the code
+        let before: String = line
+            .chars()
+            .take(span.column.saturating_sub(1))
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let remaining = line.chars().count().saturating_sub(before.chars().count());
+        let width = (span.end - span.start).min(remaining).max(1);
+
+        rendered.push_str(&format!(
+            "{} {}|{}\n",
+            gutter,
+            self.paint(BLUE),
+            self.paint(RESET)
+        ));
+        rendered.push_str(&format!(
+            "{}{} |{} {}\n",
+            self.paint(BLUE),
+            span.line,
+            self.paint(RESET),
+            line
+        ));
+        rendered.push_str(&format!(
+            "{} {}|{} {}{}{}{}\n",
+            gutter,
+            self.paint(BLUE),
+            self.paint(RESET),
+            before,
+            self.paint(accent),
+            "^".repeat(width),
+            self.paint(RESET)
+        ));
+        rendered
+    }
+
+    fn render_json(
+        &self,
+        severity: Severity,
+        name: &str,
+        code: &str,
+        message: &str,
+        span: Span,
+    ) -> String {
+        let severity = match severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        format!(
+            "{{\"severity\":\"{}\",\"kind\":{},\"code\":{},\"message\":{},\"file\":{},\"line\":{},\"column\":{}}}\n",
+            severity,
+            json_string(name),
+            json_string(code),
+            json_string(message),
+            json_string(self.name),
+            span.line,
+            span.column
+        )
+    }
+
+    fn paint(&self, code: &'static str) -> &'static str {
+        match self.style {
+            Style::Color => code,
+            _ => "",
+        }
+    }
+}
+
+// Quoted and escaped as a JSON string
+fn json_string(string: &str) -> String {
+    let mut quoted = String::with_capacity(string.len() + 2);
+    quoted.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
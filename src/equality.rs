@@ -0,0 +1,89 @@
+// How `==` compares lists, maps and sets, and what can be a key of a map,
+// the same for the interpreter and the VM
+use std::collections::{hash_map::Entry, HashMap, HashSet};
+
+//
+// They are equal when they hold equal values: lists in the same order, maps the
+// same keys with equal values, and sets the same elements in any order. A value
+// can end up holding itself, so a pair already being compared further out is
+// taken as equal rather than compared again, which ends the comparison
+
+// Addresses of the pairs being compared, outermost first
+pub type Comparing = Vec<(*const (), *const ())>;
+
+// Runs `equal` for the values at `a` and `b`, unless they are the same one or
+// are already being compared
+pub fn deep(
+    a: *const (),
+    b: *const (),
+    comparing: &mut Comparing,
+    equal: impl FnOnce(&mut Comparing) -> bool,
+) -> bool {
+    if a == b || comparing.contains(&(a, b)) {
+        return true;
+    }
+    comparing.push((a, b));
+    let equal = equal(comparing);
+    comparing.pop();
+    equal
+}
+
+// A map key or set element, as Rust hashes and compares it
+//
+// Only values that are compared by what they are and never change can be one:
+// strings, numbers, booleans and nil. Equal keys are equal values, -0 being the
+// key of 0, except for NaN: as a key, it is the same as any NaN of the same bits
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub enum Key<'a> {
+    Nil,
+    Boolean(bool),
+    Number(u64), // The bits of the number
+    String(&'a str),
+}
+
+impl Key<'_> {
+    pub fn number(number: f64) -> Key<'static> {
+        match number == 0.0 {
+            true => Key::Number(0.0f64.to_bits()),
+            false => Key::Number(number.to_bits()),
+        }
+    }
+}
+
+// Each key once, where it was first given, with the last value given for it
+// `key` gives the Key of every one of them
+pub fn unique_entries<T: Clone, V: Clone>(
+    entries: &[(T, V)],
+    key: fn(&T) -> Option<Key<'_>>,
+) -> Vec<(T, V)> {
+    let mut positions: HashMap<Key, usize> = HashMap::with_capacity(entries.len());
+    let mut unique: Vec<(T, V)> = Vec::with_capacity(entries.len());
+    for (entry_key, value) in entries.iter() {
+        let Some(hashed) = key(entry_key) else {
+            unreachable!("keys are checked before")
+        };
+        match positions.entry(hashed) {
+            Entry::Occupied(position) => unique[*position.get()].1 = value.clone(),
+            Entry::Vacant(position) => {
+                position.insert(unique.len());
+                unique.push((entry_key.clone(), value.clone()));
+            }
+        }
+    }
+    unique
+}
+
+// Each element once, where it was first given
+pub fn unique_elements<T: Clone>(elements: &[T], key: fn(&T) -> Option<Key<'_>>) -> Vec<T> {
+    let mut seen: HashSet<Key> = HashSet::with_capacity(elements.len());
+    elements
+        .iter()
+        .filter(|element| {
+            let Some(hashed) = key(element) else {
+                unreachable!("elements are checked before")
+            };
+            seen.insert(hashed)
+        })
+        .cloned()
+        .collect()
+}
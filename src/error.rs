@@ -1,36 +1,314 @@
-use std::io::{self, Write};
+use std::{
+    borrow::Cow,
+    fmt,
+    io::{self, Write},
+};
+
+use crate::lexer::token::Span;
+
+// Phase an error comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    Lex,
+    Parse,
+    Runtime,
+    Type,
+    Compile,
+}
+
+impl ErrorKind {
+    // Name errors of the kind are reported with
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorKind::Lex => "LexError",
+            ErrorKind::Parse => "ParseError",
+            ErrorKind::Runtime => "RuntimeError",
+            ErrorKind::Type => "TypeError",
+            ErrorKind::Compile => "CompileError",
+        }
+    }
+
+    // Stable code, E0001-style
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorKind::Lex => "E0001",
+            ErrorKind::Parse => "E0002",
+            ErrorKind::Runtime => "E0003",
+            ErrorKind::Type => "E0004",
+            ErrorKind::Compile => "E0005",
+        }
+    }
+}
+
+// Built-in class a runtime error is raised as, for `catch` to tell errors apart
+// They are globals of both backends, the others inherit from `Error`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    Error,
+    TypeError,      // A value of the wrong kind, like calling a number
+    NameError,      // A variable or property that doesn't exist
+    IndexError,     // An index out of range, or a key not in a map
+    DivisionByZero, // Division is IEEE, only ever thrown by scripts
+}
+
+impl ErrorClass {
+    // Superclasses first
+    pub const ALL: [ErrorClass; 5] = [
+        ErrorClass::Error,
+        ErrorClass::TypeError,
+        ErrorClass::NameError,
+        ErrorClass::IndexError,
+        ErrorClass::DivisionByZero,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorClass::Error => "Error",
+            ErrorClass::TypeError => "TypeError",
+            ErrorClass::NameError => "NameError",
+            ErrorClass::IndexError => "IndexError",
+            ErrorClass::DivisionByZero => "DivisionByZero",
+        }
+    }
+
+    pub fn superclass(&self) -> Option<ErrorClass> {
+        match self {
+            ErrorClass::Error => None,
+            _ => Some(ErrorClass::Error),
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub enum Error {
-    LexError(String, usize),
-    ParseError(String, usize),
-    InterpretError(String, usize),
+    LexError(String, Span),
+    ParseError(String, Span),
+    InterpretError(String, Span, ErrorClass),
+    // Raised by `throw`, the backend keeps the value thrown until it is caught
+    Thrown(String, Span),
+    TypeError(String, Span),
+    CompileError(String, Span),
+    // A string or bracket never closed, of the lexer or the parser, reported at
+    // the first span and opened at the second. The message is made when it is
+    // shown, so the line it was opened at follows edits that move it
+    Unclosed(ErrorKind, String, Span, Span),
 }
 
 impl Error {
-    pub fn lexer(message: String, line: usize) -> Error {
-        Error::LexError(message, line)
+    pub fn lexer(message: String, span: Span) -> Error {
+        Error::LexError(message, span)
     }
 
-    pub fn parser(message: String, line: usize) -> Error {
-        Error::ParseError(message, line)
+    pub fn parser(message: String, span: Span) -> Error {
+        Error::ParseError(message, span)
     }
 
-    pub fn interpreter(message: String, line: usize) -> Error {
-        Error::InterpretError(message, line)
+    pub fn interpreter(message: String, span: Span) -> Error {
+        Error::InterpretError(message, span, ErrorClass::Error)
     }
 
-    pub fn report(&self) {
+    // What is unclosed, `string` or the bracket in backticks
+    pub fn unclosed(kind: ErrorKind, what: String, span: Span, opened: Span) -> Error {
+        Error::Unclosed(kind, what, span, opened)
+    }
+
+    pub fn thrown(message: String, span: Span) -> Error {
+        Error::Thrown(message, span)
+    }
+
+    // The runtime error raised as an instance of `class` instead of `Error`
+    pub fn raised_as(self, class: ErrorClass) -> Error {
         match self {
-            Error::LexError(message, line) => {
-                let _ = writeln!(io::stderr(), "LexError: {} at line {}", message, line);
-            }
-            Error::ParseError(message, line) => {
-                let _ = writeln!(io::stderr(), "ParseError: {} at line {}", message, line);
-            }
-            Error::InterpretError(message, line) => {
-                let _ = writeln!(io::stderr(), "RuntimeError: {} at line {}", message, line);
+            Error::InterpretError(message, span, _) => Error::InterpretError(message, span, class),
+            error => error,
+        }
+    }
+
+    // The same error with another message, reported at another place
+    pub fn moved(self, message: String, span: Span) -> Error {
+        match self {
+            Error::LexError(..) => Error::LexError(message, span),
+            Error::ParseError(..) => Error::ParseError(message, span),
+            Error::InterpretError(_, _, class) => Error::InterpretError(message, span, class),
+            Error::Thrown(..) => Error::Thrown(message, span),
+            Error::TypeError(..) => Error::TypeError(message, span),
+            Error::CompileError(..) => Error::CompileError(message, span),
+            Error::Unclosed(ErrorKind::Lex, ..) => Error::LexError(message, span),
+            Error::Unclosed(..) => Error::ParseError(message, span),
+        }
+    }
+
+    pub fn type_checker(message: String, span: Span) -> Error {
+        Error::TypeError(message, span)
+    }
+
+    pub fn compiler(message: String, span: Span) -> Error {
+        Error::CompileError(message, span)
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::LexError(..) => ErrorKind::Lex,
+            Error::ParseError(..) => ErrorKind::Parse,
+            Error::InterpretError(..) | Error::Thrown(..) => ErrorKind::Runtime,
+            Error::TypeError(..) => ErrorKind::Type,
+            Error::CompileError(..) => ErrorKind::Compile,
+            Error::Unclosed(kind, ..) => *kind,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.kind().name()
+    }
+
+    pub fn code(&self) -> &'static str {
+        self.kind().code()
+    }
+
+    pub fn message(&self) -> Cow<'_, str> {
+        match self {
+            Error::LexError(message, _)
+            | Error::ParseError(message, _)
+            | Error::InterpretError(message, _, _)
+            | Error::Thrown(message, _)
+            | Error::TypeError(message, _)
+            | Error::CompileError(message, _) => Cow::Borrowed(message),
+            Error::Unclosed(_, what, _, opened) => {
+                Cow::Owned(format!("Unclosed {} opened at line {}", what, opened.line))
             }
-        };
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        match self {
+            Error::LexError(_, span)
+            | Error::ParseError(_, span)
+            | Error::InterpretError(_, span, _)
+            | Error::Thrown(_, span)
+            | Error::TypeError(_, span)
+            | Error::CompileError(_, span)
+            | Error::Unclosed(_, _, span, _) => *span,
+        }
+    }
+
+    pub fn span_mut(&mut self) -> &mut Span {
+        match self {
+            Error::LexError(_, span)
+            | Error::ParseError(_, span)
+            | Error::InterpretError(_, span, _)
+            | Error::Thrown(_, span)
+            | Error::TypeError(_, span)
+            | Error::CompileError(_, span)
+            | Error::Unclosed(_, _, span, _) => span,
+        }
+    }
+
+    // Where what an error is about started, apart from where it was found
+    pub fn opened_mut(&mut self) -> Option<&mut Span> {
+        match self {
+            Error::Unclosed(_, _, _, opened) => Some(opened),
+            _ => None,
+        }
+    }
+
+    // A single line, see Renderer for one showing the source
+    pub fn report(&self) {
+        let _ = writeln!(io::stderr(), "{}", self);
+    }
+}
+
+// Calling with the wrong number of arguments, the same for the interpreter and the VM
+// `add` expects 2 arguments but got 1
+pub fn arity_message(name: &str, expected: usize, got: usize) -> String {
+    let plural = if expected == 1 { "" } else { "s" };
+    format!("`{name}` expects {expected} argument{plural} but got {got}")
+}
+
+// Of a native taking any number of arguments after the first ones
+pub fn variadic_arity_message(name: &str, least: usize, got: usize) -> String {
+    let plural = if least == 1 { "" } else { "s" };
+    format!("`{name}` expects at least {least} argument{plural} but got {got}")
+}
+
+// RuntimeError: Undefined Variable q at line 3, column 9
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {} at {}", self.name(), self.message(), self.span())
+    }
+}
+
+impl std::error::Error for Error {
+    // Errors are raised by the phases themselves, never caused by another error
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+}
+
+// What a diagnostic means for the program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,   // The program can't run
+    Warning, // Probably a mistake, but the program still runs
+}
+
+// What a warning is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WarningKind {
+    UnusedVariable,
+    UnusedParameter,
+    UnreachableCode,
+    AssignmentCondition,
+    Shadowing,
+    ImplicitTruthiness,
+    SelfInitializer,
+}
+
+impl WarningKind {
+    // Stable code, W0001-style
+    pub fn code(&self) -> &'static str {
+        match self {
+            WarningKind::UnusedVariable => "W0001",
+            WarningKind::UnusedParameter => "W0002",
+            WarningKind::UnreachableCode => "W0003",
+            WarningKind::AssignmentCondition => "W0004",
+            WarningKind::Shadowing => "W0005",
+            WarningKind::ImplicitTruthiness => "W0006",
+            WarningKind::SelfInitializer => "W0007",
+        }
+    }
+}
+
+// Non-fatal diagnostics, reported without stopping the program
+// unless warnings are denied
+#[derive(Debug, Clone)]
+pub struct Warning {
+    pub kind: WarningKind,
+    pub message: String,
+    pub span: Span,
+}
+
+impl Warning {
+    pub fn new(kind: WarningKind, message: String, span: Span) -> Warning {
+        Warning {
+            kind,
+            message,
+            span,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        self.kind.code()
+    }
+
+    // A single line, see Renderer for one showing the source
+    pub fn report(&self) {
+        let _ = writeln!(io::stderr(), "{}", self);
+    }
+}
+
+// Warning: Unused variable `a` at line 3, column 9
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Warning: {} at {}", self.message, self.span)
     }
 }
@@ -0,0 +1,171 @@
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+    time::Duration,
+};
+
+use crate::shared::{Lock, Ref, Sendable, Shared};
+
+// Where the async natives wait for what they started
+//
+// `sleep_async()` and `read_file_async()` hand an Operation to the Executor and
+// return a pending Promise at once, so several operations can be under way at the
+// same time. `await` settles promises with the outcomes the executor sends back,
+// in the order they come, until the awaited one is settled. A coroutine awaiting
+// a pending promise stops instead, and `run()` waits for whichever of the
+// promises its coroutines stopped at settles first.
+//
+// Only the thread running the script waits: the operations run wherever the
+// executor runs them, another thread for each by default, or the tasks of an
+// embedder's async runtime
+pub struct EventLoop {
+    executor: Box<dyn Executor>,
+    pending: HashMap<usize, Shared<Promise>>, // By the id of their operation
+    next_id: usize,
+    sender: Sender<(usize, Result<Outcome, String>)>, // Cloned into every Completion
+    receiver: Receiver<(usize, Result<Outcome, String>)>,
+}
+
+// What an async native asks to be done
+#[derive(Debug)]
+pub enum Operation {
+    Sleep(Duration),
+    ReadFile(String), // The path
+}
+
+// What an operation gives back, plain data so it can come from another thread
+#[derive(Debug, Clone)]
+pub enum Outcome {
+    Nil,
+    String(String),
+}
+
+#[derive(Debug)]
+pub enum Promise {
+    Pending,
+    Resolved(Outcome),
+    Rejected(String), // The message of the error `await` fails with
+}
+
+// Runs operations, each has to be completed once, from any thread
+pub trait Executor: Sendable {
+    fn start(&mut self, operation: Operation, completion: Completion);
+}
+
+// How an executor hands the outcome of an operation back to the event loop
+pub struct Completion {
+    id: usize,
+    sender: Sender<(usize, Result<Outcome, String>)>,
+}
+
+// A thread for every operation
+pub struct Threads;
+
+impl EventLoop {
+    pub fn new() -> EventLoop {
+        let (sender, receiver) = mpsc::channel();
+        EventLoop {
+            executor: Box::new(Threads),
+            pending: HashMap::new(),
+            next_id: 0,
+            sender,
+            receiver,
+        }
+    }
+
+    // Where the operations run instead of on threads of their own
+    pub fn set_executor(&mut self, executor: Box<dyn Executor>) {
+        self.executor = executor;
+    }
+
+    pub fn start(&mut self, operation: Operation) -> Shared<Promise> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let promise = Ref::new(Lock::new(Promise::Pending));
+        self.pending.insert(id, Ref::clone(&promise));
+
+        let completion = Completion {
+            id,
+            sender: self.sender.clone(),
+        };
+        self.executor.start(operation, completion);
+        promise
+    }
+
+    // Settles promises as their operations complete, until this one is settled
+    pub fn wait(&mut self, promise: &Shared<Promise>) {
+        while matches!(*promise.borrow(), Promise::Pending) {
+            self.wait_any();
+        }
+    }
+
+    // Waits for the next operation to complete, and settles its promise
+    pub fn wait_any(&mut self) {
+        let Ok((id, result)) = self.receiver.recv() else {
+            unreachable!("The event loop keeps a sender")
+        };
+        self.settle(id, result);
+    }
+
+    // Settles the promises of the operations that have completed, without waiting
+    pub fn poll(&mut self) {
+        while let Ok((id, result)) = self.receiver.try_recv() {
+            self.settle(id, result);
+        }
+    }
+
+    fn settle(&mut self, id: usize, result: Result<Outcome, String>) {
+        if let Some(settled) = self.pending.remove(&id) {
+            *settled.borrow_mut() = match result {
+                Ok(outcome) => Promise::Resolved(outcome),
+                Err(message) => Promise::Rejected(message),
+            };
+        }
+    }
+}
+
+impl Default for EventLoop {
+    fn default() -> EventLoop {
+        EventLoop::new()
+    }
+}
+
+impl Operation {
+    // Does it on the current thread, for executors that have no better way
+    pub fn perform(self) -> Result<Outcome, String> {
+        match self {
+            Operation::Sleep(duration) => {
+                thread::sleep(duration);
+                Ok(Outcome::Nil)
+            }
+            Operation::ReadFile(path) => fs::read_to_string(&path)
+                .map(Outcome::String)
+                .map_err(|error| format!("Can't read {:?}: {}", path, error)),
+        }
+    }
+}
+
+impl Completion {
+    pub fn complete(self, result: Result<Outcome, String>) {
+        // The event loop is gone when the script has ended, nobody waits then
+        let _ = self.sender.send((self.id, result));
+    }
+}
+
+impl Executor for Threads {
+    fn start(&mut self, operation: Operation, completion: Completion) {
+        thread::spawn(move || completion.complete(operation.perform()));
+    }
+}
+
+impl fmt::Display for Promise {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Promise::Pending => write!(f, "Promise <pending>"),
+            Promise::Resolved(_) => write!(f, "Promise <resolved>"),
+            Promise::Rejected(_) => write!(f, "Promise <rejected>"),
+        }
+    }
+}
@@ -0,0 +1,76 @@
+// What the `fs` module does to the filesystem, the same for the interpreter and
+// the VM, which turn the results into their values
+//
+// Failures are the message the runtimes raise, with the path and the reason
+use std::{fs, io, time::UNIX_EPOCH};
+
+// What `fs.metadata` tells about a path
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metadata {
+    pub size: u64,     // In bytes
+    pub modified: f64, // Seconds since the Unix epoch, like `clock()`
+    pub directory: bool,
+}
+
+// The names in the directory, sorted so every system lists them the same
+pub fn list_dir(path: &str) -> Result<Vec<String>, String> {
+    let mut names = fs::read_dir(path)
+        .and_then(|entries| {
+            entries
+                .map(|entry| entry.map(|entry| entry.file_name().to_string_lossy().into_owned()))
+                .collect::<io::Result<Vec<String>>>()
+        })
+        .map_err(|error| format!("Can't list {:?}: {}", path, error))?;
+    names.sort();
+    Ok(names)
+}
+
+// Everything in the file, as it is
+pub fn read_bytes(path: &str) -> Result<Vec<u8>, String> {
+    fs::read(path).map_err(|error| format!("Can't read {:?}: {}", path, error))
+}
+
+// Makes the directories of the path that don't exist yet, nothing when all do
+pub fn mkdir(path: &str) -> Result<(), String> {
+    fs::create_dir_all(path).map_err(|error| format!("Can't make {:?}: {}", path, error))
+}
+
+// A file, or a directory with nothing in it
+pub fn remove(path: &str) -> Result<(), String> {
+    let removed = match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir(path),
+        Ok(_) => fs::remove_file(path),
+        Err(error) => Err(error),
+    };
+    removed.map_err(|error| format!("Can't remove {:?}: {}", path, error))
+}
+
+// A file, or a directory with everything in it
+pub fn remove_all(path: &str) -> Result<(), String> {
+    let removed = match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(error) => Err(error),
+    };
+    removed.map_err(|error| format!("Can't remove {:?}: {}", path, error))
+}
+
+// Moves a file or directory, replacing a file already at `to`
+pub fn rename(from: &str, to: &str) -> Result<(), String> {
+    fs::rename(from, to).map_err(|error| format!("Can't rename {:?} to {:?}: {}", from, to, error))
+}
+
+pub fn metadata(path: &str) -> Result<Metadata, String> {
+    let failed = |error: io::Error| format!("Can't read the metadata of {:?}: {}", path, error);
+    let metadata = fs::metadata(path).map_err(failed)?;
+    let modified = metadata.modified().map_err(failed)?;
+    Ok(Metadata {
+        size: metadata.len(),
+        // Before the epoch is negative
+        modified: match modified.duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_secs_f64(),
+            Err(before) => -before.duration().as_secs_f64(),
+        },
+        directory: metadata.is_dir(),
+    })
+}
@@ -0,0 +1,41 @@
+use std::thread;
+
+use crate::{
+    error::Error, interpreter::interpreter::STACK_SIZE, lexer::lexer::Lexer, parser::parser::Parser,
+};
+
+// Entry points for the fuzz targets in fuzz/, see the README
+//
+// Any bytes are a program, UTF-8 or not. Lexing and parsing them never panics,
+// whatever is wrong with them comes back as errors
+
+// The errors of the lexer
+pub fn fuzz_lex(data: &[u8]) -> Vec<Error> {
+    let mut lexer = Lexer::from_reader(data);
+    lexer.scan();
+    lexer.errors
+}
+
+// The errors of the lexer and the parser, on a thread with the stack parsing
+// the deepest nesting allowed takes
+pub fn fuzz_parse(data: &[u8]) -> Vec<Error> {
+    thread::scope(|scope| {
+        thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn_scoped(scope, || parse(data))
+            .expect("Can't start a thread to parse on")
+            .join()
+            .expect("Parsing never panics")
+    })
+}
+
+fn parse(data: &[u8]) -> Vec<Error> {
+    let mut lexer = Lexer::from_reader(data);
+    lexer.scan();
+    let mut errors = std::mem::take(&mut lexer.errors);
+
+    let mut parser = Parser::new(lexer.tokens);
+    parser.parse();
+    errors.extend(parser.get_errors().iter().cloned());
+    errors
+}
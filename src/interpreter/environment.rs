@@ -1,54 +1,268 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::collections::HashMap;
 
-use super::types::Type;
-use crate::{error::*, lexer::token::Token};
+use super::{
+    heap::EnvironmentId,
+    types::{Traced, Type},
+};
+use crate::{
+    error::*,
+    lexer::token::Token,
+    shared::{Lock, Ref, Shared},
+};
 
+// Indices of global names, of the Globals of an interpreter and of the Resolver
+// resolving its programs, which gives their globals the indices the Globals
+// keep them at. Only declarations and resolving number names
+#[derive(Debug, Clone, Default)]
+pub struct GlobalNames(Shared<HashMap<Ref<str>, usize>>);
+
+impl GlobalNames {
+    // The index of `name`, numbering it if it has none yet
+    pub fn index(&self, name: &Ref<str>) -> usize {
+        let mut indices = self.0.borrow_mut();
+        let next = indices.len();
+        *indices.entry(Ref::clone(name)).or_insert(next)
+    }
+
+    pub fn get(&self, name: &str) -> Option<usize> {
+        self.0.borrow().get(name).copied()
+    }
+}
+
+// The global environment, globals are kept by the index of their name
+//
+// The Resolver gives every global variable of a program the index of its name,
+// so reading and assigning a global goes straight to its value. Names are only
+// looked up when declaring, for code that wasn't resolved, and for code resolved
+// with the names of other Globals, whose indices hold other names
+#[derive(Debug, Default)]
+pub struct Globals {
+    names: GlobalNames,
+    values: Vec<Option<(Ref<str>, Type)>>, // None where the name isn't defined
+}
+
+impl Globals {
+    pub fn new() -> Globals {
+        Globals::default()
+    }
+
+    // Globals numbering their names in `names`, which other Globals may share
+    pub fn with_names(names: GlobalNames) -> Globals {
+        Globals {
+            names,
+            values: Vec::new(),
+        }
+    }
+
+    // For the Resolver, see GlobalNames
+    pub fn global_names(&self) -> GlobalNames {
+        self.names.clone()
+    }
+
+    pub fn define(&mut self, name: Ref<str>, value: Type) {
+        let index = self.names.index(&name);
+        if index >= self.values.len() {
+            self.values.resize_with(index + 1, || None);
+        }
+        self.values[index] = Some((name, value));
+    }
+
+    pub fn assign(&mut self, variable_token: &Token, value: Type) -> Result<(), Error> {
+        match self.names.get(&variable_token.lexeme) {
+            Some(index) => self.assign_at(index, variable_token, value),
+            None => Err(undefined(variable_token)),
+        }
+    }
+
+    pub fn assign_at(
+        &mut self,
+        index: usize,
+        variable_token: &Token,
+        value: Type,
+    ) -> Result<(), Error> {
+        match self.values.get_mut(index) {
+            Some(Some((name, global))) if *name == variable_token.lexeme => {
+                *global = value;
+                Ok(())
+            }
+            Some(Some(_)) => self.assign(variable_token, value),
+            _ => Err(undefined(variable_token)),
+        }
+    }
+
+    pub fn get(&self, variable_token: &Token) -> Result<Type, Error> {
+        match self.names.get(&variable_token.lexeme) {
+            Some(index) => self.get_at(index, variable_token),
+            None => Err(undefined(variable_token)),
+        }
+    }
+
+    pub fn get_at(&self, index: usize, variable_token: &Token) -> Result<Type, Error> {
+        match self.values.get(index) {
+            Some(Some((name, value))) if *name == variable_token.lexeme => Ok(value.clone()),
+            Some(Some(_)) => self.get(variable_token),
+            _ => Err(undefined(variable_token)),
+        }
+    }
+
+    pub fn value(&self, name: &str) -> Option<Type> {
+        let index = self.names.get(name)?;
+        let (_, value) = self.values.get(index)?.as_ref()?;
+        Some(value.clone())
+    }
+
+    // Every global with its value, in the order of the indices of their names
+    pub fn bindings(&self) -> impl Iterator<Item = (&Ref<str>, &Type)> {
+        self.values
+            .iter()
+            .flatten()
+            .map(|(name, value)| (name, value))
+    }
+
+    // For the REPL's completion
+    pub fn names(&self) -> Vec<Ref<str>> {
+        self.values
+            .iter()
+            .flatten()
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+}
+
+fn undefined(variable_token: &Token) -> Error {
+    Error::interpreter(
+        format!("Undefined Variable {}", variable_token.lexeme),
+        variable_token.span,
+    )
+    .raised_as(ErrorClass::NameError)
+}
+
+// A local environment, kept in the Heap
+// Locals are looked up by the slots the Resolver gave them
 #[derive(Debug)]
 pub struct Environment {
-    enclosing: Option<Rc<RefCell<Environment>>>, // Parent Environment
-    values: HashMap<String, Type>,               // Current Scope
+    pub enclosing: Option<EnvironmentId>, // Parent Environment, none in front of the globals
+    names: Vec<Ref<str>>,                 // Locals, in declaration order
+    slots: Vec<Local>,                    // Values of the locals, same order
+}
+
+// A local is moved into a box the first time a function captures it, the
+// function keeps the box, so both see every assignment made after that
+#[derive(Debug)]
+enum Local {
+    Value(Type),
+    Captured(Shared<Type>),
+}
+
+impl Local {
+    fn get(&self) -> Type {
+        match self {
+            Local::Value(value) => value.clone(),
+            Local::Captured(captured) => captured.borrow().clone(),
+        }
+    }
+
+    fn set(&mut self, value: Type) {
+        match self {
+            Local::Value(local) => *local = value,
+            Local::Captured(captured) => *captured.borrow_mut() = value,
+        }
+    }
 }
 
 impl Environment {
-    pub fn new(enclosing: Option<Rc<RefCell<Environment>>>) -> Environment {
+    pub fn new(enclosing: Option<EnvironmentId>) -> Environment {
         Environment {
             enclosing,
-            values: HashMap::<String, Type>::new(),
+            names: Vec::new(),
+            slots: Vec::new(),
         }
     }
 
-    pub fn define(&mut self, name: String, value: Type) {
-        self.values.insert(name, value);
+    // Locals take the next slot, which is the one the Resolver expects them in
+    pub fn define(&mut self, name: Ref<str>, value: Type) {
+        self.names.push(name);
+        self.slots.push(Local::Value(value));
     }
 
+    // A local that is nil until set, returns its slot
+    pub fn declare(&mut self, name: Ref<str>) -> usize {
+        self.define(name, Type::Nil);
+        self.slots.len() - 1
+    }
+
+    // A local holding a variable captured from another environment, in the next slot
+    pub fn define_captured(&mut self, name: Ref<str>, captured: Shared<Type>) {
+        self.names.push(name);
+        self.slots.push(Local::Captured(captured));
+    }
+
+    pub fn get(&self, index: usize) -> Type {
+        self.slots[index].get()
+    }
+
+    pub fn set(&mut self, index: usize, value: Type) {
+        self.slots[index].set(value)
+    }
+
+    // The name and the box of the local at `index`, boxing it if it isn't yet
+    pub fn capture(&mut self, index: usize) -> (Ref<str>, Shared<Type>) {
+        let local = &mut self.slots[index];
+        let captured = match local {
+            Local::Captured(captured) => Ref::clone(captured),
+            Local::Value(value) => {
+                let captured = Ref::new(Lock::new(std::mem::replace(value, Type::Nil)));
+                *local = Local::Captured(Ref::clone(&captured));
+                captured
+            }
+        };
+        (self.names[index].clone(), captured)
+    }
+
+    // Every local with its value, in declaration order
+    pub fn bindings(&self) -> impl Iterator<Item = (&Ref<str>, Type)> {
+        self.names.iter().zip(self.slots.iter().map(Local::get))
+    }
+
+    // By name, the latest local with it: redeclaring a variable shadows the previous one
     pub fn assign(&mut self, variable_token: &Token, value: Type) -> Result<(), Error> {
-        match self.values.get(variable_token.lexeme.as_str()) {
-            Some(_) => {
-                self.values.insert(variable_token.lexeme.clone(), value);
+        match self
+            .names
+            .iter()
+            .rposition(|local| *local == variable_token.lexeme)
+        {
+            Some(index) => {
+                self.slots[index].set(value);
                 Ok(())
             }
-            None => match &self.enclosing {
-                Some(parent_environment) => parent_environment
-                    .borrow_mut()
-                    .assign(variable_token, value),
-                None => Err(Error::interpreter(
-                    format!("Undefined Variable {}", variable_token.lexeme),
-                    variable_token.line,
-                )),
-            },
+            None => Err(Error::interpreter(
+                format!("Undefined Variable {}", variable_token.lexeme),
+                variable_token.span,
+            )
+            .raised_as(ErrorClass::NameError)),
         }
     }
 
-    pub fn get(&self, variable_token: &Token) -> Result<Type, Error> {
-        match self.values.get(variable_token.lexeme.as_str()) {
-            Some(value) => Ok((*value).clone()),
-            None => match &self.enclosing {
-                Some(parent_environment) => parent_environment.borrow().get(variable_token),
-                None => Err(Error::interpreter(
-                    format!("Undefined Variable {}", variable_token.lexeme.as_str()),
-                    variable_token.line,
-                )),
-            },
+    // What this one keeps alive, for the garbage collector
+    // What captured locals keep alive is reached through their boxes instead
+    pub fn references(&self) -> Vec<Traced<'_>> {
+        let mut references: Vec<Traced> = self.enclosing.iter().map(Traced::Environment).collect();
+        for local in self.slots.iter() {
+            if let Local::Value(value) = local {
+                value.trace(&mut references);
+            }
         }
+        references
+    }
+
+    // Boxes of the captured locals, for the garbage collector
+    pub fn captured(&self) -> Vec<&Shared<Type>> {
+        self.slots
+            .iter()
+            .filter_map(|local| match local {
+                Local::Captured(captured) => Some(captured),
+                Local::Value(_) => None,
+            })
+            .collect()
     }
 }
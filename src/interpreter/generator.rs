@@ -0,0 +1,93 @@
+use std::{fmt, vec};
+
+use super::{
+    heap::EnvironmentId,
+    types::{ClassId, Type},
+};
+use crate::{
+    eventloop::eventloop::Promise,
+    lexer::token::Token,
+    parser::stmt::Stmt,
+    shared::{Ref, Shared},
+};
+
+// A call of a generator function, run a piece at a time
+//
+// Calling a function with a `yield` in its body makes one without running any of
+// it. Every time a value is asked for, the body runs from where it stopped up to
+// its next `yield`, which gives the value, or up to its end, which ends it.
+//
+// Where it stopped is the Frames of the statements its `yield` is in: running
+// on, the interpreter enters those statements again from their frames instead of
+// from their start, so a suspended generator keeps no Rust stack
+#[derive(Debug)]
+pub struct Generator {
+    pub name: Token,
+    pub declaration: Ref<Stmt>,     // Function statement
+    pub environment: EnvironmentId, // Of the call, holding the parameters
+    pub frames: Vec<Frame>,         // Innermost first, none before the first value
+    pub state: State,
+    pub spawned: bool, // A coroutine made by `spawn`, rather than by calling a generator function
+    pub owner: Option<ClassId>, // That of the function, see Function
+}
+
+#[derive(Debug, PartialEq)]
+pub enum State {
+    Suspended,
+    Running,
+    Done,
+}
+
+// Where a suspended generator is in one of the statements its `yield` is in
+#[derive(Debug)]
+pub enum Frame {
+    // In a block or the body, the index of the statement to go on with
+    // (inside it when there are frames left) and the environment of the block
+    Statements(usize, EnvironmentId),
+    Branch(bool), // In the then branch of an if, or else in the else branch
+    While,
+    For(EnvironmentId),              // The environment of the initializer
+    ForIn(Iteration, EnvironmentId), // The environment of the current element
+    Try,                             // In the body of a try
+    Catch(usize),                    // In the body of the catch at that index
+    With(Type),                      // In the body of a with, closing the resource once done
+    Yield(Type), // At the `yield` itself, with the value the generator is resumed with
+    Await(Shared<Promise>), // At an `await` of a coroutine, until the promise settles
+}
+
+// What a for-in loop goes through
+#[derive(Debug)]
+pub enum Iteration {
+    Elements(vec::IntoIter<Type>), // Left of a list, set or map, as they were when the loop started
+    Generator(Shared<Generator>),
+}
+
+impl Generator {
+    pub fn new(name: Token, declaration: Ref<Stmt>, environment: EnvironmentId) -> Generator {
+        Generator {
+            name,
+            declaration,
+            environment,
+            frames: Vec::new(),
+            state: State::Suspended,
+            spawned: false,
+            owner: None,
+        }
+    }
+
+    // The pending promise of the `await` the coroutine stopped at, if it did
+    pub fn awaiting(&self) -> Option<&Shared<Promise>> {
+        match self.frames.first() {
+            Some(Frame::Await(promise)) if matches!(*promise.borrow(), Promise::Pending) => {
+                Some(promise)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Generator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
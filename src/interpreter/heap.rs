@@ -0,0 +1,451 @@
+use std::{
+    collections::HashMap,
+    ops::{Index, IndexMut},
+};
+
+use super::{
+    environment::Environment,
+    types::{Instance, Traced, Type},
+};
+use crate::{
+    parser::expr::Slot,
+    shared::{Lock, Ref, Shared, WeakRef},
+};
+
+// Allocations between two automatic collections
+pub const DEFAULT_GC_THRESHOLD: usize = 10_000;
+
+pub const PRUNE_MIN: usize = 1024;
+
+// A local environment, by its index in the Heap
+//
+// Ids are counted, the way Rc counts its references: an environment no id
+// refers to anymore is freed, and its index used again
+#[derive(Debug, Clone)]
+pub struct EnvironmentId(Ref<usize>);
+
+impl EnvironmentId {
+    fn index(&self) -> usize {
+        *self.0
+    }
+}
+
+// The same environment
+impl PartialEq for EnvironmentId {
+    fn eq(&self, other: &EnvironmentId) -> bool {
+        Ref::ptr_eq(&self.0, &other.0)
+    }
+}
+
+// Every local environment, the global one is the interpreter's
+//
+// Environments refer to their parent, and functions to the environment they
+// run in, by id, so dropping one never drops a chain of others, and a lookup
+// doesn't borrow the environments it goes through.
+//
+// A function keeps the boxes of the variables it captures, and a function
+// stored in one of them, like a local function calling itself, keeps the id of
+// its own environment, so neither is ever freed by counting ids.
+// Collecting works out how many ids of each environment, and references to
+// each box, instance, list and map, come from the others; the ones with more
+// than that are used from outside (the interpreter, a running call), and so is
+// everything they reach. The rest is garbage: environments are freed, the
+// others emptied, which breaks their cycles
+pub struct Heap {
+    environments: Vec<Option<Entry>>, // None once freed
+    free: Vec<usize>,                 // Indices of freed environments, used again first
+    allocated: usize,                 // Since the last collection
+    threshold: usize,                 // 0 never collects automatically
+    prune_at: usize, // Number of environments at which the ones without ids are freed
+    // Every instance made, a cycle of them no environment reaches is found
+    // through them
+    instances: Vec<WeakRef<Lock<Instance>>>,
+    instances_at: usize, // Number of instances at which the ones gone are left out
+}
+
+struct Entry {
+    id: WeakRef<usize>, // Counts the ids
+    environment: Environment,
+}
+
+impl Heap {
+    pub fn new(threshold: usize) -> Heap {
+        Heap {
+            environments: Vec::new(),
+            free: Vec::new(),
+            allocated: 0,
+            threshold,
+            prune_at: PRUNE_MIN,
+            instances: Vec::new(),
+            instances_at: PRUNE_MIN,
+        }
+    }
+
+    pub fn track(&mut self, instance: &Shared<Instance>) {
+        if self.instances.len() >= self.instances_at {
+            self.instances
+                .retain(|instance| instance.strong_count() > 0);
+            self.instances_at = PRUNE_MIN.max(self.instances.len() * 2);
+        }
+        self.instances.push(Ref::downgrade(instance));
+    }
+
+    pub fn set_threshold(&mut self, threshold: usize) {
+        self.threshold = threshold;
+    }
+
+    pub fn should_collect(&self) -> bool {
+        self.threshold != 0 && self.allocated >= self.threshold
+    }
+
+    pub fn allocate(&mut self, environment: Environment) -> EnvironmentId {
+        if self.free.is_empty() && self.environments.len() >= self.prune_at {
+            self.prune();
+        }
+
+        let index = self.free.pop().unwrap_or(self.environments.len());
+        let id = Ref::new(index);
+        let entry = Some(Entry {
+            id: Ref::downgrade(&id),
+            environment,
+        });
+        match self.environments.get_mut(index) {
+            Some(freed) => *freed = entry,
+            None => self.environments.push(entry),
+        }
+        self.allocated += 1;
+        EnvironmentId(id)
+    }
+
+    // Frees the environment at once when `id` is the last id of it, like the one
+    // of a block that ended with nothing capturing it. Its values are dropped
+    // then, not at the next collection. So is its parent when that was the last
+    // id of it, like the one holding `this` for a method call
+    pub fn release(&mut self, id: EnvironmentId) {
+        let mut next = Some(id);
+        while let Some(id) = next.take() {
+            if Ref::strong_count(&id.0) != 1 {
+                break;
+            }
+            let index = id.index();
+            drop(id);
+            if let Some(mut entry) = self.environments[index].take() {
+                next = entry.environment.enclosing.take();
+            }
+            self.free.push(index);
+        }
+    }
+
+    // The local at `slot`, from `environment`
+    pub fn get_at(&self, environment: &EnvironmentId, slot: Slot) -> Type {
+        self.ancestor(environment, slot.depth).get(slot.index)
+    }
+
+    pub fn assign_at(&mut self, environment: &EnvironmentId, slot: Slot, value: Type) {
+        self.ancestor_mut(environment, slot.depth)
+            .set(slot.index, value)
+    }
+
+    // The name and the box of the local at `slot`, boxing it if it isn't yet
+    pub fn capture(&mut self, environment: &EnvironmentId, slot: Slot) -> (Ref<str>, Shared<Type>) {
+        self.ancestor_mut(environment, slot.depth)
+            .capture(slot.index)
+    }
+
+    // Every local seen from `environment`, with its value, outermost first
+    // A name declared again further in hides the one before, which keeps its place
+    pub fn bindings(&self, environment: &EnvironmentId) -> Vec<(Ref<str>, Type)> {
+        let chain: Vec<&Environment> =
+            std::iter::successors(Some(&self[environment]), |environment| {
+                environment
+                    .enclosing
+                    .as_ref()
+                    .map(|enclosing| &self[enclosing])
+            })
+            .collect();
+        let mut bindings: Vec<(Ref<str>, Type)> = Vec::new();
+        for environment in chain.into_iter().rev() {
+            for (name, value) in environment.bindings() {
+                match bindings.iter_mut().find(|(bound, _)| bound == name) {
+                    Some((_, bound)) => *bound = value,
+                    None => bindings.push((name.clone(), value)),
+                }
+            }
+        }
+        bindings
+    }
+
+    fn ancestor(&self, environment: &EnvironmentId, depth: usize) -> &Environment {
+        let mut environment = &self[environment];
+        for _ in 0..depth {
+            environment = &self[environment
+                .enclosing
+                .as_ref()
+                .expect("Resolved deeper than the environment chain")];
+        }
+        environment
+    }
+
+    fn ancestor_mut(&mut self, environment: &EnvironmentId, depth: usize) -> &mut Environment {
+        let mut index = environment.index();
+        for _ in 0..depth {
+            index = self
+                .entry(index)
+                .environment
+                .enclosing
+                .as_ref()
+                .expect("Resolved deeper than the environment chain")
+                .index();
+        }
+        &mut self.entry_mut(index).environment
+    }
+
+    fn entry(&self, index: usize) -> &Entry {
+        self.environments[index]
+            .as_ref()
+            .expect("An environment with an id is never freed")
+    }
+
+    fn entry_mut(&mut self, index: usize) -> &mut Entry {
+        self.environments[index]
+            .as_mut()
+            .expect("An environment with an id is never freed")
+    }
+
+    // Returns the number of environments freed
+    pub fn collect(&mut self) -> usize {
+        self.allocated = 0;
+        self.prune();
+
+        // What the heap's own environments and the objects they reach refer to,
+        // the objects come after the environments. One shared by several is
+        // only traced once
+        let count = self.environments.len();
+        let mut references: Vec<Vec<usize>> = Vec::with_capacity(count);
+        let mut objects = Objects::new(count);
+        for entry in self.environments.iter() {
+            let Some(entry) = entry else {
+                references.push(Vec::new());
+                continue;
+            };
+            let mut environment_references = objects.nodes(entry.environment.references());
+            for captured_box in entry.environment.captured() {
+                environment_references
+                    .push(objects.add(Object::Captured(Ref::clone(captured_box))));
+            }
+            references.push(environment_references);
+        }
+        self.instances
+            .retain(|instance| instance.strong_count() > 0);
+        for instance in self.instances.iter().filter_map(WeakRef::upgrade) {
+            objects.add(Object::Instance(instance));
+        }
+        // Tracing an object can find more of them
+        let mut traced = 0;
+        while traced < objects.list.len() {
+            let object = objects.list[traced].clone();
+            // Only happens while one is being updated, try again next time
+            let Some(object_references) = object.references(&mut objects) else {
+                return 0;
+            };
+            references.push(object_references);
+            traced += 1;
+        }
+
+        // Objects minus the reference `objects` holds
+        let mut outside: Vec<usize> = self
+            .environments
+            .iter()
+            .map(|entry| entry.as_ref().map_or(0, |entry| entry.id.strong_count()))
+            .chain(objects.list.iter().map(|object| object.strong_count() - 1))
+            .collect();
+        for reference in references.iter().flatten() {
+            outside[*reference] = outside[*reference].saturating_sub(1);
+        }
+
+        let mut reachable = vec![false; outside.len()];
+        let roots = (0..outside.len()).filter(|&index| outside[index] > 0);
+        mark(roots.collect(), &references, &mut reachable);
+        // The `__drop` of an instance let go of here still runs on it, so the
+        // instance and what it refers to are kept for it, until a later collection
+        let mut roots = Vec::new();
+        for (index, object) in objects.list.iter().enumerate() {
+            if let (Object::Instance(instance), false) = (object, reachable[count + index]) {
+                if Instance::finalize(instance) {
+                    roots.push(count + index);
+                }
+            }
+        }
+        mark(roots, &references, &mut reachable);
+
+        // Dropped once they are all out, the ids they hold are of each other
+        let mut garbage = Vec::new();
+        for (index, entry) in self.environments.iter_mut().enumerate() {
+            if entry.is_some() && !reachable[index] {
+                garbage.push(entry.take());
+                self.free.push(index);
+            }
+        }
+        // Nothing running can reach the objects left, emptying them breaks the
+        // cycles among them
+        for (object, reachable) in objects.list.iter().zip(&reachable[count..]) {
+            if !reachable {
+                object.clear();
+            }
+        }
+        garbage.len()
+    }
+
+    // Frees the environments no id refers to anymore
+    fn prune(&mut self) {
+        for (index, entry) in self.environments.iter_mut().enumerate() {
+            if entry
+                .as_ref()
+                .is_some_and(|entry| entry.id.strong_count() == 0)
+            {
+                *entry = None;
+                self.free.push(index);
+            }
+        }
+        let used = self.environments.len() - self.free.len();
+        self.prune_at = PRUNE_MIN.max(used * 2);
+    }
+}
+
+// Marks what `roots` reach through `references` as reachable
+pub fn mark(mut roots: Vec<usize>, references: &[Vec<usize>], reachable: &mut [bool]) {
+    while let Some(index) = roots.pop() {
+        if !reachable[index] {
+            reachable[index] = true;
+            roots.extend(&references[index]);
+        }
+    }
+}
+
+// A value shared by reference the garbage collector follows, besides
+// environments: the box of a captured variable, an instance, a list or a map
+#[derive(Clone)]
+enum Object {
+    Captured(Shared<Type>),
+    Instance(Shared<Instance>),
+    List(Shared<Vec<Type>>),
+    Map(Shared<Vec<(Type, Type)>>),
+}
+
+impl Object {
+    fn address(&self) -> *const () {
+        match self {
+            Object::Captured(value) => Ref::as_ptr(value).cast(),
+            Object::Instance(instance) => Ref::as_ptr(instance).cast(),
+            Object::List(elements) => Ref::as_ptr(elements).cast(),
+            Object::Map(entries) => Ref::as_ptr(entries).cast(),
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Object::Captured(value) => Ref::strong_count(value),
+            Object::Instance(instance) => Ref::strong_count(instance),
+            Object::List(elements) => Ref::strong_count(elements),
+            Object::Map(entries) => Ref::strong_count(entries),
+        }
+    }
+
+    // The nodes of what it holds, none while it is borrowed mutably
+    fn references(&self, objects: &mut Objects) -> Option<Vec<usize>> {
+        let references = match self {
+            Object::Captured(value) => {
+                let value = value.try_borrow().ok()?;
+                let mut traced = Vec::new();
+                value.trace(&mut traced);
+                objects.nodes(traced)
+            }
+            Object::Instance(instance) => {
+                let instance = instance.try_borrow().ok()?;
+                let mut traced = Vec::new();
+                instance.trace(&mut traced);
+                objects.nodes(traced)
+            }
+            Object::List(elements) => {
+                let elements = elements.try_borrow().ok()?;
+                let mut traced = Vec::new();
+                elements
+                    .iter()
+                    .for_each(|element| element.trace(&mut traced));
+                objects.nodes(traced)
+            }
+            Object::Map(entries) => {
+                let entries = entries.try_borrow().ok()?;
+                let mut traced = Vec::new();
+                for (key, value) in entries.iter() {
+                    key.trace(&mut traced);
+                    value.trace(&mut traced);
+                }
+                objects.nodes(traced)
+            }
+        };
+        Some(references)
+    }
+
+    fn clear(&self) {
+        match self {
+            Object::Captured(value) => *value.borrow_mut() = Type::Nil,
+            Object::Instance(instance) => instance.borrow_mut().clear(),
+            Object::List(elements) => elements.borrow_mut().clear(),
+            Object::Map(entries) => entries.borrow_mut().clear(),
+        }
+    }
+}
+
+// The objects found while collecting, each with its node, numbered after the
+// `environments` environment nodes
+struct Objects {
+    list: Vec<Object>,
+    nodes: HashMap<*const (), usize>,
+    environments: usize,
+}
+
+impl Objects {
+    fn new(environments: usize) -> Objects {
+        Objects {
+            list: Vec::new(),
+            nodes: HashMap::new(),
+            environments,
+        }
+    }
+
+    fn add(&mut self, object: Object) -> usize {
+        let list = &mut self.list;
+        let first = self.environments;
+        *self.nodes.entry(object.address()).or_insert_with(|| {
+            list.push(object);
+            first + list.len() - 1
+        })
+    }
+
+    fn nodes(&mut self, traced: Vec<Traced>) -> Vec<usize> {
+        traced
+            .into_iter()
+            .map(|traced| match traced {
+                Traced::Environment(id) => id.index(),
+                Traced::Instance(instance) => self.add(Object::Instance(Ref::clone(instance))),
+                Traced::List(elements) => self.add(Object::List(Ref::clone(elements))),
+                Traced::Map(entries) => self.add(Object::Map(Ref::clone(entries))),
+            })
+            .collect()
+    }
+}
+
+impl Index<&EnvironmentId> for Heap {
+    type Output = Environment;
+
+    fn index(&self, id: &EnvironmentId) -> &Environment {
+        &self.entry(id.index()).environment
+    }
+}
+
+impl IndexMut<&EnvironmentId> for Heap {
+    fn index_mut(&mut self, id: &EnvironmentId) -> &mut Environment {
+        &mut self.entry_mut(id.index()).environment
+    }
+}
@@ -1,633 +1,3384 @@
 use std::{
-    cell::RefCell,
     collections::HashMap,
-    ops::Deref,
-    rc::Rc,
-    time::{SystemTime, UNIX_EPOCH},
+    io::{self, Write},
+    thread,
+    time::Duration,
 };
 
-use super::{environment::*, types::*};
+use super::{environment::*, generator::*, heap::*, scheduler::Scheduler, types::*};
 
+#[cfg(feature = "sockets")]
+use crate::sockets::sockets::{Socket, SocketKind};
 use crate::{
-    error::Error,
-    lexer::token::*,
+    bytes, codec,
+    coverage::coverage::Coverage,
+    equality,
+    error::{arity_message, Error, ErrorClass},
+    eventloop::eventloop::{EventLoop, Executor, Operation, Outcome, Promise},
+    files,
+    lexer::{lexer::Lexer, token::*},
+    modules::{self, Load, Module, ModuleRegistry},
+    mutability::Mutability,
+    number,
     parser::{
-        expr::{Visitor as ExpressionVisitor, *},
+        expr::{Visitable as ExpressionVisitable, Visitor as ExpressionVisitor, *},
+        formatter::Formatter,
+        parser::Parser,
+        pattern::{Arm, Pattern},
         stmt::{Visitable as StatementVisitable, Visitor as StatementVisitor, *},
     },
+    providers::providers::{Providers, System},
+    repr,
+    resolver::resolver::Resolver,
+    shared::{Lock, Output, Ref, Shared},
+    strings::{self, Argument},
+    threads::threads::Channel,
 };
 
+// Calls a script can have under way at once, in either runtime, past it is a
+// "Stack overflow" error the script can catch. The interpreter recurses on the
+// Rust stack for every call, see STACK_SIZE
+pub const CALLS_MAX: usize = 1000;
+
+// Enough stack for CALLS_MAX calls of the interpreter, and for parsing code nested
+// as deep as the parser allows, even in a debug build, for the threads they run on
+pub const STACK_SIZE: usize = 256 * 1024 * 1024;
+
 pub struct Interpreter {
-    pub globals: Rc<RefCell<Environment>>,
-    environment: Rc<RefCell<Environment>>,
+    pub globals: Shared<Globals>,
+    environment: Option<EnvironmentId>, // The current one, none at the top level
+    heap: Heap,                         // Every local environment
+    arguments: Vec<Ref<str>>,           // Given to the script on the command line
+    output: Box<dyn Output>,            // Where `print` writes, stdout unless set
+    providers: Box<dyn Providers>,      // Of `clock()`, `now()` and `random()`
+    trace: Option<Box<dyn Output>>,     // Where executed statements are logged, if anywhere
+    coverage: Option<Coverage>,         // Lines executed, when measuring coverage
+    frames: Vec<Frame>, // Where the generator running on stopped, or stops, see Generator
+    coroutine: bool,    // Running the body of a spawned coroutine, which an `await` can stop
+    calls: usize,       // Under way, up to CALLS_MAX
+    owner: Option<ClassId>, // Of the function running, see Function
+    scheduler: Scheduler<Shared<Generator>>, // Coroutines made by `spawn`
+    event_loop: EventLoop, // Of the promises of async natives
+    thrown: Option<Type>, // What the last `throw` threw, until it is caught
+    modules: ModuleRegistry<Type>, // What `import` can import
+    dropped: Shared<Vec<Function>>, // `__drop` methods of unreachable instances, to run
+}
+
+// With `sync`, a host can move the interpreter to another thread
+#[cfg(feature = "sync")]
+const _: fn() = || {
+    fn send<T: Send>() {}
+    send::<Interpreter>();
+};
+
+// How running a statement ends
+#[derive(Debug)]
+pub enum Flow {
+    Next, // On to the next statement
+    Return(Type),
+    Yield(Type), // The generator running stops, its statements leave their Frames
+}
+
+// A variable made by `declare`, waiting for its value
+enum Declaration {
+    Local(EnvironmentId, usize), // Its slot
+    Global(Token),
 }
 
 impl Interpreter {
-    pub fn new(enclosing: Option<Environment>) -> Interpreter {
-        let mut globals = Environment::new(None);
+    // Top-level code runs directly in the global environment,
+    // a fresh one unless `globals` is given
+    pub fn new(globals: Option<Globals>) -> Interpreter {
+        let mut globals = globals.unwrap_or_default();
 
         // Native Functions
-        fn clock() {
-            let start = SystemTime::now();
-            let since_the_epoch = start
-                .duration_since(UNIX_EPOCH)
-                .expect("Time went backwards");
-            let milli_secs = since_the_epoch.as_secs() * 1000
-                + since_the_epoch.subsec_nanos() as u64 / 1_000_000;
-            println!("{}", milli_secs)
-        }
-
-        globals.define(
-            "clock".to_string(),
-            Type::NativeFunction(Box::new(NativeFunction::new("clock".to_string(), clock))),
-        );
-        let globals = Rc::new(RefCell::new(globals));
+        // Seconds since the Unix epoch, to time code
+        fn clock(
+            interpreter: &mut Interpreter,
+            _arguments: &[Type],
+            _span: Span,
+        ) -> Result<Type, Error> {
+            Ok(Type::Number(interpreter.providers.clock()))
+        }
+
+        // Milliseconds since the Unix epoch
+        fn now(
+            interpreter: &mut Interpreter,
+            _arguments: &[Type],
+            _span: Span,
+        ) -> Result<Type, Error> {
+            Ok(Type::Number(interpreter.providers.now()))
+        }
+
+        // A number from 0 up to, not including, 1
+        fn random(
+            interpreter: &mut Interpreter,
+            _arguments: &[Type],
+            _span: Span,
+        ) -> Result<Type, Error> {
+            Ok(Type::Number(interpreter.providers.random()))
+        }
+
+        // NaN is the only number not equal to itself, this tells it apart
+        fn is_nan(
+            _interpreter: &mut Interpreter,
+            arguments: &[Type],
+            span: Span,
+        ) -> Result<Type, Error> {
+            match arguments {
+                [Type::Number(number)] => Ok(Type::Boolean(number.is_nan())),
+                [value] => Err(Error::interpreter(
+                    format!("Expected Number, got {}", value.repr()),
+                    span,
+                )
+                .raised_as(ErrorClass::TypeError)),
+                _ => unreachable!("is_nan takes one argument"),
+            }
+        }
+
+        // Strings are immutable, so `text = text + part` copies all of `text`
+        // every time. A builder is appended to in place instead
+        fn string_builder(
+            _interpreter: &mut Interpreter,
+            _arguments: &[Type],
+            _span: Span,
+        ) -> Result<Type, Error> {
+            Ok(Type::Builder(Ref::new(Lock::new(String::new()))))
+        }
+
+        // Adds the value, as print shows it, to the end of the builder
+        fn append(
+            _interpreter: &mut Interpreter,
+            arguments: &[Type],
+            span: Span,
+        ) -> Result<Type, Error> {
+            match arguments {
+                [Type::Builder(builder), value] => {
+                    match value {
+                        Type::String(text) => builder.borrow_mut().push_str(text),
+                        _ => builder.borrow_mut().push_str(&value.to_string()),
+                    }
+                    Ok(Type::Nil)
+                }
+                [value, _] => Err(Error::interpreter(
+                    format!(
+                        "Only string builders can be appended to, got {}",
+                        value.repr()
+                    ),
+                    span,
+                )
+                .raised_as(ErrorClass::TypeError)),
+                _ => unreachable!("append takes two arguments"),
+            }
+        }
+
+        // The text of a builder so far, or any other value as print shows it
+        fn to_string(
+            _interpreter: &mut Interpreter,
+            arguments: &[Type],
+            _span: Span,
+        ) -> Result<Type, Error> {
+            match arguments {
+                [Type::Builder(builder)] => Ok(Type::String(Ref::from(builder.borrow().as_str()))),
+                [Type::String(text)] => Ok(Type::String(Ref::clone(text))),
+                [value] => Ok(Type::String(Ref::from(value.to_string()))),
+                _ => unreachable!("to_string takes one argument"),
+            }
+        }
+
+        // The list of maps as an aligned table, a map a row, see repr::table
+        fn print_table(
+            interpreter: &mut Interpreter,
+            arguments: &[Type],
+            span: Span,
+        ) -> Result<Type, Error> {
+            let Type::List(rows) = &arguments[0] else {
+                return Err(Error::interpreter(
+                    format!("Expected a list of maps, got {}", arguments[0].repr()),
+                    span,
+                )
+                .raised_as(ErrorClass::TypeError));
+            };
+            let mut table = Vec::new();
+            for (index, row) in rows.borrow().iter().enumerate() {
+                let Type::Map(entries) = row else {
+                    return Err(Error::interpreter(
+                        format!("Row {} is not a map, got {}", index, row.repr()),
+                        span,
+                    )
+                    .raised_as(ErrorClass::TypeError));
+                };
+                let cells = entries
+                    .borrow()
+                    .iter()
+                    .map(|(key, value)| repr::Cell {
+                        column: key.to_string(),
+                        text: value.to_string(),
+                        number: matches!(value, Type::Number(_)),
+                    })
+                    .collect();
+                table.push(cells);
+            }
+            if let Some(table) = repr::table(&table) {
+                interpreter.write_output(&table, span)?;
+            }
+            Ok(Type::Nil)
+        }
+
+        // The template with its fields replaced by the values after it, see
+        // strings::format
+        fn format(
+            _interpreter: &mut Interpreter,
+            arguments: &[Type],
+            span: Span,
+        ) -> Result<Type, Error> {
+            let Type::String(template) = &arguments[0] else {
+                return Err(Error::interpreter(
+                    format!("Expected String, got {}", arguments[0].repr()),
+                    span,
+                )
+                .raised_as(ErrorClass::TypeError));
+            };
+            let values: Vec<Argument> = arguments[1..]
+                .iter()
+                .map(|value| match value {
+                    Type::Number(number) => Argument::Number(*number),
+                    _ => Argument::Text(value.to_string()),
+                })
+                .collect();
+            strings::format(template, &values)
+                .map(|text| Type::String(Ref::from(text)))
+                .map_err(|message| Error::interpreter(message, span))
+        }
+
+        // The number written in the text, nil when it isn't one, see number::parse
+        fn parse_number(
+            _interpreter: &mut Interpreter,
+            arguments: &[Type],
+            span: Span,
+        ) -> Result<Type, Error> {
+            match &arguments[0] {
+                Type::String(text) => Ok(number::parse(text).map_or(Type::Nil, Type::Number)),
+                value => Err(Error::interpreter(
+                    format!("Expected String, got {}", value.repr()),
+                    span,
+                )
+                .raised_as(ErrorClass::TypeError)),
+            }
+        }
+
+        // The text as bytes in the encoding, see bytes
+        fn encode(
+            _interpreter: &mut Interpreter,
+            arguments: &[Type],
+            span: Span,
+        ) -> Result<Type, Error> {
+            match arguments {
+                [Type::String(text), Type::String(encoding)] => bytes::encode(text, encoding)
+                    .map(|bytes| Type::Bytes(Ref::from(bytes)))
+                    .map_err(|message| Error::interpreter(message, span)),
+                [Type::String(_), value] | [value, _] => Err(Error::interpreter(
+                    format!("Expected String, got {}", value.repr()),
+                    span,
+                )
+                .raised_as(ErrorClass::TypeError)),
+                _ => unreachable!("encode takes two arguments"),
+            }
+        }
+
+        // Every element on a line of its own, indented, nesting deeper than the
+        // depth and values holding themselves shown as `[...]`
+        fn debug_repr(
+            _interpreter: &mut Interpreter,
+            arguments: &[Type],
+            span: Span,
+        ) -> Result<Type, Error> {
+            match arguments {
+                [value, Type::Number(depth)] if depth.fract() == 0.0 && *depth >= 0.0 => Ok(
+                    Type::String(Ref::from(repr::debug_repr(value, *depth as usize))),
+                ),
+                [_, depth] => Err(Error::interpreter(
+                    format!("Expected a whole number of levels, got {}", depth.repr()),
+                    span,
+                )
+                .raised_as(ErrorClass::TypeError)),
+                _ => unreachable!("debug_repr takes two arguments"),
+            }
+        }
+
+        // Collects now, returns the number of environments freed
+        fn gc(
+            interpreter: &mut Interpreter,
+            _arguments: &[Type],
+            _span: Span,
+        ) -> Result<Type, Error> {
+            Ok(Type::Number(interpreter.heap.collect() as f64))
+        }
+
+        // The names of the modules imported so far, in the order they were loaded
+        fn modules(
+            interpreter: &mut Interpreter,
+            _arguments: &[Type],
+            _span: Span,
+        ) -> Result<Type, Error> {
+            let names = interpreter
+                .modules
+                .names()
+                .into_iter()
+                .map(|name| Type::String(Ref::from(name)))
+                .collect();
+            Ok(Type::List(Ref::new(Lock::new(names))))
+        }
+
+        // The instance, from now on none of its fields can be set
+        fn freeze(
+            _interpreter: &mut Interpreter,
+            arguments: &[Type],
+            span: Span,
+        ) -> Result<Type, Error> {
+            restrict(&arguments[0], Mutability::Frozen, span)
+        }
+
+        // The instance, from now on it can't get fields it doesn't have
+        fn seal(
+            _interpreter: &mut Interpreter,
+            arguments: &[Type],
+            span: Span,
+        ) -> Result<Type, Error> {
+            restrict(&arguments[0], Mutability::Sealed, span)
+        }
+
+        // Whether `value.name` would give something rather than raise
+        fn has(
+            _interpreter: &mut Interpreter,
+            arguments: &[Type],
+            span: Span,
+        ) -> Result<Type, Error> {
+            match &arguments[1] {
+                Type::String(name) => Ok(Type::Boolean(
+                    !is_private(name) && property_of(&arguments[0], name).is_some(),
+                )),
+                value => Err(Error::interpreter(
+                    format!("Expected String, got {}", value.repr()),
+                    span,
+                )
+                .raised_as(ErrorClass::TypeError)),
+            }
+        }
+
+        // The variables the running code sees, other than globals, by name: the
+        // function's own and the ones it captured. Empty at the top level
+        fn local_variables(
+            interpreter: &mut Interpreter,
+            _arguments: &[Type],
+            _span: Span,
+        ) -> Result<Type, Error> {
+            let bindings = match &interpreter.environment {
+                Some(environment) => interpreter.heap.bindings(environment),
+                None => Vec::new(),
+            };
+            Ok(bindings_map(bindings))
+        }
+
+        // The globals, by name, but for the natives and error classes every script has
+        fn global_variables(
+            interpreter: &mut Interpreter,
+            _arguments: &[Type],
+            _span: Span,
+        ) -> Result<Type, Error> {
+            let bindings = interpreter
+                .globals
+                .borrow()
+                .bindings()
+                .filter(|(name, value)| !is_built_in(name, value))
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect();
+            Ok(bindings_map(bindings))
+        }
+
+        // Number of arguments given to the script
+        fn arg_count(
+            interpreter: &mut Interpreter,
+            _arguments: &[Type],
+            _span: Span,
+        ) -> Result<Type, Error> {
+            Ok(Type::Number(interpreter.arguments.len() as f64))
+        }
+
+        // The argument at that index, nil when there is none
+        fn arg(
+            interpreter: &mut Interpreter,
+            arguments: &[Type],
+            _span: Span,
+        ) -> Result<Type, Error> {
+            Ok(match arguments {
+                [Type::Number(index)] if index.fract() == 0.0 && *index >= 0.0 => interpreter
+                    .arguments
+                    .get(*index as usize)
+                    .map_or(Type::Nil, |argument| Type::String(Ref::clone(argument))),
+                _ => Type::Nil,
+            })
+        }
+
+        // All of the arguments, a new list every call
+        fn args(
+            interpreter: &mut Interpreter,
+            _arguments: &[Type],
+            _span: Span,
+        ) -> Result<Type, Error> {
+            let arguments = interpreter
+                .arguments
+                .iter()
+                .map(|argument| Type::String(Ref::clone(argument)))
+                .collect();
+            Ok(Type::List(Ref::new(Lock::new(arguments))))
+        }
+
+        // Elements of either set, those of the first one first, nil unless both are sets
+        fn union(
+            interpreter: &mut Interpreter,
+            arguments: &[Type],
+            _span: Span,
+        ) -> Result<Type, Error> {
+            let [Type::Set(first), Type::Set(second)] = arguments else {
+                return Ok(Type::Nil);
+            };
+            let mut elements = first.borrow().clone();
+            for element in second.borrow().iter() {
+                if !interpreter.has_element(&elements, element) {
+                    elements.push(element.clone());
+                }
+            }
+            Ok(Type::Set(Ref::new(Lock::new(elements))))
+        }
+
+        // Elements of the first set that are in the second one, nil unless both are sets
+        fn intersection(
+            interpreter: &mut Interpreter,
+            arguments: &[Type],
+            _span: Span,
+        ) -> Result<Type, Error> {
+            let [Type::Set(first), Type::Set(second)] = arguments else {
+                return Ok(Type::Nil);
+            };
+            let elements = first
+                .borrow()
+                .iter()
+                .filter(|element| interpreter.has_element(&second.borrow(), element))
+                .cloned()
+                .collect();
+            Ok(Type::Set(Ref::new(Lock::new(elements))))
+        }
+
+        // Runs the coroutine up to its next `yield`, giving it `value`, and returns
+        // what it yields, or what it returns once it is done
+        fn resume(
+            interpreter: &mut Interpreter,
+            arguments: &[Type],
+            span: Span,
+        ) -> Result<Type, Error> {
+            let [coroutine, value] = arguments else {
+                unreachable!("resume takes two arguments")
+            };
+            let Type::Generator(coroutine) = coroutine else {
+                return Err(Error::interpreter(
+                    format!("Only coroutines can be resumed, got {}", coroutine.repr()),
+                    span,
+                )
+                .raised_as(ErrorClass::TypeError));
+            };
+            match interpreter.resume(coroutine, value.clone(), span)? {
+                Flow::Yield(value) | Flow::Return(value) => Ok(value),
+                Flow::Next => Err(Error::interpreter(
+                    "Can't resume a coroutine that is done".to_string(),
+                    span,
+                )),
+            }
+        }
+
+        // "suspended", "running" or "done"
+        fn status(
+            _interpreter: &mut Interpreter,
+            arguments: &[Type],
+            span: Span,
+        ) -> Result<Type, Error> {
+            match arguments {
+                [Type::Generator(coroutine)] => {
+                    Ok(Type::String(Ref::from(match coroutine.borrow().state {
+                        State::Suspended => "suspended",
+                        State::Running => "running",
+                        State::Done => "done",
+                    })))
+                }
+                [value] => Err(Error::interpreter(
+                    format!("Only coroutines have a status, got {}", value.repr()),
+                    span,
+                )
+                .raised_as(ErrorClass::TypeError)),
+                _ => unreachable!("status takes one argument"),
+            }
+        }
+
+        // Runs the spawned coroutines in turns until they are all done. One stopped
+        // at an `await` gets no turn until its promise settles, and when all of
+        // them are waiting, the event loop waits for the first to settle
+        fn run(
+            interpreter: &mut Interpreter,
+            _arguments: &[Type],
+            span: Span,
+        ) -> Result<Type, Error> {
+            let mut waiting: Vec<Shared<Generator>> = Vec::new();
+            loop {
+                while let Some(coroutine) = interpreter.scheduler.take_turn() {
+                    if let Flow::Yield(_) = interpreter.resume(&coroutine, Type::Nil, span)? {
+                        if coroutine.borrow().awaiting().is_some() {
+                            waiting.push(coroutine);
+                        } else {
+                            interpreter.scheduler.spawn(coroutine);
+                        }
+                    }
+                }
+                if waiting.is_empty() {
+                    return Ok(Type::Nil);
+                }
+
+                interpreter.event_loop.wait_any();
+                for coroutine in std::mem::take(&mut waiting) {
+                    if coroutine.borrow().awaiting().is_some() {
+                        waiting.push(coroutine);
+                    } else {
+                        interpreter.scheduler.spawn(coroutine);
+                    }
+                }
+            }
+        }
+
+        // A promise settled with nil after that many milliseconds
+        fn sleep_async(
+            interpreter: &mut Interpreter,
+            arguments: &[Type],
+            span: Span,
+        ) -> Result<Type, Error> {
+            match arguments {
+                [Type::Number(milliseconds)]
+                    if *milliseconds >= 0.0 && milliseconds.is_finite() =>
+                {
+                    let duration = Duration::from_secs_f64(milliseconds / 1000.0);
+                    let promise = interpreter.event_loop.start(Operation::Sleep(duration));
+                    Ok(Type::Promise(promise))
+                }
+                [value] => Err(Error::interpreter(
+                    format!("Expected a number of milliseconds, got {}", value.repr()),
+                    span,
+                )
+                .raised_as(ErrorClass::TypeError)),
+                _ => unreachable!("sleep_async takes one argument"),
+            }
+        }
+
+        // A promise settled with the text of the file at that path
+        fn read_file_async(
+            interpreter: &mut Interpreter,
+            arguments: &[Type],
+            span: Span,
+        ) -> Result<Type, Error> {
+            match arguments {
+                [Type::String(path)] => {
+                    let promise = interpreter
+                        .event_loop
+                        .start(Operation::ReadFile(path.to_string()));
+                    Ok(Type::Promise(promise))
+                }
+                [value] => Err(Error::interpreter(
+                    format!("Expected a path, got {}", value.repr()),
+                    span,
+                )
+                .raised_as(ErrorClass::TypeError)),
+                _ => unreachable!("read_file_async takes one argument"),
+            }
+        }
+
+        // Runs the function in an interpreter of its own on another thread, with its
+        // end of a new channel, the caller gets the other end
+        fn spawn_thread(
+            _interpreter: &mut Interpreter,
+            arguments: &[Type],
+            span: Span,
+        ) -> Result<Type, Error> {
+            let [Type::Function(function)] = arguments else {
+                return Err(Error::interpreter(
+                    format!(
+                        "Only functions can run on a thread, got {}",
+                        arguments[0].repr()
+                    ),
+                    span,
+                )
+                .raised_as(ErrorClass::TypeError));
+            };
+            let Stmt::Function(name, parameters, ..) = &*function.declaration else {
+                unreachable!("Functions are made of function statements")
+            };
+            // Only a function capturing nothing runs right in the globals
+            if function.closure.is_some() {
+                return Err(Error::interpreter(
+                    "Functions that capture variables can't run on a thread".to_string(),
+                    span,
+                ));
+            }
+            if parameters.len() != 1 {
+                return Err(Error::interpreter(
+                    "A function run on a thread takes one parameter, its channel".to_string(),
+                    span,
+                ));
+            }
+
+            // The other interpreter gets the function as source, the statement
+            // itself can't leave this thread
+            let source = Formatter::new().format_statement(&function.declaration);
+            let name = name.lexeme.to_string();
+            let (ours, theirs) = Channel::pair();
+            let spawned = thread::Builder::new()
+                .stack_size(STACK_SIZE)
+                .spawn(move || {
+                    let result = run_thread(&source, &name, theirs.clone());
+                    theirs.close(result.map_err(|error| error.message().to_string()));
+                });
+            if let Err(error) = spawned {
+                return Err(Error::interpreter(
+                    format!("Can't start a thread: {error}"),
+                    span,
+                ));
+            }
+            Ok(Type::Channel(ours))
+        }
+
+        fn send(
+            _interpreter: &mut Interpreter,
+            arguments: &[Type],
+            span: Span,
+        ) -> Result<Type, Error> {
+            let [channel, value] = arguments else {
+                unreachable!("send takes two arguments")
+            };
+            let Type::Channel(channel) = channel else {
+                return Err(Error::interpreter(
+                    format!("Only channels can be sent on, got {}", channel.repr()),
+                    span,
+                )
+                .raised_as(ErrorClass::TypeError));
+            };
+            let Some(message) = value.to_message() else {
+                return Err(Error::interpreter(
+                    format!(
+                        "Only nil, booleans, numbers, strings, bytes, tuples, lists, maps, sets and channels can be sent, got {}",
+                        value.repr()
+                    ),
+                    span,
+                ).raised_as(ErrorClass::TypeError));
+            };
+            channel.send(message);
+            Ok(Type::Nil)
+        }
+
+        // Waits for the next value sent from the other end
+        fn recv(
+            _interpreter: &mut Interpreter,
+            arguments: &[Type],
+            span: Span,
+        ) -> Result<Type, Error> {
+            match arguments {
+                [Type::Channel(channel)] => channel
+                    .recv()
+                    .map(Type::from_message)
+                    .map_err(|message| Error::interpreter(message, span)),
+                [value] => Err(Error::interpreter(
+                    format!("Only channels can be received from, got {}", value.repr()),
+                    span,
+                )
+                .raised_as(ErrorClass::TypeError)),
+                _ => unreachable!("recv takes one argument"),
+            }
+        }
 
+        let natives: [(&str, usize, Native); 31] = [
+            ("clock", 0, clock),
+            ("now", 0, now),
+            ("random", 0, random),
+            ("is_nan", 1, is_nan),
+            ("gc", 0, gc),
+            ("arg_count", 0, arg_count),
+            ("arg", 1, arg),
+            ("args", 0, args),
+            ("union", 2, union),
+            ("intersection", 2, intersection),
+            ("resume", 2, resume),
+            ("status", 1, status),
+            ("run", 0, run),
+            ("sleep_async", 1, sleep_async),
+            ("read_file_async", 1, read_file_async),
+            ("spawn_thread", 1, spawn_thread),
+            ("send", 2, send),
+            ("recv", 1, recv),
+            ("string_builder", 0, string_builder),
+            ("append", 2, append),
+            ("to_string", 1, to_string),
+            ("debug_repr", 2, debug_repr),
+            ("print_table", 1, print_table),
+            ("parse_number", 1, parse_number),
+            ("encode", 2, encode),
+            ("modules", 0, modules),
+            ("locals", 0, local_variables),
+            ("globals", 0, global_variables),
+            ("freeze", 1, freeze),
+            ("seal", 1, seal),
+            ("has", 2, has),
+        ];
+        for (name, arity, native) in natives {
+            globals.define(
+                Ref::from(name),
+                Type::NativeFunction(Box::new(NativeFunction::new(
+                    name.to_string(),
+                    arity,
+                    native,
+                ))),
+            );
+        }
+        // The values of its fields come after the template, as many as there are
+        let format = NativeFunction::new("format".to_string(), 1, format).variadic();
+        globals.define(Ref::from("format"), Type::NativeFunction(Box::new(format)));
+        for class in ErrorClass::ALL {
+            globals.define(
+                Ref::from(class.name()),
+                Type::Class(Box::new(Class::built_in(class))),
+            );
+        }
+        let mut modules = ModuleRegistry::new();
+        modules.register("math", math);
+        modules.register("fs", fs);
+        modules.register("hash", hash);
+        modules.register("codec", codec);
+        #[cfg(feature = "sockets")]
+        modules.register("net", net);
         Interpreter {
-            globals: Rc::clone(&globals),
-            environment: Rc::new(RefCell::new(Environment::new(match enclosing {
-                Some(parent_environment) => Some(Rc::new(RefCell::new(parent_environment))),
-                None => Some(Rc::clone(&globals)),
-            }))),
+            globals: Ref::new(Lock::new(globals)),
+            environment: None,
+            heap: Heap::new(DEFAULT_GC_THRESHOLD),
+            arguments: Vec::new(),
+            output: Box::new(io::stdout()),
+            providers: Box::new(System::new()),
+            trace: None,
+            coverage: None,
+            frames: Vec::new(),
+            coroutine: false,
+            calls: 0,
+            owner: None,
+            scheduler: Scheduler::new(),
+            event_loop: EventLoop::new(),
+            thrown: None,
+            modules,
+            dropped: Ref::new(Lock::new(Vec::new())),
         }
     }
 
-    pub fn interpret(&mut self, expr_vec: &mut Vec<Box<Stmt>>) -> Result<Option<Type>, Error> {
-        for expr in expr_vec {
-            let _ = self.execute(expr)?;
+    // The modules scripts can import, a host adds its own here
+    pub fn modules(&mut self) -> &mut ModuleRegistry<Type> {
+        &mut self.modules
+    }
+
+    // What `arg_count()` and `arg(index)` give the script
+    pub fn set_arguments(&mut self, arguments: &[String]) {
+        self.arguments = arguments
+            .iter()
+            .map(|argument| Ref::from(argument.as_str()))
+            .collect();
+    }
+
+    // Where `print` and the REPL's values go instead of stdout, to capture them
+    pub fn set_output(&mut self, output: Box<dyn Output>) {
+        self.output = output;
+    }
+
+    // Where `clock()`, `now()` and `random()` get their values, the system's unless
+    // set, `Deterministic` ones make runs reproducible
+    pub fn set_providers(&mut self, providers: Box<dyn Providers>) {
+        self.providers = providers;
+    }
+
+    // What runs the operations of async natives, a thread for each unless set
+    pub fn set_executor(&mut self, executor: Box<dyn Executor>) {
+        self.event_loop.set_executor(executor);
+    }
+
+    // Log every statement before it runs, with its line, and the values of
+    // expression statements and returns after
+    //
+    //     [line 3] var total = price * count;
+    //     [line 4] total;
+    //       => 42
+    //
+    // Lines are written as they come, nothing is lost if the program then fails
+    pub fn set_trace(&mut self, trace: Box<dyn Output>) {
+        self.trace = Some(trace);
+    }
+
+    fn trace_statement(&mut self, stmt: &Stmt) {
+        let Some(trace) = self.trace.as_mut() else {
+            return;
+        };
+        // Only the header of blocks, loops and functions, their bodies are traced as they run
+        let formatted = Formatter::new().format_statement(stmt);
+        let header = formatted.lines().next().unwrap_or_default();
+        let _ = match stmt.line() {
+            Some(line) => writeln!(trace, "[line {}] {}", line, header),
+            None => writeln!(trace, "{}", header),
+        };
+    }
+
+    fn trace_value(&mut self, value: &Type) {
+        if let Some(trace) = self.trace.as_mut() {
+            let _ = writeln!(trace, "  => {}", value.repr());
         }
-        Ok(None)
     }
 
-    fn execute(&mut self, stmt: &mut Stmt) -> Result<Option<Type>, Error> {
-        let return_value = stmt.accept(self)?;
-        Ok(return_value)
+    // Count the statements executed on every line of `coverage`
+    pub fn set_coverage(&mut self, coverage: Coverage) {
+        self.coverage = Some(coverage);
     }
 
-    fn evaluate(&mut self, expr: &Box<Expr>) -> Result<Type, Error> {
-        expr.clone().accept(self)
+    pub fn take_coverage(&mut self) -> Option<Coverage> {
+        self.coverage.take()
     }
 
-    // Returns the number value if `value` is of type `Type::Number`, otherwise returns an `Error`.
-    pub fn get_number_or_return_error(&self, value: Type, line: usize) -> Result<f32, Error> {
-        match value {
-            Type::Number(val) => Ok(val),
-            _ => Err(Error::InterpretError(
-                format!("Expected Number, got {}", value),
-                line,
-            )),
+    // Number of environments created between two automatic collections, 0 turns them off
+    pub fn set_gc_threshold(&mut self, threshold: usize) {
+        self.heap.set_threshold(threshold);
+    }
+
+    pub fn new_environment(&mut self, environment: Environment) -> EnvironmentId {
+        if self.heap.should_collect() {
+            self.heap.collect();
         }
+        self.heap.allocate(environment)
     }
 
-    // Compares equality between two Type values.
-    // Returns true if both values are of the same type and have the same value.
-    // Returns false if the types are different or the values do not match.
-    pub fn is_equal(&self, left_expr: Type, right_expr: Type) -> bool {
-        match left_expr {
-            Type::Nil => match right_expr {
-                Type::Nil => true,
-                _ => false,
-            },
-            Type::Boolean(left_val) => match right_expr {
-                Type::Boolean(right_val) => left_val == right_val,
-                _ => false,
-            },
-            Type::Number(left_val) => match right_expr {
-                Type::Number(right_val) => left_val == right_val,
-                _ => false,
-            },
-            Type::String(left_val) => match right_expr {
-                Type::String(right_val) => left_val == right_val,
-                _ => false,
-            },
-            Type::Function(fun) => todo!(),
-            Type::NativeFunction(fun) => todo!(),
-            Type::Class(class) => todo!(),
-            Type::Instance(instance) => todo!(),
+    // An instance of `class`, the garbage collector keeps track of it
+    pub fn new_instance(&mut self, class: Class) -> Shared<Instance> {
+        let instance = Ref::new(Lock::new(Instance::new(class)));
+        self.heap.track(&instance);
+        instance
+    }
+
+    // An empty environment in the current one
+    fn enclosed_environment(&mut self) -> EnvironmentId {
+        self.new_environment(Environment::new(self.environment.clone()))
+    }
+
+    // Defines in the current environment, a global at the top level
+    fn define(&mut self, name: Ref<str>, value: Type) {
+        match &self.environment {
+            Some(environment) => self.heap[environment].define(name, value),
+            None => self.globals.borrow_mut().define(name, value),
         }
     }
 
-    // Determines the truthiness of a Type value.
-    // Returns true for non-empty strings, non-zero numbers, and true booleans.
-    // Returns false for zero numbers, false booleans, and Nil values.
-    pub fn is_truthly(&self, value: &Type) -> bool {
-        match value {
-            Type::String(_) => true,
-            Type::Number(val) => *val != 0.0,
-            Type::Boolean(val) => *val,
-            Type::Function(fun) => todo!(),
-            Type::NativeFunction(fun) => todo!(),
-            Type::Class(class) => todo!(),
-            Type::Instance(instance) => todo!(),
-            Type::Nil => false,
+    // Binds `name` to nil in the current environment until `initialize` gives
+    // it its value, so what is made in between, like the methods of a class,
+    // can already refer to it
+    fn declare(&mut self, name: &Token) -> Declaration {
+        match &self.environment {
+            Some(environment) => {
+                let index = self.heap[environment].declare(name.lexeme.clone());
+                Declaration::Local(environment.clone(), index)
+            }
+            None => {
+                self.globals
+                    .borrow_mut()
+                    .define(name.lexeme.clone(), Type::Nil);
+                Declaration::Global(name.clone())
+            }
         }
     }
 
-    pub fn execute_block(
-        &mut self,
-        statements: &mut Box<Vec<Stmt>>,
-        environment: Rc<RefCell<Environment>>,
-    ) -> Result<Option<Type>, Error> {
-        let temp = Rc::clone(&self.environment);
+    // Sets the very variable `declare` made, not the latest one with its name
+    fn initialize(&mut self, declaration: Declaration, value: Type) -> Result<(), Error> {
+        match declaration {
+            Declaration::Local(environment, index) => {
+                self.heap[&environment].set(index, value);
+                Ok(())
+            }
+            Declaration::Global(name) => self.globals.borrow_mut().assign(&name, value),
+        }
+    }
 
-        self.environment = environment;
+    // The current environment, where a resolved local is looked up from
+    fn locals(&self) -> &EnvironmentId {
+        self.environment
+            .as_ref()
+            .expect("Locals are only resolved in local environments")
+    }
 
-        let return_value = None;
-        for statement in (*statements).as_mut().iter_mut() {
-            match self.execute(statement) {
-                Err(error) => {
-                    self.environment = temp;
-                    return Err(error);
+    // The methods the mixins of class `name` give it, the ones in `own` aside
+    // Two mixins can't both give one, unless it is the same method
+    fn mix(
+        &mut self,
+        name: &Token,
+        mixins: &[(Box<Class>, &Expr)],
+        own: &[&str],
+    ) -> Result<Vec<(Ref<str>, Function)>, Error> {
+        let mut mixed: Vec<(Ref<str>, Function, Ref<str>)> = Vec::new(); // Name, method, mixin
+        for (class, mixin) in mixins.iter() {
+            for (method_name, method) in class.methods() {
+                if own.contains(&&*method_name) {
+                    continue;
                 }
-                Ok(value) => {
-                    if let Some(return_val) = value {
-                        return Ok(Some(return_val));
+                match mixed.iter().find(|(known, ..)| *known == method_name) {
+                    Some((_, known, first)) if !known.is_same(&method) => {
+                        let message = format!(
+                            "Mixins `{}` and `{}` both define `{}`, `{}` has to define it itself",
+                            first, class.name, method_name, name.lexeme
+                        );
+                        return Err(Error::interpreter(message, mixin.span())
+                            .raised_as(ErrorClass::NameError));
                     }
+                    Some(_) => {}
+                    None => mixed.push((method_name, method, class.name.clone())),
                 }
-            };
+            }
         }
+        Ok(mixed
+            .into_iter()
+            .map(|(method_name, method, _)| (method_name, method))
+            .collect())
+    }
 
-        self.environment = temp;
-        Ok(return_value)
+    // A method marked `override` has to replace one the class would otherwise
+    // get from a mixin or its superclass, taking as many parameters
+    // Only known now, what the parents are is up to the running program
+    fn check_override(
+        class: &Token,
+        parent: Option<&Class>,
+        mixins: &[(Box<Class>, &Expr)],
+        name: &Token,
+        arity: usize,
+    ) -> Result<(), Error> {
+        let inherited = mixins
+            .iter()
+            .find_map(|(mixin, _)| mixin.defining(&name.lexeme))
+            .or_else(|| parent.and_then(|parent| parent.defining(&name.lexeme)));
+        let (message, class) = match inherited {
+            Some((_, method)) if method.arity == arity => return Ok(()),
+            Some((owner, method)) => (
+                format!(
+                    "`{}` overrides the `{}` of `{}`, which takes {} parameter{}, but takes {}",
+                    name.lexeme,
+                    name.lexeme,
+                    owner.name,
+                    method.arity,
+                    if method.arity == 1 { "" } else { "s" },
+                    arity
+                ),
+                ErrorClass::TypeError,
+            ),
+            None => (
+                format!(
+                    "`{}` is marked `override`, but `{}` inherits no `{}`",
+                    name.lexeme, class.lexeme, name.lexeme
+                ),
+                ErrorClass::NameError,
+            ),
+        };
+        Err(Error::interpreter(message, name.span).raised_as(class))
     }
-}
 
-impl ExpressionVisitor<Result<Type, Error>> for Interpreter {
-    fn visit_binary(
-        &mut self,
-        left_expr: &mut Box<Expr>,
-        operator: &Token,
-        right_expr: &mut Box<Expr>,
-    ) -> Result<Type, Error> {
-        let left_value = self.evaluate(left_expr)?;
-        let right_value = self.evaluate(right_expr)?;
+    // What a function declared here runs in: the variables it captures, in the
+    // order of `captures`, in front of the globals. They are shared with the
+    // environments declaring them, not copied, so assignments on either side
+    // are seen by the other. Functions capturing nothing run in the globals
+    fn closure(&mut self, captures: &[Slot]) -> Option<EnvironmentId> {
+        if captures.is_empty() {
+            return None;
+        }
 
-        let line = operator.line;
-        match operator.token_type {
-            // Arithmetic operations
-            // left_number  - | / | *  right_number
-            TokenType::Minus => {
-                let left = self.get_number_or_return_error(left_value, line)?;
-                let right = self.get_number_or_return_error(right_value, line)?;
+        let mut closure = Environment::new(None);
+        for slot in captures {
+            let environment = self.locals().clone();
+            let (name, captured) = self.heap.capture(&environment, *slot);
+            closure.define_captured(name, captured);
+        }
+        Some(self.new_environment(closure))
+    }
+
+    // What a Resolver needs to give globals the indices this interpreter keeps them at
+    pub fn global_names(&self) -> GlobalNames {
+        self.globals.borrow().global_names()
+    }
 
-                return Ok(Type::Number(left - right));
+    // Expects statements that went through a Resolver `with_globals` of its global_names,
+    // so locals have their slots and globals their indices
+    // The value of the program is the value of its last statement, when that is
+    // an expression other than an assignment, like `lost` exiting with it
+    pub fn interpret(&mut self, statements: &[Stmt]) -> Result<Option<Type>, Error> {
+        let Some((last, statements)) = statements.split_last() else {
+            return Ok(None);
+        };
+        for statement in statements {
+            self.execute(statement)?;
+        }
+        match last.value() {
+            Some(expr) => {
+                self.record(last);
+                let value = self.evaluate(expr)?;
+                self.trace_value(&value);
+                self.finalize()?;
+                Ok(Some(value))
             }
-            TokenType::Slash => {
-                let right = self.get_number_or_return_error(right_value, line)?;
-                if right == 0.0 {
-                    return Err(Error::InterpretError("Division by Zero".to_string(), line));
-                }
-                Ok(Type::Number(
-                    self.get_number_or_return_error(left_value, line)? / right,
-                ))
+            None => {
+                self.execute(last)?;
+                Ok(None)
             }
-            TokenType::Star => Ok(Type::Number(
-                self.get_number_or_return_error(left_value, line)?
-                    * self.get_number_or_return_error(right_value, line)?,
-            )),
+        }
+    }
 
-            // Arithmetic operation or String concatnation
-            // left_number + right_number
-            // left_string + right_string
-            TokenType::Plus => {
-                match self.get_number_or_return_error(left_value.clone(), line) {
-                    Ok(left_number) => {
-                        // Left is a number, so right has to be a number for '+' to be valid
-                        let right_number =
-                            self.get_number_or_return_error(right_value.clone(), line)?;
-                        Ok(Type::Number(left_number + right_number))
+    // For the REPL: the value of every expression statement is printed, as if
+    // it was a print statement showing the value with Type::repr
+    // Assignments and expressions evaluating to nil (like most calls) print nothing
+    pub fn interpret_echo(&mut self, statements: &[Stmt]) -> Result<(), Error> {
+        for statement in statements {
+            match statement.value() {
+                Some(expr) => {
+                    let value = self.evaluate(expr)?;
+                    if !matches!(value, Type::Nil) {
+                        self.write_output(&value.repr(), expr.span())?;
                     }
-                    _ => match self.get_number_or_return_error(right_value.clone(), line) {
-                        // Left is a String,
-                        // so right needs to be a String
-                        Ok(_) => Err(Error::interpreter(
-                            format!("Expected String, got {}", right_value),
-                            line,
-                        )),
-                        _ => {
-                            return Ok(Type::String(format!(
-                                "{}{}",
-                                left_value.value(),
-                                right_value.value()
-                            )));
-                        }
-                    },
+                }
+                _ => {
+                    self.execute(statement)?;
                 }
             }
+        }
+        Ok(())
+    }
 
-            // Comparison operations
-            // left_number  > | >= | < | <= | == | !=  right_number
-            TokenType::Greater => Ok(Type::Boolean(
-                self.get_number_or_return_error(left_value, line)?
-                    > self.get_number_or_return_error(right_value, line)?,
-            )),
-            TokenType::GreaterEqual => Ok(Type::Boolean(
-                self.get_number_or_return_error(left_value, line)?
-                    >= self.get_number_or_return_error(right_value, line)?,
-            )),
-            TokenType::Less => Ok(Type::Boolean(
-                self.get_number_or_return_error(left_value, line)?
-                    < self.get_number_or_return_error(right_value, line)?,
-            )),
-            TokenType::LessEqual => Ok(Type::Boolean(
-                self.get_number_or_return_error(left_value, line)?
-                    <= self.get_number_or_return_error(right_value, line)?,
-            )),
+    // A line of output, failing to write it fails the program
+    fn write_output(&mut self, line: &str, span: Span) -> Result<(), Error> {
+        writeln!(self.output, "{}", line)
+            .map_err(|error| Error::interpreter(format!("Could not print: {}", error), span))
+    }
 
-            // Comparing Equality
-            // left_value_of_X_type  == | !=  right_value_of_X_type
-            TokenType::EqualEqual => Ok(Type::Boolean(self.is_equal(left_value, right_value))),
-            TokenType::BangEqual => Ok(Type::Boolean(!self.is_equal(left_value, right_value))),
+    // A statement a generator goes on inside of was already traced and counted
+    fn execute(&mut self, stmt: &Stmt) -> Result<Flow, Error> {
+        self.record(stmt);
+        let flow = stmt.accept(self)?;
+        self.finalize()?;
+        Ok(flow)
+    }
 
-            _ => {
-                return Err(Error::interpreter(
-                    format!("Unexpected Operator, got {}", operator),
-                    line,
-                ));
+    // Runs the `__drop` methods of the instances that became unreachable, after
+    // the statement or block that dropped them. An error they raise is raised there
+    fn finalize(&mut self) -> Result<(), Error> {
+        while !self.dropped.borrow().is_empty() {
+            let dropped = std::mem::take(&mut *self.dropped.borrow_mut());
+            for method in dropped {
+                method.call(self, Some(Vec::new()), method.name.span)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Traces and counts a statement about to run
+    fn record(&mut self, stmt: &Stmt) {
+        if self.frames.is_empty() {
+            self.trace_statement(stmt);
+            if let Some(coverage) = self.coverage.as_mut() {
+                coverage.record(stmt);
             }
         }
     }
 
-    fn visit_grouping(&mut self, grouping_expr: &mut Box<Expr>) -> Result<Type, Error> {
-        self.evaluate(grouping_expr)
+    // The tree is only ever borrowed, running a loop does not copy its body
+    fn evaluate(&mut self, expr: &Expr) -> Result<Type, Error> {
+        expr.accept(self)
+    }
+
+    // Returns the number value if `value` is of type `Type::Number`, otherwise returns an `Error`.
+    pub fn get_number_or_return_error(&self, value: Type, span: Span) -> Result<f64, Error> {
+        match value {
+            Type::Number(val) => Ok(val),
+            _ => Err(
+                Error::interpreter(format!("Expected Number, got {}", value), span)
+                    .raised_as(ErrorClass::TypeError),
+            ),
+        }
+    }
+
+    fn has_element(&self, elements: &[Type], element: &Type) -> bool {
+        elements.iter().any(|existing| existing.is_equal(element))
+    }
+
+    // `element in collection`: an element of a set or list, a key of a map,
+    // or a string inside a string
+    fn contains(&self, element: Type, collection: Type, span: Span) -> Result<bool, Error> {
+        match collection {
+            Type::Set(elements) | Type::List(elements) => {
+                Ok(self.has_element(&elements.borrow(), &element))
+            }
+            Type::Map(entries) => Ok(entries
+                .borrow()
+                .iter()
+                .any(|(key, _)| key.is_equal(&element))),
+            Type::String(text) => match element {
+                Type::String(part) => Ok(text.contains(&*part)),
+                _ => Err(Error::interpreter(
+                    format!("Only a string can be in a string, got {}", element.repr()),
+                    span,
+                )
+                .raised_as(ErrorClass::TypeError)),
+            },
+            _ => Err(Error::interpreter(
+                format!(
+                    "`in` needs a set, list, map or string, got {}",
+                    collection.repr()
+                ),
+                span,
+            )
+            .raised_as(ErrorClass::TypeError)),
+        }
+    }
+
+    // Where `index` is in something `length` long
+    fn position(
+        &self,
+        index: &Type,
+        length: usize,
+        kind: &str,
+        span: Span,
+    ) -> Result<usize, Error> {
+        let Type::Number(number) = index else {
+            return Err(Error::interpreter(
+                format!("Index must be a whole number, got {}", index.repr()),
+                span,
+            )
+            .raised_as(ErrorClass::IndexError));
+        };
+        if number.fract() != 0.0 {
+            return Err(Error::interpreter(
+                format!("Index must be a whole number, got {}", number),
+                span,
+            )
+            .raised_as(ErrorClass::IndexError));
+        }
+        if *number < 0.0 || *number >= length as f64 {
+            return Err(Error::interpreter(
+                format!(
+                    "Index {} is out of range for a {} of length {}",
+                    number, kind, length
+                ),
+                span,
+            )
+            .raised_as(ErrorClass::IndexError));
+        }
+        Ok(*number as usize)
+    }
+
+    // Determines the truthiness of a Type value.
+    // Returns true for non-empty strings, non-zero numbers, and true booleans.
+    // Returns false for zero numbers, false booleans, and Nil values.
+    pub fn is_truthly(&self, value: &Type) -> bool {
+        match value {
+            Type::String(_) => true,
+            Type::Number(val) => *val != 0.0 && !val.is_nan(),
+            Type::Boolean(val) => *val,
+            Type::Function(_)
+            | Type::NativeFunction(_)
+            | Type::Class(_)
+            | Type::Instance(_)
+            | Type::Tuple(_)
+            | Type::List(_)
+            | Type::Map(_)
+            | Type::Set(_)
+            | Type::Generator(_)
+            | Type::Promise(_)
+            | Type::Channel(_)
+            | Type::Builder(_)
+            | Type::Module(_)
+            | Type::Socket(_)
+            | Type::Bytes(_) => true,
+            Type::Nil => false,
+        }
+    }
+
+    // Runs the file of a module written in Lost in the globals, wherever it is imported
+    fn load(&mut self, name: &Token) -> Result<Type, Error> {
+        let (path, mut statements) = match self.modules.load(&name.lexeme, name.span)? {
+            Load::Loaded(module) => return Ok(module),
+            Load::Run(path, statements) => (path, statements),
+        };
+        Resolver::with_globals(self.global_names()).resolve(&mut statements);
+
+        let previous = self.environment.take();
+        let coroutine = std::mem::replace(&mut self.coroutine, false);
+        let result = statements
+            .iter()
+            .try_for_each(|statement| self.execute(statement).map(|_| ()));
+        self.coroutine = coroutine;
+        self.environment = previous;
+        if let Err(error) = result {
+            self.modules.loaded(None);
+            return Err(modules::raised_in(&path, error, name.span));
+        }
+
+        let globals = self.globals.borrow();
+        let members = modules::declared(&statements)
+            .into_iter()
+            .filter_map(|member| Some((member.clone(), globals.value(&member)?)))
+            .collect();
+        drop(globals);
+        let module = Type::Module(Ref::new(Module {
+            name: name.lexeme.to_string(),
+            members,
+        }));
+        self.modules.loaded(Some(module.clone()));
+        Ok(module)
+    }
+
+    // Runs the statements from the one at `start` in `environment`, the caller's
+    // environment is back in place however they end
+    pub fn execute_block(
+        &mut self,
+        statements: &[Stmt],
+        start: usize,
+        environment: EnvironmentId,
+    ) -> Result<Flow, Error> {
+        let previous = self.environment.replace(environment.clone());
+
+        let mut flow = Ok(Flow::Next);
+        for (index, statement) in statements.iter().enumerate().skip(start) {
+            flow = self.execute(statement);
+            if let Ok(Flow::Yield(_)) = flow {
+                self.frames
+                    .push(Frame::Statements(index, environment.clone()));
+            }
+            if !matches!(flow, Ok(Flow::Next)) {
+                break;
+            }
+        }
+
+        self.environment = previous;
+        self.heap.release(environment);
+        flow.and_then(|flow| {
+            self.finalize()?;
+            Ok(flow)
+        })
+    }
+
+    // Runs the body of a function called. It is no coroutine's own body, so an
+    // `await` in it waits in place even when a coroutine called it
+    pub fn execute_call(
+        &mut self,
+        body: &[Stmt],
+        environment: EnvironmentId,
+        owner: Option<ClassId>,
+        span: Span,
+    ) -> Result<Flow, Error> {
+        self.enter(span)?;
+        let coroutine = std::mem::replace(&mut self.coroutine, false);
+        let caller = std::mem::replace(&mut self.owner, owner);
+        let flow = self.execute_block(body, 0, environment);
+        self.owner = caller;
+        self.coroutine = coroutine;
+        self.calls -= 1;
+        flow
+    }
+
+    fn enter(&mut self, span: Span) -> Result<(), Error> {
+        if self.calls == CALLS_MAX {
+            return Err(Error::interpreter("Stack overflow".to_string(), span));
+        }
+        self.calls += 1;
+        Ok(())
+    }
+
+    // Runs the generator up to its next `yield`, where `value` is what the `yield`
+    // it stopped at gives. Flow::Yield has the value yielded, Flow::Return the one
+    // returned at its end, and Flow::Next is for a generator that was done already
+    // The frames of the one running, if any, are put aside meanwhile
+    pub fn resume(
+        &mut self,
+        generator: &Shared<Generator>,
+        value: Type,
+        span: Span,
+    ) -> Result<Flow, Error> {
+        let (declaration, environment, mut frames, spawned, owner) = {
+            let mut generator = generator.borrow_mut();
+            match generator.state {
+                State::Done => return Ok(Flow::Next),
+                State::Running => {
+                    return Err(Error::interpreter(
+                        "Generator is already running".to_string(),
+                        span,
+                    ))
+                }
+                State::Suspended => generator.state = State::Running,
+            }
+            (
+                Ref::clone(&generator.declaration),
+                generator.environment.clone(),
+                std::mem::take(&mut generator.frames),
+                generator.spawned,
+                generator.owner,
+            )
+        };
+        let Stmt::Function(_, _, body, ..) = &*declaration else {
+            unreachable!("Generators are made of function statements")
+        };
+        if let Some(Frame::Yield(sent)) = frames.first_mut() {
+            *sent = value;
+        }
+
+        if let Err(error) = self.enter(span) {
+            let mut generator = generator.borrow_mut();
+            generator.frames = frames;
+            generator.state = State::Suspended;
+            return Err(error);
+        }
+        let outer = std::mem::replace(&mut self.frames, frames);
+        let coroutine = std::mem::replace(&mut self.coroutine, spawned);
+        let caller = std::mem::replace(&mut self.owner, owner);
+        let flow = match self.frames.pop() {
+            Some(Frame::Statements(start, environment)) => {
+                self.execute_block(body, start, environment)
+            }
+            _ => self.execute_block(body, 0, environment),
+        };
+        self.coroutine = coroutine;
+        self.owner = caller;
+        self.calls -= 1;
+        let frames = std::mem::replace(&mut self.frames, outer);
+
+        let mut generator = generator.borrow_mut();
+        match flow {
+            Ok(Flow::Yield(value)) => {
+                generator.frames = frames;
+                generator.state = State::Suspended;
+                Ok(Flow::Yield(value))
+            }
+            Ok(Flow::Next) => {
+                generator.state = State::Done;
+                Ok(Flow::Return(Type::Nil))
+            }
+            result => {
+                generator.state = State::Done;
+                result
+            }
+        }
+    }
+
+    // `await promise;` or `var x = await promise;` in the body of a coroutine: a
+    // pending promise stops the coroutine instead of the thread, whoever resumes
+    // it gets the promise, and the `await` is run again once it is resumed
+    fn await_statement(
+        &mut self,
+        keyword: &Token,
+        promise: &Expr,
+        variable: Option<&Token>,
+    ) -> Result<Flow, Error> {
+        let promise = match self.frames.pop() {
+            Some(Frame::Await(promise)) => promise,
+            _ => self.awaited(keyword, promise)?,
+        };
+        self.event_loop.poll();
+        if matches!(*promise.borrow(), Promise::Pending) {
+            self.frames.push(Frame::Await(Ref::clone(&promise)));
+            return Ok(Flow::Yield(Type::Promise(promise)));
+        }
+
+        let value = self.settled(keyword, &promise)?;
+        match variable {
+            Some(variable) => self.define(variable.lexeme.clone(), value),
+            None => self.trace_value(&value),
+        }
+        Ok(Flow::Next)
+    }
+
+    fn awaited(&mut self, keyword: &Token, promise: &Expr) -> Result<Shared<Promise>, Error> {
+        match self.evaluate(promise)? {
+            Type::Promise(promise) => Ok(promise),
+            value => Err(Error::interpreter(
+                format!("Only promises can be awaited, got {}", value.repr()),
+                keyword.span,
+            )
+            .raised_as(ErrorClass::TypeError)),
+        }
+    }
+
+    // The value of a settled promise, or the error of its operation
+    fn settled(&self, keyword: &Token, promise: &Shared<Promise>) -> Result<Type, Error> {
+        match &*promise.borrow() {
+            Promise::Resolved(Outcome::Nil) => Ok(Type::Nil),
+            Promise::Resolved(Outcome::String(text)) => Ok(Type::String(Ref::from(text.as_str()))),
+            Promise::Rejected(message) => Err(Error::interpreter(message.clone(), keyword.span)),
+            Promise::Pending => unreachable!("Only settled promises have a value"),
+        }
+    }
+
+    // `this._x` only reaches `_x` from the methods of the instance's class and its
+    // superclasses, and the functions declared in them, not from mixed in ones
+    fn check_private(&self, object: &Type, name: &Token) -> Result<(), Error> {
+        match object {
+            Type::Instance(instance) if is_private(&name.lexeme) => {
+                instance.borrow().check_private(name, self.owner)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // What a for-in loop goes through, the keys of a map
+    fn iteration(&self, iterable: Type, span: Span) -> Result<Iteration, Error> {
+        match iterable {
+            Type::Generator(generator) => Ok(Iteration::Generator(generator)),
+            Type::List(elements) | Type::Set(elements) => {
+                Ok(Iteration::Elements(elements.borrow().clone().into_iter()))
+            }
+            Type::Map(entries) => Ok(Iteration::Elements(
+                entries
+                    .borrow()
+                    .iter()
+                    .map(|(key, _)| key.clone())
+                    .collect::<Vec<Type>>()
+                    .into_iter(),
+            )),
+            _ => Err(Error::interpreter(
+                format!(
+                    "Only generators, lists, sets and maps can be looped over, got {}",
+                    iterable.repr()
+                ),
+                span,
+            )
+            .raised_as(ErrorClass::TypeError)),
+        }
+    }
+
+    fn next_element(
+        &mut self,
+        iteration: &mut Iteration,
+        span: Span,
+    ) -> Result<Option<Type>, Error> {
+        match iteration {
+            Iteration::Elements(elements) => Ok(elements.next()),
+            Iteration::Generator(generator) => match self.resume(generator, Type::Nil, span)? {
+                Flow::Yield(element) => Ok(Some(element)),
+                _ => Ok(None),
+            },
+        }
+    }
+
+    // What a catch gets for the error: the value thrown, or an instance of the
+    // built-in class a runtime error is raised as, holding its message
+    fn caught(&mut self, error: &Error) -> Option<Type> {
+        match error {
+            Error::Thrown(..) => self.thrown.clone(),
+            Error::InterpretError(message, _, class) => {
+                let instance = self.new_instance(Class::built_in(*class));
+                let message = Type::String(Ref::from(message.as_str()));
+                instance.borrow_mut().set_field("message", message);
+                Some(Type::Instance(instance))
+            }
+            _ => None,
+        }
+    }
+
+    // The method a `with` closes the resource with, `__exit` or else `close`
+    fn closer(&self, resource: &Type, span: Span) -> Result<Function, Error> {
+        let method = match resource {
+            Type::Instance(instance) => {
+                let this = Type::Instance(Ref::clone(instance));
+                let instance = instance.borrow();
+                instance
+                    .method("__exit")
+                    .or_else(|| instance.method("close"))
+                    .map(|method| method.bind(this))
+            }
+            _ => None,
+        };
+        method.ok_or_else(|| {
+            Error::interpreter(
+                format!(
+                    "Only instances with a `close` or `__exit` method can be used in `with`, got {}",
+                    resource.repr()
+                ),
+                span,
+            )
+            .raised_as(ErrorClass::TypeError)
+        })
+    }
+
+    // The first of the catches taking the error, their classes are only looked up
+    // until one does
+    fn catching(&mut self, catches: &[Catch], error: &Type) -> Result<Option<usize>, Error> {
+        for (index, catch) in catches.iter().enumerate() {
+            let Some(class) = &catch.class else {
+                return Ok(Some(index));
+            };
+            let class = match self.evaluate(class)? {
+                Type::Class(class) => class,
+                value => {
+                    return Err(Error::interpreter(
+                        format!("Only classes can be caught, got {}", value.repr()),
+                        class.span(),
+                    )
+                    .raised_as(ErrorClass::TypeError))
+                }
+            };
+            if let Type::Instance(instance) = error {
+                if instance.borrow().is_instance_of(&class) {
+                    return Ok(Some(index));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    // Whether the value matches the pattern, the values of the names it binds are
+    // added to `bound` in the order of Pattern::bindings
+    // `classes` are those of its instance patterns, in the order of Pattern::classes
+    fn matches(
+        &self,
+        pattern: &Pattern,
+        value: &Type,
+        classes: &mut impl Iterator<Item = Class>,
+        bound: &mut Vec<(Ref<str>, Type)>,
+    ) -> bool {
+        match pattern {
+            Pattern::Wildcard(_) => true,
+            Pattern::Binding(name) => {
+                bound.push((name.lexeme.clone(), value.clone()));
+                true
+            }
+            Pattern::Literal(literal) => literal_value(literal).is_equal(value),
+            Pattern::List(_, patterns) => {
+                let Type::List(elements) = value else {
+                    return false;
+                };
+                let elements = elements.borrow().clone();
+                elements.len() == patterns.len()
+                    && patterns
+                        .iter()
+                        .zip(elements.iter())
+                        .all(|(pattern, element)| self.matches(pattern, element, classes, bound))
+            }
+            Pattern::Map(_, patterns) => {
+                let Type::Map(entries) = value else {
+                    return false;
+                };
+                let entries = entries.borrow().clone();
+                patterns.iter().all(|(key, pattern)| {
+                    let key = literal_value(key);
+                    entries
+                        .iter()
+                        .find(|(entry_key, _)| key.is_equal(entry_key))
+                        .is_some_and(|(_, entry_value)| {
+                            self.matches(pattern, entry_value, classes, bound)
+                        })
+                })
+            }
+            Pattern::Instance(_, fields) => {
+                let Some(class) = classes.next() else {
+                    return false;
+                };
+                let Type::Instance(instance) = value else {
+                    return false;
+                };
+                if !instance.borrow().is_instance_of(&class) {
+                    return false;
+                }
+                fields.iter().all(|(field, pattern)| {
+                    let field = instance.borrow().field(&field.lexeme);
+                    field.is_some_and(|field| self.matches(pattern, &field, classes, bound))
+                })
+            }
+        }
+    }
+
+    // A resumed loop goes on with its body
+    fn execute_forloop(
+        &mut self,
+        initializer: Option<&Stmt>,
+        condition: Option<&Expr>,
+        incrementer: Option<&Expr>,
+        body: &Stmt,
+        mut resuming: bool,
+    ) -> Result<Flow, Error> {
+        if let Some(initializer) = initializer.filter(|_| !resuming) {
+            self.execute(initializer)?;
+        }
+
+        loop {
+            // A missing condition loops forever
+            if let Some(condition) = condition.filter(|_| !resuming) {
+                let evaluated_condition = self.evaluate(condition)?;
+                if !self.is_truthly(&evaluated_condition) {
+                    break;
+                }
+            }
+            resuming = false;
+
+            match self.execute(body)? {
+                Flow::Next => {}
+                flow => return Ok(flow),
+            }
+
+            if let Some(incrementer) = incrementer {
+                self.evaluate(incrementer)?;
+            }
+        }
+
+        Ok(Flow::Next)
+    }
+}
+
+// The value of a literal of a pattern, the parser only makes them of literal tokens
+fn literal_value(literal: &Token) -> Type {
+    match (&literal.token_type, &literal.literal) {
+        (TokenType::Number, Some(LiteralType::NumberType(number))) => Type::Number(*number),
+        (TokenType::String, Some(LiteralType::StringType(string))) => {
+            Type::String(Ref::from(string.as_str()))
+        }
+        (TokenType::True, _) => Type::Boolean(true),
+        (TokenType::False, _) => Type::Boolean(false),
+        _ => Type::Nil,
+    }
+}
+
+impl ExpressionVisitor<Result<Type, Error>> for Interpreter {
+    fn visit_binary(
+        &mut self,
+        left_expr: &Expr,
+        operator: &Token,
+        right_expr: &Expr,
+    ) -> Result<Type, Error> {
+        let left_value = self.evaluate(left_expr)?;
+        let right_value = self.evaluate(right_expr)?;
+
+        let span = operator.span;
+        match operator.token_type {
+            // Arithmetic operations
+            // left_number  - | / | *  right_number
+            TokenType::Minus => {
+                let left = self.get_number_or_return_error(left_value, span)?;
+                let right = self.get_number_or_return_error(right_value, span)?;
+
+                Ok(Type::Number(left - right))
+            }
+            // IEEE 754: dividing by zero gives an infinity, or NaN for 0 / 0
+            TokenType::Slash => {
+                let left = self.get_number_or_return_error(left_value, span)?;
+                let right = self.get_number_or_return_error(right_value, span)?;
+
+                Ok(Type::Number(left / right))
+            }
+            // left_string * right_number repeats the string
+            TokenType::Star => match (&left_value, &right_value) {
+                (Type::String(text), Type::Number(times)) => strings::repeat(text, *times)
+                    .map(|repeated| Type::String(Ref::from(repeated)))
+                    .map_err(|message| Error::interpreter(message, span)),
+                _ => Ok(Type::Number(
+                    self.get_number_or_return_error(left_value, span)?
+                        * self.get_number_or_return_error(right_value, span)?,
+                )),
+            },
+
+            // Arithmetic operation or String concatnation
+            // left_number + right_number
+            // left_string + right_string
+            TokenType::Plus => {
+                match self.get_number_or_return_error(left_value.clone(), span) {
+                    Ok(left_number) => {
+                        // Left is a number, so right has to be a number for '+' to be valid
+                        let right_number =
+                            self.get_number_or_return_error(right_value.clone(), span)?;
+                        Ok(Type::Number(left_number + right_number))
+                    }
+                    _ => match self.get_number_or_return_error(right_value.clone(), span) {
+                        // Left is a String,
+                        // so right needs to be a String
+                        Ok(_) => Err(Error::interpreter(
+                            format!("Expected String, got {}", right_value),
+                            span,
+                        )
+                        .raised_as(ErrorClass::TypeError)),
+                        // Anything else is joined as text, collections only with a string
+                        _ if !matches!(left_value, Type::String(_))
+                            && !matches!(right_value, Type::String(_))
+                            && (is_collection(&left_value) || is_collection(&right_value)) =>
+                        {
+                            let collection = match is_collection(&left_value) {
+                                true => &left_value,
+                                false => &right_value,
+                            };
+                            Err(Error::interpreter(
+                                format!("Expected Number or String, got {}", collection.repr()),
+                                span,
+                            )
+                            .raised_as(ErrorClass::TypeError))
+                        }
+                        _ => Ok(Type::String(Ref::from(format!(
+                            "{}{}",
+                            left_value.value(),
+                            right_value.value()
+                        )))),
+                    },
+                }
+            }
+
+            // Comparison operations
+            // left_number  > | >= | < | <= | == | !=  right_number
+            TokenType::Greater => Ok(Type::Boolean(
+                self.get_number_or_return_error(left_value, span)?
+                    > self.get_number_or_return_error(right_value, span)?,
+            )),
+            TokenType::GreaterEqual => Ok(Type::Boolean(
+                self.get_number_or_return_error(left_value, span)?
+                    >= self.get_number_or_return_error(right_value, span)?,
+            )),
+            TokenType::Less => Ok(Type::Boolean(
+                self.get_number_or_return_error(left_value, span)?
+                    < self.get_number_or_return_error(right_value, span)?,
+            )),
+            TokenType::LessEqual => Ok(Type::Boolean(
+                self.get_number_or_return_error(left_value, span)?
+                    <= self.get_number_or_return_error(right_value, span)?,
+            )),
+
+            // Comparing Equality
+            // left_value_of_X_type  == | !=  right_value_of_X_type
+            TokenType::EqualEqual => Ok(Type::Boolean(left_value.is_equal(&right_value))),
+            TokenType::BangEqual => Ok(Type::Boolean(!left_value.is_equal(&right_value))),
+            TokenType::Is => Ok(Type::Boolean(left_value.is(&right_value))),
+
+            // Membership
+            // left_value  in  right_set | right_list | right_map | right_string
+            TokenType::In => Ok(Type::Boolean(self.contains(
+                left_value,
+                right_value,
+                span,
+            )?)),
+
+            _ => Err(Error::interpreter(
+                format!("Unexpected Operator, got {}", operator),
+                span,
+            )),
+        }
+    }
+
+    fn visit_grouping(&mut self, grouping_expr: &Expr) -> Result<Type, Error> {
+        self.evaluate(grouping_expr)
+    }
+
+    // Nil for a property the value doesn't have, instead of raising
+    fn visit_optional_get(&mut self, expr: &Expr, name: &Token) -> Result<Type, Error> {
+        let object = self.evaluate(expr)?;
+        self.check_private(&object, name)?;
+        Ok(property_of(&object, &name.lexeme).unwrap_or(Type::Nil))
+    }
+
+    fn visit_get(&mut self, expr: &Expr, name: &Token) -> Result<Type, Error> {
+        let object = self.evaluate(expr)?;
+        self.check_private(&object, name)?;
+        match object {
+            Type::Instance(instance) => Instance::get(&instance, name),
+            Type::Module(module) => module.members.get(&*name.lexeme).cloned().ok_or_else(|| {
+                Error::interpreter(
+                    format!("Module `{}` has no `{}`", module.name, name.lexeme),
+                    name.span,
+                )
+                .raised_as(ErrorClass::NameError)
+            }),
+            Type::Bytes(bytes) => match bytes_method(&bytes, &name.lexeme) {
+                Some(method) => Ok(Type::NativeFunction(Box::new(method))),
+                None => Err(Error::interpreter(
+                    format!("Bytes have no method `{}`", name.lexeme),
+                    name.span,
+                )
+                .raised_as(ErrorClass::NameError)),
+            },
+            #[cfg(feature = "sockets")]
+            Type::Socket(socket) => match socket_method(&socket, &name.lexeme) {
+                Some(method) => Ok(Type::NativeFunction(Box::new(method))),
+                None => Err(Error::interpreter(
+                    format!("{} has no method `{}`", socket.kind(), name.lexeme),
+                    name.span,
+                )
+                .raised_as(ErrorClass::NameError)),
+            },
+            _ => Err(Error::interpreter(
+                "Only instances and modules have properties".to_string(),
+                name.span,
+            )
+            .raised_as(ErrorClass::TypeError)),
+        }
+    }
+
+    // `list[i]` and `string[i]` count from 0, `map[key]` needs the key to be in it
+    fn visit_index(&mut self, object: &Expr, index: &Expr, close: &Token) -> Result<Type, Error> {
+        let object = self.evaluate(object)?;
+        let index = self.evaluate(index)?;
+        match object {
+            Type::List(elements) => {
+                let elements = elements.borrow();
+                let position = self.position(&index, elements.len(), "list", close.span)?;
+                Ok(elements[position].clone())
+            }
+            Type::String(text) => {
+                let length = text.chars().count();
+                let position = self.position(&index, length, "string", close.span)?;
+                Ok(Type::String(Ref::from(
+                    text.chars().nth(position).unwrap().to_string(),
+                )))
+            }
+            Type::Bytes(bytes) => {
+                let position = self.position(&index, bytes.len(), "buffer", close.span)?;
+                Ok(Type::Number(f64::from(bytes[position])))
+            }
+            Type::Map(entries) => entries
+                .borrow()
+                .iter()
+                .find(|(key, _)| key.is_equal(&index))
+                .map(|(_, value)| value.clone())
+                .ok_or_else(|| {
+                    Error::interpreter(
+                        format!("Key {} is not in the map", index.repr()),
+                        close.span,
+                    )
+                    .raised_as(ErrorClass::IndexError)
+                }),
+            _ => Err(Error::interpreter(
+                format!(
+                    "Only lists, maps, strings and bytes can be indexed, got {}",
+                    object.repr()
+                ),
+                close.span,
+            )
+            .raised_as(ErrorClass::TypeError)),
+        }
+    }
+
+    fn visit_set(&mut self, expr: &Expr, name: &Token, value: &Expr) -> Result<Type, Error> {
+        let object = self.evaluate(expr)?;
+        self.check_private(&object, name)?;
+
+        match object {
+            Type::Instance(instance) => {
+                let value = self.evaluate(value)?;
+                instance.borrow_mut().set(name, &value)?;
+                Ok(value)
+            }
+            _ => Err(
+                Error::interpreter("Only instances have fields".to_string(), name.span)
+                    .raised_as(ErrorClass::TypeError),
+            ),
+        }
+    }
+
+    fn visit_unary(&mut self, operator: &Token, unary_expr: &Expr) -> Result<Type, Error> {
+        let right = self.evaluate(unary_expr)?;
+
+        let span = operator.span;
+        match operator.token_type {
+            TokenType::Minus => Ok(Type::Number(match right {
+                Type::Number(val) => -val,
+                _ => {
+                    return Err(
+                        Error::interpreter(format!("Expected Number, got {}", right), span)
+                            .raised_as(ErrorClass::TypeError),
+                    )
+                }
+            })),
+            TokenType::Bang => Ok(Type::Boolean(!self.is_truthly(&right))),
+            _ => Err(Error::interpreter(
+                format!("Expected `!` or `-`, got {}", operator),
+                span,
+            )),
+        }
+    }
+
+    fn visit_literal(&mut self, lit: &Token) -> Result<Type, Error> {
+        let span = lit.span;
+        match lit.token_type {
+            // String and Number literals
+            TokenType::String => Ok(Type::String(match &lit.literal {
+                Some(val) => match val {
+                    LiteralType::StringType(string_val) => Ref::from(string_val.as_str()),
+                    LiteralType::NumberType(number_val) => {
+                        return Err(Error::interpreter(
+                            format!("Expected String, got Number: `{}`", number_val),
+                            span,
+                        ));
+                    }
+                },
+                None => {
+                    return Err(Error::interpreter(
+                        "Expected String, got None".to_string(),
+                        span,
+                    ))
+                }
+            })),
+            TokenType::Number => Ok(Type::Number(match lit.literal.clone() {
+                Some(val) => match val {
+                    LiteralType::NumberType(number_val) => number_val,
+                    LiteralType::StringType(string_val) => {
+                        return Err(Error::interpreter(
+                            format!("Expected String, got String: `{}`", string_val),
+                            span,
+                        ));
+                    }
+                },
+                None => {
+                    return Err(Error::interpreter(
+                        "Expected String, got None".to_string(),
+                        span,
+                    ))
+                }
+            })),
+
+            // Booleans
+            TokenType::True => Ok(Type::Boolean(true)),
+            TokenType::False => Ok(Type::Boolean(false)),
+
+            // Nil
+            TokenType::Nil => Ok(Type::Nil),
+
+            _ => Err(Error::interpreter(
+                "Unexpected! unreachable code reached".to_string(),
+                span,
+            )),
+        }
+    }
+
+    fn visit_variable(
+        &mut self,
+        variable: &Token,
+        location: Option<Location>,
+    ) -> Result<Type, Error> {
+        match location {
+            Some(Location::Local(slot)) => Ok(self.heap.get_at(self.locals(), slot)),
+            Some(Location::Global(index)) => self.globals.borrow().get_at(index, variable),
+            None => self.globals.borrow().get(variable),
+        }
+    }
+
+    fn visit_this(&mut self, keyword: &Token, location: Option<Location>) -> Result<Type, Error> {
+        self.visit_variable(keyword, location)
+    }
+
+    // The superclass' method, bound to the instance the method runs on
+    fn visit_super(
+        &mut self,
+        keyword: &Token,
+        method: &Token,
+        superclass: Option<Location>,
+        this: Option<Location>,
+    ) -> Result<Type, Error> {
+        let Type::Class(class) = self.visit_variable(keyword, superclass)? else {
+            unreachable!("`super` is always a class");
+        };
+        let this_keyword = Token {
+            lexeme: Ref::from("this"),
+            ..keyword.clone()
+        };
+        let instance = self.visit_variable(&this_keyword, this)?;
+        match class.find_method(&method.lexeme) {
+            Some(found) => Ok(Type::Function(Box::new(found.bind(instance)))),
+            None => Err(Error::interpreter(
+                format!(
+                    "Superclass `{}` has no method `{}`",
+                    class.name, method.lexeme
+                ),
+                method.span,
+            )
+            .raised_as(ErrorClass::NameError)),
+        }
+    }
+
+    fn visit_assign(
+        &mut self,
+        variable: &Token,
+        expr: &Expr,
+        location: Option<Location>,
+    ) -> Result<Type, Error> {
+        let value = self.evaluate(expr)?;
+        match location {
+            Some(Location::Local(slot)) => {
+                let environment = self.locals().clone();
+                self.heap.assign_at(&environment, slot, value.clone())
+            }
+            Some(Location::Global(index)) => {
+                self.globals
+                    .borrow_mut()
+                    .assign_at(index, variable, value.clone())?
+            }
+            None => self.globals.borrow_mut().assign(variable, value.clone())?,
+        }
+        Ok(value)
+    }
+
+    fn visit_tuple(&mut self, values: &[Expr]) -> Result<Type, Error> {
+        let values = values
+            .iter()
+            .map(|value| self.evaluate(value))
+            .collect::<Result<Vec<Type>, Error>>()?;
+        Ok(Type::Tuple(values))
+    }
+
+    fn visit_list(&mut self, elements: &[Expr]) -> Result<Type, Error> {
+        let elements = elements
+            .iter()
+            .map(|element| self.evaluate(element))
+            .collect::<Result<Vec<Type>, Error>>()?;
+        Ok(Type::List(Ref::new(Lock::new(elements))))
+    }
+
+    // A key set twice keeps its first place and its last value
+    fn visit_map(&mut self, entries: &[(Expr, Expr)]) -> Result<Type, Error> {
+        let mut map: Vec<(Type, Type)> = Vec::with_capacity(entries.len());
+        for (key_expr, value) in entries.iter() {
+            let key = self.evaluate(key_expr)?;
+            if key.key().is_none() {
+                return Err(Error::interpreter(
+                    format!(
+                        "Map keys must be strings, numbers, booleans or nil, got {}",
+                        key.repr()
+                    ),
+                    key_expr.span(),
+                )
+                .raised_as(ErrorClass::TypeError));
+            }
+            let value = self.evaluate(value)?;
+            map.push((key, value));
+        }
+        let map = equality::unique_entries(&map, Type::key);
+        Ok(Type::Map(Ref::new(Lock::new(map))))
+    }
+
+    // An element given twice is only in the set once, where it was first given
+    fn visit_set_literal(&mut self, elements: &[Expr]) -> Result<Type, Error> {
+        let mut set: Vec<Type> = Vec::with_capacity(elements.len());
+        for element_expr in elements.iter() {
+            let element = self.evaluate(element_expr)?;
+            if element.key().is_none() {
+                return Err(Error::interpreter(
+                    format!(
+                        "Set elements must be strings, numbers, booleans or nil, got {}",
+                        element.repr()
+                    ),
+                    element_expr.span(),
+                )
+                .raised_as(ErrorClass::TypeError));
+            }
+            set.push(element);
+        }
+        let set = equality::unique_elements(&set, Type::key);
+        Ok(Type::Set(Ref::new(Lock::new(set))))
+    }
+
+    // The first arm whose pattern matches is evaluated, in an environment of its own
+    // holding the names the pattern binds
+    fn visit_match(&mut self, keyword: &Token, value: &Expr, arms: &[Arm]) -> Result<Type, Error> {
+        let value = self.evaluate(value)?;
+        for arm in arms.iter() {
+            let mut classes = Vec::new();
+            for class in arm.pattern.classes() {
+                match self.evaluate(class)? {
+                    Type::Class(evaluated) => classes.push(*evaluated),
+                    _ => {
+                        return Err(Error::interpreter(
+                            "Only classes can be matched with fields".to_string(),
+                            class.span(),
+                        )
+                        .raised_as(ErrorClass::TypeError))
+                    }
+                }
+            }
+            let mut bound = Vec::new();
+            if !self.matches(&arm.pattern, &value, &mut classes.into_iter(), &mut bound) {
+                continue;
+            }
+
+            let mut environment = Environment::new(self.environment.clone());
+            for (name, value) in bound {
+                environment.define(name, value);
+            }
+            let environment = self.new_environment(environment);
+            let previous = self.environment.replace(environment);
+            let result = self.evaluate(&arm.body);
+            self.environment = previous;
+            return result;
+        }
+
+        Err(Error::interpreter(
+            format!("No arm matches {}", value.repr()),
+            keyword.span,
+        ))
+    }
+
+    fn visit_logical(
+        &mut self,
+        left_expr: &Expr,
+        logical_and_or: &Token,
+        right_expr: &Expr,
+    ) -> Result<Type, Error> {
+        let left_value = self.evaluate(left_expr)?;
+
+        match logical_and_or.token_type {
+            TokenType::Or => {
+                if self.is_truthly(&left_value) {
+                    return Ok(left_value);
+                }
+            }
+            TokenType::And => {
+                if !self.is_truthly(&left_value) {
+                    return Ok(left_value);
+                }
+            }
+            _ => {
+                println!("{:#?}", &logical_and_or);
+                unreachable!()
+            }
+        }
+
+        self.evaluate(right_expr)
+    }
+
+    fn visit_call(
+        &mut self,
+        callee: &Expr,
+        closing_paren: &Token,
+        arguments: &[Expr],
+    ) -> Result<Type, Error> {
+        let callee = self.evaluate(callee)?;
+
+        let mut evaluated_arguments = Vec::new();
+        for argument in arguments.iter() {
+            evaluated_arguments.push(self.evaluate(argument)?);
+        }
+
+        match callee {
+            Type::Function(to_call) => {
+                if to_call.arity != evaluated_arguments.len() {
+                    return Err(Error::interpreter(
+                        arity_message(
+                            &to_call.name.lexeme,
+                            to_call.arity,
+                            evaluated_arguments.len(),
+                        ),
+                        closing_paren.span,
+                    )
+                    .raised_as(ErrorClass::TypeError));
+                }
+                to_call.call(self, Some(evaluated_arguments), closing_paren.span)
+            }
+            Type::NativeFunction(to_call) => {
+                if let Some(message) = to_call.arity_error(evaluated_arguments.len()) {
+                    return Err(Error::interpreter(message, closing_paren.span)
+                        .raised_as(ErrorClass::TypeError));
+                }
+                to_call.call(self, Some(evaluated_arguments), closing_paren.span)
+            }
+            Type::Class(to_call) => {
+                // Errors without an initializer take their message, or leave it nil
+                let error = to_call.is_error() && to_call.initializer().is_none();
+                let count = evaluated_arguments.len();
+                if to_call.arity() != count && !(error && count == 1) {
+                    let expected = if error { 1 } else { to_call.arity() };
+                    return Err(Error::interpreter(
+                        arity_message(&to_call.name, expected, count),
+                        closing_paren.span,
+                    )
+                    .raised_as(ErrorClass::TypeError));
+                }
+
+                if !error {
+                    return to_call.call(self, Some(evaluated_arguments), closing_paren.span);
+                }
+                let instance = to_call.call(self, None, closing_paren.span)?;
+                if let Type::Instance(instance) = &instance {
+                    let message = evaluated_arguments.pop().unwrap_or(Type::Nil);
+                    instance.borrow_mut().set_field("message", message);
+                }
+                Ok(instance)
+            }
+            _ => Err(
+                Error::interpreter("Not a function".to_string(), closing_paren.span)
+                    .raised_as(ErrorClass::TypeError),
+            ),
+        }
+    }
+
+    // Waits for the promise to settle, the script runs no further until it has
+    fn visit_await(&mut self, keyword: &Token, promise: &Expr) -> Result<Type, Error> {
+        let promise = self.awaited(keyword, promise)?;
+        self.event_loop.wait(&promise);
+        self.settled(keyword, &promise)
+    }
+
+    // A call made into a coroutine, suspended before any of its body runs
+    fn visit_spawn(&mut self, _keyword: &Token, call: &Expr) -> Result<Type, Error> {
+        let Expr::Call(callee, closing_paren, arguments) = call else {
+            unreachable!("the parser only spawns calls")
+        };
+        let callee = self.evaluate(callee)?;
+
+        let mut evaluated_arguments = Vec::new();
+        for argument in arguments.iter() {
+            evaluated_arguments.push(self.evaluate(argument)?);
+        }
+
+        let Type::Function(function) = callee else {
+            return Err(Error::interpreter(
+                format!("Only functions can be spawned, got {}", callee.repr()),
+                closing_paren.span,
+            )
+            .raised_as(ErrorClass::TypeError));
+        };
+        if function.arity != evaluated_arguments.len() {
+            return Err(Error::interpreter(
+                arity_message(
+                    &function.name.lexeme,
+                    function.arity,
+                    evaluated_arguments.len(),
+                ),
+                closing_paren.span,
+            )
+            .raised_as(ErrorClass::TypeError));
+        }
+
+        let mut coroutine = function.suspended_call(self, evaluated_arguments);
+        coroutine.spawned = true;
+        let coroutine = Ref::new(Lock::new(coroutine));
+        self.scheduler.spawn(Ref::clone(&coroutine));
+        Ok(Type::Generator(coroutine))
+    }
+}
+
+impl StatementVisitor<Result<Flow, Error>> for Interpreter {
+    fn visit_block(&mut self, statements: &[Stmt]) -> Result<Flow, Error> {
+        match self.frames.pop() {
+            Some(Frame::Statements(start, environment)) => {
+                self.execute_block(statements, start, environment)
+            }
+            _ => {
+                let new_env = self.enclosed_environment();
+                self.execute_block(statements, 0, new_env)
+            }
+        }
+    }
+
+    fn visit_class(
+        &mut self,
+        name: &Token,
+        superclass: Option<&Expr>,
+        mixins: &[Expr],
+        statements: &[Stmt],
+        sealed: bool,
+    ) -> Result<Flow, Error> {
+        // Everything that can fail is checked before the name is declared, so
+        // a broken declaration leaves nothing bound
+        let parent = match superclass {
+            Some(parent) => match self.evaluate(parent)? {
+                Type::Class(parent_class) => Some(parent_class),
+                _ => {
+                    return Err(Error::interpreter(
+                        "Superclass must be a class".to_string(),
+                        parent.span(),
+                    )
+                    .raised_as(ErrorClass::TypeError))
+                }
+            },
+            None => None,
+        };
+        let mut evaluated = Vec::with_capacity(mixins.len());
+        for mixin in mixins.iter() {
+            let Type::Class(class) = self.evaluate(mixin)? else {
+                return Err(
+                    Error::interpreter("Mixin must be a class".to_string(), mixin.span())
+                        .raised_as(ErrorClass::TypeError),
+                );
+            };
+            evaluated.push((class, mixin));
+        }
+        let mut declarations = Vec::with_capacity(statements.len());
+        for method in statements.iter() {
+            match method {
+                Stmt::Function(method_name, parameters, _, signature, captures) => {
+                    if signature.overriding {
+                        Self::check_override(
+                            name,
+                            parent.as_deref(),
+                            &evaluated,
+                            method_name,
+                            parameters.len(),
+                        )?;
+                    }
+                    declarations.push((method, method_name, parameters.len(), captures))
+                }
+                _ => {
+                    return Err(Error::interpreter(
+                        "Method is not a function statement".to_string(),
+                        name.span,
+                    ))
+                }
+            }
+        }
+        let own: Vec<&str> = declarations
+            .iter()
+            .map(|(_, method_name, ..)| &*method_name.lexeme)
+            .collect();
+        let mixed = self.mix(name, &evaluated, &own)?;
+
+        // Declared before the methods capture it, so they can refer to the class
+        // Like any function, a method runs in the variables it captures, which
+        // outlive the scope the class is declared in
+        let declaration = self.declare(name);
+        // With a superclass, the methods capture `super` from a class environment
+        let enclosing = self.environment.clone();
+        if let Some(parent) = &parent {
+            let mut environment = Environment::new(enclosing.clone());
+            environment.define(Ref::from("super"), Type::Class(parent.clone()));
+            self.environment = Some(self.new_environment(environment));
+        }
+        let mut methods = HashMap::<Ref<str>, Function>::new();
+        for (method, method_name, arity, captures) in declarations {
+            let function = Function::new(
+                method_name.clone(),
+                arity,
+                Ref::new(method.clone()),
+                self.closure(captures),
+            );
+            methods.insert(method_name.lexeme.clone(), function.method());
+        }
+        self.environment = enclosing;
+        methods.extend(mixed);
+
+        let class = Class::new(name.lexeme.clone(), parent, methods)
+            .finalized_in(Ref::clone(&self.dropped))
+            .sealed(sealed);
+        let class = Box::new(class);
+        self.initialize(declaration, Type::Class(class))?;
+        Ok(Flow::Next)
+    }
+
+    fn visit_expression(&mut self, expr: &Expr) -> Result<Flow, Error> {
+        if let (true, Expr::Await(keyword, promise)) = (self.coroutine, expr) {
+            return self.await_statement(keyword, promise, None);
+        }
+        let value = self.evaluate(expr)?;
+        self.trace_value(&value);
+
+        Ok(Flow::Next)
+    }
+
+    // Binds the module to its name, it is loaded the first time it is imported
+    fn visit_import(&mut self, _keyword: &Token, name: &Token) -> Result<Flow, Error> {
+        let module = self
+            .modules
+            .import(&name.lexeme, |module| Type::Module(Ref::new(module)));
+        let module = match module {
+            Some(module) => module,
+            None => self.load(name)?,
+        };
+        self.define(name.lexeme.clone(), module);
+        Ok(Flow::Next)
+    }
+
+    fn visit_print(&mut self, expr: &Expr) -> Result<Flow, Error> {
+        let value = self.evaluate(expr)?;
+        self.write_output(&value.to_string(), expr.span())?;
+
+        Ok(Flow::Next)
+    }
+
+    fn visit_var(
+        &mut self,
+        token: &Token,
+        _annotation: &Option<Token>,
+        expr: Option<&Expr>,
+    ) -> Result<Flow, Error> {
+        // token is the variable
+        // expr is the value for the variable // initializer
+        match expr {
+            Some(Expr::Await(keyword, promise)) if self.coroutine => {
+                return self.await_statement(keyword, promise, Some(token));
+            }
+            Some(val) => {
+                let val = self.evaluate(val)?;
+                self.define(token.lexeme.clone(), val.clone());
+            }
+            _ => self.define(token.lexeme.clone(), Type::Nil),
+        }
+        Ok(Flow::Next)
+    }
+
+    fn visit_unpack(&mut self, names: &[Token], expr: Option<&Expr>) -> Result<Flow, Error> {
+        let values = match expr {
+            Some(expr) => match self.evaluate(expr)? {
+                Type::Tuple(values) if values.len() == names.len() => values,
+                value => {
+                    let count = match value {
+                        Type::Tuple(values) => values.len(),
+                        _ => 1,
+                    };
+                    return Err(Error::interpreter(
+                        format!("Expected {} values to unpack, got {}", names.len(), count),
+                        expr.span(),
+                    ));
+                }
+            },
+            None => vec![Type::Nil; names.len()],
+        };
+
+        for (name, value) in names.iter().zip(values) {
+            self.define(name.lexeme.clone(), value);
+        }
+        Ok(Flow::Next)
+    }
+
+    fn visit_ifelse(
+        &mut self,
+        condition: &Expr,
+        then_branch: &Stmt,
+        else_branch: Option<&Stmt>,
+    ) -> Result<Flow, Error> {
+        let then = match self.frames.pop() {
+            Some(Frame::Branch(then)) => then,
+            _ => {
+                let condition_evaluated = self.evaluate(condition)?;
+                self.is_truthly(&condition_evaluated)
+            }
+        };
+        let flow = match (then, else_branch) {
+            (true, _) => self.execute(then_branch)?,
+            (false, Some(else_branch)) => self.execute(else_branch)?,
+            (false, None) => Flow::Next,
+        };
+        if let Flow::Yield(_) = flow {
+            self.frames.push(Frame::Branch(then));
+        }
+        Ok(flow)
+    }
+
+    fn visit_whileloop(&mut self, condition: &Expr, statement: &Stmt) -> Result<Flow, Error> {
+        // A resumed loop goes on with its body
+        let mut resuming = matches!(self.frames.pop(), Some(Frame::While));
+
+        loop {
+            if !resuming {
+                let evaluated_condition = self.evaluate(condition)?;
+                if !self.is_truthly(&evaluated_condition) {
+                    break;
+                }
+            }
+            resuming = false;
+
+            match self.execute(statement)? {
+                Flow::Next => {}
+                Flow::Yield(value) => {
+                    self.frames.push(Frame::While);
+                    return Ok(Flow::Yield(value));
+                }
+                flow => return Ok(flow),
+            }
+        }
+
+        Ok(Flow::Next)
+    }
+
+    fn visit_forloop(
+        &mut self,
+        initializer: Option<&Stmt>,
+        condition: Option<&Expr>,
+        incrementer: Option<&Expr>,
+        body: &Stmt,
+    ) -> Result<Flow, Error> {
+        // The initializer is scoped to the loop
+        let (environment, resuming) = match self.frames.pop() {
+            Some(Frame::For(environment)) => (environment, true),
+            _ => (self.enclosed_environment(), false),
+        };
+        let previous = self.environment.replace(environment.clone());
+
+        let flow = self.execute_forloop(initializer, condition, incrementer, body, resuming);
+
+        self.environment = previous;
+        if let Ok(Flow::Yield(_)) = flow {
+            self.frames.push(Frame::For(environment));
+        }
+        flow
+    }
+
+    fn visit_forin(&mut self, name: &Token, iterable: &Expr, body: &Stmt) -> Result<Flow, Error> {
+        let (mut iteration, mut resumed) = match self.frames.pop() {
+            Some(Frame::ForIn(iteration, environment)) => (iteration, Some(environment)),
+            _ => {
+                let value = self.evaluate(iterable)?;
+                (self.iteration(value, iterable.span())?, None)
+            }
+        };
+
+        loop {
+            // Every element has its own environment, closures in the body keep theirs
+            let environment = match resumed.take() {
+                Some(environment) => environment,
+                None => match self.next_element(&mut iteration, iterable.span())? {
+                    Some(element) => {
+                        let mut environment = Environment::new(self.environment.clone());
+                        environment.define(name.lexeme.clone(), element);
+                        self.new_environment(environment)
+                    }
+                    None => break,
+                },
+            };
+
+            let previous = self.environment.replace(environment.clone());
+            let flow = self.execute(body);
+            self.environment = previous;
+            match flow? {
+                Flow::Next => {}
+                Flow::Yield(value) => {
+                    self.frames.push(Frame::ForIn(iteration, environment));
+                    return Ok(Flow::Yield(value));
+                }
+                flow => return Ok(flow),
+            }
+        }
+
+        Ok(Flow::Next)
+    }
+
+    // Only errors can be thrown, they are reported with their class and message
+    // when nothing catches them
+    fn visit_throw(&mut self, keyword: &Token, value: &Expr) -> Result<Flow, Error> {
+        let thrown = self.evaluate(value)?;
+        let message = match &thrown {
+            Type::Instance(instance)
+                if instance
+                    .borrow()
+                    .is_instance_of(&Class::built_in(ErrorClass::Error)) =>
+            {
+                let instance = instance.borrow();
+                match instance.field("message") {
+                    None | Some(Type::Nil) => instance.to_string(),
+                    Some(message) => format!("{}: {}", instance, message),
+                }
+            }
+            _ => {
+                return Err(Error::interpreter(
+                    format!(
+                        "Only instances of Error and its subclasses can be thrown, got {}",
+                        thrown.repr()
+                    ),
+                    keyword.span,
+                )
+                .raised_as(ErrorClass::TypeError))
+            }
+        };
+        self.thrown = Some(thrown);
+        Err(Error::thrown(message, keyword.span))
+    }
+
+    // An error in the body goes to the first catch taking it, or on out when none does
+    // The catch runs in an environment of its own, holding the error
+    fn visit_try(&mut self, body: &[Stmt], catches: &[Catch]) -> Result<Flow, Error> {
+        let (index, start, environment) = match self.frames.pop() {
+            Some(Frame::Catch(index)) => match self.frames.pop() {
+                Some(Frame::Statements(start, environment)) => (index, start, environment),
+                _ => unreachable!("A catch stops in its statements"),
+            },
+            _ => {
+                let error = match self.visit_block(body) {
+                    Ok(Flow::Yield(value)) => {
+                        self.frames.push(Frame::Try);
+                        return Ok(Flow::Yield(value));
+                    }
+                    Err(error) => error,
+                    flow => return flow,
+                };
+                let Some(value) = self.caught(&error) else {
+                    return Err(error);
+                };
+                let Some(index) = self.catching(catches, &value)? else {
+                    return Err(error);
+                };
+                self.thrown = None;
+
+                let mut environment = Environment::new(self.environment.clone());
+                environment.define(catches[index].name.lexeme.clone(), value);
+                (index, 0, self.new_environment(environment))
+            }
+        };
+
+        let flow = self.execute_block(&catches[index].body, start, environment);
+        if let Ok(Flow::Yield(_)) = flow {
+            self.frames.push(Frame::Catch(index));
+        }
+        flow
+    }
+
+    // The resource is closed however the body ends, an error closing it is raised
+    // in place of whatever the body ended with
+    // The body runs in an environment of its own, holding the resource
+    fn visit_with(
+        &mut self,
+        keyword: &Token,
+        resource: &Expr,
+        name: &Token,
+        body: &[Stmt],
+    ) -> Result<Flow, Error> {
+        let (value, start, environment) = match self.frames.pop() {
+            Some(Frame::With(value)) => match self.frames.pop() {
+                Some(Frame::Statements(start, environment)) => (value, start, environment),
+                _ => unreachable!("A with stops in its statements"),
+            },
+            _ => {
+                let value = self.evaluate(resource)?;
+                self.closer(&value, resource.span())?;
+                let mut environment = Environment::new(self.environment.clone());
+                environment.define(name.lexeme.clone(), value.clone());
+                (value, 0, self.new_environment(environment))
+            }
+        };
+
+        let flow = self.execute_block(body, start, environment);
+        if let Ok(Flow::Yield(_)) = flow {
+            self.frames.push(Frame::With(value));
+            return flow;
+        }
+
+        // What the body threw is put aside, closing can throw and catch errors of its own
+        let thrown = self.thrown.take();
+        let close = self.closer(&value, resource.span())?;
+        if close.arity != 0 {
+            return Err(Error::interpreter(
+                arity_message(&close.name.lexeme, close.arity, 0),
+                keyword.span,
+            )
+            .raised_as(ErrorClass::TypeError));
+        }
+        close.call(self, Some(Vec::new()), keyword.span)?;
+        self.thrown = thrown;
+        flow
+    }
+
+    fn visit_function(
+        &mut self,
+        name: &Token,
+        parameters: &[Token],
+        body: &[Stmt],
+        signature: &Signature,
+        captures: &[Slot],
+    ) -> Result<Flow, Error> {
+        let function_name = name.clone();
+        let arity = parameters.len();
+
+        // Declared before it is captured, so a local function can call itself
+        let declaration = self.declare(name);
+        let function = Function::new(
+            function_name,
+            arity,
+            Ref::new(Stmt::function(
+                name.clone(),
+                parameters.to_vec(),
+                body.to_vec(),
+                signature.clone(),
+            )),
+            self.closure(captures),
+        )
+        .owned_by(self.owner);
+        self.initialize(declaration, Type::Function(Box::new(function)))?;
+        Ok(Flow::Next)
+    }
+
+    fn visit_return(&mut self, _token: &Token, expr: Option<&Expr>) -> Result<Flow, Error> {
+        let value = match expr {
+            Some(expr) => self.evaluate(expr)?,
+            None => Type::Nil,
+        };
+        self.trace_value(&value);
+        Ok(Flow::Return(value))
+    }
+
+    // Run again once resumed, then it is done and gives what it was resumed with
+    fn visit_yield(
+        &mut self,
+        _keyword: &Token,
+        expr: Option<&Expr>,
+        variable: &Option<Token>,
+    ) -> Result<Flow, Error> {
+        if let Some(Frame::Yield(sent)) = self.frames.pop() {
+            if let Some(variable) = variable {
+                self.define(variable.lexeme.clone(), sent);
+            }
+            return Ok(Flow::Next);
+        }
+
+        let value = match expr {
+            Some(expr) => self.evaluate(expr)?,
+            None => Type::Nil,
+        };
+        self.trace_value(&value);
+        self.frames.push(Frame::Yield(Type::Nil));
+        Ok(Flow::Yield(value))
+    }
+
+    fn visit_error(&mut self, tokens: &[Token]) -> Result<Flow, Error> {
+        let span = tokens.first().map(|token| token.span).unwrap_or_default();
+        Err(Error::interpreter(
+            "Can't run a statement that failed to parse".to_string(),
+            span,
+        ))
+    }
+}
+
+// `math`, numbers in and out
+fn math() -> Vec<(String, Type)> {
+    fn number(value: &Type, span: Span) -> Result<f64, Error> {
+        match value {
+            Type::Number(number) => Ok(*number),
+            _ => Err(
+                Error::interpreter(format!("Expected Number, got {}", value.repr()), span)
+                    .raised_as(ErrorClass::TypeError),
+            ),
+        }
+    }
+
+    fn sqrt(_interpreter: &mut Interpreter, arguments: &[Type], span: Span) -> Result<Type, Error> {
+        Ok(Type::Number(number(&arguments[0], span)?.sqrt()))
+    }
+
+    fn floor(
+        _interpreter: &mut Interpreter,
+        arguments: &[Type],
+        span: Span,
+    ) -> Result<Type, Error> {
+        Ok(Type::Number(number(&arguments[0], span)?.floor()))
+    }
+
+    fn ceil(_interpreter: &mut Interpreter, arguments: &[Type], span: Span) -> Result<Type, Error> {
+        Ok(Type::Number(number(&arguments[0], span)?.ceil()))
+    }
+
+    fn abs(_interpreter: &mut Interpreter, arguments: &[Type], span: Span) -> Result<Type, Error> {
+        Ok(Type::Number(number(&arguments[0], span)?.abs()))
+    }
+
+    fn pow(_interpreter: &mut Interpreter, arguments: &[Type], span: Span) -> Result<Type, Error> {
+        let base = number(&arguments[0], span)?;
+        Ok(Type::Number(base.powf(number(&arguments[1], span)?)))
+    }
+
+    fn min(_interpreter: &mut Interpreter, arguments: &[Type], span: Span) -> Result<Type, Error> {
+        let a = number(&arguments[0], span)?;
+        Ok(Type::Number(a.min(number(&arguments[1], span)?)))
+    }
+
+    fn max(_interpreter: &mut Interpreter, arguments: &[Type], span: Span) -> Result<Type, Error> {
+        let a = number(&arguments[0], span)?;
+        Ok(Type::Number(a.max(number(&arguments[1], span)?)))
+    }
+
+    let functions: [(&str, usize, Native); 7] = [
+        ("sqrt", 1, sqrt),
+        ("floor", 1, floor),
+        ("ceil", 1, ceil),
+        ("abs", 1, abs),
+        ("pow", 2, pow),
+        ("min", 2, min),
+        ("max", 2, max),
+    ];
+    let mut members: Vec<(String, Type)> = functions
+        .into_iter()
+        .map(|(name, arity, native)| {
+            let native = NativeFunction::new(name.to_string(), arity, native);
+            (name.to_string(), Type::NativeFunction(Box::new(native)))
+        })
+        .collect();
+    members.push(("pi".to_string(), Type::Number(std::f64::consts::PI)));
+    members.push(("e".to_string(), Type::Number(std::f64::consts::E)));
+    members
+}
+
+// `fs`, directories and what's in them, see files
+fn fs() -> Vec<(String, Type)> {
+    fn path(value: &Type, span: Span) -> Result<&str, Error> {
+        match value {
+            Type::String(path) => Ok(path),
+            _ => Err(
+                Error::interpreter(format!("Expected a path, got {}", value.repr()), span)
+                    .raised_as(ErrorClass::TypeError),
+            ),
+        }
+    }
+
+    fn done(result: Result<(), String>, span: Span) -> Result<Type, Error> {
+        result
+            .map(|_| Type::Nil)
+            .map_err(|message| Error::interpreter(message, span))
+    }
+
+    fn list_dir(
+        _interpreter: &mut Interpreter,
+        arguments: &[Type],
+        span: Span,
+    ) -> Result<Type, Error> {
+        let names = files::list_dir(path(&arguments[0], span)?)
+            .map_err(|message| Error::interpreter(message, span))?;
+        let names = names
+            .into_iter()
+            .map(|name| Type::String(Ref::from(name)))
+            .collect();
+        Ok(Type::List(Ref::new(Lock::new(names))))
+    }
+
+    fn mkdir(
+        _interpreter: &mut Interpreter,
+        arguments: &[Type],
+        span: Span,
+    ) -> Result<Type, Error> {
+        done(files::mkdir(path(&arguments[0], span)?), span)
+    }
+
+    fn remove(
+        _interpreter: &mut Interpreter,
+        arguments: &[Type],
+        span: Span,
+    ) -> Result<Type, Error> {
+        done(files::remove(path(&arguments[0], span)?), span)
+    }
+
+    fn remove_all(
+        _interpreter: &mut Interpreter,
+        arguments: &[Type],
+        span: Span,
+    ) -> Result<Type, Error> {
+        done(files::remove_all(path(&arguments[0], span)?), span)
     }
 
-    fn visit_get(&mut self, expr: &mut Box<Expr>, name: &Token) -> Result<Type, Error> {
-        let mut object = self.evaluate(expr)?;
-        match object {
-            Type::Instance(mut instance) => instance.get(name),
-            _ => Err(Error::interpreter(
-                "Only instances have properties".to_string(),
-                name.line,
-            )),
-        }
+    fn rename(
+        _interpreter: &mut Interpreter,
+        arguments: &[Type],
+        span: Span,
+    ) -> Result<Type, Error> {
+        let from = path(&arguments[0], span)?;
+        done(files::rename(from, path(&arguments[1], span)?), span)
     }
 
-    fn visit_set(
-        &mut self,
-        expr: &mut Box<Expr>,
-        name: &Token,
-        value: &mut Box<Expr>,
+    fn read_bytes(
+        _interpreter: &mut Interpreter,
+        arguments: &[Type],
+        span: Span,
     ) -> Result<Type, Error> {
-        let object = self.evaluate(expr)?;
+        files::read_bytes(path(&arguments[0], span)?)
+            .map(|bytes| Type::Bytes(Ref::from(bytes)))
+            .map_err(|message| Error::interpreter(message, span))
+    }
 
-        match object {
-            Type::Instance(mut instance) => {
-                let value = self.evaluate(value)?;
-                instance.set(name, &value);
-                Ok(Type::Nil)
-            }
-            _ => Err(Error::interpreter(
-                "Only instances have fields".to_string(),
-                name.line,
-            )),
-        }
+    fn metadata(
+        _interpreter: &mut Interpreter,
+        arguments: &[Type],
+        span: Span,
+    ) -> Result<Type, Error> {
+        let metadata = files::metadata(path(&arguments[0], span)?)
+            .map_err(|message| Error::interpreter(message, span))?;
+        let entries = vec![
+            (
+                Type::String(Ref::from("size")),
+                Type::Number(metadata.size as f64),
+            ),
+            (
+                Type::String(Ref::from("modified")),
+                Type::Number(metadata.modified),
+            ),
+            (
+                Type::String(Ref::from("directory")),
+                Type::Boolean(metadata.directory),
+            ),
+        ];
+        Ok(Type::Map(Ref::new(Lock::new(entries))))
     }
 
-    fn visit_unary(&mut self, operator: &Token, unary_expr: &mut Box<Expr>) -> Result<Type, Error> {
-        let right = self.evaluate(unary_expr)?;
+    let functions: [(&str, usize, Native); 7] = [
+        ("read_bytes", 1, read_bytes),
+        ("list_dir", 1, list_dir),
+        ("mkdir", 1, mkdir),
+        ("remove", 1, remove),
+        ("remove_all", 1, remove_all),
+        ("rename", 2, rename),
+        ("metadata", 1, metadata),
+    ];
+    functions
+        .into_iter()
+        .map(|(name, arity, function)| {
+            let native = NativeFunction::new(name.to_string(), arity, function);
+            (name.to_string(), Type::NativeFunction(Box::new(native)))
+        })
+        .collect()
+}
 
-        let line = operator.line;
-        match operator.token_type {
-            TokenType::Minus => Ok(Type::Number(match right {
-                Type::Number(val) => -val,
-                _ => {
-                    return Err(Error::interpreter(
-                        format!("Expected Number, got {}", right),
-                        line,
-                    ))
-                }
-            })),
-            TokenType::Bang => Ok(Type::Boolean(!self.is_truthly(&right))),
-            _ => Err(Error::interpreter(
-                format!("Expected `!` or `-`, got {}", operator),
-                line,
-            )),
-        }
+// `hash`, digests of strings and bytes as hex, see codec
+fn hash() -> Vec<(String, Type)> {
+    fn md5(_interpreter: &mut Interpreter, arguments: &[Type], span: Span) -> Result<Type, Error> {
+        let digest = codec::md5(data(&arguments[0], span)?);
+        Ok(Type::String(Ref::from(digest)))
     }
 
-    fn visit_literal(&mut self, lit: &Token) -> Result<Type, Error> {
-        let line = lit.line;
-        match lit.token_type {
-            // String and Number literals
-            TokenType::String => Ok(Type::String(match lit.literal.clone() {
-                Some(val) => match val {
-                    LiteralType::StringType(string_val) => string_val,
-                    LiteralType::NumberType(number_val) => {
-                        return Err(Error::interpreter(
-                            format!("Expected String, got Number: `{}`", number_val),
-                            line,
-                        ));
-                    }
-                },
-                None => {
-                    return Err(Error::interpreter(
-                        format!("Expected String, got None"),
-                        line,
-                    ))
-                }
-            })),
-            TokenType::Number => Ok(Type::Number(match lit.literal.clone() {
-                Some(val) => match val {
-                    LiteralType::NumberType(number_val) => number_val,
-                    LiteralType::StringType(string_val) => {
-                        return Err(Error::interpreter(
-                            format!("Expected String, got String: `{}`", string_val),
-                            line,
-                        ));
-                    }
-                },
-                None => {
-                    return Err(Error::interpreter(
-                        format!("Expected String, got None"),
-                        line,
-                    ))
-                }
-            })),
-
-            // Booleans
-            TokenType::True => Ok(Type::Boolean(true)),
-            TokenType::False => Ok(Type::Boolean(false)),
+    fn sha256(
+        _interpreter: &mut Interpreter,
+        arguments: &[Type],
+        span: Span,
+    ) -> Result<Type, Error> {
+        let digest = codec::sha256(data(&arguments[0], span)?);
+        Ok(Type::String(Ref::from(digest)))
+    }
 
-            // Nil
-            TokenType::Nil => Ok(Type::Nil),
+    let functions: [(&str, usize, Native); 2] = [("md5", 1, md5), ("sha256", 1, sha256)];
+    functions
+        .into_iter()
+        .map(|(name, arity, function)| {
+            let native = NativeFunction::new(name.to_string(), arity, function);
+            (name.to_string(), Type::NativeFunction(Box::new(native)))
+        })
+        .collect()
+}
 
-            _ => Err(Error::interpreter(
-                format!("Unexpected! unreachable code reached"),
-                line,
-            )),
+// `codec`, strings and bytes as text and back, see codec
+fn codec() -> Vec<(String, Type)> {
+    fn text(value: &Type, span: Span) -> Result<&str, Error> {
+        match value {
+            Type::String(text) => Ok(text),
+            _ => Err(
+                Error::interpreter(format!("Expected String, got {}", value.repr()), span)
+                    .raised_as(ErrorClass::TypeError),
+            ),
         }
     }
 
-    fn visit_variable(&mut self, variable: &Token) -> Result<Type, Error> {
-        (*self.environment).borrow().get(variable)
+    fn encoded(text: String) -> Result<Type, Error> {
+        Ok(Type::String(Ref::from(text)))
     }
 
-    fn visit_assign(&mut self, variable: &Token, expr: &mut Box<Expr>) -> Result<Type, Error> {
-        let value = self.evaluate(expr)?;
-        let _ = (*self.environment)
-            .borrow_mut()
-            .assign(variable, value.clone())?;
-        Ok(value)
+    fn decoded(result: Result<Vec<u8>, String>, span: Span) -> Result<Type, Error> {
+        result
+            .map(|bytes| Type::Bytes(Ref::from(bytes)))
+            .map_err(|message| Error::interpreter(message, span))
     }
 
-    fn visit_logical(
-        &mut self,
-        left_expr: &mut Box<Expr>,
-        logical_and_or: &mut Token,
-        right_expr: &mut Box<Expr>,
+    fn base64_encode(
+        _interpreter: &mut Interpreter,
+        arguments: &[Type],
+        span: Span,
     ) -> Result<Type, Error> {
-        let left_value = self.evaluate(&left_expr)?;
+        encoded(codec::base64_encode(data(&arguments[0], span)?))
+    }
 
-        match logical_and_or.token_type {
-            TokenType::Or => {
-                if self.is_truthly(&left_value) {
-                    return Ok(left_value);
-                }
-            }
-            TokenType::And => {
-                if !self.is_truthly(&left_value) {
-                    return Ok(left_value);
-                }
-            }
-            _ => {
-                println!("{:#?}", &logical_and_or);
-                unreachable!()
-            }
-        }
+    fn base64_decode(
+        _interpreter: &mut Interpreter,
+        arguments: &[Type],
+        span: Span,
+    ) -> Result<Type, Error> {
+        decoded(codec::base64_decode(text(&arguments[0], span)?), span)
+    }
 
-        self.evaluate(&right_expr)
+    fn hex_encode(
+        _interpreter: &mut Interpreter,
+        arguments: &[Type],
+        span: Span,
+    ) -> Result<Type, Error> {
+        encoded(codec::hex_encode(data(&arguments[0], span)?))
     }
 
-    fn visit_call(
-        &mut self,
-        callee: &mut Box<Expr>,
-        closing_paren: &Token,
-        arguments: &mut Box<Vec<Expr>>,
+    fn hex_decode(
+        _interpreter: &mut Interpreter,
+        arguments: &[Type],
+        span: Span,
     ) -> Result<Type, Error> {
-        let callee = self.evaluate(callee)?;
+        decoded(codec::hex_decode(text(&arguments[0], span)?), span)
+    }
 
-        let mut evaluated_arguments = Vec::new();
-        for argument in &mut (**arguments) {
-            evaluated_arguments.push(self.evaluate(&Box::new(argument.clone()))?);
+    fn url_encode(
+        _interpreter: &mut Interpreter,
+        arguments: &[Type],
+        span: Span,
+    ) -> Result<Type, Error> {
+        encoded(codec::url_encode(data(&arguments[0], span)?))
+    }
+
+    fn url_decode(
+        _interpreter: &mut Interpreter,
+        arguments: &[Type],
+        span: Span,
+    ) -> Result<Type, Error> {
+        codec::url_decode(text(&arguments[0], span)?)
+            .map(|text| Type::String(Ref::from(text)))
+            .map_err(|message| Error::interpreter(message, span))
+    }
+
+    let functions: [(&str, usize, Native); 6] = [
+        ("base64_encode", 1, base64_encode),
+        ("base64_decode", 1, base64_decode),
+        ("hex_encode", 1, hex_encode),
+        ("hex_decode", 1, hex_decode),
+        ("url_encode", 1, url_encode),
+        ("url_decode", 1, url_decode),
+    ];
+    functions
+        .into_iter()
+        .map(|(name, arity, function)| {
+            let native = NativeFunction::new(name.to_string(), arity, function);
+            (name.to_string(), Type::NativeFunction(Box::new(native)))
+        })
+        .collect()
+}
+
+// The instance after `freeze` or `seal`, anything else can't be restricted
+fn restrict(value: &Type, mutability: Mutability, span: Span) -> Result<Type, Error> {
+    let Type::Instance(instance) = value else {
+        let message = match mutability {
+            Mutability::Frozen => format!("Only instances can be frozen, got {}", value.repr()),
+            _ => format!("Only instances can be sealed, got {}", value.repr()),
+        };
+        return Err(Error::interpreter(message, span).raised_as(ErrorClass::TypeError));
+    };
+    instance.borrow_mut().restrict(mutability);
+    Ok(value.clone())
+}
+
+// Bindings as a map from their names, sorted so both runtimes list them the same
+fn bindings_map(mut bindings: Vec<(Ref<str>, Type)>) -> Type {
+    bindings.sort_by(|(first, _), (second, _)| first.cmp(second));
+    let entries = bindings
+        .into_iter()
+        .map(|(name, value)| (Type::String(name), value))
+        .collect();
+    Type::Map(Ref::new(Lock::new(entries)))
+}
+
+// A global still holding the native or error class defined under its name
+fn is_built_in(name: &str, value: &Type) -> bool {
+    match value {
+        Type::NativeFunction(native) => native.name == name,
+        Type::Class(class) => {
+            *class.name == *name && ErrorClass::ALL.iter().any(|class| class.name() == name)
         }
+        _ => false,
+    }
+}
 
-        match callee {
-            Type::Function(to_call) => {
-                if to_call.arity != evaluated_arguments.len() {
-                    return Err(Error::interpreter(
-                        "Number of arguments does not match number of parameters".to_string(),
-                        closing_paren.line,
-                    ));
-                }
-                to_call.call(self, Some(evaluated_arguments))
-            }
-            Type::NativeFunction(to_call) => {
-                if to_call.arity != evaluated_arguments.len() {
-                    return Err(Error::interpreter(
-                        "Number of arguments does not match number of parameters".to_string(),
-                        closing_paren.line,
-                    ));
-                }
-                to_call.call(self, None)
-            }
-            Type::Class(to_call) => {
-                if evaluated_arguments.len() != 0 {
-                    return Err(Error::interpreter(
-                        "Number of arguments does not match number of parameters".to_string(),
-                        closing_paren.line,
-                    ));
-                }
+// What `+` joins as text only with a string, `[1] + [2]` is not "[1][2]"
+fn is_collection(value: &Type) -> bool {
+    matches!(
+        value,
+        Type::List(_) | Type::Map(_) | Type::Set(_) | Type::Tuple(_)
+    )
+}
 
-                to_call.call(self, None)
-            }
-            _ => Err(Error::interpreter(
-                "Not a function".to_string(),
-                closing_paren.line,
-            )),
+// What `hash` and `codec` work on, the UTF-8 of a string or bytes as they are
+fn data(value: &Type, span: Span) -> Result<&[u8], Error> {
+    match value {
+        Type::String(text) => Ok(text.as_bytes()),
+        Type::Bytes(bytes) => Ok(bytes),
+        _ => Err(Error::interpreter(
+            format!("Expected String or Bytes, got {}", value.repr()),
+            span,
+        )
+        .raised_as(ErrorClass::TypeError)),
+    }
+}
+
+// What `value.?name` gives and `has` looks for, none when the value has no
+// such property or no properties at all
+fn property_of(value: &Type, name: &str) -> Option<Type> {
+    match value {
+        Type::Instance(instance) => Instance::property(instance, name),
+        Type::Module(module) => module.members.get(name).cloned(),
+        Type::Bytes(bytes) => {
+            bytes_method(bytes, name).map(|method| Type::NativeFunction(Box::new(method)))
+        }
+        #[cfg(feature = "sockets")]
+        Type::Socket(socket) => {
+            socket_method(socket, name).map(|method| Type::NativeFunction(Box::new(method)))
         }
+        _ => None,
     }
 }
 
-impl StatementVisitor<Result<Option<Type>, Error>> for Interpreter {
-    fn visit_block(&mut self, statements: &mut Box<Vec<Stmt>>) -> Result<Option<Type>, Error> {
-        let new_env = Environment::new(Some(Rc::clone(&self.environment)));
-        self.execute_block(statements, Rc::new(RefCell::new(new_env)))?;
-        Ok(None)
+// `bytes.name`, the method bound to them
+fn bytes_method(bytes: &Ref<[u8]>, name: &str) -> Option<NativeFunction> {
+    // The bytes the method is bound to, its first argument
+    fn receiver(arguments: &[Type]) -> &[u8] {
+        match &arguments[0] {
+            Type::Bytes(bytes) => bytes,
+            _ => unreachable!("Methods of bytes are bound to bytes"),
+        }
     }
 
-    fn visit_class(
-        &mut self,
-        name: &Token,
-        superclass: &mut Option<Box<Expr>>,
-        statements: &mut Box<Vec<Stmt>>,
-    ) -> Result<Option<Type>, Error> {
-        let mut parent = None;
-        if let Some(parent_) = superclass {
-            parent = Some(self.evaluate(parent_)?);
-            match parent {
-                Some(parent) => match parent {
-                    Type::Class(_) => {}
-                    _ => {
-                        return Err(Error::interpreter(
-                            "Superclass must be a class".to_string(),
-                            name.line,
-                        ))
-                    }
-                },
-                None => {
-                    return Err(Error::interpreter(
-                        "How did this happen?".to_string(),
-                        name.line,
-                    ))
-                }
-            }
+    fn text(value: &Type, span: Span) -> Result<&str, Error> {
+        match value {
+            Type::String(text) => Ok(text),
+            _ => Err(
+                Error::interpreter(format!("Expected String, got {}", value.repr()), span)
+                    .raised_as(ErrorClass::TypeError),
+            ),
         }
-        self.environment
-            .deref()
-            .borrow_mut()
-            .define(name.lexeme.clone(), Type::Nil);
+    }
 
-        let mut methods = HashMap::<String, Function>::new();
-        for method in statements.iter() {
-            let (method_name, arity) = match method {
-                Stmt::Function(m_name, parameters, _) => (m_name.lexeme.clone(), parameters.len()),
-                _ => {
-                    return Err(Error::interpreter(
-                        "Method is not a function statement".to_string(),
-                        name.line,
-                    ))
-                }
-            };
-            let function = Function::new(
-                name.clone(),
-                arity,
-                Rc::new(RefCell::new(method.clone())),
-                Rc::clone(&self.environment),
-            );
-            methods.insert(method_name, function);
+    fn bound(value: &Type, span: Span) -> Result<f64, Error> {
+        match value {
+            Type::Number(number) => Ok(*number),
+            _ => Err(
+                Error::interpreter(format!("Expected Number, got {}", value.repr()), span)
+                    .raised_as(ErrorClass::TypeError),
+            ),
         }
+    }
 
-        let superclass = match superclass {
-            Some(some_parent) => Some(self.evaluate(some_parent)?),
-            None => None,
-        };
+    fn length(
+        _interpreter: &mut Interpreter,
+        arguments: &[Type],
+        _span: Span,
+    ) -> Result<Type, Error> {
+        Ok(Type::Number(receiver(arguments).len() as f64))
+    }
 
-        let parent = match superclass {
-            Some(parent_val) => match parent_val {
-                Type::Class(parent_class) => Some(parent_class),
-                _ => {
-                    return Err(Error::interpreter(
-                        "Sueprclass must be a class".to_string(),
-                        name.line,
-                    ))
-                }
-            },
-            None => None,
-        };
+    fn slice(
+        _interpreter: &mut Interpreter,
+        arguments: &[Type],
+        span: Span,
+    ) -> Result<Type, Error> {
+        let (start, end) = (bound(&arguments[1], span)?, bound(&arguments[2], span)?);
+        bytes::slice(receiver(arguments), start, end)
+            .map(|bytes| Type::Bytes(Ref::from(bytes)))
+            .map_err(|message| Error::interpreter(message, span).raised_as(ErrorClass::IndexError))
+    }
 
-        let class = Box::new(Class::new(name.lexeme.clone(), parent, methods));
-        self.environment
-            .deref()
-            .borrow_mut()
-            .assign(name, Type::Class(class));
-        Ok(None)
+    fn decode(
+        _interpreter: &mut Interpreter,
+        arguments: &[Type],
+        span: Span,
+    ) -> Result<Type, Error> {
+        let encoding = text(&arguments[1], span)?;
+        bytes::decode(receiver(arguments), encoding)
+            .map(|text| Type::String(Ref::from(text)))
+            .map_err(|message| Error::interpreter(message, span))
     }
 
-    fn visit_expression(&mut self, expr: &Box<Expr>) -> Result<Option<Type>, Error> {
-        let _ = self.evaluate(expr)?;
+    let methods: [(&str, usize, Native); 3] = [
+        ("length", 0, length),
+        ("slice", 2, slice),
+        ("decode", 1, decode),
+    ];
+    let (name, arity, method) = methods.into_iter().find(|(method, ..)| *method == name)?;
+    let native = NativeFunction::new(name.to_string(), arity, method);
+    Some(native.bound(Type::Bytes(Ref::clone(bytes))))
+}
 
-        Ok(None)
+// `net`, TCP and UDP sockets, see Socket
+#[cfg(feature = "sockets")]
+fn net() -> Vec<(String, Type)> {
+    fn open(
+        arguments: &[Type],
+        span: Span,
+        open: fn(&str) -> Result<Socket, String>,
+    ) -> Result<Type, Error> {
+        let address = socket_text(&arguments[0], span)?;
+        open(address)
+            .map(Type::Socket)
+            .map_err(|message| Error::interpreter(message, span))
     }
 
-    fn visit_print(&mut self, expr: &Box<Expr>) -> Result<Option<Type>, Error> {
-        let value = self.evaluate(expr)?;
-        println!("{}", value);
+    fn connect(
+        _interpreter: &mut Interpreter,
+        arguments: &[Type],
+        span: Span,
+    ) -> Result<Type, Error> {
+        open(arguments, span, Socket::connect)
+    }
 
-        Ok(None)
+    fn listen(
+        _interpreter: &mut Interpreter,
+        arguments: &[Type],
+        span: Span,
+    ) -> Result<Type, Error> {
+        open(arguments, span, Socket::listen)
     }
 
-    fn visit_var(
-        &mut self,
-        token: &Token,
-        expr: &Option<Box<Expr>>,
-    ) -> Result<Option<Type>, Error> {
-        // token is the variable
-        // expr is the value for the variable // initializer
-        match expr {
-            Some(val) => {
-                let val = self.evaluate(val)?;
-                (*self.environment)
-                    .borrow_mut()
-                    .define(token.lexeme.clone(), val.clone());
-            }
-            _ => (*self.environment)
-                .borrow_mut()
-                .define(token.lexeme.clone(), Type::Nil),
-        }
-        Ok(None)
+    fn bind(_interpreter: &mut Interpreter, arguments: &[Type], span: Span) -> Result<Type, Error> {
+        open(arguments, span, Socket::bind)
     }
 
-    fn visit_ifelse(
-        &mut self,
-        condition: &Box<Expr>,
-        then_branch: &Box<Stmt>,
-        else_branch: &Option<Box<Stmt>>,
-    ) -> Result<Option<Type>, Error> {
-        let condition_evaluated = self.evaluate(condition)?;
-        if self.is_truthly(&condition_evaluated) {
-            let mut then_branch = then_branch.clone();
-            self.execute(&mut then_branch)
-        } else {
-            match else_branch {
-                Some(else_branch) => self.execute(&mut (**else_branch).clone()),
-                _ => Ok(None),
-            }
+    let functions: [(&str, usize, Native); 3] = [
+        ("connect", 1, connect),
+        ("listen", 1, listen),
+        ("bind", 1, bind),
+    ];
+    functions
+        .into_iter()
+        .map(|(name, arity, function)| {
+            let native = NativeFunction::new(name.to_string(), arity, function);
+            (name.to_string(), Type::NativeFunction(Box::new(native)))
+        })
+        .collect()
+}
+
+// `socket.name`, the method of that kind of socket bound to it
+#[cfg(feature = "sockets")]
+fn socket_method(socket: &Socket, name: &str) -> Option<NativeFunction> {
+    // The socket the method is bound to, its first argument
+    fn receiver(arguments: &[Type]) -> &Socket {
+        match &arguments[0] {
+            Type::Socket(socket) => socket,
+            _ => unreachable!("Methods of sockets are bound to a socket"),
         }
     }
 
-    fn visit_whileloop(
-        &mut self,
-        condition: &Box<Expr>,
-        statement: &mut Box<Stmt>,
-    ) -> Result<Option<Type>, Error> {
-        let mut evaluated_condition = self.evaluate(condition)?;
+    fn failed(message: String, span: Span) -> Error {
+        Error::interpreter(message, span)
+    }
+
+    fn accept(
+        _interpreter: &mut Interpreter,
+        arguments: &[Type],
+        span: Span,
+    ) -> Result<Type, Error> {
+        receiver(arguments)
+            .accept()
+            .map(Type::Socket)
+            .map_err(|message| failed(message, span))
+    }
+
+    fn send(_interpreter: &mut Interpreter, arguments: &[Type], span: Span) -> Result<Type, Error> {
+        let text = socket_text(&arguments[1], span)?;
+        receiver(arguments)
+            .send(text)
+            .map(|_| Type::Nil)
+            .map_err(|message| failed(message, span))
+    }
 
-        while self.is_truthly(&evaluated_condition) {
-            self.execute(&mut *statement)?;
+    fn send_to(
+        _interpreter: &mut Interpreter,
+        arguments: &[Type],
+        span: Span,
+    ) -> Result<Type, Error> {
+        let text = socket_text(&arguments[1], span)?;
+        let address = socket_text(&arguments[2], span)?;
+        receiver(arguments)
+            .send_to(text, address)
+            .map(|_| Type::Nil)
+            .map_err(|message| failed(message, span))
+    }
 
-            evaluated_condition = self.evaluate(condition)?;
+    fn recv(_interpreter: &mut Interpreter, arguments: &[Type], span: Span) -> Result<Type, Error> {
+        match receiver(arguments).recv() {
+            Ok(Some(text)) => Ok(Type::String(Ref::from(text))),
+            Ok(None) => Ok(Type::Nil),
+            Err(message) => Err(failed(message, span)),
         }
+    }
 
-        Ok(None)
+    fn recv_from(
+        _interpreter: &mut Interpreter,
+        arguments: &[Type],
+        span: Span,
+    ) -> Result<Type, Error> {
+        let (text, from) = receiver(arguments)
+            .recv_from()
+            .map_err(|message| failed(message, span))?;
+        Ok(Type::Tuple(vec![
+            Type::String(Ref::from(text)),
+            Type::String(Ref::from(from)),
+        ]))
     }
 
-    fn visit_function(
-        &mut self,
-        name: &Token,
-        parameters: &Box<Vec<Token>>,
-        body: &mut Box<Vec<Stmt>>,
-    ) -> Result<Option<Type>, Error> {
-        let function_name = name.clone();
-        let arity = parameters.len();
+    fn address(
+        _interpreter: &mut Interpreter,
+        arguments: &[Type],
+        span: Span,
+    ) -> Result<Type, Error> {
+        receiver(arguments)
+            .address()
+            .map(|address| Type::String(Ref::from(address)))
+            .map_err(|message| failed(message, span))
+    }
 
-        let function = Function::new(
-            function_name,
-            arity,
-            Rc::new(RefCell::new(Stmt::function(
-                name.clone(),
-                parameters.clone(),
-                body.clone(),
-            ))),
-            Rc::clone(&self.environment),
-        );
-        let mut environment = self.globals.deref().borrow_mut();
-
-        environment.define(name.lexeme.clone(), Type::Function(Box::new(function)));
-        Ok(None)
+    fn peer(_interpreter: &mut Interpreter, arguments: &[Type], span: Span) -> Result<Type, Error> {
+        receiver(arguments)
+            .peer()
+            .map(|address| Type::String(Ref::from(address)))
+            .map_err(|message| failed(message, span))
+    }
+
+    fn close(
+        _interpreter: &mut Interpreter,
+        arguments: &[Type],
+        _span: Span,
+    ) -> Result<Type, Error> {
+        receiver(arguments).close();
+        Ok(Type::Nil)
     }
 
-    fn visit_return(&mut self, _token: &Token, expr: &Box<Expr>) -> Result<Option<Type>, Error> {
-        Ok(Some(self.evaluate(expr)?))
+    let methods: &[(&str, usize, Native)] = match socket.kind() {
+        SocketKind::Connection => &[
+            ("send", 1, send),
+            ("recv", 0, recv),
+            ("address", 0, address),
+            ("peer", 0, peer),
+            ("close", 0, close),
+        ],
+        SocketKind::Listener => &[
+            ("accept", 0, accept),
+            ("address", 0, address),
+            ("close", 0, close),
+        ],
+        SocketKind::Datagram => &[
+            ("send", 2, send_to),
+            ("recv", 0, recv_from),
+            ("address", 0, address),
+            ("close", 0, close),
+        ],
+    };
+    let (name, arity, method) = methods.iter().find(|(method, ..)| *method == name)?;
+    let native = NativeFunction::new(name.to_string(), *arity, *method);
+    Some(native.bound(Type::Socket(socket.clone())))
+}
+
+// The text argument of a `net` native, an address or what is sent
+#[cfg(feature = "sockets")]
+fn socket_text(value: &Type, span: Span) -> Result<&str, Error> {
+    match value {
+        Type::String(text) => Ok(text),
+        _ => Err(
+            Error::interpreter(format!("Expected String, got {}", value.repr()), span)
+                .raised_as(ErrorClass::TypeError),
+        ),
     }
 }
+
+// What a thread of `spawn_thread()` runs: the function declared by `source`,
+// called with the thread's end of the channel
+fn run_thread(source: &str, name: &str, channel: Channel) -> Result<(), Error> {
+    let mut lexer = Lexer::new(source.to_string());
+    lexer.scan();
+    let mut parser = Parser::new(lexer.tokens);
+    parser.parse();
+    // Formatted from a function that parsed, so it parses again
+    let mut statements = std::mem::take(parser.get_parsed_statements());
+    let mut interpreter = Interpreter::new(None);
+    Resolver::with_globals(interpreter.global_names()).resolve(&mut statements);
+    interpreter.interpret(&statements)?;
+    let Some(Type::Function(function)) = interpreter.globals.borrow().value(name) else {
+        unreachable!("The source declares the function")
+    };
+    function.call(
+        &mut interpreter,
+        Some(vec![Type::Channel(channel)]),
+        function.name.span,
+    )?;
+    Ok(())
+}
@@ -1,3 +1,6 @@
+pub mod environment;
+pub mod generator;
+pub mod heap;
 pub mod interpreter;
+pub mod scheduler;
 pub mod types;
-pub mod environment;
\ No newline at end of file
@@ -0,0 +1,32 @@
+use std::collections::VecDeque;
+
+// Coroutines made by `spawn`, taking turns when `run()` runs them
+//
+// Each one runs up to its next `yield` and goes to the back of the queue, until
+// it is done. Nothing runs in between, so the coroutines need no locks
+pub struct Scheduler<T> {
+    tasks: VecDeque<T>,
+}
+
+impl<T> Default for Scheduler<T> {
+    fn default() -> Scheduler<T> {
+        Scheduler::new()
+    }
+}
+
+impl<T> Scheduler<T> {
+    pub fn new() -> Scheduler<T> {
+        Scheduler {
+            tasks: VecDeque::new(),
+        }
+    }
+
+    pub fn spawn(&mut self, task: T) {
+        self.tasks.push_back(task);
+    }
+
+    // The coroutine whose turn it is, it leaves the queue until spawned again
+    pub fn take_turn(&mut self) -> Option<T> {
+        self.tasks.pop_front()
+    }
+}
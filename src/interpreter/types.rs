@@ -10,26 +10,42 @@ string      String
 */
 
 use std::{
-    cell::RefCell,
     collections::HashMap,
-    fmt::{self, write},
-    rc::Rc,
-    time::Instant,
+    fmt,
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
-use crate::{error::Error, lexer::token::Token, parser::stmt::Stmt};
+use crate::{
+    bytes,
+    equality::{self, Comparing, Key},
+    error::{arity_message, variadic_arity_message, Error, ErrorClass},
+    eventloop::eventloop::Promise,
+    lexer::token::{is_private, Span, Token},
+    modules::Module,
+    mutability::Mutability,
+    number,
+    parser::stmt::Stmt,
+    repr::{self, Contents, Nested},
+    shared::{Lock, Ref, Shared},
+    sockets::sockets::Socket,
+    threads::threads::{Channel, Message},
+};
 
 use super::{
-    environment::{self, Environment},
-    interpreter::Interpreter,
+    environment::Environment,
+    generator::Generator,
+    heap::EnvironmentId,
+    interpreter::{Flow, Interpreter},
 };
 
+// `span` is where the call is, for the errors of the callee
 pub trait Callable {
     fn arity(&self) -> usize;
     fn call(
         &self,
         interpreter: &mut Interpreter,
         arguments: Option<Vec<Type>>,
+        span: Span,
     ) -> Result<Type, Error>;
 }
 
@@ -37,27 +53,99 @@ pub trait Callable {
 pub struct Function {
     pub name: Token,
     pub arity: usize,
-    pub declaration: Rc<RefCell<Stmt>>, // Function statement
-    pub closure: Rc<RefCell<Environment>>,
+    pub declaration: Ref<Stmt>, // Function statement, shared by every copy of the function
+    pub closure: Option<EnvironmentId>, // None runs in the globals
+    generator: bool,            // Its body yields, a call makes a Generator
+    this: Option<Box<Type>>,    // Of a method, the instance it is bound to, nil until it is
+    // The class declaring it as a method, or declaring the method it is declared
+    // in, whose instances' private members it reaches
+    owner: Option<ClassId>,
 }
 
 impl Function {
     pub fn new(
         name: Token,
         arity: usize,
-        declaration: Rc<RefCell<Stmt>>,
-        closure: Rc<RefCell<Environment>>,
+        declaration: Ref<Stmt>,
+        closure: Option<EnvironmentId>,
     ) -> Function {
-        let declaration_borrowed = declaration.borrow();
+        let generator = match &*declaration {
+            Stmt::Function(_, _, body, ..) => body.iter().any(Stmt::yields),
+            _ => panic!("Tried to create a funciton with non funciton body"),
+        };
         Function {
             name,
             arity,
-            declaration: match &*declaration_borrowed {
-                Stmt::Function(_, _, _) => Rc::clone(&declaration),
-                _ => panic!("Tried to create a funciton with non funciton body"),
-            },
+            declaration,
             closure,
+            generator,
+            this: None,
+            owner: None,
+        }
+    }
+
+    pub fn owned_by(mut self, owner: Option<ClassId>) -> Function {
+        self.owner = owner;
+        self
+    }
+
+    // The function as a method, it runs with `this` in an environment between
+    // its captures and its call
+    pub fn method(mut self) -> Function {
+        self.this = Some(Box::new(Type::Nil));
+        self
+    }
+
+    // The method, with `this` being `instance`
+    pub fn bind(&self, instance: Type) -> Function {
+        Function {
+            this: Some(Box::new(instance)),
+            ..self.clone()
+        }
+    }
+
+    // Copies of the same function, made where it was declared, bound to the same instance
+    pub fn is_same(&self, other: &Function) -> bool {
+        let same_this = match (&self.this, &other.this) {
+            (Some(this), Some(other)) => this.is_equal(other),
+            (this, other) => this.is_none() && other.is_none(),
+        };
+        Ref::ptr_eq(&self.declaration, &other.declaration)
+            && self.closure == other.closure
+            && same_this
+    }
+
+    // A call that runs none of the body yet, the body runs as the generator is resumed
+    pub fn suspended_call(&self, interpreter: &mut Interpreter, arguments: Vec<Type>) -> Generator {
+        let environment = self.call_environment(interpreter, arguments);
+        let mut generator = Generator::new(
+            self.name.clone(),
+            Ref::clone(&self.declaration),
+            environment,
+        );
+        generator.owner = self.owner;
+        generator
+    }
+
+    // The environment of a call, holding the arguments as the parameters
+    fn call_environment(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Type>,
+    ) -> EnvironmentId {
+        let mut enclosing = self.closure.clone();
+        if let Some(this) = &self.this {
+            let mut receiver = Environment::new(enclosing);
+            receiver.define(Ref::from("this"), (**this).clone());
+            enclosing = Some(interpreter.new_environment(receiver));
         }
+        let mut environment = Environment::new(enclosing);
+        if let Stmt::Function(_, parameters, ..) = &*self.declaration {
+            for (parameter, argument) in parameters.iter().zip(arguments) {
+                environment.define(parameter.lexeme.clone(), argument);
+            }
+        }
+        interpreter.new_environment(environment)
     }
 }
 
@@ -70,54 +158,86 @@ impl Callable for Function {
         &self,
         interpreter: &mut Interpreter,
         arguments: Option<Vec<Type>>,
+        span: Span,
     ) -> Result<Type, Error> {
-        // let mut environment = Environment::new(Some(Rc::clone(&interpreter.globals)));
-        let mut environment = Environment::new(Some(Rc::clone(&self.closure)));
-        let arguments = arguments.unwrap_or_else(|| Vec::<Type>::new());
+        let arguments = arguments.unwrap_or_default();
+        if self.generator {
+            let generator = self.suspended_call(interpreter, arguments);
+            return Ok(Type::Generator(Ref::new(Lock::new(generator))));
+        }
 
-        let (name, parameters, body) = match &mut *self.declaration.borrow_mut() {
-            Stmt::Function(name, parameters, body) => {
-                (name.clone(), parameters.clone(), body.clone())
-            }
+        let body = match &*self.declaration {
+            Stmt::Function(_, _, body, ..) => body,
             _ => {
                 return Err(Error::interpreter(
                     "Calling a non-callable".to_string(),
-                    self.name.line,
-                ))
+                    self.name.span,
+                )
+                .raised_as(ErrorClass::TypeError))
             }
         };
-
-        for i in 0..parameters.len() {
-            environment.define(parameters[i].lexeme.clone(), arguments[i].clone());
-        }
-
-        match interpreter.execute_block(&mut body.clone(), Rc::new(RefCell::new(environment)))? {
-            Some(return_value) => Ok(return_value),
-            None => Ok(Type::Nil),
+        let environment = self.call_environment(interpreter, arguments);
+        match interpreter.execute_call(body, environment, self.owner, span)? {
+            Flow::Return(return_value) => Ok(return_value),
+            _ => Ok(Type::Nil),
         }
     }
 }
 
-impl ToString for Function {
-    fn to_string(&self) -> String {
-        self.name.to_string()
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
     }
 }
 
+// Natives are plain functions of the interpreter, the arguments of the call and
+// where the call is, for their errors
+pub type Native = fn(&mut Interpreter, &[Type], Span) -> Result<Type, Error>;
+
 #[derive(Debug, Clone)]
 pub struct NativeFunction {
     pub name: String,
     pub arity: usize,
-    to_call: fn(), // Currently only no parameters
-                   // and no return value native functions
+    to_call: Native,             // Given `arity` arguments
+    receiver: Option<Box<Type>>, // Given before them, for the method of a socket
+    variadic: bool,              // `arity` is the least it takes, more are given too
 }
 
 impl NativeFunction {
-    pub fn new(name: String, to_call: fn()) -> NativeFunction {
+    pub fn new(name: String, arity: usize, to_call: Native) -> NativeFunction {
         NativeFunction {
             name,
-            arity: 0,
+            arity,
             to_call,
+            receiver: None,
+            variadic: false,
+        }
+    }
+
+    // The native taking `arity` arguments or more
+    pub fn variadic(self) -> NativeFunction {
+        NativeFunction {
+            variadic: true,
+            ..self
+        }
+    }
+
+    // Why a call with `count` arguments can't be made, none when it can
+    pub fn arity_error(&self, count: usize) -> Option<String> {
+        match self.variadic {
+            true if count < self.arity => {
+                Some(variadic_arity_message(&self.name, self.arity, count))
+            }
+            false if count != self.arity => Some(arity_message(&self.name, self.arity, count)),
+            _ => None,
+        }
+    }
+
+    // The native called with `receiver` as its first argument
+    pub fn bound(self, receiver: Type) -> NativeFunction {
+        NativeFunction {
+            receiver: Some(Box::new(receiver)),
+            ..self
         }
     }
 }
@@ -131,23 +251,36 @@ impl Callable for NativeFunction {
         &self,
         interpreter: &mut Interpreter,
         arguments: Option<Vec<Type>>,
+        span: Span,
     ) -> Result<Type, Error> {
-        let res = (self.to_call)();
-
-        Ok(Type::Nil) // Native Functions will reutrn nothing for now
+        let mut arguments = arguments.unwrap_or_default();
+        if let Some(receiver) = &self.receiver {
+            arguments.insert(0, (**receiver).clone());
+        }
+        (self.to_call)(interpreter, &arguments, span)
     }
 }
 
-impl ToString for NativeFunction {
-    fn to_string(&self) -> String {
-        self.name.clone()
+impl fmt::Display for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
     }
 }
 
+// What a value refers to that the garbage collector follows: environments,
+// and the instances, lists and maps it goes through in turn, see Heap::collect
+pub enum Traced<'a> {
+    Environment(&'a EnvironmentId),
+    Instance(&'a Shared<Instance>),
+    List(&'a Shared<Vec<Type>>),
+    Map(&'a Shared<Vec<(Type, Type)>>),
+}
+
 #[derive(Debug, Clone)]
 pub struct Instance {
     class: Class,
-    fields: HashMap<String, Type>,
+    fields: HashMap<Ref<str>, Type>,
+    mutability: Mutability,
 }
 
 impl Instance {
@@ -155,101 +288,329 @@ impl Instance {
         Instance {
             class,
             fields: HashMap::new(),
+            mutability: Mutability::Open,
         }
     }
 
-    pub fn get(&mut self, name: &Token) -> Result<Type, Error> {
-        if let Some(val) = self.fields.get(&name.lexeme) {
-            return Ok(val.clone());
+    pub fn get(instance: &Shared<Instance>, name: &Token) -> Result<Type, Error> {
+        Instance::property(instance, &name.lexeme).ok_or_else(|| {
+            Error::interpreter("Property does not exist".to_string(), name.span)
+                .raised_as(ErrorClass::NameError)
+        })
+    }
+
+    // The field, or else the method bound to the instance, none when it has neither
+    pub fn property(instance: &Shared<Instance>, name: &str) -> Option<Type> {
+        if let Some(val) = instance.borrow().fields.get(name) {
+            return Some(val.clone());
         }
+        let method = instance.borrow().class.find_method(name)?;
+        let bound = method.bind(Type::Instance(Ref::clone(instance)));
+        Some(Type::Function(Box::new(bound)))
+    }
 
-        if let Some(method) = self.class.find_method(&name.lexeme) {
-            let method = Type::Function(Box::new(method));
-            return Ok(method);
+    // Fails once the instance is frozen, or sealed and without the field
+    pub fn set(&mut self, name: &Token, value: &Type) -> Result<(), Error> {
+        let exists = self.fields.contains_key(&name.lexeme);
+        if let Some(message) = self
+            .mutability
+            .set_error(&self.class.name, &name.lexeme, exists)
+        {
+            return Err(Error::interpreter(message, name.span).raised_as(ErrorClass::TypeError));
         }
+        self.fields.insert(name.lexeme.clone(), value.clone());
+        Ok(())
+    }
 
-        Err(Error::interpreter(
-            "Property does not exist".to_string(),
-            name.line,
-        ))
+    // Sealing a frozen instance leaves it frozen
+    pub fn restrict(&mut self, mutability: Mutability) {
+        self.mutability.restrict(mutability);
     }
 
-    pub fn set(&mut self, name: &Token, value: &Type) {
-        self.fields.insert(name.lexeme.clone(), value.clone());
+    // Its class and fields, for the garbage collector
+    pub fn trace<'a>(&'a self, traced: &mut Vec<Traced<'a>>) {
+        self.class.trace(traced);
+        self.fields.values().for_each(|value| value.trace(traced));
+    }
+
+    // Lets go of its fields, to break a cycle the garbage collector found
+    pub fn clear(&mut self) {
+        self.fields.clear();
+    }
+
+    pub fn set_field(&mut self, name: &str, value: Type) {
+        self.fields.insert(Ref::from(name), value);
+    }
+
+    // A method of its class, fields aside
+    pub fn method(&self, name: &str) -> Option<Function> {
+        self.class.find_method(name)
+    }
+
+    // A field, methods aside
+    pub fn field(&self, name: &str) -> Option<Type> {
+        self.fields.get(name).cloned()
+    }
+
+    // Made by the class, or by a subclass of it
+    pub fn is_instance_of(&self, class: &Class) -> bool {
+        self.class
+            .ancestors()
+            .any(|ancestor| ancestor.id == class.id)
+    }
+
+    // Whether a function of `owner` reaches the private member, see Class::is_owner
+    pub fn check_private(&self, name: &Token, owner: Option<ClassId>) -> Result<(), Error> {
+        if self.class.is_owner(owner) {
+            return Ok(());
+        }
+        let message = format!(
+            "`{}` is private to the methods of `{}` and its superclasses",
+            name.lexeme, self.class.name
+        );
+        Err(Error::interpreter(message, name.span).raised_as(ErrorClass::NameError))
+    }
+
+    // Leaves its `__drop` method, bound to it, to run once, see Class::finalized_in
+    // False when it has none, or it was already left
+    pub fn finalize(instance: &Shared<Instance>) -> bool {
+        let mut borrowed = instance.borrow_mut();
+        let (Some(dropped), Some(method)) = (
+            borrowed.class.dropped.take(),
+            borrowed.class.find_method("__drop"),
+        ) else {
+            return false;
+        };
+        drop(borrowed);
+        let bound = method.bind(Type::Instance(Ref::clone(instance)));
+        dropped.borrow_mut().push(bound);
+        true
+    }
+
+    // Public fields and methods, inherited ones included
+    pub fn property_names(&self) -> Vec<Ref<str>> {
+        let mut names: Vec<Ref<str>> = self.fields.keys().cloned().collect();
+        names.extend(self.class.method_names());
+        names.retain(|name| !is_private(name));
+        names
     }
 }
 
-impl ToString for Instance {
-    fn to_string(&self) -> String {
-        self.class.name.clone()
+// The `__drop` method of an instance no longer reachable waits for the
+// interpreter to be between statements, see Interpreter::finalize. It is bound
+// to an instance taking over the fields, which is dropped without running it again
+impl Drop for Instance {
+    fn drop(&mut self) {
+        if self.class.dropped.is_none() || self.class.find_method("__drop").is_none() {
+            return;
+        }
+        let alive = Instance {
+            class: self.class.clone(),
+            fields: std::mem::take(&mut self.fields),
+            mutability: self.mutability,
+        };
+        Instance::finalize(&Ref::new(Lock::new(alive)));
     }
 }
 
+impl fmt::Display for Instance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.class.name)
+    }
+}
+
+// Which class it is, copies of a class are the same class and two declarations
+// with the same name are not
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClassId {
+    BuiltIn(ErrorClass),
+    Declared(usize),
+}
+
+static DECLARED: AtomicUsize = AtomicUsize::new(0);
+
 #[derive(Debug, Clone)]
 pub struct Class {
-    pub name: String,
-    arity: usize,
+    pub name: Ref<str>,
+    id: ClassId,
     superclass: Option<Box<Class>>,
-    methods: HashMap<String, Function>,
+    methods: HashMap<Ref<str>, Function>,
+    dropped: Option<Shared<Vec<Function>>>, // Where its instances leave their `__drop` method
+    sealed: bool,                           // Declared `sealed class`
 }
 
 impl Class {
+    // Methods without an owner are its own, mixed in ones keep theirs
     pub fn new(
-        name: String,
+        name: Ref<str>,
         superclass: Option<Box<Class>>,
-        methods: HashMap<String, Function>,
+        mut methods: HashMap<Ref<str>, Function>,
     ) -> Class {
+        let id = ClassId::Declared(DECLARED.fetch_add(1, Ordering::Relaxed));
+        for method in methods.values_mut() {
+            method.owner.get_or_insert(id);
+        }
         Class {
             name,
-            arity: 0,
+            id,
             superclass,
             methods,
+            dropped: None,
+            sealed: false,
+        }
+    }
+
+    // Instances with a `__drop` method leave it in `dropped` once unreachable
+    pub fn finalized_in(mut self, dropped: Shared<Vec<Function>>) -> Class {
+        self.dropped = Some(dropped);
+        self
+    }
+
+    // Its instances are sealed once their initializer has run
+    pub fn sealed(mut self, sealed: bool) -> Class {
+        self.sealed = sealed;
+        self
+    }
+
+    // A method of `owner` reaches the private members of the class' instances
+    // when `owner` is the class or one of its superclasses, not a mixin
+    pub fn is_owner(&self, owner: Option<ClassId>) -> bool {
+        self.ancestors().any(|class| Some(class.id) == owner)
+    }
+
+    // Declared `sealed class`, or inheriting from one
+    fn is_sealed(&self) -> bool {
+        self.ancestors().any(|class| class.sealed)
+    }
+
+    // The built-in class errors of `class` are raised as, it has no methods
+    pub fn built_in(class: ErrorClass) -> Class {
+        let superclass = class
+            .superclass()
+            .map(|superclass| Box::new(Class::built_in(superclass)));
+        Class {
+            id: ClassId::BuiltIn(class),
+            ..Class::new(Ref::from(class.name()), superclass, HashMap::new())
         }
     }
 
-    fn find_method(&self, method_name: &String) -> Option<Function> {
-        match self.methods.get(method_name).cloned() {
-            Some(method) => Some(method),
-            None => {
-                if let Some(parent) = &self.superclass {
-                    parent.find_method(method_name)
-                } else {
-                    None
+    // Its `init` method, or an inherited one, run on every new instance
+    pub fn initializer(&self) -> Option<Function> {
+        self.find_method("init")
+    }
+
+    // `Error` or one of its subclasses, their instances can be thrown
+    pub fn is_error(&self) -> bool {
+        self.ancestors()
+            .any(|class| class.id == ClassId::BuiltIn(ErrorClass::Error))
+    }
+
+    // The class, then its superclass, and so on up the chain
+    // A superclass is a copy made when the subclass is declared, so the chain
+    // always ends, whatever is declared afterwards
+    fn ancestors(&self) -> impl Iterator<Item = &Class> {
+        std::iter::successors(Some(self), |class| class.superclass.as_deref())
+    }
+
+    fn trace<'a>(&'a self, traced: &mut Vec<Traced<'a>>) {
+        for class in self.ancestors() {
+            for method in class.methods.values() {
+                traced.extend(method.closure.iter().map(Traced::Environment));
+            }
+        }
+    }
+
+    // Every method of the class, inherited ones included, by name
+    // The closest one wins, like `find_method`
+    pub fn methods(&self) -> Vec<(Ref<str>, Function)> {
+        let mut methods: Vec<(Ref<str>, Function)> = Vec::new();
+        for class in self.ancestors() {
+            for (name, method) in class.methods.iter() {
+                if !methods.iter().any(|(known, _)| known == name) {
+                    methods.push((name.clone(), method.clone()));
                 }
             }
         }
+        methods.sort_by(|(first, _), (second, _)| first.cmp(second));
+        methods
+    }
+
+    // The closest class up the chain with a method `name` of its own, and
+    // that method
+    pub fn defining(&self, method_name: &str) -> Option<(&Class, &Function)> {
+        self.ancestors()
+            .find_map(|class| class.methods.get(method_name).map(|method| (class, method)))
+    }
+
+    fn method_names(&self) -> Vec<Ref<str>> {
+        self.ancestors()
+            .flat_map(|class| class.methods.keys().cloned())
+            .collect()
+    }
+
+    // The closest one, a subclass' own method overrides the inherited one
+    pub fn find_method(&self, method_name: &str) -> Option<Function> {
+        self.ancestors()
+            .find_map(|class| class.methods.get(method_name).cloned())
     }
 }
 
 impl Callable for Class {
+    // That of its initializer, none without one
     fn arity(&self) -> usize {
-        self.arity
+        self.initializer().map_or(0, |init| init.arity)
     }
 
+    // The new instance, once its initializer has run with the arguments
+    // What the initializer returns is left out, the fields it set are the only
+    // ones an instance of a sealed class gets
     fn call(
         &self,
         interpreter: &mut Interpreter,
         arguments: Option<Vec<Type>>,
+        span: Span,
     ) -> Result<Type, Error> {
-        Ok(Type::Instance(Box::new(Instance::new(self.clone()))))
+        let instance = interpreter.new_instance(self.clone());
+        if let Some(initializer) = self.initializer() {
+            initializer.bind(Type::Instance(instance.clone())).call(
+                interpreter,
+                arguments,
+                span,
+            )?;
+        }
+        if self.is_sealed() {
+            instance.borrow_mut().restrict(Mutability::Sealed);
+        }
+        Ok(Type::Instance(instance))
     }
 }
 
-impl ToString for Class {
-    fn to_string(&self) -> String {
-        self.name.clone()
+impl fmt::Display for Class {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
     }
 }
 
 #[derive(Debug, Clone)]
 pub enum Type {
-    String(String),
-    Number(f32),
+    String(Ref<str>), // Immutable, copies share the text
+    Bytes(Ref<[u8]>), // Immutable like strings, its methods are read as properties
+    Number(f64),
     Boolean(bool),
     Function(Box<Function>),
     NativeFunction(Box<NativeFunction>),
     Class(Box<Class>),
-    Instance(Box<Instance>),
+    Instance(Shared<Instance>), // Shared by every reference, setting a field is seen through all
+    Tuple(Vec<Type>),           // Returned by `return a, b;`, unpacked by `var a, b = ...;`
+    List(Shared<Vec<Type>>),
+    Map(Shared<Vec<(Type, Type)>>), // Entries in the order their keys were first set
+    Set(Shared<Vec<Type>>),         // Elements in the order they were first added
+    Generator(Shared<Generator>),
+    Promise(Shared<Promise>),  // Of an async native, settled by `await`
+    Channel(Channel),          // An end of the channel to or from a thread
+    Builder(Shared<String>),   // Of `string_builder()`, `append` adds to it in place
+    Module(Ref<Module<Type>>), // Of `import`, its members are read as properties
+    Socket(Socket),            // Of the `net` module, its methods are read as properties
     Nil,
 }
 
@@ -257,28 +618,330 @@ impl Type {
     pub fn value(&self) -> String {
         match self {
             Type::String(val) => val.to_string(),
-            Type::Number(val) => val.to_string(),
+            Type::Number(val) => number::format(*val),
             Type::Boolean(val) => val.to_string(),
             Type::Function(fun) => fun.to_string(),
             Type::NativeFunction(fun) => fun.to_string(),
             Type::Class(class) => class.to_string(),
-            Type::Instance(instance) => instance.to_string(),
+            Type::Instance(instance) => instance.borrow().to_string(),
+            Type::Tuple(_)
+            | Type::List(_)
+            | Type::Map(_)
+            | Type::Set(_)
+            | Type::Generator(_)
+            | Type::Promise(_)
+            | Type::Channel(_)
+            | Type::Builder(_)
+            | Type::Module(_)
+            | Type::Socket(_)
+            | Type::Bytes(_) => self.to_string(),
             Type::Nil => "nil".to_string(),
         }
     }
+
+    // A copy that can go to another thread, none for values made of functions,
+    // classes, instances, generators or promises
+    pub fn to_message(&self) -> Option<Message> {
+        let all = |values: &[Type]| values.iter().map(Type::to_message).collect::<Option<_>>();
+        Some(match self {
+            Type::Nil => Message::Nil,
+            Type::Boolean(value) => Message::Boolean(*value),
+            Type::Number(value) => Message::Number(*value),
+            Type::String(value) => Message::String(value.to_string()),
+            Type::Bytes(value) => Message::Bytes(value.to_vec()),
+            Type::Tuple(values) => Message::Tuple(all(values)?),
+            Type::List(elements) => Message::List(all(&elements.borrow())?),
+            Type::Set(elements) => Message::Set(all(&elements.borrow())?),
+            Type::Map(entries) => Message::Map(
+                entries
+                    .borrow()
+                    .iter()
+                    .map(|(key, value)| Some((key.to_message()?, value.to_message()?)))
+                    .collect::<Option<_>>()?,
+            ),
+            Type::Channel(channel) => Message::Channel(channel.clone()),
+            _ => return None,
+        })
+    }
+
+    pub fn from_message(message: Message) -> Type {
+        let all = |messages: Vec<Message>| messages.into_iter().map(Type::from_message).collect();
+        match message {
+            Message::Nil => Type::Nil,
+            Message::Boolean(value) => Type::Boolean(value),
+            Message::Number(value) => Type::Number(value),
+            Message::String(value) => Type::String(Ref::from(value)),
+            Message::Bytes(value) => Type::Bytes(Ref::from(value)),
+            Message::Tuple(messages) => Type::Tuple(all(messages)),
+            Message::List(messages) => Type::List(Ref::new(Lock::new(all(messages)))),
+            Message::Set(messages) => Type::Set(Ref::new(Lock::new(all(messages)))),
+            Message::Map(entries) => Type::Map(Ref::new(Lock::new(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (Type::from_message(key), Type::from_message(value)))
+                    .collect(),
+            ))),
+            Message::Channel(channel) => Type::Channel(channel),
+        }
+    }
+
+    // `==`, lists, maps and sets holding equal values are equal, see equality
+    // Instances, generators and the like are only equal to themselves
+    pub fn is_equal(&self, other: &Type) -> bool {
+        self.equal_in(other, &mut Vec::new())
+    }
+
+    fn equal_in(&self, other: &Type, comparing: &mut Comparing) -> bool {
+        match (self, other) {
+            (Type::Nil, Type::Nil) => true,
+            (Type::Boolean(a), Type::Boolean(b)) => a == b,
+            (Type::Number(a), Type::Number(b)) => a == b,
+            (Type::String(a), Type::String(b)) => a == b,
+            (Type::Bytes(a), Type::Bytes(b)) => a == b,
+            (Type::Function(a), Type::Function(b)) => a.is_same(b),
+            (Type::NativeFunction(a), Type::NativeFunction(b)) => a.name == b.name,
+            (Type::Class(a), Type::Class(b)) => a.name == b.name,
+            (Type::Instance(a), Type::Instance(b)) => Ref::ptr_eq(a, b),
+            (Type::Tuple(a), Type::Tuple(b)) => {
+                a.len() == b.len()
+                    && a.iter()
+                        .zip(b.iter())
+                        .all(|(a, b)| a.equal_in(b, comparing))
+            }
+            (Type::List(a), Type::List(b)) => equality::deep(
+                Ref::as_ptr(a).cast(),
+                Ref::as_ptr(b).cast(),
+                comparing,
+                |comparing| {
+                    let (a, b) = (a.borrow(), b.borrow());
+                    a.len() == b.len()
+                        && a.iter()
+                            .zip(b.iter())
+                            .all(|(a, b)| a.equal_in(b, comparing))
+                },
+            ),
+            // Keys of a map and elements of a set appear once
+            (Type::Map(a), Type::Map(b)) => equality::deep(
+                Ref::as_ptr(a).cast(),
+                Ref::as_ptr(b).cast(),
+                comparing,
+                |comparing| {
+                    let (a, b) = (a.borrow(), b.borrow());
+                    a.len() == b.len()
+                        && a.iter().all(|(key, value)| {
+                            b.iter().any(|(other_key, other_value)| {
+                                key.equal_in(other_key, comparing)
+                                    && value.equal_in(other_value, comparing)
+                            })
+                        })
+                },
+            ),
+            (Type::Set(a), Type::Set(b)) => equality::deep(
+                Ref::as_ptr(a).cast(),
+                Ref::as_ptr(b).cast(),
+                comparing,
+                |comparing| {
+                    let (a, b) = (a.borrow(), b.borrow());
+                    a.len() == b.len()
+                        && a.iter()
+                            .all(|element| b.iter().any(|other| element.equal_in(other, comparing)))
+                },
+            ),
+            (Type::Generator(a), Type::Generator(b)) => Ref::ptr_eq(a, b),
+            (Type::Promise(a), Type::Promise(b)) => Ref::ptr_eq(a, b),
+            (Type::Channel(a), Type::Channel(b)) => a.is(b),
+            (Type::Builder(a), Type::Builder(b)) => Ref::ptr_eq(a, b),
+            (Type::Module(a), Type::Module(b)) => Ref::ptr_eq(a, b),
+            (Type::Socket(a), Type::Socket(b)) => a.is(b),
+            _ => false,
+        }
+    }
+
+    // None for a value that can't be a map key or a set element
+    pub fn key(&self) -> Option<Key<'_>> {
+        match self {
+            Type::Nil => Some(Key::Nil),
+            Type::Boolean(boolean) => Some(Key::Boolean(*boolean)),
+            Type::Number(number) => Some(Key::number(*number)),
+            Type::String(text) => Some(Key::String(text)),
+            _ => None,
+        }
+    }
+
+    // `is`, the very same list, map or set rather than an equal one
+    // Anything else is compared as by `==`
+    pub fn is(&self, other: &Type) -> bool {
+        match (self, other) {
+            (Type::List(a), Type::List(b)) | (Type::Set(a), Type::Set(b)) => Ref::ptr_eq(a, b),
+            (Type::Map(a), Type::Map(b)) => Ref::ptr_eq(a, b),
+            (Type::Tuple(a), Type::Tuple(b)) => {
+                a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| a.is(b))
+            }
+            _ => self.is_equal(other),
+        }
+    }
+
+    // How the REPL echoes the value, strings are quoted to tell "1" from 1
+    pub fn repr(&self) -> String {
+        match self {
+            Type::String(val) => format!("{:?}", val),
+            Type::Tuple(values) => format!(
+                "({})",
+                values
+                    .iter()
+                    .map(Type::repr)
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            _ => self.to_string(),
+        }
+    }
+
+    // What the value keeps alive, for the garbage collector
+    pub fn trace<'a>(&'a self, traced: &mut Vec<Traced<'a>>) {
+        match self {
+            Type::Function(function) => {
+                traced.extend(function.closure.iter().map(Traced::Environment));
+                if let Some(this) = &function.this {
+                    this.trace(traced);
+                }
+            }
+            Type::Class(class) => class.trace(traced),
+            Type::Instance(instance) => traced.push(Traced::Instance(instance)),
+            Type::Tuple(values) => values.iter().for_each(|value| value.trace(traced)),
+            Type::List(elements) => traced.push(Traced::List(elements)),
+            Type::Map(entries) => traced.push(Traced::Map(entries)),
+            Type::Module(module) => module
+                .members
+                .values()
+                .for_each(|value| value.trace(traced)),
+            // What a generator or promise holds isn't traced: the environments it
+            // reaches look used from outside and are kept, cycles through it are
+            // never collected
+            // Sets only hold strings, numbers, booleans and nil
+            _ => {}
+        }
+    }
 }
 
 impl fmt::Display for Type {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Type::String(val) => write!(f, "{}", val),
-            Type::Number(val) => write!(f, "{}", val),
+            Type::Number(val) => write!(f, "{}", number::format(*val)),
             Type::Boolean(val) => write!(f, "{}", val),
-            Type::Function(fun) => write!(f, "Function <{}>", fun.to_string()),
-            Type::NativeFunction(fun) => write!(f, "Native Function <{}>", fun.to_string()),
-            Type::Class(class) => write!(f, "Class <{}>", class.to_string()),
-            Type::Instance(instance) => write!(f, "Instance of <{}>", instance.to_string()),
+            Type::Function(fun) => write!(f, "Function <{}>", fun),
+            Type::NativeFunction(fun) => write!(f, "Native Function <{}>", fun),
+            Type::Class(class) => write!(f, "Class <{}>", class),
+            Type::Instance(instance) => write!(f, "Instance of <{}>", instance.borrow()),
+            Type::Tuple(values) => {
+                let values: Vec<String> = values.iter().map(Type::to_string).collect();
+                write!(f, "({})", values.join(", "))
+            }
+            Type::List(elements) => {
+                let elements = repr::nested(Ref::as_ptr(elements).cast(), repr::MAX_DEPTH, || {
+                    let elements: Vec<String> = elements.borrow().iter().map(Type::repr).collect();
+                    elements.join(", ")
+                });
+                write!(f, "[{}]", elements.as_deref().unwrap_or("..."))
+            }
+            Type::Map(entries) => {
+                let entries = repr::nested(Ref::as_ptr(entries).cast(), repr::MAX_DEPTH, || {
+                    let entries: Vec<String> = entries
+                        .borrow()
+                        .iter()
+                        .map(|(key, value)| format!("{}: {}", key.repr(), value.repr()))
+                        .collect();
+                    entries.join(", ")
+                });
+                write!(f, "{{{}}}", entries.as_deref().unwrap_or("..."))
+            }
+            Type::Set(elements) => {
+                let elements: Vec<String> = elements.borrow().iter().map(Type::repr).collect();
+                write!(f, "#{{{}}}", elements.join(", "))
+            }
+            Type::Generator(generator) => {
+                let generator = generator.borrow();
+                match generator.spawned {
+                    true => write!(f, "Coroutine <{}>", generator),
+                    false => write!(f, "Generator <{}>", generator),
+                }
+            }
+            Type::Promise(promise) => write!(f, "{}", promise.borrow()),
+            Type::Channel(_) => write!(f, "Channel"),
+            Type::Builder(_) => write!(f, "String builder"),
+            Type::Module(module) => write!(f, "Module <{}>", module.name),
+            Type::Socket(socket) => write!(f, "{}", socket),
+            Type::Bytes(bytes) => bytes::format(bytes, f),
             Type::Nil => write!(f, "nil"),
         }
     }
 }
+
+impl Nested for Type {
+    fn contents(&self) -> Option<Contents<Type>> {
+        let (address, open, close, items) = match self {
+            Type::List(elements) => (
+                Ref::as_ptr(elements).cast(),
+                "[".to_string(),
+                "]",
+                elements
+                    .borrow()
+                    .iter()
+                    .map(|element| (None, element.clone()))
+                    .collect(),
+            ),
+            Type::Set(elements) => (
+                Ref::as_ptr(elements).cast(),
+                "#{".to_string(),
+                "}",
+                elements
+                    .borrow()
+                    .iter()
+                    .map(|element| (None, element.clone()))
+                    .collect(),
+            ),
+            Type::Map(entries) => (
+                Ref::as_ptr(entries).cast(),
+                "{".to_string(),
+                "}",
+                entries
+                    .borrow()
+                    .iter()
+                    .map(|(key, value)| (Some(key.repr()), value.clone()))
+                    .collect(),
+            ),
+            // Public fields by name, the order they were set in isn't kept
+            Type::Instance(instance) => {
+                let borrowed = instance.borrow();
+                let mut fields: Vec<(Option<String>, Type)> = borrowed
+                    .fields
+                    .iter()
+                    .filter(|(name, _)| !is_private(name))
+                    .map(|(name, value)| (Some(name.to_string()), value.clone()))
+                    .collect();
+                if fields.is_empty() {
+                    return None;
+                }
+                fields.sort_by(|(a, _), (b, _)| a.cmp(b));
+                (
+                    Ref::as_ptr(instance).cast(),
+                    format!("Instance of <{}> {{", borrowed),
+                    "}",
+                    fields,
+                )
+            }
+            _ => return None,
+        };
+        Some(Contents {
+            address,
+            open,
+            close,
+            items,
+        })
+    }
+
+    fn inline(&self) -> String {
+        self.repr()
+    }
+}
@@ -0,0 +1,105 @@
+use super::{lexer::Lexer, token::*};
+
+// What an editor colors a token as
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SemanticKind {
+    Keyword,
+    String,
+    Number,
+    Identifier,
+    Comment,
+    Operator,
+    Punctuation, // Parentheses, braces, `,`, `.`, `;` and `:`
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SemanticToken {
+    pub kind: SemanticKind,
+    pub span: Span,
+}
+
+impl SemanticKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SemanticKind::Keyword => "keyword",
+            SemanticKind::String => "string",
+            SemanticKind::Number => "number",
+            SemanticKind::Identifier => "identifier",
+            SemanticKind::Comment => "comment",
+            SemanticKind::Operator => "operator",
+            SemanticKind::Punctuation => "punctuation",
+        }
+    }
+
+    fn of(token_type: &TokenType) -> Option<SemanticKind> {
+        let kind = match token_type {
+            TokenType::LeftParen
+            | TokenType::RightParen
+            | TokenType::LeftBrace
+            | TokenType::RightBrace
+            | TokenType::LeftBracket
+            | TokenType::RightBracket
+            | TokenType::HashBrace
+            | TokenType::Colon
+            | TokenType::Comma
+            | TokenType::Dot
+            | TokenType::DotQuestion
+            | TokenType::SemiColon => SemanticKind::Punctuation,
+            TokenType::Minus
+            | TokenType::Plus
+            | TokenType::Slash
+            | TokenType::Star
+            | TokenType::Arrow
+            | TokenType::FatArrow
+            | TokenType::Bang
+            | TokenType::BangEqual
+            | TokenType::Equal
+            | TokenType::EqualEqual
+            | TokenType::Greater
+            | TokenType::GreaterEqual
+            | TokenType::Less
+            | TokenType::LessEqual => SemanticKind::Operator,
+            TokenType::Identifier => SemanticKind::Identifier,
+            TokenType::String => SemanticKind::String,
+            TokenType::Number => SemanticKind::Number,
+            TokenType::Comment => SemanticKind::Comment,
+            keyword if keyword.is_keyword() => SemanticKind::Keyword,
+            // Only EOF is left
+            _ => return None,
+        };
+        Some(kind)
+    }
+}
+
+// Every token of the source and every comment, classified for highlighting, in order
+//
+// Spans are the ones of the lexer, character offsets with their line and column,
+// so what's between two tokens is exactly the whitespace of the source.
+// Characters the lexer rejects are left out, the source doesn't have to be valid
+pub fn semantic_tokens(source: &str) -> Vec<SemanticToken> {
+    let mut lexer = Lexer::new(source.to_string());
+    lexer.scan();
+
+    let mut tokens: Vec<SemanticToken> = lexer
+        .tokens
+        .iter()
+        .chain(lexer.comments.iter())
+        .filter_map(|token| {
+            SemanticKind::of(&token.token_type).map(|kind| SemanticToken {
+                kind,
+                span: token.span,
+            })
+        })
+        .collect();
+
+    // The `#!` line is skipped by the lexer, after any byte order mark
+    if let Some(shebang) = &lexer.shebang {
+        let start = usize::from(source.starts_with('\u{feff}'));
+        tokens.push(SemanticToken {
+            kind: SemanticKind::Comment,
+            span: Span::new(start, start + shebang.chars().count(), 1, 1),
+        });
+    }
+    tokens.sort_by_key(|token| token.span.start);
+    tokens
+}
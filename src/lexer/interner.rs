@@ -0,0 +1,28 @@
+use std::collections::HashSet;
+
+use crate::shared::Ref;
+
+// Every distinct lexeme is allocated once and shared by all the tokens spelling it,
+// so cloning a token, or using its lexeme as a variable name, only bumps a counter
+#[derive(Debug, Default)]
+pub struct Interner {
+    symbols: HashSet<Ref<str>>,
+}
+
+impl Interner {
+    pub fn new() -> Interner {
+        Interner {
+            symbols: HashSet::new(),
+        }
+    }
+
+    pub fn intern(&mut self, text: &str) -> Ref<str> {
+        if let Some(symbol) = self.symbols.get(text) {
+            return Ref::clone(symbol);
+        }
+
+        let symbol: Ref<str> = Ref::from(text);
+        self.symbols.insert(Ref::clone(&symbol));
+        symbol
+    }
+}
@@ -1,85 +1,228 @@
-use super::token::*;
-use crate::error::*;
-
-use std::collections::HashMap;
-
+use super::{interner::Interner, source::Source, token::*};
+use crate::{error::*, shared::Ref};
+
+use std::{char::REPLACEMENT_CHARACTER, collections::HashMap, io::Read};
+
+// The words the lexer reserves, and their tokens
+pub const KEYWORDS: [(&str, TokenType); 30] = [
+    ("and", TokenType::And),
+    ("as", TokenType::As),
+    ("await", TokenType::Await),
+    ("catch", TokenType::Catch),
+    ("class", TokenType::Class),
+    ("else", TokenType::Else),
+    ("false", TokenType::False),
+    ("for", TokenType::For),
+    ("fun", TokenType::Fun),
+    ("if", TokenType::If),
+    ("import", TokenType::Import),
+    ("in", TokenType::In),
+    ("is", TokenType::Is),
+    ("match", TokenType::Match),
+    ("nil", TokenType::Nil),
+    ("or", TokenType::Or),
+    ("override", TokenType::Override),
+    ("print", TokenType::Print),
+    ("return", TokenType::Return),
+    ("sealed", TokenType::Sealed),
+    ("spawn", TokenType::Spawn),
+    ("super", TokenType::Super),
+    ("this", TokenType::This),
+    ("throw", TokenType::Throw),
+    ("true", TokenType::True),
+    ("try", TokenType::Try),
+    ("var", TokenType::Var),
+    ("while", TokenType::While),
+    ("with", TokenType::With),
+    ("yield", TokenType::Yield),
+];
+
+// Turns source code into Tokens
+//
+// `scan` lexes everything into `tokens`. `stream` hands out tokens one at a
+// time instead, and with `from_reader` the source is only read as far as the
+// lexer got, for programs too big to hold whole
 pub struct Lexer<'lexer> {
-    pub source_code: Vec<char>,
+    source: Source<'lexer>,
     pub tokens: Vec<Token>,
+    pub comments: Vec<Token>,
+    pub shebang: Option<String>, // `#!/usr/bin/env lost`, kept for the formatter
     pub start: usize,
     pub current: usize,
     pub line: usize,
+    line_start: usize, // Offset of the first character of the current line
+    start_line: usize, // Line and column of `start`
+    start_column: usize,
     pub errors: Vec<Error>,
     keywords: HashMap<&'lexer str, TokenType>,
+    interner: Interner,
+    finished: bool, // EOF was added
 }
 
 impl<'lexer> Lexer<'lexer> {
     pub fn new(source_code: String) -> Lexer<'lexer> {
+        Lexer::with_source(Source::from_string(source_code))
+    }
+
+    // Any reader of UTF-8, a file or `source.as_bytes()` for a &str
+    pub fn from_reader(reader: impl Read + 'lexer) -> Lexer<'lexer> {
+        Lexer::with_source(Source::from_reader(reader))
+    }
+
+    // Lexes on from the middle of a program, `reader` reads it from the start of a
+    // token at `offset`, `line` and `column`. Spans are the ones in the whole program,
+    // and there's no byte order mark or `#!` line to skip
+    pub fn resume(
+        reader: impl Read + 'lexer,
+        offset: usize,
+        line: usize,
+        column: usize,
+    ) -> Lexer<'lexer> {
+        let mut lexer = Lexer::at(Source::from_reader(reader).starting_at(offset));
+        lexer.start = offset;
+        lexer.current = offset;
+        lexer.line = line;
+        lexer.line_start = (offset + 1).saturating_sub(column);
+        lexer
+    }
+
+    fn with_source(source: Source<'lexer>) -> Lexer<'lexer> {
+        let mut lexer = Lexer::at(source);
+        lexer.skip_preamble();
+        lexer
+    }
+
+    fn at(source: Source<'lexer>) -> Lexer<'lexer> {
         Lexer {
-            source_code: source_code.chars().collect(),
+            source,
             tokens: Vec::new(),
+            comments: Vec::new(),
+            shebang: None,
             start: 0,   // Starts at the 0th character
             current: 0, // Current == Start in the beginning
             line: 1,    // Begin at line number 1
+            line_start: 0,
+            start_line: 1,
+            start_column: 1,
             errors: Vec::new(),
-            keywords: HashMap::from([
-                ("and", TokenType::And),
-                ("class", TokenType::Class),
-                ("else", TokenType::Else),
-                ("false", TokenType::False),
-                ("for", TokenType::For),
-                ("fun", TokenType::Fun),
-                ("if", TokenType::If),
-                ("nil", TokenType::Nil),
-                ("or", TokenType::Or),
-                ("print", TokenType::Print),
-                ("return", TokenType::Return),
-                ("super", TokenType::Super),
-                ("this", TokenType::This),
-                ("true", TokenType::True),
-                ("var", TokenType::Var),
-                ("while", TokenType::While),
-            ]),
+            keywords: HashMap::from(KEYWORDS),
+            interner: Interner::new(),
+            finished: false,
+        }
+    }
+
+    // The tokens of a whole source, ending in EOF, or every error in it
+    pub fn tokenize(source: &str) -> Result<Vec<Token>, Vec<Error>> {
+        let mut lexer = Lexer::from_reader(source.as_bytes());
+        lexer.scan();
+        match lexer.errors.is_empty() {
+            true => Ok(lexer.tokens),
+            false => Err(lexer.errors),
         }
     }
 
     pub fn scan(&mut self) {
         // Keep scanning for Tokens untill the end of file
-        while !self.is_at_end() {
-            // start holds the start of the current lexeme being scanned
-            // current tells the scan_token the position in the lexeme
-            self.start = self.current;
+        while !self.finished {
+            self.scan_next();
+        }
+    }
+
+    // Scans one lexeme, which may not be a token (whitespace, comments),
+    // or adds EOF at the end
+    fn scan_next(&mut self) {
+        // start holds the start of the current lexeme being scanned
+        // current tells the scan_token the position in the lexeme
+        self.start = self.current;
+        self.start_line = self.line;
+        self.start_column = self.start - self.line_start + 1;
+        self.source.release(self.start);
+
+        if !self.is_at_end() {
             self.scan_token();
+            return;
+        }
+
+        if let Some(error) = self.source.take_error() {
+            self.errors.push(Error::lexer(
+                format!("Could not read the source: {}", error),
+                self.span(),
+            ));
         }
 
         // Add the final Token, denoting the end of file
-        self.tokens.push(Token::new(
-            TokenType::EOF,
-            String::from(""),
-            None,
-            self.line,
-        ));
+        let eof = self.interner.intern("");
+        self.tokens
+            .push(Token::new(TokenType::EOF, eof, None, self.span()));
+        self.finished = true;
+    }
+
+    // A byte order mark and a `#!` line on top of the file are not part of the program
+    // Columns of the first line are still counted from after the byte order mark
+    fn skip_preamble(&mut self) {
+        if self.peek() == '\u{feff}' {
+            self.current += 1;
+            self.line_start = self.current;
+        }
+
+        if self.peek() == '#' && self.peek_next() == '!' {
+            let start = self.current;
+            while self.peek() != '\n' && !self.is_at_end() {
+                let _ = self.advance();
+            }
+            self.shebang = Some(self.source.text(start, self.current));
+        }
+    }
+
+    // The lexeme being scanned
+    fn span(&self) -> Span {
+        Span::new(self.start, self.current, self.start_line, self.start_column)
+    }
+
+    fn new_line(&mut self) {
+        self.line += 1;
+        self.line_start = self.current;
     }
 
     fn scan_token(&mut self) {
         let c = self.advance(); // Get current char and move current index
         match c {
             // New line
-            '\n' => self.line += 1,
+            '\n' => self.new_line(),
+
+            // Whitespace
+            ' ' | '\r' | '\t' => {}
 
             // Single Character tokens
             '(' => self.add_token(TokenType::LeftParen, None),
             ')' => self.add_token(TokenType::RightParen, None),
             '{' => self.add_token(TokenType::LeftBrace, None),
             '}' => self.add_token(TokenType::RightBrace, None),
-            '.' => self.add_token(TokenType::Dot, None),
+            '[' => self.add_token(TokenType::LeftBracket, None),
+            ']' => self.add_token(TokenType::RightBracket, None),
             ',' => self.add_token(TokenType::Comma, None),
+            ':' => self.add_token(TokenType::Colon, None),
             '+' => self.add_token(TokenType::Plus, None),
-            '-' => self.add_token(TokenType::Minus, None),
             '*' => self.add_token(TokenType::Star, None),
             ';' => self.add_token(TokenType::SemiColon, None),
 
             // Single or Double Character tokens
+            '.' => {
+                // '.?' (property that may be missing) or '.'
+                if self.match_next('?') {
+                    self.add_token(TokenType::DotQuestion, None);
+                } else {
+                    self.add_token(TokenType::Dot, None);
+                }
+            }
+            '-' => {
+                // '->' (return type annotation) or '-'
+                if self.match_next('>') {
+                    self.add_token(TokenType::Arrow, None);
+                } else {
+                    self.add_token(TokenType::Minus, None);
+                }
+            }
             '!' => {
                 // '!=' or just '='
                 let is_bang_equal = self.match_next('=');
@@ -91,11 +234,13 @@ impl<'lexer> Lexer<'lexer> {
                 }
             }
             '=' => {
-                // '==' or '='
+                // '==', '=>' (arm of a match) or '='
                 let is_equal_equal = self.match_next('=');
 
                 if is_equal_equal {
                     self.add_token(TokenType::EqualEqual, None);
+                } else if self.match_next('>') {
+                    self.add_token(TokenType::FatArrow, None);
                 } else {
                     self.add_token(TokenType::Equal, None);
                 }
@@ -110,6 +255,15 @@ impl<'lexer> Lexer<'lexer> {
                     self.add_token(TokenType::Less, None);
                 }
             }
+            '#' => {
+                // '#{' (set literal), a '#' alone is nothing
+                if self.match_next('{') {
+                    self.add_token(TokenType::HashBrace, None);
+                } else {
+                    self.errors
+                        .push(Error::lexer("Unexpected Token".to_string(), self.span()));
+                }
+            }
             '>' => {
                 // '>=' or '<'
                 let is_greater_equal = self.match_next('=');
@@ -126,11 +280,18 @@ impl<'lexer> Lexer<'lexer> {
                 // '//' (comment) or '/' (division)
                 if self.match_next('/') {
                     // Ignore everything till the end of line
-                    let mut next_char = self.peek();
-                    while next_char != '\n' && next_char != '\0' {
+                    while self.peek() != '\n' && !self.is_at_end() {
                         let _ = self.advance();
-                        next_char = self.peek();
                     }
+
+                    // Not part of the program, but kept aside for tools like the formatter
+                    let text = self.source.text(self.start, self.current);
+                    self.comments.push(Token::new(
+                        TokenType::Comment,
+                        Ref::from(text),
+                        None,
+                        self.span(),
+                    ));
                 } else {
                     self.add_token(TokenType::Slash, None)
                 }
@@ -140,19 +301,19 @@ impl<'lexer> Lexer<'lexer> {
             '"' => self.string_literal(),
 
             c => {
-                if c.is_digit(10) {
+                if c.is_ascii_digit() {
                     // Numeric literals
                     self.number_literal();
                 } else if Self::is_alpha(c) {
                     // Identifier (user defined and Keywords)
                     self.identifier();
-                } else {
+                } else if !self.source.is_invalid(self.start) {
                     // Invalid character
                     // Add the error to the list, main will report
                     self.errors
-                        .push(Error::lexer("Unexpected Token".to_string(), self.line));
+                        .push(Error::lexer("Unexpected Token".to_string(), self.span()));
                 }
-            },
+            }
         }
     }
 
@@ -162,7 +323,7 @@ impl<'lexer> Lexer<'lexer> {
             self.advance();
         }
 
-        let identifier_text: String = self.source_code[self.start..self.current].iter().collect();
+        let identifier_text = self.source.text(self.start, self.current);
         match self.keywords.get(&identifier_text.as_str()) {
             Some(val) => self.add_token(val.clone(), None),
             None => self.add_token(TokenType::Identifier, None),
@@ -170,29 +331,35 @@ impl<'lexer> Lexer<'lexer> {
     }
 
     fn string_literal(&mut self) {
-        // Get the complete literal
-        let mut next_char = self.peek();
-        while next_char != '"' && next_char != '\0' {
-            if next_char == '\n' {
-                self.line += 1;
+        // Get the complete literal, a NUL in it is just a character
+        while self.peek() != '"' && !self.is_at_end() {
+            if self.advance() == '\n' {
+                self.new_line();
             }
-            let _ = self.advance();
-            next_char = self.peek();
         }
 
-        if next_char == '\0' {
+        if self.is_at_end() {
             // The string literal was not terminated
-            self.errors
-                .push(Error::lexer("Unterminated String".to_string(), self.line));
+            let opened = Span::new(
+                self.start,
+                self.start + 1,
+                self.start_line,
+                self.start_column,
+            );
+            self.errors.push(Error::unclosed(
+                ErrorKind::Lex,
+                "string".to_string(),
+                self.span(),
+                opened,
+            ));
+            return;
         }
 
         // Consume the closing quote "
         let _ = self.advance();
 
         // Remove the surrounding quotes ->"..."<-
-        let string_literal: String = self.source_code[self.start + 1..self.current - 1]
-            .iter()
-            .collect();
+        let string_literal = self.source.text(self.start + 1, self.current - 1);
         self.add_token(
             TokenType::String,
             Some(LiteralType::StringType(string_literal)),
@@ -200,34 +367,36 @@ impl<'lexer> Lexer<'lexer> {
     }
 
     fn number_literal(&mut self) {
-        while self.peek().is_digit(10) {
+        while self.peek().is_ascii_digit() {
             self.advance();
         }
 
         // Decimals
-        if self.peek() == '.' && self.peek_next().is_digit(10) {
+        if self.peek() == '.' && self.peek_next().is_ascii_digit() {
             // Consume the "."
             self.advance();
-            while self.peek().is_digit(10) {
+            while self.peek().is_ascii_digit() {
                 self.advance();
             }
         }
 
-        let num_literal: f32 = self.source_code[self.start..self.current]
-            .iter()
-            .collect::<String>()
-            .parse()
-            .unwrap();
-        self.add_token(
-            TokenType::Number,
-            Some(LiteralType::NumberType(num_literal)),
-        )
+        match self.source.text(self.start, self.current).parse::<f64>() {
+            Ok(num_literal) => self.add_token(
+                TokenType::Number,
+                Some(LiteralType::NumberType(num_literal)),
+            ),
+            Err(error) => self.errors.push(Error::lexer(
+                format!("Invalid number: {}", error),
+                self.span(),
+            )),
+        }
     }
 
     fn add_token(&mut self, token_type: TokenType, literal: Option<LiteralType>) {
-        let text: String = self.source_code[self.start..self.current].iter().collect();
+        let text = self.source.text(self.start, self.current);
+        let lexeme = self.interner.intern(&text);
         self.tokens
-            .push(Token::new(token_type, text.to_string(), literal, self.line))
+            .push(Token::new(token_type, lexeme, literal, self.span()))
     }
 
     fn is_alpha(c: char) -> bool {
@@ -237,42 +406,70 @@ impl<'lexer> Lexer<'lexer> {
 
     fn is_alphanumeric(c: char) -> bool {
         // abc..z + ABC..Z + _ + 0..9
-        Self::is_alpha(c) || c.is_digit(10)
+        Self::is_alpha(c) || c.is_ascii_digit()
     }
 
-    fn is_at_end(&self) -> bool {
-        self.current >= self.source_code.len()
+    fn is_at_end(&mut self) -> bool {
+        self.source.get(self.current).is_none()
     }
 
     fn match_next(&mut self, expected_next: char) -> bool {
         // There is no next character if already at end
-        if self.is_at_end() {
-            return false;
-        }
-
-        if self.source_code[self.current] != expected_next {
+        if self.source.get(self.current) != Some(expected_next) {
             return false;
         }
         let _ = self.advance(); // Move current, it is the part of this token
         true
     }
 
-    fn peek(&self) -> char {
-        if self.is_at_end() {
-            return '\0'; // The End
-        }
-        self.source_code[self.current]
+    fn peek(&mut self) -> char {
+        self.source.get(self.current).unwrap_or('\0') // '\0' is The End
     }
 
-    fn peek_next(&self) -> char {
-        if self.current + 1 >= self.source_code.len() {
-            return '\0'; // The End
-        }
-        self.source_code[self.current + 1]
+    fn peek_next(&mut self) -> char {
+        self.source.get(self.current + 1).unwrap_or('\0')
     }
 
+    // Only called when not at the end
+    // Bytes that are not UTF-8 are an error, in a string or a comment too
     fn advance(&mut self) -> char {
         self.current += 1;
-        self.source_code[self.current - 1]
+        let offset = self.current - 1;
+        let c = self.source.get(offset).unwrap_or('\0');
+        if c == REPLACEMENT_CHARACTER && self.source.is_invalid(offset) {
+            let column = offset - self.line_start + 1;
+            self.errors.push(Error::lexer(
+                "Invalid UTF-8".to_string(),
+                Span::new(offset, offset + 1, self.line, column),
+            ));
+        }
+        c
+    }
+}
+
+// Tokens one at a time, EOF last, see Lexer::stream
+pub struct Stream<'stream, 'lexer> {
+    lexer: &'stream mut Lexer<'lexer>,
+}
+
+impl<'lexer> Lexer<'lexer> {
+    // The tokens are not kept once handed out
+    // Errors still collect in `errors`, comments in `comments`
+    pub fn stream(&mut self) -> Stream<'_, 'lexer> {
+        Stream { lexer: self }
+    }
+}
+
+impl Iterator for Stream<'_, '_> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        while self.lexer.tokens.is_empty() && !self.lexer.finished {
+            self.lexer.scan_next();
+        }
+        match self.lexer.tokens.is_empty() {
+            true => None,
+            false => Some(self.lexer.tokens.remove(0)),
+        }
     }
 }
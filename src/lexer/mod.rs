@@ -1,2 +1,6 @@
+pub mod highlight;
+pub mod interner;
 pub mod lexer;
-pub mod token;
\ No newline at end of file
+pub mod source;
+pub mod token;
+pub mod trivia;
@@ -0,0 +1,158 @@
+use std::{
+    char::REPLACEMENT_CHARACTER,
+    collections::VecDeque,
+    io::{self, Cursor, ErrorKind, Read},
+};
+
+const CHUNK: usize = 8 * 1024; // Bytes read at a time
+
+// The characters of a program, decoded from UTF-8 as the lexer asks for them
+//
+// Only the characters from the start of the current lexeme on are kept, `release`
+// drops the ones before it, so a program read from a file is never in memory whole.
+// Bytes that are not UTF-8 become U+FFFD, and their offsets are kept for the
+// lexer to reject them, wherever they are
+pub struct Source<'source> {
+    reader: Box<dyn Read + 'source>,
+    bytes: Vec<u8>,         // Read but not decoded yet
+    window: VecDeque<char>, // Decoded and not released
+    window_start: usize,    // Character offset of the first character of the window
+    exhausted: bool,
+    error: Option<io::Error>, // Why reading stopped early, if it did
+    invalid: Vec<usize>,      // Offsets of the U+FFFD standing for bytes that are not UTF-8
+}
+
+impl<'source> Source<'source> {
+    pub fn from_reader(reader: impl Read + 'source) -> Source<'source> {
+        Source {
+            reader: Box::new(reader),
+            bytes: Vec::new(),
+            window: VecDeque::new(),
+            window_start: 0,
+            exhausted: false,
+            error: None,
+            invalid: Vec::new(),
+        }
+    }
+
+    // Decoded a chunk at a time like any reader, the string is not copied into characters
+    pub fn from_string(source: String) -> Source<'source> {
+        Source::from_reader(Cursor::new(source.into_bytes()))
+    }
+
+    // For a piece of a bigger text, its first character is at `offset` of that text
+    pub fn starting_at(mut self, offset: usize) -> Source<'source> {
+        self.window_start = offset;
+        self
+    }
+
+    // The character at a character offset, None past the end
+    // Offsets before a released one are gone
+    pub fn get(&mut self, offset: usize) -> Option<char> {
+        while offset >= self.window_start + self.window.len() {
+            if !self.fill() {
+                return None;
+            }
+        }
+        offset
+            .checked_sub(self.window_start)
+            .and_then(|index| self.window.get(index).copied())
+    }
+
+    // The text between two offsets the lexer has already looked at
+    pub fn text(&self, start: usize, end: usize) -> String {
+        let start = start
+            .saturating_sub(self.window_start)
+            .min(self.window.len());
+        let end = end.saturating_sub(self.window_start).min(self.window.len());
+        self.window.range(start..end.max(start)).collect()
+    }
+
+    // Nothing before `offset` is needed anymore
+    pub fn release(&mut self, offset: usize) {
+        let released = offset
+            .saturating_sub(self.window_start)
+            .min(self.window.len());
+        self.window.drain(..released);
+        self.window_start += released;
+    }
+
+    // Whether the character at the offset stands for bytes that are not UTF-8
+    pub fn is_invalid(&self, offset: usize) -> bool {
+        self.invalid.binary_search(&offset).is_ok()
+    }
+
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    // Reads and decodes the next chunk, false once there is nothing left
+    fn fill(&mut self) -> bool {
+        if self.exhausted {
+            return false;
+        }
+
+        let mut chunk = [0; CHUNK];
+        let read = loop {
+            match self.reader.read(&mut chunk) {
+                Ok(read) => break read,
+                Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+                Err(error) => {
+                    self.error = Some(error);
+                    break 0;
+                }
+            }
+        };
+
+        if read == 0 {
+            self.exhausted = true;
+            // The source ends in the middle of a character
+            if !self.bytes.is_empty() {
+                self.bytes.clear();
+                self.push_invalid();
+                return true;
+            }
+            return false;
+        }
+
+        self.bytes.extend_from_slice(&chunk[..read]);
+        self.decode();
+        true
+    }
+
+    // Moves the complete characters of `bytes` to the window
+    fn decode(&mut self) {
+        let mut decoded = 0;
+        while decoded < self.bytes.len() {
+            match std::str::from_utf8(&self.bytes[decoded..]) {
+                Ok(text) => {
+                    self.window.extend(text.chars());
+                    decoded = self.bytes.len();
+                }
+                Err(error) => {
+                    let valid = decoded + error.valid_up_to();
+                    if let Ok(text) = std::str::from_utf8(&self.bytes[decoded..valid]) {
+                        self.window.extend(text.chars());
+                    }
+                    match error.error_len() {
+                        Some(invalid) => {
+                            self.push_invalid();
+                            decoded = valid + invalid;
+                        }
+                        // Cut off by the end of the chunk, the next read completes it
+                        None => {
+                            decoded = valid;
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        self.bytes.drain(..decoded);
+    }
+
+    fn push_invalid(&mut self) {
+        self.invalid.push(self.window_start + self.window.len());
+        self.window.push_back(REPLACEMENT_CHARACTER);
+    }
+}
@@ -1,12 +1,18 @@
 use std::fmt;
 
+use crate::shared::Ref;
+
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TokenType {
     // Single-character tokens
     LeftParen,
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
+    Colon,
     Comma,
     Dot,
     SemiColon,
@@ -16,6 +22,10 @@ pub enum TokenType {
     Star,
 
     // One or two character tokens
+    Arrow,
+    DotQuestion,
+    FatArrow,
+    HashBrace,
     Bang,
     BangEqual,
     Equal,
@@ -32,57 +42,157 @@ pub enum TokenType {
 
     // Keywords
     And,
+    As,
+    Await,
+    Catch,
     Class,
     Else,
     False,
     Fun,
     For,
     If,
+    Import,
+    In,
+    Is,
+    Match,
     Nil,
     Or,
+    Override,
     Print,
     Return,
+    Sealed,
+    Spawn,
     Super,
     This,
+    Throw,
     True,
+    Try,
     Var,
     While,
+    With,
+    Yield,
+
+    // Comments are kept out of the token stream, see Lexer::comments
+    Comment,
 
     EOF,
 }
 
+impl TokenType {
+    // Words the lexer reserves, they can't name anything
+    pub fn is_keyword(&self) -> bool {
+        matches!(
+            self,
+            TokenType::And
+                | TokenType::As
+                | TokenType::Await
+                | TokenType::Catch
+                | TokenType::Class
+                | TokenType::Else
+                | TokenType::False
+                | TokenType::Fun
+                | TokenType::For
+                | TokenType::If
+                | TokenType::Import
+                | TokenType::In
+                | TokenType::Is
+                | TokenType::Match
+                | TokenType::Nil
+                | TokenType::Or
+                | TokenType::Override
+                | TokenType::Print
+                | TokenType::Return
+                | TokenType::Sealed
+                | TokenType::Spawn
+                | TokenType::Super
+                | TokenType::This
+                | TokenType::Throw
+                | TokenType::True
+                | TokenType::Try
+                | TokenType::Var
+                | TokenType::While
+                | TokenType::With
+                | TokenType::Yield
+        )
+    }
+}
+
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LiteralType {
     StringType(String),
-    NumberType(f32),
+    NumberType(f64),
+}
+
+// Where a piece of code is in the source
+// `start` and `end` are character offsets, `line` and `column` are where it starts
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize, // From 1, like lines
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize, line: usize, column: usize) -> Span {
+        Span {
+            start,
+            end,
+            line,
+            column,
+        }
+    }
+
+    // From the start of this span to the end of `other`
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            end: other.end.max(self.end),
+            ..self
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Token {
     pub token_type: TokenType,
-    pub lexeme: String,
+    pub lexeme: Ref<str>, // Interned, see Interner
     pub literal: Option<LiteralType>,
-    pub line: usize,
+    pub span: Span,
 }
 
 impl Token {
     pub fn new(
         token_type: TokenType,
-        lexeme: String,
+        lexeme: Ref<str>,
         literal: Option<LiteralType>,
-        line: usize,
+        span: Span,
     ) -> Token {
         Token {
-            token_type: token_type,
-            lexeme: lexeme,
-            literal: literal,
-            line: line,
+            token_type,
+            lexeme,
+            literal,
+            span,
         }
     }
 }
 
+// A field or method only `this` can reach, named with one leading underscore
+// `__drop` and `__exit` are run by the language itself, they stay public
+pub fn is_private(name: &str) -> bool {
+    name.starts_with('_') && !name.starts_with("__")
+}
+
 impl fmt::Display for Token {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{} at line {}", self.lexeme, self.line)        
+        write!(f, "{} at {}", self.lexeme, self.span)
     }
 }
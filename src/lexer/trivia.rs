@@ -0,0 +1,149 @@
+use super::{lexer::Lexer, token::*};
+
+// What the lexer skips between tokens
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TriviaKind {
+    Whitespace, // Spaces and tabs
+    Newline,    // `\n` or `\r\n`
+    Comment,
+    Shebang,
+    ByteOrderMark,
+    Skipped, // Characters in error, like a stray `@` or an unterminated string
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trivia {
+    pub kind: TriviaKind,
+    pub text: String,
+    pub span: Span,
+}
+
+// A token with the source text around it
+//
+// Trailing trivia runs to the end of the token's line, its newline included,
+// everything else before the next token is that token's leading trivia
+#[derive(Debug, Clone)]
+pub struct LosslessToken {
+    pub token: Token,
+    pub text: String, // As written, quotes of strings included
+    pub leading: Vec<Trivia>,
+    pub trailing: Vec<Trivia>,
+}
+
+impl LosslessToken {
+    pub fn to_source(&self) -> String {
+        let mut source: String = self.leading.iter().map(|trivia| &*trivia.text).collect();
+        source.push_str(&self.text);
+        source.extend(self.trailing.iter().map(|trivia| &*trivia.text));
+        source
+    }
+}
+
+// The tokens of the source, EOF last, with every other character attached as trivia
+// Joining their `to_source` gives back the source exactly, even when it doesn't lex
+pub fn lossless_tokens(source: &str) -> Vec<LosslessToken> {
+    let mut lexer = Lexer::new(source.to_string());
+    lexer.scan();
+    let characters: Vec<char> = source.chars().collect();
+
+    // Lines and columns of trivia, the lexer's spans already have theirs
+    let line_starts: Vec<usize> = std::iter::once(0)
+        .chain(
+            characters
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| **c == '\n')
+                .map(|(offset, _)| offset + 1),
+        )
+        .collect();
+    let span = |start: usize, end: usize| {
+        let line = line_starts.partition_point(|line_start| *line_start <= start);
+        Span::new(start, end, line, start - line_starts[line - 1] + 1)
+    };
+    let text = |start: usize, end: usize| characters[start..end].iter().collect::<String>();
+
+    let shebang = lexer.shebang.as_ref().map(|shebang| {
+        let start = usize::from(characters.first() == Some(&'\u{feff}'));
+        (start, start + shebang.chars().count())
+    });
+
+    // The trivia between two offsets, in order
+    let trivia_between = |mut start: usize, end: usize| {
+        let mut trivia = Vec::new();
+        while start < end {
+            let comment = lexer
+                .comments
+                .iter()
+                .find(|comment| comment.span.start == start);
+            let (kind, length) = match (characters[start], comment) {
+                (_, Some(comment)) => (TriviaKind::Comment, comment.span.end - start),
+                _ if shebang.is_some_and(|(shebang_start, _)| shebang_start == start) => {
+                    (TriviaKind::Shebang, shebang.unwrap().1 - start)
+                }
+                ('\u{feff}', _) if start == 0 => (TriviaKind::ByteOrderMark, 1),
+                ('\n', _) => (TriviaKind::Newline, 1),
+                ('\r', _) if characters.get(start + 1) == Some(&'\n') => (TriviaKind::Newline, 2),
+                (c, _) => {
+                    let kind = match c {
+                        ' ' | '\t' | '\r' => TriviaKind::Whitespace,
+                        _ => TriviaKind::Skipped,
+                    };
+                    // A run of the same kind, up to the next line or comment
+                    let length = characters[start..end]
+                        .iter()
+                        .enumerate()
+                        .take_while(|(index, c)| {
+                            let same_kind = match kind {
+                                TriviaKind::Whitespace => matches!(c, ' ' | '\t' | '\r'),
+                                _ => !c.is_whitespace(),
+                            };
+                            same_kind
+                                && (*index == 0
+                                    || !lexer
+                                        .comments
+                                        .iter()
+                                        .any(|comment| comment.span.start == start + index))
+                        })
+                        .count();
+                    (kind, length)
+                }
+            };
+            let length = length.clamp(1, end - start);
+            trivia.push(Trivia {
+                kind,
+                text: text(start, start + length),
+                span: span(start, start + length),
+            });
+            start += length;
+        }
+        trivia
+    };
+
+    let mut tokens: Vec<LosslessToken> = Vec::with_capacity(lexer.tokens.len());
+    let mut offset = 0;
+    for token in lexer.tokens.iter() {
+        let mut leading = trivia_between(offset, token.span.start.max(offset));
+
+        // What follows the previous token on its line is its trailing trivia
+        if let Some(previous) = tokens.last_mut() {
+            let newline = leading
+                .iter()
+                .position(|trivia| trivia.kind == TriviaKind::Newline);
+            let trailing = match newline {
+                Some(newline) => newline + 1,
+                None => leading.len(),
+            };
+            previous.trailing = leading.drain(..trailing).collect();
+        }
+
+        let end = token.span.end.max(token.span.start.max(offset));
+        tokens.push(LosslessToken {
+            token: token.clone(),
+            text: text(token.span.start.max(offset), end),
+            leading,
+            trailing: Vec::new(),
+        });
+        offset = end;
+    }
+    tokens
+}
@@ -1,4 +1,32 @@
+#![allow(clippy::module_inception)]
+
+pub mod bytes;
+pub mod codec;
+pub mod coverage;
+pub mod diagnostics;
+pub mod equality;
 pub mod error;
+pub mod eventloop;
+pub mod files;
+mod fuzz;
+pub mod interpreter;
 pub mod lexer;
+pub mod linter;
+pub mod lsp;
+pub mod modules;
+pub mod mutability;
+pub mod number;
+pub mod optimizer;
 pub mod parser;
-pub mod interpreter;
\ No newline at end of file
+pub mod providers;
+pub mod repl;
+pub mod repr;
+pub mod resolver;
+pub mod shared;
+pub mod sockets;
+pub mod strings;
+pub mod threads;
+pub mod typechecker;
+pub mod vm;
+
+pub use fuzz::{fuzz_lex, fuzz_parse};
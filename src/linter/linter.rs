@@ -0,0 +1,475 @@
+use crate::{
+    error::{Warning, WarningKind},
+    lexer::token::*,
+    parser::{
+        expr::{Visitable as ExpressionVisitable, Visitor as ExpressionVisitor, *},
+        pattern::Arm,
+        stmt::{Visitable as StatementVisitable, Visitor as StatementVisitor, *},
+    },
+    shared::Ref,
+};
+
+// Static checks over a parsed program
+// Everything found is a Warning, the program is still valid
+//
+// - unused local variables and function parameters
+// - unreachable code after `return` or `throw`
+// - assignments used as conditions (`if (a = b)`)
+// - variables shadowing (or redeclaring) another variable
+pub struct Linter {
+    scopes: Vec<Vec<Binding>>,
+    warnings: Vec<Warning>,
+}
+
+#[derive(PartialEq)]
+enum BindingKind {
+    Variable,
+    Parameter,
+    Declaration, // functions and classes, never reported as unused
+}
+
+struct Binding {
+    name: Ref<str>,
+    span: Span,
+    kind: BindingKind,
+    used: bool,
+}
+
+impl Default for Linter {
+    fn default() -> Linter {
+        Linter::new()
+    }
+}
+
+impl Linter {
+    pub fn new() -> Linter {
+        Linter {
+            scopes: vec![Vec::new()], // Global scope
+            warnings: Vec::new(),
+        }
+    }
+
+    pub fn lint(&mut self, statements: &[Stmt]) -> Vec<Warning> {
+        for statement in statements.iter() {
+            statement.accept(self);
+        }
+        self.check_unreachable(statements.iter());
+
+        let mut warnings = std::mem::take(&mut self.warnings);
+        warnings.sort_by_key(|warning| warning.span.start);
+        warnings
+    }
+
+    fn warn(&mut self, kind: WarningKind, message: String, span: Span) {
+        self.warnings.push(Warning::new(kind, message, span));
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(Vec::new());
+    }
+
+    // Bindings of the scope that were never read are unused
+    // Globals are never reported, other scripts (or the REPL) may read them
+    fn end_scope(&mut self) {
+        let scope = self.scopes.pop().unwrap_or_default();
+        for binding in scope {
+            if binding.used || binding.name.starts_with('_') {
+                continue;
+            }
+
+            match binding.kind {
+                BindingKind::Variable => self.warn(
+                    WarningKind::UnusedVariable,
+                    format!("Unused variable `{}`", binding.name),
+                    binding.span,
+                ),
+                BindingKind::Parameter => self.warn(
+                    WarningKind::UnusedParameter,
+                    format!("Unused parameter `{}`", binding.name),
+                    binding.span,
+                ),
+                BindingKind::Declaration => {}
+            }
+        }
+    }
+
+    fn declare(&mut self, name: &Token, kind: BindingKind) {
+        let shadowed = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| {
+                scope
+                    .iter()
+                    .rev()
+                    .find(|binding| binding.name == name.lexeme)
+            })
+            .map(|binding| binding.span.line);
+
+        if let Some(line) = shadowed {
+            if kind != BindingKind::Declaration {
+                self.warn(
+                    WarningKind::Shadowing,
+                    format!(
+                        "`{}` shadows the variable declared on line {}",
+                        name.lexeme, line
+                    ),
+                    name.span,
+                );
+            }
+        }
+
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.push(Binding {
+                name: name.lexeme.clone(),
+                span: name.span,
+                kind,
+                used: false,
+            });
+        }
+    }
+
+    // Reading a variable marks its nearest declaration as used
+    fn mark_used(&mut self, name: &Token) {
+        for scope in self.scopes.iter_mut().rev() {
+            if let Some(binding) = scope
+                .iter_mut()
+                .rev()
+                .find(|binding| binding.name == name.lexeme)
+            {
+                binding.used = true;
+                return;
+            }
+        }
+    }
+
+    // if (a = b) is usually a typo of if (a == b)
+    // Wrapping the assignment in parentheses silences the warning
+    fn check_condition(&mut self, condition: &Expr) {
+        match condition {
+            Expr::Assign(..) | Expr::Set(..) => self.warn(
+                WarningKind::AssignmentCondition,
+                "Assignment used as a condition, did you mean `==`?".to_string(),
+                condition.span(),
+            ),
+            _ => {}
+        }
+    }
+
+    // Statements following a `return` or a `throw` in the same list never run
+    fn check_unreachable<'a>(&mut self, statements: impl Iterator<Item = &'a Stmt>) {
+        let mut statements = statements.peekable();
+        while let Some(statement) = statements.next() {
+            if let Stmt::Return(keyword, _) | Stmt::Throw(keyword, _) = statement {
+                if statements.peek().is_some() {
+                    self.warn(
+                        WarningKind::UnreachableCode,
+                        format!("Unreachable code after `{}`", keyword.lexeme),
+                        keyword.span,
+                    );
+                }
+                return;
+            }
+        }
+    }
+
+    fn lint_block(&mut self, statements: &[Stmt]) {
+        for statement in statements.iter() {
+            statement.accept(self);
+        }
+        self.check_unreachable(statements.iter());
+    }
+
+    fn lint_function(&mut self, parameters: &[Token], body: &[Stmt]) {
+        self.begin_scope();
+        for parameter in parameters {
+            self.declare(parameter, BindingKind::Parameter);
+        }
+        self.lint_block(body);
+        self.end_scope();
+    }
+}
+
+impl ExpressionVisitor<()> for Linter {
+    fn visit_binary(&mut self, left_expr: &Expr, _operator: &Token, right_expr: &Expr) {
+        left_expr.accept(self);
+        right_expr.accept(self);
+    }
+
+    fn visit_call(&mut self, callee: &Expr, _closing_paren: &Token, arguments: &[Expr]) {
+        callee.accept(self);
+        for argument in arguments.iter() {
+            argument.accept(self);
+        }
+    }
+
+    fn visit_grouping(&mut self, grouping_expr: &Expr) {
+        grouping_expr.accept(self);
+    }
+
+    fn visit_unary(&mut self, _operator: &Token, unary_expr: &Expr) {
+        unary_expr.accept(self);
+    }
+
+    fn visit_spawn(&mut self, _keyword: &Token, call: &Expr) {
+        call.accept(self);
+    }
+
+    fn visit_await(&mut self, _keyword: &Token, promise: &Expr) {
+        promise.accept(self);
+    }
+
+    fn visit_literal(&mut self, _lit: &Token) {}
+
+    fn visit_logical(&mut self, left_expr: &Expr, _logical_and_or: &Token, right_expr: &Expr) {
+        left_expr.accept(self);
+        right_expr.accept(self);
+    }
+
+    fn visit_variable(&mut self, variable: &Token, _location: Option<Location>) {
+        self.mark_used(variable);
+    }
+
+    fn visit_this(&mut self, _keyword: &Token, _location: Option<Location>) {}
+
+    fn visit_super(
+        &mut self,
+        _keyword: &Token,
+        _method: &Token,
+        _superclass: Option<Location>,
+        _this: Option<Location>,
+    ) {
+    }
+
+    // Assigning to a variable is not reading it
+    fn visit_assign(&mut self, _variable: &Token, expr: &Expr, _location: Option<Location>) {
+        expr.accept(self);
+    }
+
+    fn visit_get(&mut self, expr: &Expr, _name: &Token) {
+        expr.accept(self);
+    }
+
+    fn visit_optional_get(&mut self, expr: &Expr, _name: &Token) {
+        expr.accept(self);
+    }
+
+    fn visit_index(&mut self, object: &Expr, index: &Expr, _close: &Token) {
+        object.accept(self);
+        index.accept(self);
+    }
+
+    fn visit_set(&mut self, expr: &Expr, _name: &Token, value: &Expr) {
+        expr.accept(self);
+        value.accept(self);
+    }
+
+    fn visit_tuple(&mut self, values: &[Expr]) {
+        for value in values.iter() {
+            value.accept(self);
+        }
+    }
+
+    fn visit_list(&mut self, elements: &[Expr]) {
+        for element in elements.iter() {
+            element.accept(self);
+        }
+    }
+
+    fn visit_set_literal(&mut self, elements: &[Expr]) {
+        for element in elements.iter() {
+            element.accept(self);
+        }
+    }
+
+    fn visit_map(&mut self, entries: &[(Expr, Expr)]) {
+        for (key, value) in entries.iter() {
+            key.accept(self);
+            value.accept(self);
+        }
+    }
+
+    // The names an arm binds are its own variables, `_` ones are not reported
+    fn visit_match(&mut self, _keyword: &Token, value: &Expr, arms: &[Arm]) {
+        value.accept(self);
+        for arm in arms.iter() {
+            for class in arm.pattern.classes() {
+                class.accept(self);
+            }
+            self.begin_scope();
+            for name in arm.pattern.bindings() {
+                self.declare(name, BindingKind::Variable);
+            }
+            arm.body.accept(self);
+            self.end_scope();
+        }
+    }
+}
+
+impl StatementVisitor<()> for Linter {
+    fn visit_block(&mut self, statements: &[Stmt]) {
+        self.begin_scope();
+        self.lint_block(statements);
+        self.end_scope();
+    }
+
+    fn visit_class(
+        &mut self,
+        name: &Token,
+        superclass: Option<&Expr>,
+        mixins: &[Expr],
+        statements: &[Stmt],
+        _sealed: bool,
+    ) {
+        self.declare(name, BindingKind::Declaration);
+        if let Some(superclass) = superclass {
+            superclass.accept(self);
+        }
+        for mixin in mixins.iter() {
+            mixin.accept(self);
+        }
+
+        for method in statements.iter() {
+            if let Stmt::Function(_, parameters, body, ..) = method {
+                self.lint_function(parameters, body);
+            }
+        }
+    }
+
+    fn visit_expression(&mut self, expr: &Expr) {
+        expr.accept(self);
+    }
+
+    fn visit_forloop(
+        &mut self,
+        initializer: Option<&Stmt>,
+        condition: Option<&Expr>,
+        incrementer: Option<&Expr>,
+        body: &Stmt,
+    ) {
+        self.begin_scope();
+        if let Some(initializer) = initializer {
+            initializer.accept(self);
+        }
+        if let Some(condition) = condition {
+            self.check_condition(condition);
+            condition.accept(self);
+        }
+        if let Some(incrementer) = incrementer {
+            incrementer.accept(self);
+        }
+        body.accept(self);
+        self.end_scope();
+    }
+
+    // The variable takes every element in turn, `_` ones are not reported
+    fn visit_forin(&mut self, name: &Token, iterable: &Expr, body: &Stmt) {
+        iterable.accept(self);
+        self.begin_scope();
+        self.declare(name, BindingKind::Variable);
+        body.accept(self);
+        self.end_scope();
+    }
+
+    fn visit_ifelse(&mut self, condition: &Expr, then_branch: &Stmt, else_branch: Option<&Stmt>) {
+        self.check_condition(condition);
+        condition.accept(self);
+        then_branch.accept(self);
+        if let Some(else_branch) = else_branch {
+            else_branch.accept(self);
+        }
+    }
+
+    fn visit_import(&mut self, _keyword: &Token, name: &Token) {
+        self.declare(name, BindingKind::Variable);
+    }
+
+    fn visit_print(&mut self, expr: &Expr) {
+        expr.accept(self);
+    }
+
+    fn visit_return(&mut self, _token: &Token, expr: Option<&Expr>) {
+        if let Some(expr) = expr {
+            expr.accept(self);
+        }
+    }
+
+    fn visit_throw(&mut self, _keyword: &Token, value: &Expr) {
+        value.accept(self);
+    }
+
+    fn visit_try(&mut self, body: &[Stmt], catches: &[Catch]) {
+        self.visit_block(body);
+        for catch in catches.iter() {
+            if let Some(class) = &catch.class {
+                class.accept(self);
+            }
+            self.begin_scope();
+            self.declare(&catch.name, BindingKind::Variable);
+            self.lint_block(&catch.body);
+            self.end_scope();
+        }
+    }
+
+    fn visit_yield(&mut self, _keyword: &Token, expr: Option<&Expr>, variable: &Option<Token>) {
+        if let Some(expr) = expr {
+            expr.accept(self);
+        }
+        if let Some(variable) = variable {
+            self.declare(variable, BindingKind::Variable);
+        }
+    }
+
+    // What failed to parse may still read variables, they aren't reported as unused
+    fn visit_error(&mut self, tokens: &[Token]) {
+        for token in tokens.iter() {
+            if token.token_type == TokenType::Identifier {
+                self.mark_used(token);
+            }
+        }
+    }
+
+    // The initializer is linted before the variable exists
+    fn visit_var(&mut self, token: &Token, _annotation: &Option<Token>, expr: Option<&Expr>) {
+        if let Some(initializer) = expr {
+            initializer.accept(self);
+        }
+        self.declare(token, BindingKind::Variable);
+    }
+
+    // Values not needed can go to names starting with `_`, `var _, y = f();`
+    fn visit_unpack(&mut self, names: &[Token], expr: Option<&Expr>) {
+        if let Some(initializer) = expr {
+            initializer.accept(self);
+        }
+        for name in names.iter() {
+            self.declare(name, BindingKind::Variable);
+        }
+    }
+
+    fn visit_whileloop(&mut self, condition: &Expr, statement: &Stmt) {
+        self.check_condition(condition);
+        condition.accept(self);
+        statement.accept(self);
+    }
+
+    fn visit_with(&mut self, _keyword: &Token, resource: &Expr, name: &Token, body: &[Stmt]) {
+        resource.accept(self);
+        self.begin_scope();
+        self.declare(name, BindingKind::Variable);
+        self.lint_block(body);
+        self.end_scope();
+    }
+
+    fn visit_function(
+        &mut self,
+        name: &Token,
+        parameters: &[Token],
+        body: &[Stmt],
+        _signature: &Signature,
+        _captures: &[Slot],
+    ) {
+        self.declare(name, BindingKind::Declaration);
+        self.lint_function(parameters, body);
+    }
+}
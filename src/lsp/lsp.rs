@@ -0,0 +1,428 @@
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+};
+
+use serde_json::{json, Value};
+
+use crate::{
+    error::{Severity, Warning},
+    lexer::{
+        highlight::{semantic_tokens, SemanticKind, SemanticToken},
+        token::Span,
+    },
+    linter::linter::Linter,
+    parser::incremental::Document,
+    resolver::{resolver::Resolver, symbols::SymbolTable},
+};
+
+// JSON-RPC error codes of the protocol
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+
+// The semantic token types the server sends, their index is what goes on the wire
+// Punctuation is left to the editor's own syntax highlighting
+const TOKEN_TYPES: [&str; 6] = [
+    "keyword", "string", "number", "variable", "comment", "operator",
+];
+
+// A Language Server Protocol server, `lost lsp`, talking JSON-RPC over stdin and stdout
+//
+// Documents are synced incrementally and kept parsed, see parser::incremental,
+// a change only parses again the statements it touches. Resolving and linting
+// run on the whole program again, on every change and request
+//
+// - diagnostics: the errors of the lexer and parser, the warnings of the
+//   parser, the resolver and the linter
+// - go-to-definition and hover: from the SymbolTable of the Resolver
+// - semantic tokens: from lexer::highlight
+pub struct Server {
+    documents: HashMap<String, Document>, // The open documents, by URI
+    shutdown: bool,                       // Asked to shut down, `exit` is next
+}
+
+// What an analysis of a document found
+struct Analysis {
+    source: Vec<char>, // Spans are character offsets into this
+    diagnostics: Vec<Value>,
+    symbols: SymbolTable,
+}
+
+impl Default for Server {
+    fn default() -> Server {
+        Server::new()
+    }
+}
+
+impl Server {
+    pub fn new() -> Server {
+        Server {
+            documents: HashMap::new(),
+            shutdown: false,
+        }
+    }
+
+    // Serves until the client sends `exit`, returns the exit code the protocol asks for:
+    // 0 after a `shutdown` request, 1 otherwise or when the input ends first
+    pub fn run(&mut self, input: &mut impl BufRead, output: &mut impl Write) -> io::Result<i32> {
+        while let Some(body) = read_message(input)? {
+            let message: Value = match serde_json::from_slice(&body) {
+                Ok(message) => message,
+                Err(error) => {
+                    let response = error_response(Value::Null, PARSE_ERROR, &error.to_string());
+                    write_message(output, &response)?;
+                    continue;
+                }
+            };
+
+            let method = message["method"].as_str().unwrap_or_default();
+            let params = &message["params"];
+            let id = message.get("id").cloned();
+
+            let result = match method {
+                "initialize" => Some(json!({
+                    "capabilities": {
+                        "textDocumentSync": 2, // Incremental
+                        "definitionProvider": true,
+                        "hoverProvider": true,
+                        "semanticTokensProvider": {
+                            "legend": {"tokenTypes": TOKEN_TYPES, "tokenModifiers": []},
+                            "full": true,
+                        },
+                    },
+                    "serverInfo": {
+                        "name": "lost",
+                        "version": env!("CARGO_PKG_VERSION"),
+                    },
+                })),
+                "shutdown" => {
+                    self.shutdown = true;
+                    Some(Value::Null)
+                }
+                "exit" => return Ok(if self.shutdown { 0 } else { 1 }),
+                "textDocument/didOpen" => {
+                    let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+                    let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                    self.documents.insert(uri.to_string(), Document::new(text));
+                    self.publish_diagnostics(output, uri)?;
+                    None
+                }
+                "textDocument/didChange" => {
+                    let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+                    if let Some(document) = self.documents.get_mut(uri) {
+                        let changes = params["contentChanges"].as_array();
+                        for change in changes.into_iter().flatten() {
+                            apply_change(document, change);
+                        }
+                    }
+                    self.publish_diagnostics(output, uri)?;
+                    None
+                }
+                "textDocument/didClose" => {
+                    let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+                    self.documents.remove(uri);
+                    self.publish_diagnostics(output, uri)?;
+                    None
+                }
+                "textDocument/definition" => Some(self.definition(params)),
+                "textDocument/hover" => Some(self.hover(params)),
+                "textDocument/semanticTokens/full" => Some(self.semantic_tokens(params)),
+                _ => None,
+            };
+
+            // Notifications have no id and get no response, even when unknown
+            if let Some(id) = id {
+                let response = match result {
+                    Some(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+                    None => {
+                        error_response(id, METHOD_NOT_FOUND, &format!("Unknown method `{method}`"))
+                    }
+                };
+                write_message(output, &response)?;
+            }
+        }
+        Ok(1)
+    }
+
+    // Closed documents get an empty list, which clears what was shown
+    fn publish_diagnostics(&self, output: &mut impl Write, uri: &str) -> io::Result<()> {
+        let diagnostics = match self.documents.get(uri) {
+            Some(document) => analyze(document).diagnostics,
+            None => Vec::new(),
+        };
+        let notification = json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": {"uri": uri, "diagnostics": diagnostics},
+        });
+        write_message(output, &notification)
+    }
+
+    // The document and the symbol at the position of a request
+    fn symbol_at(&self, params: &Value) -> Option<(Analysis, usize)> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        let analysis = analyze(self.documents.get(uri)?);
+        let offset = offset(
+            &analysis.source,
+            params["position"]["line"].as_u64()?,
+            params["position"]["character"].as_u64()?,
+        );
+        let symbol = analysis.symbols.at(offset)?;
+        Some((analysis, symbol))
+    }
+
+    fn definition(&self, params: &Value) -> Value {
+        match self.symbol_at(params) {
+            Some((analysis, symbol)) => json!({
+                "uri": params["textDocument"]["uri"],
+                "range": range(&analysis.source, analysis.symbols.symbols[symbol].span),
+            }),
+            None => Value::Null,
+        }
+    }
+
+    // The declaration of the symbol, and what kind of symbol it is
+    fn hover(&self, params: &Value) -> Value {
+        match self.symbol_at(params) {
+            Some((analysis, symbol)) => {
+                let symbol = &analysis.symbols.symbols[symbol];
+                json!({
+                    "contents": {
+                        "kind": "markdown",
+                        "value": format!(
+                            "```lost\n{}\n```\n{} declared on line {}",
+                            symbol.detail,
+                            symbol.kind.name(),
+                            symbol.span.line
+                        ),
+                    },
+                })
+            }
+            None => Value::Null,
+        }
+    }
+
+    fn semantic_tokens(&self, params: &Value) -> Value {
+        let Some(document) = params["textDocument"]["uri"]
+            .as_str()
+            .and_then(|uri| self.documents.get(uri))
+        else {
+            return Value::Null;
+        };
+        let text: String = document.source().iter().collect();
+        json!({"data": encode_semantic_tokens(document.source(), &semantic_tokens(&text))})
+    }
+}
+
+fn token_type(kind: SemanticKind) -> Option<usize> {
+    let name = match kind {
+        SemanticKind::Identifier => "variable",
+        SemanticKind::Punctuation => return None,
+        kind => kind.name(),
+    };
+    TOKEN_TYPES
+        .iter()
+        .position(|token_type| *token_type == name)
+}
+
+// Five numbers per token: line and start relative to the previous token, length,
+// type and modifiers. Tokens spanning lines, like strings, are sent line by line
+fn encode_semantic_tokens(source: &[char], tokens: &[SemanticToken]) -> Vec<usize> {
+    let mut data = Vec::new();
+    let mut previous = (0, 0);
+    let mut push = |(line, character): (usize, usize), length: usize, token_type: usize| {
+        if length == 0 {
+            return;
+        }
+        let delta_character = match line == previous.0 {
+            true => character - previous.1,
+            false => character,
+        };
+        data.extend([line - previous.0, delta_character, length, token_type, 0]);
+        previous = (line, character);
+    };
+
+    // Line and UTF-16 character of `offset`, moving forward with the tokens
+    let (mut offset, mut position) = (0, (0, 0));
+    let step = |c: char, (line, character): (usize, usize)| match c {
+        '\n' => (line + 1, 0),
+        c => (line, character + c.len_utf16()),
+    };
+
+    for token in tokens {
+        let Some(token_type) = token_type(token.kind) else {
+            continue;
+        };
+        let end = token.span.end.min(source.len());
+        while offset < token.span.start.min(end) {
+            position = step(source[offset], position);
+            offset += 1;
+        }
+
+        let (mut start, mut length) = (position, 0);
+        while offset < end {
+            let c = source[offset];
+            position = step(c, position);
+            offset += 1;
+            if c == '\n' {
+                push(start, length, token_type);
+                (start, length) = (position, 0);
+            } else {
+                length += c.len_utf16();
+            }
+        }
+        push(start, length, token_type);
+    }
+    data
+}
+
+// A change replaces a range of the document, or the whole text when it has none
+fn apply_change(document: &mut Document, change: &Value) {
+    let text = change["text"].as_str().unwrap_or_default();
+    let range = &change["range"];
+    if range.is_null() {
+        *document = Document::new(text);
+        return;
+    }
+    let position = |position: &Value| {
+        offset(
+            document.source(),
+            position["line"].as_u64().unwrap_or_default(),
+            position["character"].as_u64().unwrap_or_default(),
+        )
+    };
+    let (start, end) = (position(&range["start"]), position(&range["end"]));
+    document.edit(start, end, text);
+}
+
+// Everything the command line would report, as LSP diagnostics, and the symbols
+fn analyze(document: &Document) -> Analysis {
+    let source = document.source().to_vec();
+    let mut diagnostics: Vec<Value> = document
+        .errors()
+        .map(|error| {
+            diagnostic(
+                &source,
+                Severity::Error,
+                error.code(),
+                &error.message(),
+                error.span(),
+            )
+        })
+        .collect();
+
+    // The parser recovers from errors, what it did parse is still checked
+    let mut warnings: Vec<Warning> = document.warnings().cloned().collect();
+    let mut statements = document.statements();
+    let mut resolver = Resolver::with_symbols();
+    warnings.extend(resolver.resolve(&mut statements));
+    warnings.extend(Linter::new().lint(&statements));
+    diagnostics.extend(warnings.iter().map(|warning| {
+        diagnostic(
+            &source,
+            Severity::Warning,
+            warning.code(),
+            &warning.message,
+            warning.span,
+        )
+    }));
+
+    Analysis {
+        source,
+        diagnostics,
+        symbols: resolver.take_symbols(),
+    }
+}
+
+fn diagnostic(source: &[char], severity: Severity, code: &str, message: &str, span: Span) -> Value {
+    json!({
+        "range": range(source, span),
+        "severity": match severity {
+            Severity::Error => 1,
+            Severity::Warning => 2,
+        },
+        "code": code,
+        "source": "lost",
+        "message": message,
+    })
+}
+
+fn range(source: &[char], span: Span) -> Value {
+    json!({
+        "start": position(source, span.start),
+        "end": position(source, span.end.max(span.start)),
+    })
+}
+
+// LSP positions count lines from 0 and characters in UTF-16 code units
+fn position(source: &[char], offset: usize) -> Value {
+    let (mut line, mut character) = (0, 0);
+    for c in source.iter().take(offset) {
+        if *c == '\n' {
+            line += 1;
+            character = 0;
+        } else {
+            character += c.len_utf16();
+        }
+    }
+    json!({"line": line, "character": character})
+}
+
+// The character offset of an LSP position, positions past the end of a line are at its end
+fn offset(source: &[char], line: u64, character: u64) -> usize {
+    let (mut current_line, mut current_character) = (0, 0);
+    for (offset, c) in source.iter().enumerate() {
+        if current_line == line && (current_character >= character || *c == '\n') {
+            return offset;
+        }
+        if *c == '\n' {
+            current_line += 1;
+        } else if current_line == line {
+            current_character += c.len_utf16() as u64;
+        }
+    }
+    source.len()
+}
+
+fn error_response(id: Value, code: i64, message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": {"code": code, "message": message},
+    })
+}
+
+// Messages are a `Content-Length` header, an empty line and that many bytes of JSON
+// None once the input is closed
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Vec<u8>>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+
+    let Some(length) = length else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Message without a Content-Length header",
+        ));
+    };
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(body))
+}
+
+fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
@@ -1,84 +1,1009 @@
 use std::{
+    collections::HashMap,
     env, fs,
-    io::{self, Write},
+    io::{self, IsTerminal},
+    path::{Path, PathBuf},
+    process,
+    rc::Rc,
+    sync::OnceLock,
+    thread,
+    time::{Duration, SystemTime},
 };
 
 use interpreter::Interpreter;
 use lost::{
+    coverage::coverage::Coverage,
+    diagnostics::diagnostics::{Renderer, Style},
+    error::{Error, Warning},
     interpreter::*,
-    lexer::lexer::*,
-    parser::{astprinter::AstPrinter, parser::*},
+    lexer::{
+        lexer::*,
+        token::{LiteralType, TokenType},
+    },
+    linter::linter::Linter,
+    lsp::lsp::Server,
+    modules,
+    optimizer::optimizer::Optimizer,
+    parser::{astprinter::AstPrinter, formatter::Formatter, parser::*, stmt::Stmt},
+    providers::providers::{Deterministic, Providers, System},
+    repl::repl::ReplHelper,
+    resolver::resolver::Resolver,
+    shared::{Output, Ref},
+    typechecker::typechecker::TypeChecker,
+    vm::{
+        compiler::Compiler,
+        serialize,
+        value::{FunctionProto, Value},
+        vm::VM,
+    },
 };
+use rustyline::{error::ReadlineError, history::DefaultHistory, Editor};
+use types::Type;
 
+// Exit codes, from sysexits.h like other Lox implementations
+const EXIT_USAGE: i32 = 64;
+const EXIT_DATA: i32 = 65; // The code doesn't lex, parse, type check or compile
+const EXIT_NO_INPUT: i32 = 66; // The script can't be read
+const EXIT_SOFTWARE: i32 = 70; // The program failed at runtime
+const EXIT_CANT_CREATE: i32 = 73; // An output file can't be created
+
+// Flags that apply to every command
+#[derive(Clone, Copy)]
+struct Options {
+    optimize: bool,      // Unless debugging the unoptimized program, --no-optimize
+    deny_warnings: bool, // Warnings stop the program like errors, --deny-warnings
+    json: bool,          // Diagnostics as JSON lines, --diagnostics=json
+    trace: bool,         // Log the executed statements, --trace or --trace=<file>
+    coverage: bool,      // Report the lines that ran, --coverage or --coverage=<lcov file>
+    deterministic: bool, // Same time and random numbers every run, --deterministic[=<seed>]
+}
+
+// Everything after the script on the command line, for `arg_count()` and `arg(index)`
+static SCRIPT_ARGUMENTS: OnceLock<Vec<String>> = OnceLock::new();
+
+// The file of --trace=<file>, the trace goes to stderr without one
+static TRACE_FILE: OnceLock<String> = OnceLock::new();
+
+// The file of --coverage=<file>, written in the LCOV format after the run
+static COVERAGE_FILE: OnceLock<String> = OnceLock::new();
+
+// Set by --watch for the runs it starts, the file they list their modules' files in
+const WATCH_RECORD: &str = "LOST_WATCH_RECORD";
+
+// The seed of --deterministic=<seed>, 0 without one
+static SEED: OnceLock<u64> = OnceLock::new();
+
+// The interpreter recurses on the stack, so lost runs on a thread with enough of it
 fn main() {
-    let argv: Vec<String> = env::args().collect();
+    let lost = thread::Builder::new()
+        .stack_size(interpreter::STACK_SIZE)
+        .spawn(lost);
+    if !matches!(lost.map(|lost| lost.join()), Ok(Ok(()))) {
+        process::exit(EXIT_SOFTWARE);
+    }
+}
+
+fn lost() {
+    let (mut argv, script_arguments) = split_script_arguments(env::args().collect());
+    SCRIPT_ARGUMENTS.get_or_init(|| script_arguments);
+
+    let mut options = Options {
+        optimize: !argv.iter().any(|arg| arg == "--no-optimize"),
+        deny_warnings: argv.iter().any(|arg| arg == "--deny-warnings"),
+        json: false,
+        trace: false,
+        coverage: false,
+        deterministic: false,
+    };
+    argv.retain(|arg| arg != "--no-optimize" && arg != "--deny-warnings");
+
+    // --trace logs to stderr, --trace=<file> to the file
+    for arg in argv.iter() {
+        if arg == "--trace" {
+            options.trace = true;
+        } else if let Some(file) = arg.strip_prefix("--trace=") {
+            options.trace = true;
+            let _ = TRACE_FILE.set(file.to_string());
+        }
+    }
+    argv.retain(|arg| arg != "--trace" && !arg.starts_with("--trace="));
+
+    // --coverage prints a summary, --coverage=<file> also writes an LCOV file
+    for arg in argv.iter() {
+        if arg == "--coverage" {
+            options.coverage = true;
+        } else if let Some(file) = arg.strip_prefix("--coverage=") {
+            options.coverage = true;
+            let _ = COVERAGE_FILE.set(file.to_string());
+        }
+    }
+    argv.retain(|arg| arg != "--coverage" && !arg.starts_with("--coverage="));
+
+    // --deterministic makes `clock()`, `now()` and `random()` the same every run,
+    // --deterministic=<seed> picks other random numbers
+    for arg in argv.iter() {
+        if arg == "--deterministic" {
+            options.deterministic = true;
+        } else if let Some(seed) = arg.strip_prefix("--deterministic=") {
+            options.deterministic = true;
+            match seed.parse() {
+                Ok(seed) => {
+                    let _ = SEED.set(seed);
+                }
+                Err(_) => {
+                    eprintln!("The seed `{seed}` is not a whole number");
+                    process::exit(EXIT_USAGE);
+                }
+            }
+        }
+    }
+    argv.retain(|arg| arg != "--deterministic" && !arg.starts_with("--deterministic="));
+
+    // --diagnostics=human (the default) or --diagnostics=json
+    for arg in argv.iter().filter(|arg| arg.starts_with("--diagnostics=")) {
+        match &arg["--diagnostics=".len()..] {
+            "human" => options.json = false,
+            "json" => options.json = true,
+            format => {
+                eprintln!("Unknown diagnostics format `{format}`, expected `human` or `json`");
+                process::exit(EXIT_USAGE);
+            }
+        }
+    }
+    argv.retain(|arg| !arg.starts_with("--diagnostics="));
+
+    if let Some(position) = argv.iter().position(|arg| arg == "--watch") {
+        argv.remove(position);
+        match watched_script(&argv) {
+            Some(script) => watch(script),
+            None => {
+                eprintln!("--watch needs a script to run");
+                process::exit(EXIT_USAGE);
+            }
+        }
+    }
+
+    if argv.len() > 1 && argv[1] == "fmt" {
+        format_files(&argv[2..], options);
+        return;
+    }
+    if argv.len() > 1 && argv[1] == "compile" {
+        compile_file(&argv[2..], options);
+        return;
+    }
+    if argv.len() == 2 && argv[1] == "lsp" {
+        serve_lsp();
+    }
+    if argv.len() > 2 && argv[1] == "--check" {
+        check_files(&argv[2..], options);
+        return;
+    }
+    if argv.len() == 3 && argv[1] == "run" {
+        run_any(&argv[2], options);
+        return;
+    }
 
     match argv.len() {
         1 => {
             // Run Repl
             // > ...
-            run_prompt();
+            run_prompt(options);
         }
-        2 => run_file(&argv[1]),
+        2 => run_file(&argv[1], options),
+        3 if argv[1] == "--tokens" => print_tokens(&argv[2], options),
+        3 if argv[1] == "--ast" => print_ast(&argv[2], options),
+        3 if argv[1] == "--lint" => lint_file(&argv[2], options),
+        3 if argv[1] == "--check-types" => check_types(&argv[2], options),
+        3 if argv[1] == "--vm" => run_file_vm(&argv[2], options),
+        3 if argv[1] == "-e" || argv[1] == "--eval" => run("<eval>", argv[2].clone(), options),
         _ => {
-            eprintln!("Usage: {} [script]", argv[0]);
+            eprintln!(
+                "Usage: {} [--tokens | --ast | --lint | --check-types | --vm] [--no-optimize] [--deny-warnings] [--diagnostics=human|json] [--trace[=<file>]] [--coverage[=<file>]] [--deterministic[=<seed>]] [script [arguments...]]",
+                argv[0]
+            );
+            eprintln!("       {} -e | --eval <code> [arguments...]", argv[0]);
+            eprintln!("       {} --check <script>...", argv[0]);
+            eprintln!("       {} lsp", argv[0]);
+            eprintln!("       {} --watch [flags] <script> [arguments...]", argv[0]);
+            eprintln!("       {} fmt [--check] <script>...", argv[0]);
+            eprintln!("       {} compile <script> [-o <output>]", argv[0]);
+            eprintln!(
+                "       {} run <script | compiled script> [arguments...]",
+                argv[0]
+            );
+            process::exit(EXIT_USAGE);
         }
     }
 }
 
-fn run_file(filepath: &String) {
+// `lost [flags] script arguments...`, flags after the script are its own
+// The code of -e and the script of `run` end the flags the same way
+// `fmt`, `compile` and --check take several files and run nothing
+fn split_script_arguments(mut argv: Vec<String>) -> (Vec<String>, Vec<String>) {
+    let mut flags = argv.iter().skip(1).take_while(|arg| arg.starts_with('-'));
+    let command = argv.iter().skip(1).find(|arg| !arg.starts_with('-'));
+    if flags.any(|flag| flag == "--check")
+        || matches!(command.map(String::as_str), Some("fmt" | "compile"))
+    {
+        return (argv, Vec::new());
+    }
+
+    let script = (1..argv.len()).find(|&i| {
+        matches!(argv[i - 1].as_str(), "-e" | "--eval")
+            || (!argv[i].starts_with('-') && argv[i] != "run")
+    });
+    match script {
+        Some(script) => {
+            let arguments = argv.split_off(script + 1);
+            (argv, arguments)
+        }
+        None => (argv, Vec::new()),
+    }
+}
+
+fn script_arguments() -> &'static [String] {
+    SCRIPT_ARGUMENTS.get().map_or(&[], Vec::as_slice)
+}
+
+// The script of a command that runs one, not of fmt, compile or --check and not code of -e
+fn watched_script(argv: &[String]) -> Option<&String> {
+    if argv.len() < 2 || matches!(argv[1].as_str(), "fmt" | "compile" | "--check") {
+        return None;
+    }
+    let script = argv.last()?;
+    let before = &argv[argv.len() - 2];
+    if script.starts_with('-') || before == "-e" || before == "--eval" {
+        return None;
+    }
+    Some(script)
+}
+
+// lost --watch [flags] <script> [arguments...]
+// Runs the same command in a child process, again whenever the script or a module
+// file it imported changes on disk
+// A fresh process per run leaves no state behind, and a run still going is stopped
+fn watch(script: &String) -> ! {
+    let mut command: Vec<String> = env::args().skip(1).collect();
+    if let Some(position) = command.iter().position(|arg| arg == "--watch") {
+        command.remove(position);
+    }
+    let Ok(executable) = env::current_exe() else {
+        eprintln!("Can't find the lost executable to run the script with");
+        process::exit(EXIT_SOFTWARE);
+    };
+
+    // The run lists the files of the modules it imports in `record`, they are watched too
+    let record = env::temp_dir().join(format!("lost-watch-{}", process::id()));
+    let watched = || -> Vec<PathBuf> {
+        let modules = fs::read_to_string(&record).unwrap_or_default();
+        let mut files = vec![PathBuf::from(script)];
+        files.extend(modules.lines().map(PathBuf::from));
+        files
+    };
+    let modified = |file: &Path| -> Option<SystemTime> { fs::metadata(file).ok()?.modified().ok() };
+    let spawn = || {
+        let _ = fs::write(&record, "");
+        let child = process::Command::new(&executable)
+            .args(&command)
+            .env(WATCH_RECORD, &record)
+            .spawn();
+        match child {
+            Ok(child) => Some(child),
+            Err(error) => {
+                eprintln!("[watch] Can't run `{script}`: {error}");
+                None
+            }
+        }
+    };
+
+    // A file first seen is only remembered, only a later change runs again
+    let mut last_modified: HashMap<PathBuf, Option<SystemTime>> = HashMap::new();
+    last_modified.insert(PathBuf::from(script), modified(Path::new(script)));
+    let mut child = spawn();
+    loop {
+        thread::sleep(Duration::from_millis(200));
+
+        if let Some(running) = child.as_mut() {
+            if let Ok(Some(status)) = running.try_wait() {
+                match status.code() {
+                    Some(0) => eprintln!("[watch] Done, waiting for changes"),
+                    Some(code) => eprintln!("[watch] Exited with {code}, waiting for changes"),
+                    None => eprintln!("[watch] Stopped, waiting for changes"),
+                }
+                child = None;
+            }
+        }
+
+        let mut changed = None;
+        for file in watched() {
+            let now_modified = modified(&file);
+            match last_modified.insert(file.clone(), now_modified) {
+                Some(before) if before != now_modified => changed = changed.or(Some(file)),
+                _ => {}
+            }
+        }
+        if let Some(file) = changed {
+            if let Some(mut running) = child.take() {
+                let _ = running.kill();
+                let _ = running.wait();
+            }
+            eprintln!("[watch] `{}` changed, running again", file.display());
+            child = spawn();
+        }
+    }
+}
+
+// The caller decides how to fail, usually with EXIT_NO_INPUT
+fn read_source(filepath: &String) -> Option<String> {
+    let bytes = match fs::read(filepath) {
+        Ok(bytes) => bytes,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {
+            eprintln!("`{filepath}` does not exist");
+            return None;
+        }
+        Err(error) => {
+            eprintln!("Could not read `{filepath}`: {error}");
+            return None;
+        }
+    };
+    match String::from_utf8(bytes) {
+        Ok(source) => Some(source),
+        Err(error) => {
+            // Point at the first byte that isn't UTF-8
+            let valid = &error.as_bytes()[..error.utf8_error().valid_up_to()];
+            let line = valid.iter().filter(|&&byte| byte == b'\n').count() + 1;
+            let line_start = valid
+                .iter()
+                .rposition(|&byte| byte == b'\n')
+                .map_or(0, |i| i + 1);
+            let column = String::from_utf8_lossy(&valid[line_start..])
+                .chars()
+                .count()
+                + 1;
+            eprintln!("Could not read `{filepath}`: invalid UTF-8 at line {line}, column {column}");
+            None
+        }
+    }
+}
+
+fn run_file(filepath: &String, options: Options) {
     // Get the source code from the file
-    let source_code = match fs::read_to_string(filepath) {
-        Ok(file) => file,
+    let Some(source_code) = read_source(filepath) else {
+        process::exit(EXIT_NO_INPUT);
+    };
+
+    // Start interpreting
+    run(filepath, source_code, options)
+}
+
+// Errors are shown with the code they point at, colored when stderr is a terminal
+fn renderer<'a>(name: &'a str, source_code: &'a str, options: Options) -> Renderer<'a> {
+    let style = if options.json {
+        Style::Json
+    } else if io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none() {
+        Style::Color
+    } else {
+        Style::Plain
+    };
+    Renderer::new(name, source_code, style)
+}
+
+// Every error of a phase at once, in the order they appear in the source
+fn report_errors<'e>(renderer: &Renderer, errors: impl IntoIterator<Item = &'e Error>) {
+    let mut errors: Vec<&Error> = errors.into_iter().collect();
+    errors.sort_by_key(|error| error.span().start);
+    for error in errors.iter() {
+        renderer.report(error)
+    }
+    renderer.report_summary(errors.len(), 0);
+}
+
+// Warnings don't stop the program, unless they are denied
+// Denied warnings exit with the same code as type errors
+fn report_warnings(renderer: &Renderer, mut warnings: Vec<Warning>, options: Options) {
+    warnings.sort_by_key(|warning| warning.span.start);
+    for warning in warnings.iter() {
+        renderer.report_warning(warning)
+    }
+    renderer.report_summary(0, warnings.len());
+
+    if options.deny_warnings && !warnings.is_empty() {
+        if !options.json {
+            eprintln!("Stopping, warnings are denied by --deny-warnings");
+        }
+        process::exit(EXIT_DATA);
+    }
+}
+
+// Lex and parse the source, reporting the errors if it is not a valid program
+// The parser still runs after lexing errors, the characters in error are skipped
+fn parse_source(filepath: &str, source_code: &str, options: Options) -> Option<Vec<Stmt>> {
+    let (statements, valid) = parse_partial(filepath, source_code, options);
+    valid.then_some(statements)
+}
+
+// Same, but the statements of a program with errors are kept too, the ones that
+// failed to parse as Error statements, for the tools that show what was parsed
+fn parse_partial(filepath: &str, source_code: &str, options: Options) -> (Vec<Stmt>, bool) {
+    let mut lexer: Lexer = Lexer::from_reader(source_code.as_bytes());
+    lexer.scan();
+
+    let mut parser = Parser::new(lexer.tokens);
+    parser.parse();
+    let statements = std::mem::take(parser.get_parsed_statements());
+
+    if !lexer.errors.is_empty() || !parser.get_errors().is_empty() {
+        report_errors(
+            &renderer(filepath, source_code, options),
+            lexer.errors.iter().chain(parser.get_errors().iter()),
+        );
+        return (statements, false);
+    }
+
+    report_warnings(
+        &renderer(filepath, source_code, options),
+        parser.get_warnings().clone(),
+        options,
+    );
+    (statements, true)
+}
+
+fn optimized(mut statements: Vec<Stmt>, optimize: bool) -> Vec<Stmt> {
+    if optimize {
+        Optimizer::new().optimize(&mut statements);
+    }
+    statements
+}
+
+// Lex, parse and compile the file to bytecode, reporting the errors of every step
+fn compile_source(
+    filepath: &str,
+    source_code: &str,
+    options: Options,
+) -> Option<Rc<FunctionProto>> {
+    let statements = parse_source(filepath, source_code, options)?;
+    let statements = optimized(statements, options.optimize);
+
+    match Compiler::new().compile(&statements) {
+        Ok(function) => Some(function),
+        Err(compile_errors) => {
+            report_errors(&renderer(filepath, source_code, options), &compile_errors);
+            None
+        }
+    }
+}
+
+// Tracing and coverage follow the statements of the tree-walker, the VM has none to follow
+fn reject_tree_walker_options(options: Options) {
+    for (set, flag) in [(options.trace, "--trace"), (options.coverage, "--coverage")] {
+        if set {
+            eprintln!("{flag} only works with the tree-walker, not with --vm or a compiled script");
+            process::exit(EXIT_USAGE);
+        }
+    }
+}
+
+fn run_bytecode(function: Rc<FunctionProto>, renderer: &Renderer, options: Options) {
+    let mut vm = VM::new();
+    vm.set_arguments(script_arguments());
+    vm.modules().set_search_path(search_path(renderer.name()));
+    if let Some(record) = env::var_os(WATCH_RECORD) {
+        vm.modules().record_files(PathBuf::from(record));
+    }
+    vm.set_providers(providers(options));
+    match vm.interpret(function) {
+        Ok(Value::Number(code)) => process::exit(exit_status(code)),
+        Ok(_) => {}
+        Err(vm_error) => {
+            renderer.report(&vm_error);
+            process::exit(EXIT_SOFTWARE);
+        }
+    }
+}
+
+// Compile the file to bytecode and run it on the VM instead of the tree-walker
+fn run_file_vm(filepath: &String, options: Options) {
+    reject_tree_walker_options(options);
+    let Some(source_code) = read_source(filepath) else {
+        process::exit(EXIT_NO_INPUT);
+    };
+    let Some(function) = compile_source(filepath, &source_code, options) else {
+        process::exit(EXIT_DATA);
+    };
+    run_bytecode(
+        function,
+        &renderer(filepath, &source_code, options),
+        options,
+    );
+}
+
+// lost compile <script> [-o <output>]
+// Writes the bytecode next to the script, as a .lostc file, unless told otherwise
+fn compile_file(args: &[String], options: Options) {
+    let (filepath, output) = match args {
+        [filepath] => (filepath, Path::new(filepath).with_extension("lostc")),
+        [filepath, flag, output] if flag == "-o" => (filepath, Path::new(output).to_path_buf()),
+        _ => {
+            eprintln!("Usage: lost compile <script> [-o <output>]");
+            process::exit(EXIT_USAGE);
+        }
+    };
+
+    let Some(source_code) = read_source(filepath) else {
+        process::exit(EXIT_NO_INPUT);
+    };
+    let Some(function) = compile_source(filepath, &source_code, options) else {
+        process::exit(EXIT_DATA);
+    };
+
+    if let Err(error) = fs::write(&output, serialize::encode(&function)) {
+        eprintln!("Could not write `{}`: {}", output.display(), error);
+        process::exit(1);
+    }
+}
+
+// lost run <script>
+// Compiled scripts skip lexing and parsing and go straight to the VM,
+// source scripts are run by the tree-walker
+fn run_any(filepath: &String, options: Options) {
+    let bytes = match fs::read(filepath) {
+        Ok(bytes) => bytes,
         _ => {
             eprintln!("`{filepath}` does not exist");
-            return;
+            process::exit(EXIT_NO_INPUT);
         }
     };
 
-    // Start interpreting
-    run(source_code)
+    if !serialize::is_compiled(&bytes) {
+        match String::from_utf8(bytes) {
+            Ok(source_code) => run(filepath, source_code, options),
+            Err(_) => {
+                eprintln!("`{filepath}` is neither a script nor a compiled script");
+                process::exit(EXIT_DATA);
+            }
+        }
+        return;
+    }
+
+    reject_tree_walker_options(options);
+    match serialize::decode(&bytes) {
+        // Compiled scripts come without their source, errors only show where they are
+        Ok(function) => run_bytecode(function, &renderer(filepath, "", options), options),
+        Err(message) => {
+            eprintln!("Could not load `{filepath}`: {message}");
+            process::exit(EXIT_DATA);
+        }
+    }
 }
 
-fn run_prompt() {
-    loop {
-        print!("> ");
-        io::stdout().flush().unwrap();
-
-        let mut new_input = String::new();
-        match io::stdin().read_line(&mut new_input) {
-            Err(_) => continue,
-            Ok(_) => {
-                run(new_input);
+// Lex the file and print its tokens instead of running it, one per line:
+// line:column, type, lexeme and the value of literals
+fn print_tokens(filepath: &String, options: Options) {
+    let Some(source_code) = read_source(filepath) else {
+        process::exit(EXIT_NO_INPUT);
+    };
+
+    let mut lexer: Lexer = Lexer::from_reader(source_code.as_bytes());
+    lexer.scan();
+
+    for token in lexer.tokens.iter() {
+        let position = format!("{}:{}", token.span.line, token.span.column);
+        let token_type = format!("{:?}", token.token_type);
+        match &token.literal {
+            Some(LiteralType::NumberType(number)) => {
+                println!("{position:<8} {token_type:<14} {} {number}", token.lexeme)
+            }
+            Some(LiteralType::StringType(string)) => {
+                println!("{position:<8} {token_type:<14} {} {string:?}", token.lexeme)
             }
+            None => println!("{position:<8} {token_type:<14} {}", token.lexeme),
+        }
+    }
+
+    if !lexer.errors.is_empty() {
+        report_errors(&renderer(filepath, &source_code, options), &lexer.errors);
+        process::exit(EXIT_DATA);
+    }
+}
+
+// Parse the file and print its syntax tree instead of running it
+// With errors, what did parse is printed too
+fn print_ast(filepath: &String, options: Options) {
+    let Some(source_code) = read_source(filepath) else {
+        process::exit(EXIT_NO_INPUT);
+    };
+    let (statements, valid) = parse_partial(filepath, &source_code, options);
+
+    let mut ast_printer = AstPrinter;
+    println!("{}", ast_printer.print_program(&statements));
+    if !valid {
+        process::exit(EXIT_DATA);
+    }
+}
+
+// Report the linter's warnings for the file instead of running it
+fn lint_file(filepath: &String, options: Options) {
+    let Some(source_code) = read_source(filepath) else {
+        process::exit(EXIT_NO_INPUT);
+    };
+    let Some(statements) = parse_source(filepath, &source_code, options) else {
+        process::exit(EXIT_DATA);
+    };
+
+    report_warnings(
+        &renderer(filepath, &source_code, options),
+        Linter::new().lint(&statements),
+        options,
+    );
+}
+
+// Run the file only if its type annotations are respected
+fn check_types(filepath: &String, options: Options) {
+    let Some(source_code) = read_source(filepath) else {
+        process::exit(EXIT_NO_INPUT);
+    };
+    let Some(statements) = parse_source(filepath, &source_code, options) else {
+        process::exit(EXIT_DATA);
+    };
+
+    let renderer = renderer(filepath, &source_code, options);
+    let type_errors = TypeChecker::new().check(&statements);
+    if !type_errors.is_empty() {
+        report_errors(&renderer, &type_errors);
+        process::exit(EXIT_DATA);
+    }
+
+    exit_with(interpret(
+        &mut new_interpreter(options),
+        statements,
+        options,
+        &renderer,
+    ));
+}
+
+// lost lsp
+// A language server for editors, on stdin and stdout until the editor stops it
+fn serve_lsp() -> ! {
+    let (stdin, stdout) = (io::stdin(), io::stdout());
+    match Server::new().run(&mut stdin.lock(), &mut stdout.lock()) {
+        Ok(code) => process::exit(code),
+        Err(error) => {
+            eprintln!("Language server stopped: {error}");
+            process::exit(EXIT_SOFTWARE);
+        }
+    }
+}
+
+// lost --check <script>...
+// Lexes, parses, resolves and lints every script without running any, for editors and hooks
+fn check_files(filepaths: &[String], options: Options) {
+    let mut exit_code = 0;
+    for filepath in filepaths {
+        let Some(source_code) = read_source(filepath) else {
+            exit_code = EXIT_NO_INPUT;
+            continue;
         };
+        let Some(mut statements) = parse_source(filepath, &source_code, options) else {
+            exit_code = exit_code.max(EXIT_DATA);
+            continue;
+        };
+
+        let mut warnings = Resolver::new().resolve(&mut statements);
+        warnings.extend(Linter::new().lint(&statements));
+        report_warnings(
+            &renderer(filepath, &source_code, options),
+            warnings,
+            options,
+        );
     }
+    process::exit(exit_code);
 }
 
-fn run(code: String) {
-    let mut lexer: Lexer = Lexer::new(code);
-    lexer.scan();
+// lost fmt [--check] <script>...
+// Rewrites every script in canonical form, or only reports the unformatted ones with --check
+fn format_files(args: &[String], options: Options) {
+    let check = args.iter().any(|arg| arg == "--check");
+    let filepaths: Vec<&String> = args.iter().filter(|arg| *arg != "--check").collect();
 
-    let tokens = lexer.tokens;
+    if filepaths.is_empty() {
+        eprintln!("Usage: lost fmt [--check] <script>...");
+        process::exit(EXIT_USAGE);
+    }
 
-    let mut parser = Parser::new(tokens);
-    parser.parse();
+    let mut failed = false;
+    for filepath in filepaths {
+        let Some(source_code) = read_source(filepath) else {
+            failed = true;
+            continue;
+        };
+
+        let mut lexer: Lexer = Lexer::from_reader(source_code.as_bytes());
+        lexer.scan();
+
+        let lexer_errors = lexer.errors.clone();
+        let mut parser = Parser::new(lexer.tokens);
+        parser.parse();
 
-    let parser_errors = parser.get_errors();
+        // Never rewrite a file that doesn't lex or parse, it would lose code
+        if !lexer_errors.is_empty() || !parser.get_errors().is_empty() {
+            report_errors(
+                &renderer(filepath, &source_code, options),
+                lexer_errors.iter().chain(parser.get_errors().iter()),
+            );
+            failed = true;
+            continue;
+        }
+
+        let mut formatted =
+            Formatter::new().format_source(&source_code, parser.get_parsed_statements());
+        if let Some(shebang) = &lexer.shebang {
+            formatted = format!("{}\n{}", shebang, formatted);
+        }
+        if formatted == source_code {
+            continue;
+        }
 
-    if parser_errors.len() > 0 {
-        for parser_error in parser_errors {
-            parser_error.report()
+        if check {
+            println!("`{filepath}` is not formatted");
+            failed = true;
+        } else if let Err(error) = fs::write(filepath, formatted) {
+            eprintln!("Could not write `{filepath}`: {error}");
+            failed = true;
+        } else {
+            println!("Formatted `{filepath}`");
         }
+    }
+
+    if failed {
+        process::exit(1);
+    }
+}
 
+// Lines are collected until their braces and parentheses are closed,
+// so functions and blocks can span several lines
+// One interpreter runs the whole session, so definitions persist from one input to the next
+fn run_prompt(options: Options) {
+    let mut interpreter = new_interpreter(options);
+
+    let mut editor: Editor<ReplHelper, DefaultHistory> = match Editor::new() {
+        Ok(editor) => editor,
+        Err(error) => {
+            eprintln!("Could not start the REPL: {error}");
+            process::exit(1);
+        }
+    };
+    editor.set_helper(Some(ReplHelper::new(Ref::clone(&interpreter.globals))));
+    let history = history_path();
+    if let Some(history) = &history {
+        let _ = editor.load_history(history); // Missing on the first session
+    }
+
+    let mut buffer = String::new();
+    loop {
+        let prompt = if buffer.is_empty() { "> " } else { "... " };
+        match editor.readline(prompt) {
+            Ok(line) => {
+                buffer.push_str(&line);
+                buffer.push('\n');
+            }
+            // Ctrl-C drops the input being typed
+            Err(ReadlineError::Interrupted) => {
+                buffer.clear();
+                continue;
+            }
+            Err(_) => break, // End of input
+        }
+
+        // `:` never starts Lost code, a line starting with it is a command
+        if buffer.trim_start().starts_with(':') {
+            let _ = editor.add_history_entry(buffer.trim_end());
+            run_command(&mut interpreter, &std::mem::take(&mut buffer), options);
+            continue;
+        }
+        if is_complete(&buffer) {
+            let _ = editor.add_history_entry(buffer.trim_end());
+            run_input(&mut interpreter, std::mem::take(&mut buffer), options);
+        }
+    }
+
+    if let Some(history) = &history {
+        let _ = editor.save_history(history);
+    }
+}
+
+// A REPL command, a `//` comment can follow it
+// `:reload <module>` runs the file of the module again and binds the new module
+// to its name, the rest of the session stays as it is
+fn run_command(interpreter: &mut Interpreter, line: &str, options: Options) {
+    let command = line.split("//").next().unwrap_or_default();
+    let words: Vec<&str> = command.split_whitespace().collect();
+    match words.as_slice() {
+        [":reload", name] => {
+            if !interpreter.modules().forget(name) {
+                eprintln!("No module named `{name}` was imported");
+                return;
+            }
+            run_input(interpreter, format!("import {name};"), options);
+        }
+        [":reload", ..] => eprintln!("Usage: :reload <module>"),
+        _ => eprintln!("Unknown command `{}`", command.trim()),
+    }
+}
+
+// ~/.lost_history
+fn history_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| Path::new(&home).join(".lost_history"))
+}
+
+// False while a brace, bracket, parenthesis or string is left open
+fn is_complete(source_code: &str) -> bool {
+    let mut lexer: Lexer = Lexer::from_reader(source_code.as_bytes());
+    lexer.scan();
+
+    if lexer
+        .errors
+        .iter()
+        .any(|error| error.message().starts_with("Unclosed string"))
+    {
+        return false;
+    }
+
+    let mut depth: i64 = 0;
+    for token in lexer.tokens.iter() {
+        match token.token_type {
+            TokenType::LeftBrace
+            | TokenType::LeftParen
+            | TokenType::LeftBracket
+            | TokenType::HashBrace => depth += 1,
+            TokenType::RightBrace | TokenType::RightParen | TokenType::RightBracket => depth -= 1,
+            _ => {}
+        }
+    }
+    // Extra closing ones are left for the parser to report
+    depth <= 0
+}
+
+fn run(name: &str, code: String, options: Options) {
+    let Some(statements) = parse_source(name, &code, options) else {
+        process::exit(EXIT_DATA);
+    };
+
+    let renderer = renderer(name, &code, options);
+    exit_with(interpret(
+        &mut new_interpreter(options),
+        statements,
+        options,
+        &renderer,
+    ));
+}
+
+// A REPL input, bare expressions have their value printed and don't need a `;`
+// Inputs are not optimized, the optimizer drops the expressions without effects
+fn run_input(interpreter: &mut Interpreter, mut code: String, options: Options) {
+    let trimmed = code.trim_end();
+    if trimmed.is_empty() {
+        return;
+    }
+    if !trimmed.ends_with(';') && !trimmed.ends_with('}') {
+        code = format!("{};", trimmed);
+    }
+
+    let Some(mut statements) = parse_source("<repl>", &code, options) else {
         return;
+    };
+
+    let renderer = renderer("<repl>", &code, options);
+    let warnings = Resolver::with_globals(interpreter.global_names()).resolve(&mut statements);
+    report_warnings(&renderer, warnings, options);
+
+    if let Err(interpreter_err) = interpreter.interpret_echo(&statements) {
+        renderer.report(&interpreter_err);
     }
+}
 
-    let statements = parser.get_parsed_statements();
-    let ast_printer = AstPrinter;
+// Of `clock()`, `now()` and `random()`
+fn providers(options: Options) -> Box<dyn Providers> {
+    match options.deterministic {
+        true => Box::new(Deterministic::new(SEED.get().copied().unwrap_or(0))),
+        false => Box::new(System::new()),
+    }
+}
+
+fn new_interpreter(options: Options) -> Interpreter {
     let mut interpreter = Interpreter::new(None);
+    // Environments created between two garbage collections, 0 only collects on `gc()`
+    if let Some(threshold) = env::var("LOST_GC_THRESHOLD")
+        .ok()
+        .and_then(|threshold| threshold.parse().ok())
+    {
+        interpreter.set_gc_threshold(threshold);
+    }
+    interpreter.set_arguments(script_arguments());
+    interpreter.set_providers(providers(options));
+
+    if options.trace {
+        let trace: Box<dyn Output> = match TRACE_FILE.get() {
+            Some(file) => match fs::File::create(file) {
+                Ok(file) => Box::new(io::LineWriter::new(file)),
+                Err(error) => {
+                    eprintln!("Could not create the trace file `{file}`: {error}");
+                    process::exit(EXIT_CANT_CREATE);
+                }
+            },
+            None => Box::new(io::stderr()),
+        };
+        interpreter.set_trace(trace);
+    }
+    interpreter
+}
+
+// Run the program with the tree-walking interpreter, giving its value
+fn interpret(
+    interpreter: &mut Interpreter,
+    statements: Vec<Stmt>,
+    options: Options,
+    renderer: &Renderer,
+) -> Result<Option<Type>, Error> {
+    // Lines come from the program as written, code the optimizer drops never runs
+    if options.coverage {
+        interpreter.set_coverage(Coverage::new(&statements));
+    }
 
-    if let Err(interpreter_err) = interpreter.interpret(statements) {
-        interpreter_err.report();
+    let mut statements = optimized(statements, options.optimize);
+    let warnings = Resolver::with_globals(interpreter.global_names()).resolve(&mut statements);
+    report_warnings(renderer, warnings, options);
+
+    interpreter
+        .modules()
+        .set_search_path(search_path(renderer.name()));
+    if let Some(record) = env::var_os(WATCH_RECORD) {
+        interpreter.modules().record_files(PathBuf::from(record));
+    }
+    let result = interpreter.interpret(&statements);
+    if let Err(interpreter_err) = &result {
+        renderer.report(interpreter_err);
+    }
+
+    // Reported for failed runs too, they show how far the program got
+    if let Some(coverage) = interpreter.take_coverage() {
+        report_coverage(&coverage, renderer.name());
+    }
+    result
+}
+
+// Modules are looked for next to the script, for `<eval>` in the working directory
+fn search_path(script: &str) -> Vec<PathBuf> {
+    modules::search_path(Path::new(script).parent().unwrap_or(Path::new("")))
+}
+
+// A program whose value is a number exits with it
+fn exit_with(result: Result<Option<Type>, Error>) {
+    match result {
+        Ok(Some(Type::Number(code))) => process::exit(exit_status(code)),
+        Ok(_) => {}
+        Err(_) => process::exit(EXIT_SOFTWARE),
+    }
+}
+
+// The OS keeps only the low byte of a status, so anything else would exit with
+// another number. The run succeeded, it still exits with 0 then
+fn exit_status(code: f64) -> i32 {
+    if code.fract() == 0.0 && (0.0..=255.0).contains(&code) {
+        return code as i32;
+    }
+    eprintln!(
+        "Exiting with 0, the script's value {} isn't an exit status, an integer from 0 to 255",
+        Type::Number(code)
+    );
+    0
+}
+
+fn report_coverage(coverage: &Coverage, filepath: &str) {
+    eprintln!("{}", coverage.summary());
+    if let Some(file) = COVERAGE_FILE.get() {
+        if let Err(error) = fs::write(file, coverage.lcov(filepath)) {
+            eprintln!("Could not write the coverage file `{file}`: {error}");
+            process::exit(EXIT_CANT_CREATE);
+        }
     }
 }
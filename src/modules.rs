@@ -0,0 +1,283 @@
+// Modules `import` binds to a variable, the same for the interpreter and the VM,
+// each with its own values
+//
+// A native module is made by its loader the first time a script imports it, so
+// natives a script never imports cost nothing. Importing it again gives the same
+// module. Hosts add modules of their own with `register`
+//
+// A name without a native module is a module written in Lost, the first
+// `<name>.lost` of the search path, or of the directory of the file importing
+// it before that. The runtime runs its file in its globals, the module holds
+// the names the file declares at its top level. Files are known by their
+// canonical path, one reached through several paths runs once
+use std::{
+    collections::HashMap,
+    env, fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::{Error, ErrorClass},
+    lexer::{lexer::Lexer, token::Span},
+    parser::{parser::Parser, stmt::Stmt},
+};
+
+// What a module holds, by name
+#[derive(Debug)]
+pub struct Module<V> {
+    pub name: String,
+    pub members: HashMap<String, V>,
+}
+
+type Loader<V> = Box<dyn Fn() -> Vec<(String, V)> + Send>;
+
+pub struct ModuleRegistry<V> {
+    loaders: HashMap<String, Loader<V>>,
+    natives: HashMap<String, V>, // The native modules imported so far
+    files: HashMap<PathBuf, V>,  // The modules written in Lost so far, by canonical path
+    imported: Vec<(String, Option<PathBuf>)>, // Every module loaded, in order, with the file of the ones written in Lost
+    search: Vec<PathBuf>, // Directories of the modules written in Lost, in order
+    loading: Vec<Loading>, // Modules whose files are running, innermost last
+    record: Option<PathBuf>, // Where the files of modules are listed as they are found, for `--watch`
+}
+
+// A module written in Lost whose file is running
+struct Loading {
+    name: String,
+    file: PathBuf, // Canonical
+    path: PathBuf, // As found, its directory is searched first by the imports of the file
+}
+
+// What `load` found for a module written in Lost
+pub enum Load<V> {
+    Loaded(V),               // Its file already ran, imported through this path or another
+    Run(PathBuf, Vec<Stmt>), // Its file as found and its statements, to run and give to `loaded`
+}
+
+// Where modules written in Lost are looked for, in order: the directory of the
+// script, its `lost_modules/` folder, then the directories of LOST_PATH
+pub fn search_path(directory: &Path) -> Vec<PathBuf> {
+    let mut directories = vec![directory.to_path_buf(), directory.join("lost_modules")];
+    if let Some(path) = env::var_os("LOST_PATH") {
+        directories.extend(env::split_paths(&path).filter(|path| !path.as_os_str().is_empty()));
+    }
+    directories
+}
+
+// The names a module's file declares at its top level, its members
+pub fn declared(statements: &[Stmt]) -> Vec<String> {
+    let mut names = Vec::new();
+    for statement in statements {
+        match statement {
+            Stmt::Class(name, ..)
+            | Stmt::Function(name, ..)
+            | Stmt::Import(_, name)
+            | Stmt::Var(name, ..) => names.push(name.lexeme.to_string()),
+            Stmt::Unpack(tokens, _) => {
+                names.extend(tokens.iter().map(|name| name.lexeme.to_string()))
+            }
+            _ => {}
+        }
+    }
+    names
+}
+
+// An error raised while a module's file runs, moved to the `import` of it
+// Reports only show the script's source, where it happened goes in the message
+pub fn raised_in(path: &Path, error: Error, span: Span) -> Error {
+    let message = format!(
+        "{}, in `{}` at {}",
+        error.message(),
+        path.display(),
+        error.span()
+    );
+    error.moved(message, span)
+}
+
+impl<V: Clone> ModuleRegistry<V> {
+    pub fn new() -> ModuleRegistry<V> {
+        ModuleRegistry {
+            loaders: HashMap::new(),
+            natives: HashMap::new(),
+            files: HashMap::new(),
+            imported: Vec::new(),
+            search: search_path(Path::new("")),
+            loading: Vec::new(),
+            record: None,
+        }
+    }
+
+    // Appends the canonical path of every module file found from now on to
+    // `record`, one per line, even the ones that don't parse
+    pub fn record_files(&mut self, record: PathBuf) {
+        self.record = Some(record);
+    }
+
+    // Where modules written in Lost are looked for, the working directory and
+    // LOST_PATH unless set, see `search_path`
+    pub fn set_search_path(&mut self, directories: Vec<PathBuf>) {
+        self.search = directories;
+    }
+
+    // Replaces a module of the same name, scripts importing it from then on get this one
+    pub fn register(&mut self, name: &str, loader: impl Fn() -> Vec<(String, V)> + Send + 'static) {
+        self.natives.remove(name);
+        self.loaders.insert(name.to_string(), Box::new(loader));
+    }
+
+    // The module as a value, `wrap` makes it one the first time it is imported
+    // None when there is no module of that name
+    pub fn import(&mut self, name: &str, wrap: impl FnOnce(Module<V>) -> V) -> Option<V> {
+        if let Some(module) = self.natives.get(name) {
+            return Some(module.clone());
+        }
+        let loader = self.loaders.get(name)?;
+        let module = wrap(Module {
+            name: name.to_string(),
+            members: loader().into_iter().collect(),
+        });
+        self.natives.insert(name.to_string(), module.clone());
+        self.imported.push((name.to_string(), None));
+        Some(module)
+    }
+
+    // The names of the modules imported so far, in the order they were loaded
+    // A file imported under several names has the first
+    pub fn names(&self) -> Vec<&str> {
+        self.imported
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+
+    // Drops the module imported under `name`, importing it again loads it again
+    // False when no module was imported under that name
+    pub fn forget(&mut self, name: &str) -> bool {
+        let Some(index) = self
+            .imported
+            .iter()
+            .position(|(imported, _)| imported == name)
+        else {
+            return false;
+        };
+        match self.imported.remove(index) {
+            (_, Some(file)) => self.files.remove(&file),
+            (name, None) => self.natives.remove(&name),
+        };
+        true
+    }
+
+    // The module written in Lost, or its file and statements for the runtime to
+    // run. `span` is where the error goes: a module that isn't on the search
+    // path, or whose file doesn't parse
+    pub fn load(&mut self, name: &str, span: Span) -> Result<Load<V>, Error> {
+        let mut directories: Vec<&Path> = self
+            .loading
+            .last()
+            .and_then(|loading| loading.path.parent())
+            .into_iter()
+            .collect();
+        for directory in self.search.iter() {
+            if !directories.contains(&directory.as_path()) {
+                directories.push(directory);
+            }
+        }
+        let candidates: Vec<PathBuf> = directories
+            .into_iter()
+            .map(|directory| directory.join(format!("{name}.lost")))
+            .collect();
+        let Some(path) = candidates.iter().find(|path| path.is_file()) else {
+            let searched: Vec<String> = candidates
+                .iter()
+                .map(|path| format!("`{}`", path.display()))
+                .collect();
+            let message = format!(
+                "Module `{}` not found, searched: {}",
+                name,
+                searched.join(", ")
+            );
+            return Err(Error::interpreter(message, span).raised_as(ErrorClass::NameError));
+        };
+
+        let file = fs::canonicalize(path).unwrap_or_else(|_| path.clone());
+        if let Some(module) = self.files.get(&file) {
+            return Ok(Load::Loaded(module.clone()));
+        }
+        if let Some(record) = &self.record {
+            // Only watching needs it, a run doesn't fail for it
+            if let Ok(mut listed) = fs::OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(record)
+            {
+                let _ = writeln!(listed, "{}", file.display());
+            }
+        }
+        if let Some(start) = self.loading.iter().position(|loading| loading.file == file) {
+            // Only the modules of the cycle, not the ones that led to it
+            let chain: Vec<String> = self.loading[start..]
+                .iter()
+                .map(|loading| format!("`{}` (`{}`)", loading.name, loading.path.display()))
+                .collect();
+            let message = format!(
+                "Circular import of `{}`: {}, which imports `{}`",
+                name,
+                chain.join(" imports "),
+                name
+            );
+            return Err(Error::interpreter(message, span).raised_as(ErrorClass::NameError));
+        }
+
+        let statements = fs::read_to_string(path)
+            .map_err(|error| format!("Could not read `{}`: {}", path.display(), error))
+            .and_then(|source| {
+                Lexer::tokenize(&source)
+                    .and_then(Parser::parse_program)
+                    .map_err(|errors| {
+                        let first = &errors[0];
+                        format!(
+                            "{}, in `{}` at {}",
+                            first.message(),
+                            path.display(),
+                            first.span()
+                        )
+                    })
+            })
+            .map_err(|message| Error::interpreter(message, span))?;
+        self.loading.push(Loading {
+            name: name.to_string(),
+            file,
+            path: path.clone(),
+        });
+        Ok(Load::Run(path.clone(), statements))
+    }
+
+    // Ends the `load` of the innermost module, with its value when its file ran
+    // without errors
+    pub fn loaded(&mut self, module: Option<V>) {
+        let Some(loading) = self.loading.pop() else {
+            return;
+        };
+        if let Some(module) = module {
+            self.files.insert(loading.file.clone(), module);
+            self.imported.push((loading.name, Some(loading.file)));
+        }
+    }
+}
+
+impl<V: Clone> Default for ModuleRegistry<V> {
+    fn default() -> ModuleRegistry<V> {
+        ModuleRegistry::new()
+    }
+}
+
+impl<V> std::fmt::Debug for ModuleRegistry<V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut names: Vec<&String> = self.loaders.keys().collect();
+        names.sort();
+        f.debug_struct("ModuleRegistry")
+            .field("modules", &names)
+            .finish()
+    }
+}
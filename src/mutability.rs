@@ -0,0 +1,33 @@
+// How far the fields of an instance can change, the same for the interpreter
+// and the VM. `seal` stops new fields from being added, `freeze` stops any
+// field from being set. Neither can be undone
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Mutability {
+    #[default]
+    Open,
+    Sealed, // The fields it has can be set, no others added
+    Frozen, // No field can be set
+}
+
+impl Mutability {
+    // Sealing a frozen instance leaves it frozen
+    pub fn restrict(&mut self, mutability: Mutability) {
+        *self = (*self).max(mutability);
+    }
+
+    // Why the field can't be set on an instance of `class`, none when it can
+    // `exists` is whether the instance has the field already
+    pub fn set_error(self, class: &str, name: &str, exists: bool) -> Option<String> {
+        match self {
+            Mutability::Frozen => Some(format!(
+                "Can't set `{}`, the {} instance is frozen",
+                name, class
+            )),
+            Mutability::Sealed if !exists => Some(format!(
+                "Can't add `{}`, the {} instance is sealed",
+                name, class
+            )),
+            _ => None,
+        }
+    }
+}
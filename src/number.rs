@@ -0,0 +1,63 @@
+// How numbers print and are read from text, the same for the interpreter and the VM
+//
+// Numbers print the shortest decimal that reads back as the same f64, so
+// 0.1 + 0.2 is 0.30000000000000004 and 0.1 is 0.1. Whole numbers have no
+// fraction, and there is never an exponent, whatever the magnitude: 1e22 prints
+// as 10000000000000000000000, digits past what an f64 holds being zeros.
+// -0 keeps its sign, NaN and the infinities print as NaN, inf and -inf.
+//
+// That is what Rust's Display does for f64, which is relied on here rather than
+// whatever `{}` happens to give at each place a number is printed
+pub fn format(number: f64) -> String {
+    number.to_string()
+}
+
+// A number as `format` writes it, with its whole part in groups of three
+// digits, separated by commas: 1234567.5 is 1,234,567.5
+pub fn grouped(text: &str) -> String {
+    let (sign, text) = match text.strip_prefix('-') {
+        Some(text) => ("-", text),
+        None => ("", text),
+    };
+    let whole = text
+        .find(|character: char| !character.is_ascii_digit())
+        .unwrap_or(text.len());
+    let (digits, rest) = text.split_at(whole);
+    let mut grouped = String::with_capacity(text.len() + digits.len() / 3 + 1);
+    grouped.push_str(sign);
+    for (index, digit) in digits.chars().enumerate() {
+        if index > 0 && (digits.len() - index) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped.push_str(rest);
+    grouped
+}
+
+// The number written in the text, none for anything else. The text is trimmed,
+// and underscores can separate digits, `1_000_000`. Always `.` for the point,
+// whatever the locale, and an exponent can follow, `1.5e-3`
+pub fn parse(text: &str) -> Option<f64> {
+    let text = text.trim();
+    let characters: Vec<char> = text.chars().collect();
+    let mut digits = String::with_capacity(text.len());
+    for (index, &character) in characters.iter().enumerate() {
+        match character {
+            '_' => {
+                let digit = |index: Option<usize>| {
+                    index
+                        .and_then(|index| characters.get(index))
+                        .is_some_and(char::is_ascii_digit)
+                };
+                if !digit(index.checked_sub(1)) || !digit(Some(index + 1)) {
+                    return None;
+                }
+            }
+            // Rust also reads `inf`, `NaN` and the like, scripts don't
+            '0'..='9' | '.' | 'e' | 'E' | '+' | '-' => digits.push(character),
+            _ => return None,
+        }
+    }
+    digits.parse().ok()
+}
@@ -0,0 +1,423 @@
+use crate::{
+    lexer::token::*,
+    parser::{
+        expr::Expr,
+        pattern::Arm,
+        stmt::{Catch, Stmt},
+    },
+    shared::Ref,
+    strings,
+};
+
+// Simplifies the syntax tree before it is run
+//
+// - arithmetic, comparisons and logic over literals are folded: 1 + 2 * 3 -> 7
+// - branches and loops that can never run are removed: if (false) ...
+// - statements without any effect are dropped: `1;`, `{}`, code after `return` or `throw`
+//
+// Anything that fails at runtime (-"a", 1 + "a") is left untouched,
+// so the program reports the same errors with or without the pass. Code that
+// never runs but has a `yield` is kept too, its function is still a generator
+// function
+#[derive(Default)]
+pub struct Optimizer;
+
+// Value of a literal expression
+enum Constant {
+    Number(f64),
+    String(String),
+    Boolean(bool),
+    Nil,
+}
+
+impl Constant {
+    fn of(expr: &Expr) -> Option<Constant> {
+        let Expr::Literal(token) = expr else {
+            return None;
+        };
+
+        match (&token.token_type, &token.literal) {
+            (TokenType::Number, Some(LiteralType::NumberType(number))) => {
+                Some(Constant::Number(*number))
+            }
+            (TokenType::String, Some(LiteralType::StringType(string))) => {
+                Some(Constant::String(string.clone()))
+            }
+            (TokenType::True, _) => Some(Constant::Boolean(true)),
+            (TokenType::False, _) => Some(Constant::Boolean(false)),
+            (TokenType::Nil, _) => Some(Constant::Nil),
+            _ => None,
+        }
+    }
+
+    // Same rules as the interpreter, 0 is false
+    fn is_truthy(&self) -> bool {
+        match self {
+            Constant::Number(number) => *number != 0.0 && !number.is_nan(),
+            Constant::String(_) => true,
+            Constant::Boolean(boolean) => *boolean,
+            Constant::Nil => false,
+        }
+    }
+
+    fn is_equal(&self, other: &Constant) -> bool {
+        match (self, other) {
+            (Constant::Number(a), Constant::Number(b)) => a == b,
+            (Constant::String(a), Constant::String(b)) => a == b,
+            (Constant::Boolean(a), Constant::Boolean(b)) => a == b,
+            (Constant::Nil, Constant::Nil) => true,
+            _ => false,
+        }
+    }
+
+    // A literal token for the value, as the lexer would have produced it
+    fn into_expr(self, span: Span) -> Expr {
+        let token = match self {
+            Constant::Number(number) => Token::new(
+                TokenType::Number,
+                Ref::from(number.to_string()),
+                Some(LiteralType::NumberType(number)),
+                span,
+            ),
+            Constant::String(string) => Token::new(
+                TokenType::String,
+                Ref::from(format!("\"{}\"", string)),
+                Some(LiteralType::StringType(string)),
+                span,
+            ),
+            Constant::Boolean(true) => Token::new(TokenType::True, Ref::from("true"), None, span),
+            Constant::Boolean(false) => {
+                Token::new(TokenType::False, Ref::from("false"), None, span)
+            }
+            Constant::Nil => Token::new(TokenType::Nil, Ref::from("nil"), None, span),
+        };
+        Expr::literal(token)
+    }
+}
+
+impl Optimizer {
+    pub fn new() -> Optimizer {
+        Optimizer
+    }
+
+    pub fn optimize(&mut self, statements: &mut Vec<Stmt>) {
+        let mut program = std::mem::take(statements);
+        // The last expression is the value of the program, it stays even when
+        // constant, see Stmt::value
+        let value = match program.last() {
+            Some(last) if last.value().is_some() => program.pop(),
+            _ => None,
+        };
+        let mut optimized = self.optimize_statements(program);
+        if let Some(Stmt::Expression(expr)) = value {
+            optimized.push(Stmt::expression(self.fold(*expr)));
+        }
+        *statements = optimized;
+    }
+
+    fn optimize_statements(&mut self, statements: Vec<Stmt>) -> Vec<Stmt> {
+        let mut optimized = Vec::new();
+        let mut statements = statements.into_iter();
+        while let Some(statement) = statements.next() {
+            let Some(statement) = self.optimize_statement(statement) else {
+                continue;
+            };
+
+            let returns = matches!(statement, Stmt::Return(..) | Stmt::Throw(..));
+            optimized.push(statement);
+            if returns {
+                // The rest can never run
+                let rest: Vec<Stmt> = statements.collect();
+                if rest.iter().any(Stmt::yields) {
+                    optimized.extend(rest);
+                }
+                break;
+            }
+        }
+        optimized
+    }
+
+    // Bodies of if/while/for that are optimized away become an empty block
+    fn optimize_body(&mut self, body: Stmt) -> Stmt {
+        self.optimize_statement(body)
+            .unwrap_or(Stmt::block(Vec::new()))
+    }
+
+    // None when the statement has no effect
+    fn optimize_statement(&mut self, statement: Stmt) -> Option<Stmt> {
+        match statement {
+            Stmt::Block(statements) => {
+                let statements = self.optimize_statements(statements);
+                if statements.is_empty() {
+                    return None;
+                }
+                Some(Stmt::block(statements))
+            }
+            Stmt::Class(name, superclass, mixins, methods, sealed) => {
+                let methods = methods
+                    .into_iter()
+                    .filter_map(|method| self.optimize_statement(method))
+                    .collect();
+                Some(Stmt::class(
+                    name,
+                    superclass.map(|superclass| *superclass),
+                    mixins,
+                    methods,
+                    sealed,
+                ))
+            }
+            Stmt::Expression(expr) => {
+                let expr = self.fold(*expr);
+                if Constant::of(&expr).is_some() {
+                    return None;
+                }
+                Some(Stmt::expression(expr))
+            }
+            Stmt::ForLoop(initializer, condition, incrementer, body) => {
+                let condition = condition.map(|condition| self.fold(*condition));
+                let initializer =
+                    initializer.and_then(|initializer| self.optimize_statement(*initializer));
+
+                // Only the initializer runs, in its own scope
+                if let Some(condition) = condition.as_ref().and_then(Constant::of) {
+                    if !condition.is_truthy() && !body.yields() {
+                        return initializer.map(|initializer| Stmt::block(vec![initializer]));
+                    }
+                }
+
+                Some(Stmt::forloop(
+                    initializer,
+                    condition,
+                    incrementer.map(|incrementer| self.fold(*incrementer)),
+                    self.optimize_body(*body),
+                ))
+            }
+            Stmt::Function(name, parameters, body, signature, _) => {
+                let body = self.optimize_statements(body);
+                Some(Stmt::function(name, parameters, body, *signature))
+            }
+            Stmt::IfElse(condition, then_branch, else_branch) => {
+                let condition = self.fold(*condition);
+                match Constant::of(&condition).map(|condition| condition.is_truthy()) {
+                    Some(true) if !else_branch.as_ref().is_some_and(|branch| branch.yields()) => {
+                        return self.optimize_statement(*then_branch)
+                    }
+                    Some(false) if !then_branch.yields() => {
+                        return else_branch.and_then(|branch| self.optimize_statement(*branch))
+                    }
+                    _ => {}
+                }
+
+                Some(Stmt::ifelse(
+                    condition,
+                    self.optimize_body(*then_branch),
+                    else_branch.and_then(|branch| self.optimize_statement(*branch)),
+                ))
+            }
+            Stmt::ForIn(name, iterable, body) => Some(Stmt::forin(
+                name,
+                self.fold(*iterable),
+                self.optimize_body(*body),
+            )),
+            Stmt::Import(keyword, name) => Some(Stmt::import(keyword, name)),
+            Stmt::Print(expr) => Some(Stmt::print(self.fold(*expr))),
+            Stmt::Throw(keyword, value) => Some(Stmt::throw(keyword, self.fold(*value))),
+            // Nothing can fail in a body that does nothing, the catches never run
+            Stmt::Try(body, catches) => {
+                let body = self.optimize_statements(body);
+                if body.is_empty() {
+                    return None;
+                }
+                let catches = catches
+                    .into_iter()
+                    .map(|catch| Catch {
+                        body: self.optimize_statements(catch.body),
+                        ..catch
+                    })
+                    .collect();
+                Some(Stmt::try_catch(body, catches))
+            }
+            Stmt::Return(keyword, expr) => {
+                Some(Stmt::ret(keyword, expr.map(|expr| self.fold(*expr))))
+            }
+            Stmt::Var(name, annotation, initializer) => Some(Stmt::var(
+                name,
+                annotation,
+                initializer.map(|initializer| self.fold(*initializer)),
+            )),
+            Stmt::Yield(keyword, expr, variable) => Some(Stmt::Yield(
+                keyword,
+                expr.map(|expr| Box::new(self.fold(*expr))),
+                variable,
+            )),
+            Stmt::Unpack(names, initializer) => Some(Stmt::unpack(
+                names,
+                initializer.map(|initializer| self.fold(*initializer)),
+            )),
+            Stmt::WhileLoop(condition, body) => {
+                let condition = self.fold(*condition);
+                if let Some(condition) = Constant::of(&condition) {
+                    if !condition.is_truthy() && !body.yields() {
+                        return None;
+                    }
+                }
+                Some(Stmt::whileloop(condition, self.optimize_body(*body)))
+            }
+            // Kept when the body does nothing, the resource is still closed
+            Stmt::With(keyword, resource, name, body) => Some(Stmt::with(
+                keyword,
+                self.fold(*resource),
+                name,
+                self.optimize_statements(body),
+            )),
+            Stmt::Error(tokens) => Some(Stmt::Error(tokens)),
+        }
+    }
+
+    fn fold(&mut self, expr: Expr) -> Expr {
+        match expr {
+            Expr::Binary(left, operator, right) => {
+                let left = self.fold(*left);
+                let right = self.fold(*right);
+                match (Constant::of(&left), Constant::of(&right)) {
+                    (Some(a), Some(b)) => match Self::fold_binary(a, &operator, b) {
+                        Some(constant) => constant.into_expr(left.span().to(right.span())),
+                        None => Expr::binary(left, operator, right),
+                    },
+                    _ => Expr::binary(left, operator, right),
+                }
+            }
+            Expr::Call(callee, paren, arguments) => Expr::call(
+                self.fold(*callee),
+                paren,
+                arguments
+                    .into_iter()
+                    .map(|argument| self.fold(argument))
+                    .collect(),
+            ),
+            Expr::Get(object, name) => Expr::get(self.fold(*object), name),
+            Expr::OptionalGet(object, name) => Expr::optional_get(self.fold(*object), name),
+            Expr::Index(object, index, close) => {
+                Expr::index(self.fold(*object), self.fold(*index), close)
+            }
+            Expr::Set(object, name, value) => {
+                Expr::set(self.fold(*object), name, self.fold(*value))
+            }
+            Expr::Spawn(keyword, call) => Expr::spawn(keyword, self.fold(*call)),
+            Expr::Await(keyword, promise) => Expr::await_promise(keyword, self.fold(*promise)),
+            // (1 + 2) is just 3
+            Expr::Grouping(expr) => {
+                let expr = self.fold(*expr);
+                match Constant::of(&expr) {
+                    Some(_) => expr,
+                    None => Expr::grouping(expr),
+                }
+            }
+            Expr::Unary(operator, operand) => {
+                let operand = self.fold(*operand);
+                let folded = match (&operator.token_type, Constant::of(&operand)) {
+                    (TokenType::Minus, Some(Constant::Number(number))) => {
+                        Some(Constant::Number(-number))
+                    }
+                    (TokenType::Bang, Some(constant)) => {
+                        Some(Constant::Boolean(!constant.is_truthy()))
+                    }
+                    _ => None,
+                };
+                match folded {
+                    Some(constant) => constant.into_expr(operator.span.to(operand.span())),
+                    None => Expr::unary(operator, operand),
+                }
+            }
+            // `and` and `or` with a literal on the left always pick the same operand
+            Expr::Logical(left, operator, right) => {
+                let left = self.fold(*left);
+                let right = self.fold(*right);
+                let Some(constant) = Constant::of(&left) else {
+                    return Expr::logical(left, operator, right);
+                };
+
+                let short_circuits = match operator.token_type {
+                    TokenType::Or => constant.is_truthy(),
+                    _ => !constant.is_truthy(),
+                };
+                if short_circuits {
+                    left
+                } else {
+                    right
+                }
+            }
+            Expr::Assign(name, value, location) => {
+                Expr::Assign(name, Box::new(self.fold(*value)), location)
+            }
+            Expr::Tuple(values) => {
+                Expr::tuple(values.into_iter().map(|value| self.fold(value)).collect())
+            }
+            Expr::List(open, elements, close) => Expr::list(
+                open,
+                elements
+                    .into_iter()
+                    .map(|element| self.fold(element))
+                    .collect(),
+                close,
+            ),
+            Expr::SetLiteral(open, elements, close) => Expr::set_literal(
+                open,
+                elements
+                    .into_iter()
+                    .map(|element| self.fold(element))
+                    .collect(),
+                close,
+            ),
+            Expr::Map(open, entries, close) => Expr::map(
+                open,
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (self.fold(key), self.fold(value)))
+                    .collect(),
+                close,
+            ),
+            // Patterns are left as they are, only the value and the bodies fold
+            Expr::Match(keyword, value, arms, close) => Expr::matching(
+                keyword,
+                self.fold(*value),
+                arms.into_iter()
+                    .map(|arm| Arm {
+                        pattern: arm.pattern,
+                        body: self.fold(arm.body),
+                    })
+                    .collect(),
+                close,
+            ),
+            Expr::Literal(_) | Expr::Variable(..) | Expr::This(..) | Expr::Super(..) => expr,
+        }
+    }
+
+    // None when the operation has to be left for the runtime, usually to fail there
+    fn fold_binary(left: Constant, operator: &Token, right: Constant) -> Option<Constant> {
+        use Constant::*;
+
+        let folded = match (&operator.token_type, left, right) {
+            (TokenType::Plus, Number(a), Number(b)) => Number(a + b),
+            (TokenType::Plus, String(a), String(b)) => String(a + &b),
+            (TokenType::Minus, Number(a), Number(b)) => Number(a - b),
+            (TokenType::Star, Number(a), Number(b)) => Number(a * b),
+            (TokenType::Star, String(a), Number(b)) => String(strings::repeat(&a, b).ok()?),
+            (TokenType::Slash, Number(a), Number(b)) => Number(a / b),
+            (TokenType::Greater, Number(a), Number(b)) => Boolean(a > b),
+            (TokenType::GreaterEqual, Number(a), Number(b)) => Boolean(a >= b),
+            (TokenType::Less, Number(a), Number(b)) => Boolean(a < b),
+            (TokenType::LessEqual, Number(a), Number(b)) => Boolean(a <= b),
+            (TokenType::In, String(a), String(b)) => Boolean(b.contains(&a)),
+            (TokenType::EqualEqual, a, b) => Boolean(a.is_equal(&b)),
+            (TokenType::BangEqual, a, b) => Boolean(!a.is_equal(&b)),
+            (TokenType::Is, a, b) => Boolean(a.is_equal(&b)), // Constants are never objects
+            _ => return None,
+        };
+        // A literal can't spell infinities or NaN, they are left to be made at runtime
+        match folded {
+            Number(number) if !number.is_finite() => None,
+            _ => Some(folded),
+        }
+    }
+}
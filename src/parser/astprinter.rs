@@ -1,21 +1,43 @@
-use super::expr::*;
+use super::{
+    expr::*,
+    pattern::Arm,
+    stmt::{Catch, Signature, Stmt, Visitable as StatementVisitable, Visitor as StatementVisitor},
+};
 use crate::lexer::token::*;
 
 pub struct AstPrinter;
 
 impl AstPrinter {
-    pub fn print(&mut self, expr: &mut Expr) -> String {
+    pub fn print(&mut self, expr: &Expr) -> String {
         expr.accept(self)
     }
+
+    pub fn print_statement(&mut self, stmt: &Stmt) -> String {
+        stmt.accept(self)
+    }
+
+    // One top-level statement per line
+    pub fn print_program(&mut self, statements: &[Stmt]) -> String {
+        statements
+            .iter()
+            .map(|statement| self.print_statement(statement))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    // Nested statements are printed on their own lines, indented under their parent
+    fn print_body(&mut self, statements: &[Stmt]) -> String {
+        let mut body = String::new();
+        for statement in statements.iter() {
+            body += "\n  ";
+            body += &statement.accept(self).replace('\n', "\n  ");
+        }
+        body
+    }
 }
 
 impl Visitor<String> for AstPrinter {
-    fn visit_binary(
-        &mut self,
-        left_expr: &mut Box<Expr>,
-        operator: &Token,
-        right_expr: &mut Box<Expr>,
-    ) -> String {
+    fn visit_binary(&mut self, left_expr: &Expr, operator: &Token, right_expr: &Expr) -> String {
         format!(
             "({} {} {})",
             left_expr.accept(self),
@@ -24,15 +46,10 @@ impl Visitor<String> for AstPrinter {
         )
     }
 
-    fn visit_call(
-        &mut self,
-        callee: &mut Box<Expr>,
-        closing_paren: &Token,
-        arguments: &mut Box<Vec<Expr>>,
-    ) -> String {
+    fn visit_call(&mut self, callee: &Expr, _closing_paren: &Token, arguments: &[Expr]) -> String {
         let mut comma_seperated_arguments = String::new();
-        for argument in (*arguments).iter_mut() {
-            if comma_seperated_arguments.len() != 0 {
+        for argument in (*arguments).iter() {
+            if !comma_seperated_arguments.is_empty() {
                 comma_seperated_arguments += ", ";
             }
             comma_seperated_arguments += &argument.accept(self)
@@ -40,55 +57,370 @@ impl Visitor<String> for AstPrinter {
         format!("{}({})", callee.accept(self), comma_seperated_arguments)
     }
 
-    fn visit_get(&mut self, expr: &mut Box<Expr>, name: &Token) -> String {
-        String::from("I dont care")
+    fn visit_get(&mut self, expr: &Expr, name: &Token) -> String {
+        format!("{}.{}", expr.accept(self), name.lexeme)
+    }
+
+    fn visit_optional_get(&mut self, expr: &Expr, name: &Token) -> String {
+        format!("{}.?{}", expr.accept(self), name.lexeme)
+    }
+
+    fn visit_index(&mut self, object: &Expr, index: &Expr, _close: &Token) -> String {
+        format!("{}[{}]", object.accept(self), index.accept(self))
     }
 
-    fn visit_set(&mut self, expr: &mut Box<Expr>, name: &Token, value: &mut Box<Expr>) -> String {
-        String::from("I dont care again")
+    fn visit_set(&mut self, expr: &Expr, name: &Token, value: &Expr) -> String {
+        format!(
+            "({}.{} = {})",
+            expr.accept(self),
+            name.lexeme,
+            value.accept(self)
+        )
     }
 
-    fn visit_grouping(&mut self, grouping_expr: &mut Box<Expr>) -> String {
+    fn visit_grouping(&mut self, grouping_expr: &Expr) -> String {
         format!("({})", grouping_expr.accept(self))
     }
 
-    fn visit_unary(&mut self, operator: &Token, unary_expr: &mut Box<Expr>) -> String {
+    fn visit_unary(&mut self, operator: &Token, unary_expr: &Expr) -> String {
         format!("({} {})", operator.lexeme, unary_expr.accept(self))
     }
 
+    fn visit_spawn(&mut self, _keyword: &Token, call: &Expr) -> String {
+        format!("(spawn {})", call.accept(self))
+    }
+
+    fn visit_await(&mut self, _keyword: &Token, promise: &Expr) -> String {
+        format!("(await {})", promise.accept(self))
+    }
+
     fn visit_literal(&mut self, token: &Token) -> String {
         match token.token_type {
             TokenType::String
             | TokenType::Number
             | TokenType::True
             | TokenType::False
-            | TokenType::Nil => token.lexeme.clone(),
+            | TokenType::Nil => token.lexeme.to_string(),
             _ => "(NOT IMPLEMENTED)".to_string(),
         }
     }
 
-    fn visit_variable(&mut self, variable: &Token) -> String {
+    fn visit_variable(&mut self, variable: &Token, _location: Option<Location>) -> String {
         match variable.token_type {
-            TokenType::Identifier => variable.lexeme.clone(),
+            TokenType::Identifier => variable.lexeme.to_string(),
             _ => "(NOT IMPLEMENTED)".to_string(),
         }
     }
 
-    fn visit_assign(&mut self, variable: &Token, expr: &mut Box<Expr>) -> String {
-        format!("{} {}", variable.lexeme, expr.accept(self))
+    fn visit_this(&mut self, _keyword: &Token, _location: Option<Location>) -> String {
+        "this".to_string()
+    }
+
+    fn visit_super(
+        &mut self,
+        _keyword: &Token,
+        method: &Token,
+        _superclass: Option<Location>,
+        _this: Option<Location>,
+    ) -> String {
+        format!("(super {})", method.lexeme)
+    }
+
+    fn visit_assign(
+        &mut self,
+        variable: &Token,
+        expr: &Expr,
+        _location: Option<Location>,
+    ) -> String {
+        format!("({} = {})", variable.lexeme, expr.accept(self))
     }
 
     fn visit_logical(
         &mut self,
-        left_expr: &mut Box<Expr>,
-        logical_and_or: &mut Token,
-        right_expr: &mut Box<Expr>,
+        left_expr: &Expr,
+        logical_and_or: &Token,
+        right_expr: &Expr,
     ) -> String {
         format!(
-            "{} {} {}",
+            "({} {} {})",
             left_expr.accept(self),
             logical_and_or.lexeme,
             right_expr.accept(self)
         )
     }
+
+    fn visit_tuple(&mut self, values: &[Expr]) -> String {
+        let values = values
+            .iter()
+            .map(|value| value.accept(self))
+            .collect::<Vec<String>>()
+            .join(" ");
+        format!("(tuple {})", values)
+    }
+
+    fn visit_list(&mut self, elements: &[Expr]) -> String {
+        let elements = elements
+            .iter()
+            .map(|element| format!(" {}", element.accept(self)))
+            .collect::<String>();
+        format!("(list{})", elements)
+    }
+
+    fn visit_map(&mut self, entries: &[(Expr, Expr)]) -> String {
+        let entries = entries
+            .iter()
+            .map(|(key, value)| format!(" ({} {})", key.accept(self), value.accept(self)))
+            .collect::<String>();
+        format!("(map{})", entries)
+    }
+
+    fn visit_set_literal(&mut self, elements: &[Expr]) -> String {
+        let elements = elements
+            .iter()
+            .map(|element| format!(" {}", element.accept(self)))
+            .collect::<String>();
+        format!("(set{})", elements)
+    }
+
+    // Patterns as they are written, `(match x ([a, b] a) (_ 0))`
+    fn visit_match(&mut self, _keyword: &Token, value: &Expr, arms: &[Arm]) -> String {
+        let arms = arms
+            .iter()
+            .map(|arm| format!(" ({} {})", arm.pattern, arm.body.accept(self)))
+            .collect::<String>();
+        format!("(match {}{})", value.accept(self), arms)
+    }
+}
+
+// Statements are printed as s-expressions
+//
+// var a = 1 + 2;       (var a (1 + 2))
+// if (a) print a;      (if a
+//                        (print a))
+impl StatementVisitor<String> for AstPrinter {
+    fn visit_block(&mut self, statements: &[Stmt]) -> String {
+        format!("(block{})", self.print_body(statements))
+    }
+
+    fn visit_class(
+        &mut self,
+        name: &Token,
+        superclass: Option<&Expr>,
+        mixins: &[Expr],
+        statements: &[Stmt],
+        sealed: bool,
+    ) -> String {
+        let superclass = match superclass {
+            Some(superclass) => format!(" < {}", superclass.accept(self)),
+            None => String::new(),
+        };
+        let mixins: String = mixins
+            .iter()
+            .map(|mixin| format!(" with {}", mixin.accept(self)))
+            .collect();
+        format!(
+            "({}class {}{}{}{})",
+            if sealed { "sealed " } else { "" },
+            name.lexeme,
+            superclass,
+            mixins,
+            self.print_body(statements)
+        )
+    }
+
+    fn visit_expression(&mut self, expr: &Expr) -> String {
+        format!("(expr {})", expr.accept(self))
+    }
+
+    fn visit_forloop(
+        &mut self,
+        initializer: Option<&Stmt>,
+        condition: Option<&Expr>,
+        incrementer: Option<&Expr>,
+        body: &Stmt,
+    ) -> String {
+        let initializer = match initializer {
+            Some(initializer) => initializer.accept(self),
+            None => "()".to_string(),
+        };
+        let condition = match condition {
+            Some(condition) => condition.accept(self),
+            None => "()".to_string(),
+        };
+        let incrementer = match incrementer {
+            Some(incrementer) => incrementer.accept(self),
+            None => "()".to_string(),
+        };
+        format!(
+            "(for {} {} {}{})",
+            initializer,
+            condition,
+            incrementer,
+            self.print_body(std::slice::from_ref(body))
+        )
+    }
+
+    fn visit_forin(&mut self, name: &Token, iterable: &Expr, body: &Stmt) -> String {
+        format!(
+            "(for {} in {}{})",
+            name.lexeme,
+            iterable.accept(self),
+            self.print_body(std::slice::from_ref(body))
+        )
+    }
+
+    fn visit_ifelse(
+        &mut self,
+        condition: &Expr,
+        then_branch: &Stmt,
+        else_branch: Option<&Stmt>,
+    ) -> String {
+        let mut branches = self.print_body(std::slice::from_ref(then_branch));
+        if let Some(else_branch) = else_branch {
+            branches += &self.print_body(std::slice::from_ref(else_branch));
+        }
+        format!("(if {}{})", condition.accept(self), branches)
+    }
+
+    fn visit_import(&mut self, _keyword: &Token, name: &Token) -> String {
+        format!("(import {})", name.lexeme)
+    }
+
+    fn visit_print(&mut self, expr: &Expr) -> String {
+        format!("(print {})", expr.accept(self))
+    }
+
+    fn visit_return(&mut self, _token: &Token, expr: Option<&Expr>) -> String {
+        match expr {
+            Some(expr) => format!("(return {})", expr.accept(self)),
+            None => "(return)".to_string(),
+        }
+    }
+
+    fn visit_throw(&mut self, _keyword: &Token, value: &Expr) -> String {
+        format!("(throw {})", value.accept(self))
+    }
+
+    fn visit_try(&mut self, body: &[Stmt], catches: &[Catch]) -> String {
+        let mut statement = format!("(try{}", self.print_body(body));
+        for catch in catches.iter() {
+            let name = match &catch.class {
+                Some(class) => format!("{}: {}", catch.name.lexeme, class.accept(self)),
+                None => catch.name.lexeme.to_string(),
+            };
+            let clause = format!("(catch {}{})", name, self.print_body(&catch.body));
+            statement += "\n  ";
+            statement += &clause.replace('\n', "\n  ");
+        }
+        statement + ")"
+    }
+
+    fn visit_yield(
+        &mut self,
+        _keyword: &Token,
+        expr: Option<&Expr>,
+        variable: &Option<Token>,
+    ) -> String {
+        let statement = match expr {
+            Some(expr) => format!("(yield {})", expr.accept(self)),
+            None => "(yield)".to_string(),
+        };
+        match variable {
+            Some(variable) => format!("(var {} {})", variable.lexeme, statement),
+            None => statement,
+        }
+    }
+
+    fn visit_error(&mut self, tokens: &[Token]) -> String {
+        let lexemes: Vec<&str> = tokens.iter().map(|token| &*token.lexeme).collect();
+        format!("(error {})", lexemes.join(" "))
+    }
+
+    fn visit_var(
+        &mut self,
+        token: &Token,
+        annotation: &Option<Token>,
+        expr: Option<&Expr>,
+    ) -> String {
+        let name = match annotation {
+            Some(annotation) => format!("{}: {}", token.lexeme, annotation.lexeme),
+            None => token.lexeme.to_string(),
+        };
+        match expr {
+            Some(initializer) => format!("(var {} {})", name, initializer.accept(self)),
+            None => format!("(var {})", name),
+        }
+    }
+
+    fn visit_unpack(&mut self, names: &[Token], expr: Option<&Expr>) -> String {
+        let names = names
+            .iter()
+            .map(|name| name.lexeme.to_string())
+            .collect::<Vec<String>>()
+            .join(" ");
+        match expr {
+            Some(initializer) => format!("(var ({}) {})", names, initializer.accept(self)),
+            None => format!("(var ({}))", names),
+        }
+    }
+
+    fn visit_whileloop(&mut self, condition: &Expr, statement: &Stmt) -> String {
+        format!(
+            "(while {}{})",
+            condition.accept(self),
+            self.print_body(std::slice::from_ref(statement))
+        )
+    }
+
+    fn visit_with(
+        &mut self,
+        _keyword: &Token,
+        resource: &Expr,
+        name: &Token,
+        body: &[Stmt],
+    ) -> String {
+        format!(
+            "(with {} as {}{})",
+            resource.accept(self),
+            name.lexeme,
+            self.print_body(body)
+        )
+    }
+
+    fn visit_function(
+        &mut self,
+        name: &Token,
+        parameters: &[Token],
+        body: &[Stmt],
+        signature: &Signature,
+        _captures: &[Slot],
+    ) -> String {
+        let parameters = parameters
+            .iter()
+            .enumerate()
+            .map(|(index, parameter)| {
+                match signature.parameters.get(index).and_then(|a| a.as_ref()) {
+                    Some(annotation) => format!("{}: {}", parameter.lexeme, annotation.lexeme),
+                    None => parameter.lexeme.to_string(),
+                }
+            })
+            .collect::<Vec<String>>()
+            .join(" ");
+        let return_type = match &signature.return_type {
+            Some(return_type) => format!(" -> {}", return_type.lexeme),
+            None => String::new(),
+        };
+        format!(
+            "({} {} ({}){}{})",
+            if signature.overriding {
+                "override"
+            } else {
+                "fun"
+            },
+            name.lexeme,
+            parameters,
+            return_type,
+            self.print_body(body)
+        )
+    }
 }
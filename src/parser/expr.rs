@@ -1,6 +1,9 @@
+use super::pattern::Arm;
+
 use crate::lexer::token::*;
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Expr {
     // AST nodes
 
@@ -10,15 +13,44 @@ pub enum Expr {
     //      Expr    Token   Expr
     //      ...             ...
     Binary(Box<Expr>, Token, Box<Expr>),
-    Call(Box<Expr>, Token, Box<Vec<Expr>>),
+    Call(Box<Expr>, Token, Vec<Expr>),
     Get(Box<Expr>, Token),
+    OptionalGet(Box<Expr>, Token), // `object.?name`, nil when there is no such property
+    Index(Box<Expr>, Box<Expr>, Token), // Object, index, `]`
     Set(Box<Expr>, Token, Box<Expr>),
     Grouping(Box<Expr>),
     Unary(Token, Box<Expr>),
     Literal(Token),
     Logical(Box<Expr>, Token, Box<Expr>),
-    Variable(Token),
-    Assign(Token, Box<Expr>),
+    Variable(Token, Option<Location>),
+    This(Token, Option<Location>), // The instance a method was called on
+    Super(Token, Token, Option<Location>, Option<Location>), // `super`, the method, where the superclass and `this` are
+    Assign(Token, Box<Expr>, Option<Location>),
+    Tuple(Vec<Expr>),              // Values of `return a, b;` or `var a, b = 1, 2;`
+    List(Token, Vec<Expr>, Token), // `[`, elements, `]`
+    Map(Token, Vec<(Expr, Expr)>, Token), // `{`, keys and values, `}`
+    SetLiteral(Token, Vec<Expr>, Token), // `#{`, elements, `}`
+    Match(Token, Box<Expr>, Vec<Arm>, Token), // `match`, value, arms, `}`
+    Spawn(Token, Box<Expr>),       // `spawn`, the call
+    Await(Token, Box<Expr>),       // `await`, the promise
+}
+
+// Where a variable lives at runtime, filled in by the Resolver
+// Variables left without one are looked up by name
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Location {
+    Local(Slot),
+    Global(usize), // The index of its name in the globals, see GlobalNames
+}
+
+// Where a local variable lives at runtime
+// `depth` environments up from the current one, at `index` in its slots
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Slot {
+    pub depth: usize,
+    pub index: usize,
 }
 
 // Will change the Taking of owned variables and then converting it to Box
@@ -29,13 +61,21 @@ impl Expr {
     }
 
     pub fn call(callee: Expr, closing_paren: Token, arguments: Vec<Expr>) -> Expr {
-        Expr::Call(Box::new(callee), closing_paren, Box::new(arguments))
+        Expr::Call(Box::new(callee), closing_paren, arguments)
     }
 
     pub fn get(object: Expr, name: Token) -> Expr {
         Expr::Get(Box::new(object), name)
     }
 
+    pub fn optional_get(object: Expr, name: Token) -> Expr {
+        Expr::OptionalGet(Box::new(object), name)
+    }
+
+    pub fn index(object: Expr, index: Expr, close: Token) -> Expr {
+        Expr::Index(Box::new(object), Box::new(index), close)
+    }
+
     pub fn set(object: Expr, name: Token, value: Expr) -> Expr {
         Expr::Set(Box::new(object), name, Box::new(value))
     }
@@ -57,26 +97,95 @@ impl Expr {
     }
 
     pub fn variable(variable_name: Token) -> Expr {
-        Expr::Variable(variable_name)
+        Expr::Variable(variable_name, None)
+    }
+
+    pub fn this(keyword: Token) -> Expr {
+        Expr::This(keyword, None)
+    }
+
+    pub fn super_method(keyword: Token, method: Token) -> Expr {
+        Expr::Super(keyword, method, None, None)
     }
 
     pub fn assign(token: Token, expression: Expr) -> Expr {
-        Expr::Assign(token, Box::new(expression))
+        Expr::Assign(token, Box::new(expression), None)
+    }
+
+    pub fn tuple(values: Vec<Expr>) -> Expr {
+        Expr::Tuple(values)
+    }
+
+    pub fn list(open: Token, elements: Vec<Expr>, close: Token) -> Expr {
+        Expr::List(open, elements, close)
+    }
+
+    pub fn map(open: Token, entries: Vec<(Expr, Expr)>, close: Token) -> Expr {
+        Expr::Map(open, entries, close)
+    }
+
+    pub fn set_literal(open: Token, elements: Vec<Expr>, close: Token) -> Expr {
+        Expr::SetLiteral(open, elements, close)
+    }
+
+    pub fn matching(keyword: Token, value: Expr, arms: Vec<Arm>, close: Token) -> Expr {
+        Expr::Match(keyword, Box::new(value), arms, close)
+    }
+
+    pub fn spawn(keyword: Token, call: Expr) -> Expr {
+        Expr::Spawn(keyword, Box::new(call))
+    }
+
+    pub fn await_promise(keyword: Token, promise: Expr) -> Expr {
+        Expr::Await(keyword, Box::new(promise))
+    }
+
+    // Source code the expression was parsed from, as far as its tokens tell
+    // A grouping does not keep its parentheses, it covers its inner expression
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Binary(left, _, right) | Expr::Logical(left, _, right) => {
+                left.span().to(right.span())
+            }
+            Expr::Call(callee, paren, _) => callee.span().to(paren.span),
+            Expr::Get(object, name) | Expr::OptionalGet(object, name) => {
+                object.span().to(name.span)
+            }
+            Expr::Index(object, _, close) => object.span().to(close.span),
+            Expr::Set(object, _, value) => object.span().to(value.span()),
+            Expr::Grouping(expr) => expr.span(),
+            Expr::Unary(operator, expr)
+            | Expr::Spawn(operator, expr)
+            | Expr::Await(operator, expr) => operator.span.to(expr.span()),
+            Expr::Literal(token) | Expr::Variable(token, _) | Expr::This(token, _) => token.span,
+            Expr::Super(keyword, method, ..) => keyword.span.to(method.span),
+            Expr::Assign(name, value, _) => name.span.to(value.span()),
+            Expr::Tuple(values) => match (values.first(), values.last()) {
+                (Some(first), Some(last)) => first.span().to(last.span()),
+                _ => Span::default(),
+            },
+            Expr::List(open, _, close)
+            | Expr::Map(open, _, close)
+            | Expr::SetLiteral(open, _, close)
+            | Expr::Match(open, _, _, close) => open.span.to(close.span),
+        }
     }
 }
 
 pub trait Visitable<T> {
-    fn accept(&mut self, visitor: &mut impl Visitor<T>) -> T;
+    fn accept(&self, visitor: &mut impl Visitor<T>) -> T;
 }
 
 impl<T> Visitable<T> for Expr {
-    fn accept(&mut self, visitor: &mut impl Visitor<T>) -> T {
+    fn accept(&self, visitor: &mut impl Visitor<T>) -> T {
         match self {
             Expr::Binary(left, operator, right) => visitor.visit_binary(left, operator, right),
             Expr::Call(callee, closing_paren, arguments) => {
                 visitor.visit_call(callee, closing_paren, arguments)
             }
             Expr::Get(expr, name) => visitor.visit_get(expr, name),
+            Expr::OptionalGet(expr, name) => visitor.visit_optional_get(expr, name),
+            Expr::Index(object, index, close) => visitor.visit_index(object, index, close),
             Expr::Set(expr, name, value) => visitor.visit_set(expr, name, value),
             Expr::Grouping(expr) => visitor.visit_grouping(expr),
             Expr::Unary(operator, right) => visitor.visit_unary(operator, right),
@@ -84,37 +193,50 @@ impl<T> Visitable<T> for Expr {
             Expr::Logical(left_expr, logical_and_or, right_expr) => {
                 visitor.visit_logical(left_expr, logical_and_or, right_expr)
             }
-            Expr::Variable(variable) => visitor.visit_variable(variable),
-            Expr::Assign(token, expr) => visitor.visit_assign(token, expr),
+            Expr::Variable(variable, location) => visitor.visit_variable(variable, *location),
+            Expr::This(keyword, location) => visitor.visit_this(keyword, *location),
+            Expr::Super(keyword, method, superclass, this) => {
+                visitor.visit_super(keyword, method, *superclass, *this)
+            }
+            Expr::Assign(token, expr, location) => visitor.visit_assign(token, expr, *location),
+            Expr::Tuple(values) => visitor.visit_tuple(values),
+            Expr::List(_, elements, _) => visitor.visit_list(elements),
+            Expr::Map(_, entries, _) => visitor.visit_map(entries),
+            Expr::SetLiteral(_, elements, _) => visitor.visit_set_literal(elements),
+            Expr::Match(keyword, value, arms, _) => visitor.visit_match(keyword, value, arms),
+            Expr::Spawn(keyword, call) => visitor.visit_spawn(keyword, call),
+            Expr::Await(keyword, promise) => visitor.visit_await(keyword, promise),
         }
     }
 }
 
 // Any Visitor class to Expr must implement Visitor trait
 pub trait Visitor<T> {
-    fn visit_binary(
-        &mut self,
-        left_expr: &mut Box<Expr>,
-        operator: &Token,
-        right_expr: &mut Box<Expr>,
-    ) -> T;
-    fn visit_call(
-        &mut self,
-        callee: &mut Box<Expr>,
-        closing_paren: &Token,
-        arguments: &mut Box<Vec<Expr>>,
-    ) -> T;
-    fn visit_grouping(&mut self, grouping_expr: &mut Box<Expr>) -> T;
-    fn visit_unary(&mut self, operator: &Token, unary_expr: &mut Box<Expr>) -> T;
+    fn visit_binary(&mut self, left_expr: &Expr, operator: &Token, right_expr: &Expr) -> T;
+    fn visit_call(&mut self, callee: &Expr, closing_paren: &Token, arguments: &[Expr]) -> T;
+    fn visit_grouping(&mut self, grouping_expr: &Expr) -> T;
+    fn visit_unary(&mut self, operator: &Token, unary_expr: &Expr) -> T;
     fn visit_literal(&mut self, lit: &Token) -> T;
-    fn visit_logical(
+    fn visit_logical(&mut self, left_expr: &Expr, logical_and_or: &Token, right_expr: &Expr) -> T;
+    fn visit_variable(&mut self, variable: &Token, location: Option<Location>) -> T;
+    fn visit_this(&mut self, keyword: &Token, location: Option<Location>) -> T;
+    fn visit_super(
         &mut self,
-        left_expr: &mut Box<Expr>,
-        logical_and_or: &mut Token,
-        right_expr: &mut Box<Expr>,
+        keyword: &Token,
+        method: &Token,
+        superclass: Option<Location>,
+        this: Option<Location>,
     ) -> T;
-    fn visit_variable(&mut self, variable: &Token) -> T;
-    fn visit_assign(&mut self, variable: &Token, expr: &mut Box<Expr>) -> T;
-    fn visit_get(&mut self, expr: &mut Box<Expr>, name: &Token) -> T;
-    fn visit_set(&mut self, expr: &mut Box<Expr>, name: &Token, value: &mut Box<Expr>) -> T;
+    fn visit_assign(&mut self, variable: &Token, expr: &Expr, location: Option<Location>) -> T;
+    fn visit_get(&mut self, expr: &Expr, name: &Token) -> T;
+    fn visit_optional_get(&mut self, expr: &Expr, name: &Token) -> T;
+    fn visit_index(&mut self, object: &Expr, index: &Expr, close: &Token) -> T;
+    fn visit_set(&mut self, expr: &Expr, name: &Token, value: &Expr) -> T;
+    fn visit_tuple(&mut self, values: &[Expr]) -> T;
+    fn visit_list(&mut self, elements: &[Expr]) -> T;
+    fn visit_map(&mut self, entries: &[(Expr, Expr)]) -> T;
+    fn visit_set_literal(&mut self, elements: &[Expr]) -> T;
+    fn visit_match(&mut self, keyword: &Token, value: &Expr, arms: &[Arm]) -> T;
+    fn visit_spawn(&mut self, keyword: &Token, call: &Expr) -> T;
+    fn visit_await(&mut self, keyword: &Token, promise: &Expr) -> T;
 }
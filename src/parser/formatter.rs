@@ -0,0 +1,714 @@
+use super::{
+    expr::*,
+    pattern::Arm,
+    stmt::{Catch, Signature, Stmt, Visitable as StatementVisitable, Visitor as StatementVisitor},
+};
+use crate::lexer::{
+    token::*,
+    trivia::{lossless_tokens, LosslessToken, TriviaKind},
+};
+
+const INDENT: &str = "    ";
+
+// How far a token of the source and one of the formatted program may drift
+// apart, like a trailing comma the formatter drops, before they are taken as
+// different tokens in the same place
+const DRIFT: usize = 4;
+
+// Regenerates canonical source code from the syntax tree
+//
+// Statements are returned without their leading indentation,
+// nested lines are indented relative to the current depth
+#[derive(Default)]
+pub struct Formatter {
+    depth: usize,
+}
+
+impl Formatter {
+    pub fn new() -> Formatter {
+        Formatter { depth: 0 }
+    }
+
+    // One statement per line, with declarations set apart by blank lines
+    pub fn format_program(&mut self, statements: &[Stmt]) -> String {
+        let mut source = String::new();
+        let mut previous_was_declaration = false;
+
+        for (index, statement) in statements.iter().enumerate() {
+            let is_declaration = matches!(statement, Stmt::Function(..) | Stmt::Class(..));
+            if index > 0 && (is_declaration || previous_was_declaration) {
+                source.push('\n');
+            }
+
+            source += &self.format_statement(statement);
+            source.push('\n');
+            previous_was_declaration = is_declaration;
+        }
+        source
+    }
+
+    // The program formatted, with the comments of the source it was parsed from
+    pub fn format_source(&mut self, source: &str, statements: &[Stmt]) -> String {
+        keep_comments(source, &self.format_program(statements))
+    }
+
+    pub fn format_statement(&mut self, stmt: &Stmt) -> String {
+        stmt.accept(self)
+    }
+
+    fn indentation(&self) -> String {
+        INDENT.repeat(self.depth)
+    }
+
+    // `{` and `}` around statements on their own, further indented, lines
+    fn format_block(&mut self, statements: &[Stmt]) -> String {
+        if statements.is_empty() {
+            return "{}".to_string();
+        }
+
+        self.depth += 1;
+        let mut block = "{\n".to_string();
+        for statement in statements.iter() {
+            block += &self.indentation();
+            block += &statement.accept(self);
+            block.push('\n');
+        }
+        self.depth -= 1;
+
+        block += &self.indentation();
+        block.push('}');
+        block
+    }
+
+    // Block bodies of if/while/for open on the same line,
+    // any other statement goes on the next line
+    fn format_body(&mut self, body: &Stmt) -> String {
+        match body {
+            Stmt::Block(statements) => format!(" {}", self.format_block(statements)),
+            _ => {
+                self.depth += 1;
+                let body = format!("\n{}{}", self.indentation(), body.accept(self));
+                self.depth -= 1;
+                body
+            }
+        }
+    }
+
+    // name(parameters) -> type { ... }
+    // shared by functions and class methods
+    fn format_function(
+        &mut self,
+        name: &Token,
+        parameters: &[Token],
+        body: &[Stmt],
+        signature: &Signature,
+    ) -> String {
+        let parameters = parameters
+            .iter()
+            .enumerate()
+            .map(|(index, parameter)| {
+                match signature.parameters.get(index).and_then(|a| a.as_ref()) {
+                    Some(annotation) => format!("{}: {}", parameter.lexeme, annotation.lexeme),
+                    None => parameter.lexeme.to_string(),
+                }
+            })
+            .collect::<Vec<String>>()
+            .join(", ");
+        let return_type = match &signature.return_type {
+            Some(return_type) => format!(" -> {}", return_type.lexeme),
+            None => String::new(),
+        };
+        format!(
+            "{}{}({}){} {}",
+            if signature.overriding {
+                "override "
+            } else {
+                ""
+            },
+            name.lexeme,
+            parameters,
+            return_type,
+            self.format_block(body)
+        )
+    }
+}
+
+impl Visitor<String> for Formatter {
+    fn visit_binary(&mut self, left_expr: &Expr, operator: &Token, right_expr: &Expr) -> String {
+        format!(
+            "{} {} {}",
+            left_expr.accept(self),
+            operator.lexeme,
+            right_expr.accept(self)
+        )
+    }
+
+    fn visit_call(&mut self, callee: &Expr, _closing_paren: &Token, arguments: &[Expr]) -> String {
+        let arguments = arguments
+            .iter()
+            .map(|argument| argument.accept(self))
+            .collect::<Vec<String>>()
+            .join(", ");
+        format!("{}({})", callee.accept(self), arguments)
+    }
+
+    fn visit_get(&mut self, expr: &Expr, name: &Token) -> String {
+        format!("{}.{}", expr.accept(self), name.lexeme)
+    }
+
+    fn visit_optional_get(&mut self, expr: &Expr, name: &Token) -> String {
+        format!("{}.?{}", expr.accept(self), name.lexeme)
+    }
+
+    fn visit_index(&mut self, object: &Expr, index: &Expr, _close: &Token) -> String {
+        format!("{}[{}]", object.accept(self), index.accept(self))
+    }
+
+    fn visit_set(&mut self, expr: &Expr, name: &Token, value: &Expr) -> String {
+        format!(
+            "{}.{} = {}",
+            expr.accept(self),
+            name.lexeme,
+            value.accept(self)
+        )
+    }
+
+    fn visit_grouping(&mut self, grouping_expr: &Expr) -> String {
+        format!("({})", grouping_expr.accept(self))
+    }
+
+    fn visit_unary(&mut self, operator: &Token, unary_expr: &Expr) -> String {
+        format!("{}{}", operator.lexeme, unary_expr.accept(self))
+    }
+
+    fn visit_spawn(&mut self, _keyword: &Token, call: &Expr) -> String {
+        format!("spawn {}", call.accept(self))
+    }
+
+    fn visit_await(&mut self, _keyword: &Token, promise: &Expr) -> String {
+        format!("await {}", promise.accept(self))
+    }
+
+    fn visit_literal(&mut self, lit: &Token) -> String {
+        lit.lexeme.to_string()
+    }
+
+    fn visit_logical(
+        &mut self,
+        left_expr: &Expr,
+        logical_and_or: &Token,
+        right_expr: &Expr,
+    ) -> String {
+        format!(
+            "{} {} {}",
+            left_expr.accept(self),
+            logical_and_or.lexeme,
+            right_expr.accept(self)
+        )
+    }
+
+    fn visit_variable(&mut self, variable: &Token, _location: Option<Location>) -> String {
+        variable.lexeme.to_string()
+    }
+
+    fn visit_this(&mut self, _keyword: &Token, _location: Option<Location>) -> String {
+        "this".to_string()
+    }
+
+    fn visit_super(
+        &mut self,
+        _keyword: &Token,
+        method: &Token,
+        _superclass: Option<Location>,
+        _this: Option<Location>,
+    ) -> String {
+        format!("super.{}", method.lexeme)
+    }
+
+    fn visit_assign(
+        &mut self,
+        variable: &Token,
+        expr: &Expr,
+        _location: Option<Location>,
+    ) -> String {
+        format!("{} = {}", variable.lexeme, expr.accept(self))
+    }
+
+    fn visit_tuple(&mut self, values: &[Expr]) -> String {
+        values
+            .iter()
+            .map(|value| value.accept(self))
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+
+    fn visit_list(&mut self, elements: &[Expr]) -> String {
+        let elements = elements
+            .iter()
+            .map(|element| element.accept(self))
+            .collect::<Vec<String>>()
+            .join(", ");
+        format!("[{}]", elements)
+    }
+
+    fn visit_map(&mut self, entries: &[(Expr, Expr)]) -> String {
+        let entries = entries
+            .iter()
+            .map(|(key, value)| format!("{}: {}", key.accept(self), value.accept(self)))
+            .collect::<Vec<String>>()
+            .join(", ");
+        format!("{{{}}}", entries)
+    }
+
+    fn visit_set_literal(&mut self, elements: &[Expr]) -> String {
+        let elements = elements
+            .iter()
+            .map(|element| element.accept(self))
+            .collect::<Vec<String>>()
+            .join(", ");
+        format!("#{{{}}}", elements)
+    }
+
+    // An arm a line, each ending in a `,`
+    fn visit_match(&mut self, _keyword: &Token, value: &Expr, arms: &[Arm]) -> String {
+        let mut source = format!("match {} {{\n", value.accept(self));
+        self.depth += 1;
+        for arm in arms.iter() {
+            source += &format!(
+                "{}{} => {},\n",
+                self.indentation(),
+                arm.pattern,
+                arm.body.accept(self)
+            );
+        }
+        self.depth -= 1;
+        source += &self.indentation();
+        source.push('}');
+        source
+    }
+}
+
+impl StatementVisitor<String> for Formatter {
+    fn visit_block(&mut self, statements: &[Stmt]) -> String {
+        self.format_block(statements)
+    }
+
+    fn visit_class(
+        &mut self,
+        name: &Token,
+        superclass: Option<&Expr>,
+        mixins: &[Expr],
+        statements: &[Stmt],
+        sealed: bool,
+    ) -> String {
+        let mut class = format!(
+            "{}class {}",
+            if sealed { "sealed " } else { "" },
+            name.lexeme
+        );
+        if let Some(superclass) = superclass {
+            class += &format!(" < {}", superclass.accept(self));
+        }
+        if !mixins.is_empty() {
+            let mixins: Vec<String> = mixins.iter().map(|mixin| mixin.accept(self)).collect();
+            class += &format!(" with {}", mixins.join(", "));
+        }
+
+        if statements.is_empty() {
+            return class + " {}";
+        }
+
+        // Methods are written without `fun` and separated by blank lines
+        self.depth += 1;
+        let mut methods = Vec::new();
+        for method in statements.iter() {
+            let method = match method {
+                Stmt::Function(name, parameters, body, signature, _) => {
+                    self.format_function(name, parameters, body, signature)
+                }
+                _ => method.accept(self),
+            };
+            methods.push(format!("{}{}", self.indentation(), method));
+        }
+        self.depth -= 1;
+
+        format!(
+            "{} {{\n{}\n{}}}",
+            class,
+            methods.join("\n\n"),
+            self.indentation()
+        )
+    }
+
+    fn visit_expression(&mut self, expr: &Expr) -> String {
+        format!("{};", expr.accept(self))
+    }
+
+    fn visit_forloop(
+        &mut self,
+        initializer: Option<&Stmt>,
+        condition: Option<&Expr>,
+        incrementer: Option<&Expr>,
+        body: &Stmt,
+    ) -> String {
+        // The initializer is a statement, so it brings its own `;`
+        let mut clauses = match initializer {
+            Some(initializer) => initializer.accept(self),
+            None => ";".to_string(),
+        };
+        if let Some(condition) = condition {
+            clauses += &format!(" {}", condition.accept(self));
+        }
+        clauses.push(';');
+        if let Some(incrementer) = incrementer {
+            clauses += &format!(" {}", incrementer.accept(self));
+        }
+
+        format!("for ({}){}", clauses, self.format_body(body))
+    }
+
+    fn visit_forin(&mut self, name: &Token, iterable: &Expr, body: &Stmt) -> String {
+        format!(
+            "for (var {} in {}){}",
+            name.lexeme,
+            iterable.accept(self),
+            self.format_body(body)
+        )
+    }
+
+    fn visit_ifelse(
+        &mut self,
+        condition: &Expr,
+        then_branch: &Stmt,
+        else_branch: Option<&Stmt>,
+    ) -> String {
+        let mut if_statement = format!(
+            "if ({}){}",
+            condition.accept(self),
+            self.format_body(then_branch)
+        );
+
+        if let Some(else_branch) = else_branch {
+            // `else` follows a closing brace, or starts its own line
+            if matches!(*then_branch, Stmt::Block(_)) {
+                if_statement += " else";
+            } else {
+                if_statement += &format!("\n{}else", self.indentation());
+            }
+
+            match *else_branch {
+                Stmt::IfElse(..) => if_statement += &format!(" {}", else_branch.accept(self)),
+                _ => if_statement += &self.format_body(else_branch),
+            }
+        }
+        if_statement
+    }
+
+    fn visit_import(&mut self, _keyword: &Token, name: &Token) -> String {
+        format!("import {};", name.lexeme)
+    }
+
+    fn visit_print(&mut self, expr: &Expr) -> String {
+        format!("print {};", expr.accept(self))
+    }
+
+    fn visit_return(&mut self, _token: &Token, expr: Option<&Expr>) -> String {
+        match expr {
+            Some(expr) => format!("return {};", expr.accept(self)),
+            None => "return;".to_string(),
+        }
+    }
+
+    fn visit_throw(&mut self, _keyword: &Token, value: &Expr) -> String {
+        format!("throw {};", value.accept(self))
+    }
+
+    fn visit_try(&mut self, body: &[Stmt], catches: &[Catch]) -> String {
+        let mut statement = format!("try {}", self.format_block(body));
+        for catch in catches.iter() {
+            let name = match &catch.class {
+                Some(class) => format!("{}: {}", catch.name.lexeme, class.accept(self)),
+                None => catch.name.lexeme.to_string(),
+            };
+            statement += &format!(" catch ({}) {}", name, self.format_block(&catch.body));
+        }
+        statement
+    }
+
+    fn visit_yield(
+        &mut self,
+        _keyword: &Token,
+        expr: Option<&Expr>,
+        variable: &Option<Token>,
+    ) -> String {
+        let statement = match expr {
+            Some(expr) => format!("yield {};", expr.accept(self)),
+            None => "yield;".to_string(),
+        };
+        match variable {
+            Some(variable) => format!("var {} = {}", variable.lexeme, statement),
+            None => statement,
+        }
+    }
+
+    // Left as it was written, with single spaces between its tokens
+    fn visit_error(&mut self, tokens: &[Token]) -> String {
+        let lexemes: Vec<&str> = tokens.iter().map(|token| &*token.lexeme).collect();
+        lexemes.join(" ")
+    }
+
+    fn visit_var(
+        &mut self,
+        token: &Token,
+        annotation: &Option<Token>,
+        expr: Option<&Expr>,
+    ) -> String {
+        let name = match annotation {
+            Some(annotation) => format!("{}: {}", token.lexeme, annotation.lexeme),
+            None => token.lexeme.to_string(),
+        };
+        match expr {
+            Some(initializer) => format!("var {} = {};", name, initializer.accept(self)),
+            None => format!("var {};", name),
+        }
+    }
+
+    fn visit_unpack(&mut self, names: &[Token], expr: Option<&Expr>) -> String {
+        let names = names
+            .iter()
+            .map(|name| name.lexeme.to_string())
+            .collect::<Vec<String>>()
+            .join(", ");
+        match expr {
+            Some(initializer) => format!("var {} = {};", names, initializer.accept(self)),
+            None => format!("var {};", names),
+        }
+    }
+
+    fn visit_whileloop(&mut self, condition: &Expr, statement: &Stmt) -> String {
+        format!(
+            "while ({}){}",
+            condition.accept(self),
+            self.format_body(statement)
+        )
+    }
+
+    fn visit_with(
+        &mut self,
+        _keyword: &Token,
+        resource: &Expr,
+        name: &Token,
+        body: &[Stmt],
+    ) -> String {
+        format!(
+            "with {} as {} {}",
+            resource.accept(self),
+            name.lexeme,
+            self.format_block(body)
+        )
+    }
+
+    fn visit_function(
+        &mut self,
+        name: &Token,
+        parameters: &[Token],
+        body: &[Stmt],
+        signature: &Signature,
+        _captures: &[Slot],
+    ) -> String {
+        format!(
+            "fun {}",
+            self.format_function(name, parameters, body, signature)
+        )
+    }
+}
+
+// A comment of the source, where it goes in the formatted program
+struct Comment {
+    text: String,
+    blank_before: bool, // A blank line came before it, kept unless something else is there
+    blank_after: bool,
+}
+
+// Puts the comments of `source` back into `formatted`, the same program formatted
+//
+// A comment on its own line goes on its own line before the line of the token it
+// came before, indented like it, or like a block's statements when that token
+// closes the block. A comment after code goes at the end of the line of the token
+// before it, and above that line when another one is already at its end
+fn keep_comments(source: &str, formatted: &str) -> String {
+    let original = lossless_tokens(source);
+    let has_comments = original.iter().any(|token| {
+        token
+            .leading
+            .iter()
+            .chain(token.trailing.iter())
+            .any(|trivia| trivia.kind == TriviaKind::Comment)
+    });
+    if !has_comments {
+        return formatted.to_string();
+    }
+    let output = lossless_tokens(formatted);
+    let aligned = align(&original, &output);
+
+    let mut before: Vec<Vec<Comment>> = output.iter().map(|_| Vec::new()).collect();
+    let mut after: Vec<Vec<String>> = output.iter().map(|_| Vec::new()).collect();
+    for (index, token) in original.iter().enumerate() {
+        // Leading comments go with the next token that has a place, trailing
+        // ones with the previous one
+        let next = aligned[index..].iter().flatten().next();
+        let previous = aligned[..=index].iter().rev().flatten().next();
+        // The line before the first comment was ended by the token before it,
+        // the lines before the others by the comment before them
+        let mut newlines = 1;
+        let mut comments = Vec::new();
+        for trivia in token.leading.iter() {
+            match trivia.kind {
+                TriviaKind::Newline => newlines += 1,
+                TriviaKind::Comment => {
+                    comments.push(Comment {
+                        text: trivia.text.clone(),
+                        blank_before: newlines > 1,
+                        blank_after: false,
+                    });
+                    newlines = 0;
+                }
+                _ => {}
+            }
+        }
+        if let Some(last) = comments.last_mut() {
+            last.blank_after = newlines > 1;
+        }
+        if let Some(next) = next {
+            before[*next].extend(comments);
+        }
+        let trailing = token
+            .trailing
+            .iter()
+            .filter(|trivia| trivia.kind == TriviaKind::Comment);
+        for trivia in trailing {
+            if let Some(previous) = previous.or(next) {
+                after[*previous].push(trivia.text.clone());
+            }
+        }
+    }
+
+    let mut source = String::new();
+    let mut line_comments: Vec<String> = Vec::new(); // At the end of the line being written
+    for (index, token) in output.iter().enumerate() {
+        for trivia in token.leading.iter() {
+            if trivia.kind == TriviaKind::Newline {
+                end_line(&mut source, &mut line_comments);
+            }
+            source.push_str(&trivia.text);
+        }
+        let closes_block = token.token.token_type == TokenType::RightBrace;
+        for comment in before[index].iter() {
+            insert_line(
+                &mut source,
+                &comment.text,
+                comment.blank_before,
+                closes_block,
+            );
+            if comment.blank_after && !closes_block {
+                insert_blank(&mut source);
+            }
+        }
+        source.push_str(&token.text);
+        line_comments.extend(after[index].iter().cloned());
+        for trivia in token.trailing.iter() {
+            if trivia.kind == TriviaKind::Newline {
+                end_line(&mut source, &mut line_comments);
+            }
+            source.push_str(&trivia.text);
+        }
+    }
+    end_line(&mut source, &mut line_comments);
+    source
+}
+
+// The comments of the line being written, the first at its end and the others above it
+fn end_line(source: &mut String, comments: &mut Vec<String>) {
+    let mut comments = std::mem::take(comments).into_iter();
+    let Some(first) = comments.next() else {
+        return;
+    };
+    for comment in comments {
+        insert_line(source, &comment, false, false);
+    }
+    source.push(' ');
+    source.push_str(&first);
+}
+
+// A blank line before the line being written, unless one is already there
+fn insert_blank(source: &mut String) {
+    let line_start = source.rfind('\n').map_or(0, |newline| newline + 1);
+    if !source[..line_start].ends_with("\n\n") {
+        source.insert(line_start, '\n');
+    }
+}
+
+// A line with the comment, before the line being written and indented like it
+fn insert_line(source: &mut String, comment: &str, blank_before: bool, closes_block: bool) {
+    let line_start = source.rfind('\n').map_or(0, |newline| newline + 1);
+    let mut indentation: String = source[line_start..]
+        .chars()
+        .take_while(|c| *c == ' ' || *c == '\t')
+        .collect();
+    if closes_block {
+        indentation += INDENT;
+    }
+    let written = &source[..line_start];
+    let blank = blank_before
+        && !written.is_empty()
+        && !written.ends_with("\n\n")
+        && !written.trim_end().ends_with('{');
+    let line = format!(
+        "{}{}{}\n",
+        if blank { "\n" } else { "" },
+        indentation,
+        comment
+    );
+    source.insert_str(line_start, &line);
+}
+
+// For every token of the source, the token of the formatted program in its
+// place, none for the few the formatter leaves out
+fn align(original: &[LosslessToken], output: &[LosslessToken]) -> Vec<Option<usize>> {
+    let same = |from: usize, to: usize| match (original.get(from), output.get(to)) {
+        (Some(from), Some(to)) => from.token.token_type == to.token.token_type,
+        _ => false,
+    };
+    let mut aligned = vec![None; original.len()];
+    let (mut from, mut to) = (0, 0);
+    while from < original.len() && to < output.len() {
+        if same(from, to) {
+            aligned[from] = Some(to);
+            from += 1;
+            to += 1;
+            continue;
+        }
+        // The nearest place they are the same again, leaving tokens out of either
+        let skip = (1..=DRIFT).find_map(|skip| {
+            if same(from + skip, to) {
+                Some((skip, 0))
+            } else if same(from, to + skip) {
+                Some((0, skip))
+            } else {
+                None
+            }
+        });
+        match skip {
+            Some((skip_original, skip_output)) => {
+                from += skip_original;
+                to += skip_output;
+            }
+            None => {
+                aligned[from] = Some(to);
+                from += 1;
+                to += 1;
+            }
+        }
+    }
+    aligned
+}
@@ -0,0 +1,557 @@
+use std::io::{self, Read};
+
+use super::{expr::*, parser::Parser, pattern::Pattern, stmt::*};
+use crate::{
+    error::*,
+    lexer::{lexer::Lexer, token::*},
+    shared::Ref,
+};
+
+// A program kept parsed while it's edited, for editors
+//
+// The source is split into its top-level statements, each parsed on its own.
+// An edit lexes and parses again the statements it touches, and the ones after it
+// until the split lines up with the one before the edit. The others are kept,
+// their spans moved by the edit, so a keystroke costs about a statement
+pub struct Document {
+    source: Vec<char>,
+    items: Vec<Item>,
+    reparsed: usize, // Items parsed by the last edit
+}
+
+// A top-level statement, from the end of the one before up to and including its
+// `;` or `}`, and what parsing it found. The last one runs to the end of the source
+struct Item {
+    start: Position,
+    statements: Vec<Stmt>,
+    errors: Vec<Error>,
+    warnings: Vec<Warning>,
+}
+
+// Where a character is, the way the lexer counts
+#[derive(Debug, Clone, Copy)]
+struct Position {
+    offset: usize,
+    line: usize,
+    column: usize,
+}
+
+impl Position {
+    // Past the characters, the lexer counts columns from 1 after a newline
+    fn after(mut self, characters: &[char]) -> Position {
+        for c in characters {
+            self.offset += 1;
+            match c {
+                '\n' => (self.line, self.column) = (self.line + 1, 1),
+                _ => self.column += 1,
+            }
+        }
+        self
+    }
+}
+
+impl Document {
+    pub fn new(source: &str) -> Document {
+        let source: Vec<char> = source.chars().collect();
+        let (items, _) = split(&source, start_of(&source), |_| false);
+        Document {
+            reparsed: items.len(),
+            source,
+            items,
+        }
+    }
+
+    // Replaces the characters from `start` to `end`, character offsets, with `text`
+    pub fn edit(&mut self, start: usize, end: usize, text: &str) {
+        let end = end.min(self.source.len());
+        let start = start.min(end);
+        let text: Vec<char> = text.chars().collect();
+
+        // Before the edit, and where its end was and is now
+        let old_start = self.position(start);
+        let old_end = old_start.after(&self.source[start..end]);
+        let new_end = old_start.after(&text);
+        self.source.splice(start..end, text);
+
+        // From the item before the edited one, the edit may add an `else` to its statement
+        let first = self
+            .items
+            .partition_point(|item| item.start.offset <= start)
+            .saturating_sub(2);
+        let items = &self.items;
+        let (mut parsed, resumed) = split(&self.source, self.items[first].start, |offset| {
+            offset >= new_end.offset
+                && items
+                    .binary_search_by_key(&(offset - new_end.offset + old_end.offset), |item| {
+                        item.start.offset
+                    })
+                    .is_ok_and(|index| index > first)
+        });
+        self.reparsed = parsed.len();
+
+        // The items after the ones parsed again are the same text, somewhere else
+        let mut kept = match resumed {
+            Some(offset) => self.items.split_off(self.items.partition_point(|item| {
+                item.start.offset < offset - new_end.offset + old_end.offset
+            })),
+            None => Vec::new(),
+        };
+        let shift = Shift {
+            from: old_end,
+            to: new_end,
+        };
+        for item in kept.iter_mut() {
+            shift.item(item);
+        }
+        self.items.truncate(first);
+        self.items.append(&mut parsed);
+        self.items.append(&mut kept);
+    }
+
+    pub fn source(&self) -> &[char] {
+        &self.source
+    }
+
+    // A copy, resolving fills in the slots of variables
+    pub fn statements(&self) -> Vec<Stmt> {
+        self.items
+            .iter()
+            .flat_map(|item| item.statements.iter().cloned())
+            .collect()
+    }
+
+    // Of the lexer and the parser, in order
+    pub fn errors(&self) -> impl Iterator<Item = &Error> {
+        self.items.iter().flat_map(|item| item.errors.iter())
+    }
+
+    // Of the parser
+    pub fn warnings(&self) -> impl Iterator<Item = &Warning> {
+        self.items.iter().flat_map(|item| item.warnings.iter())
+    }
+
+    // Top-level statements lexed and parsed by the last edit, or by `new`
+    pub fn reparsed(&self) -> usize {
+        self.reparsed
+    }
+
+    fn position(&self, offset: usize) -> Position {
+        let item = &self.items[self
+            .items
+            .partition_point(|item| item.start.offset <= offset)
+            .saturating_sub(1)];
+        item.start
+            .after(&self.source[item.start.offset.min(offset)..offset])
+    }
+}
+
+// The start of a source, a byte order mark doesn't count for columns
+fn start_of(source: &[char]) -> Position {
+    Position {
+        offset: 0,
+        line: 1,
+        column: match source.first() {
+            Some('\u{feff}') => 0,
+            _ => 1,
+        },
+    }
+}
+
+// Lexes and parses items from `start` to the end of the source, or until `stop`
+// accepts the offset an item would start at, which is returned then
+fn split(
+    source: &[char],
+    start: Position,
+    stop: impl Fn(usize) -> bool,
+) -> (Vec<Item>, Option<usize>) {
+    let reader = Characters(&source[start.offset..]);
+    let mut lexer = match start.offset {
+        0 => Lexer::from_reader(reader),
+        _ => Lexer::resume(reader, start.offset, start.line, start.column),
+    };
+
+    let mut items = Vec::new();
+    let mut item_start = start;
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut depth = 0; // Of parentheses, brackets and braces
+    let mut end: Option<(Position, TokenType)> = None; // Of the statement, unless it goes on
+
+    while let Some(token) = lexer.stream().next() {
+        if let Some((end, last)) = end.take() {
+            if !goes_on(&last, &token.token_type) {
+                let errors = take_errors(&mut lexer.errors, end.offset);
+                items.push(parse(item_start, std::mem::take(&mut tokens), errors, end));
+                item_start = end;
+                if stop(end.offset) {
+                    return (items, Some(end.offset));
+                }
+            }
+        }
+
+        match token.token_type {
+            TokenType::LeftParen
+            | TokenType::LeftBrace
+            | TokenType::LeftBracket
+            | TokenType::HashBrace => depth += 1,
+            TokenType::RightParen | TokenType::RightBracket => {
+                depth = usize::saturating_sub(depth, 1)
+            }
+            TokenType::RightBrace | TokenType::SemiColon => {
+                if token.token_type == TokenType::RightBrace {
+                    depth = usize::saturating_sub(depth, 1);
+                }
+                if depth == 0 {
+                    let position = Position {
+                        offset: token.span.end,
+                        line: token.span.line,
+                        column: token.span.column + 1,
+                    };
+                    end = Some((position, token.token_type.clone()));
+                }
+            }
+            _ => {}
+        }
+
+        let eof = token.token_type == TokenType::EOF;
+        tokens.push(token);
+        if eof {
+            let errors = std::mem::take(&mut lexer.errors);
+            items.push(parse(item_start, tokens, errors, item_start));
+            break;
+        }
+    }
+    (items, None)
+}
+
+// Whether the statement ended by `last` goes on with `next`: an `if` with its `else`,
+// or an expression after the `}` of a map or a `match`. Tokens that could also start
+// a statement keep the two together, parsing them as one item is still right
+fn goes_on(last: &TokenType, next: &TokenType) -> bool {
+    use TokenType::*;
+    match last {
+        RightBrace => !matches!(
+            next,
+            Class
+                | Fun
+                | For
+                | If
+                | Print
+                | Return
+                | Var
+                | While
+                | Yield
+                | LeftBrace
+                | Identifier
+                | EOF
+        ),
+        _ => *next == Else,
+    }
+}
+
+// The lexer errors before `end`, the lexer may have gone on to the next token
+fn take_errors(errors: &mut Vec<Error>, end: usize) -> Vec<Error> {
+    let after = errors.iter().position(|error| error.span().start >= end);
+    let rest = errors.split_off(after.unwrap_or(errors.len()));
+    std::mem::replace(errors, rest)
+}
+
+// `end` is where an EOF goes, when the tokens don't end in one
+fn parse(start: Position, mut tokens: Vec<Token>, mut errors: Vec<Error>, end: Position) -> Item {
+    if tokens.last().map(|token| &token.token_type) != Some(&TokenType::EOF) {
+        tokens.push(Token::new(
+            TokenType::EOF,
+            Ref::from(""),
+            None,
+            Span::new(end.offset, end.offset, end.line, end.column),
+        ));
+    }
+
+    let mut parser = Parser::new(tokens);
+    parser.parse();
+    errors.extend(parser.get_errors().iter().cloned());
+    Item {
+        start,
+        statements: std::mem::take(parser.get_parsed_statements()),
+        errors,
+        warnings: parser.get_warnings().clone(),
+    }
+}
+
+// Reads characters as UTF-8, so the lexer gets them without a copy of the source
+// A buffer too short for the next character reads nothing, Source's never is
+struct Characters<'characters>(&'characters [char]);
+
+impl Read for Characters<'_> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let mut read = 0;
+        while let Some(c) = self.0.first() {
+            if read + c.len_utf8() > buffer.len() {
+                break;
+            }
+            read += c.encode_utf8(&mut buffer[read..]).len();
+            self.0 = &self.0[1..];
+        }
+        Ok(read)
+    }
+}
+
+// Moves what's after an edit to where it is now, `from` and `to` are the end of the
+// edit before and after it. Only columns on the line the edit ends on change
+struct Shift {
+    from: Position,
+    to: Position,
+}
+
+impl Shift {
+    fn position(&self, position: &mut Position) {
+        let mut span = Span::new(
+            position.offset,
+            position.offset,
+            position.line,
+            position.column,
+        );
+        self.span(&mut span);
+        (position.offset, position.line, position.column) = (span.start, span.line, span.column);
+    }
+
+    fn span(&self, span: &mut Span) {
+        span.start = span.start - self.from.offset + self.to.offset;
+        span.end = span.end - self.from.offset + self.to.offset;
+        if span.line == self.from.line {
+            span.column = span.column - self.from.column + self.to.column;
+        }
+        span.line = span.line - self.from.line + self.to.line;
+    }
+
+    fn item(&self, item: &mut Item) {
+        self.position(&mut item.start);
+        for statement in item.statements.iter_mut() {
+            self.statement(statement);
+        }
+        for error in item.errors.iter_mut() {
+            self.span(error.span_mut());
+            if let Some(opened) = error.opened_mut() {
+                self.span(opened);
+            }
+        }
+        for warning in item.warnings.iter_mut() {
+            self.span(&mut warning.span);
+        }
+    }
+
+    fn token(&self, token: &mut Token) {
+        self.span(&mut token.span);
+    }
+
+    fn statement(&self, statement: &mut Stmt) {
+        match statement {
+            Stmt::Block(statements) => statements.iter_mut().for_each(|stmt| self.statement(stmt)),
+            Stmt::Class(name, superclass, mixins, methods, _) => {
+                self.token(name);
+                if let Some(superclass) = superclass {
+                    self.expression(superclass);
+                }
+                mixins.iter_mut().for_each(|mixin| self.expression(mixin));
+                methods.iter_mut().for_each(|method| self.statement(method));
+            }
+            Stmt::Expression(expr) | Stmt::Print(expr) => self.expression(expr),
+            Stmt::Import(keyword, name) => {
+                self.token(keyword);
+                self.token(name);
+            }
+            Stmt::ForLoop(initializer, condition, incrementer, body) => {
+                if let Some(initializer) = initializer {
+                    self.statement(initializer);
+                }
+                for expr in [condition, incrementer].into_iter().flatten() {
+                    self.expression(expr);
+                }
+                self.statement(body);
+            }
+            Stmt::ForIn(name, iterable, body) => {
+                self.token(name);
+                self.expression(iterable);
+                self.statement(body);
+            }
+            Stmt::Function(name, parameters, body, signature, _) => {
+                self.token(name);
+                parameters
+                    .iter_mut()
+                    .for_each(|parameter| self.token(parameter));
+                body.iter_mut().for_each(|stmt| self.statement(stmt));
+                for annotation in signature
+                    .parameters
+                    .iter_mut()
+                    .chain([&mut signature.return_type])
+                    .flatten()
+                {
+                    self.token(annotation);
+                }
+            }
+            Stmt::IfElse(condition, then_branch, else_branch) => {
+                self.expression(condition);
+                self.statement(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch);
+                }
+            }
+            Stmt::Return(keyword, value) => {
+                self.token(keyword);
+                if let Some(value) = value {
+                    self.expression(value);
+                }
+            }
+            Stmt::Throw(keyword, value) => {
+                self.token(keyword);
+                self.expression(value);
+            }
+            Stmt::Try(body, catches) => {
+                body.iter_mut().for_each(|stmt| self.statement(stmt));
+                for catch in catches.iter_mut() {
+                    self.token(&mut catch.name);
+                    if let Some(class) = &mut catch.class {
+                        self.expression(class);
+                    }
+                    catch.body.iter_mut().for_each(|stmt| self.statement(stmt));
+                }
+            }
+            Stmt::Yield(keyword, value, variable) => {
+                self.token(keyword);
+                if let Some(value) = value {
+                    self.expression(value);
+                }
+                if let Some(variable) = variable {
+                    self.token(variable);
+                }
+            }
+            Stmt::Var(name, annotation, initializer) => {
+                self.token(name);
+                if let Some(annotation) = annotation {
+                    self.token(annotation);
+                }
+                if let Some(initializer) = initializer {
+                    self.expression(initializer);
+                }
+            }
+            Stmt::Unpack(names, initializer) => {
+                names.iter_mut().for_each(|name| self.token(name));
+                if let Some(initializer) = initializer {
+                    self.expression(initializer);
+                }
+            }
+            Stmt::WhileLoop(condition, body) => {
+                self.expression(condition);
+                self.statement(body);
+            }
+            Stmt::With(keyword, resource, name, body) => {
+                self.token(keyword);
+                self.expression(resource);
+                self.token(name);
+                body.iter_mut().for_each(|stmt| self.statement(stmt));
+            }
+            Stmt::Error(tokens) => tokens.iter_mut().for_each(|token| self.token(token)),
+        }
+    }
+
+    fn expression(&self, expr: &mut Expr) {
+        match expr {
+            Expr::Binary(left, operator, right) | Expr::Logical(left, operator, right) => {
+                self.expression(left);
+                self.token(operator);
+                self.expression(right);
+            }
+            Expr::Call(callee, paren, arguments) => {
+                self.expression(callee);
+                self.token(paren);
+                arguments
+                    .iter_mut()
+                    .for_each(|argument| self.expression(argument));
+            }
+            Expr::Get(object, name) | Expr::OptionalGet(object, name) => {
+                self.expression(object);
+                self.token(name);
+            }
+            Expr::Index(object, index, close) => {
+                self.expression(object);
+                self.expression(index);
+                self.token(close);
+            }
+            Expr::Set(object, name, value) => {
+                self.expression(object);
+                self.token(name);
+                self.expression(value);
+            }
+            Expr::Grouping(expr) => self.expression(expr),
+            Expr::Unary(operator, right)
+            | Expr::Spawn(operator, right)
+            | Expr::Await(operator, right) => {
+                self.token(operator);
+                self.expression(right);
+            }
+            Expr::Literal(token) | Expr::Variable(token, _) | Expr::This(token, _) => {
+                self.token(token)
+            }
+            Expr::Super(keyword, method, ..) => {
+                self.token(keyword);
+                self.token(method);
+            }
+            Expr::Assign(name, value, _) => {
+                self.token(name);
+                self.expression(value);
+            }
+            Expr::Tuple(values) => values.iter_mut().for_each(|value| self.expression(value)),
+            Expr::List(open, elements, close) | Expr::SetLiteral(open, elements, close) => {
+                self.token(open);
+                elements
+                    .iter_mut()
+                    .for_each(|element| self.expression(element));
+                self.token(close);
+            }
+            Expr::Map(open, entries, close) => {
+                self.token(open);
+                for (key, value) in entries.iter_mut() {
+                    self.expression(key);
+                    self.expression(value);
+                }
+                self.token(close);
+            }
+            Expr::Match(keyword, value, arms, close) => {
+                self.token(keyword);
+                self.expression(value);
+                for arm in arms.iter_mut() {
+                    self.pattern(&mut arm.pattern);
+                    self.expression(&mut arm.body);
+                }
+                self.token(close);
+            }
+        }
+    }
+
+    fn pattern(&self, pattern: &mut Pattern) {
+        match pattern {
+            Pattern::Wildcard(token) | Pattern::Literal(token) | Pattern::Binding(token) => {
+                self.token(token)
+            }
+            Pattern::List(open, elements) => {
+                self.token(open);
+                elements
+                    .iter_mut()
+                    .for_each(|element| self.pattern(element));
+            }
+            Pattern::Map(open, entries) => {
+                self.token(open);
+                for (key, pattern) in entries.iter_mut() {
+                    self.token(key);
+                    self.pattern(pattern);
+                }
+            }
+            Pattern::Instance(class, fields) => {
+                self.expression(class);
+                for (field, pattern) in fields.iter_mut() {
+                    self.token(field);
+                    self.pattern(pattern);
+                }
+            }
+        }
+    }
+}
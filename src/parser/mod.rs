@@ -1,4 +1,7 @@
 pub mod astprinter;
 pub mod expr;
-pub mod stmt;
+pub mod formatter;
+pub mod incremental;
 pub mod parser;
+pub mod pattern;
+pub mod stmt;
@@ -1,16 +1,36 @@
-use super::{expr::*, stmt::*};
+use super::{expr::*, pattern::*, stmt::*};
 use std::{
-    io::{self, Write},
+    collections::{HashMap, HashSet},
     ops::Deref,
 };
 
-use crate::{error::*, lexer::token::*};
+use crate::{error::*, lexer::token::*, shared::Ref};
+
+// Deeper than this is an error. Parsing this deep takes more stack than a thread
+// gets by default, some 16 MB in a debug build, see interpreter::STACK_SIZE
+const MAX_NESTING: usize = 256;
+
+// Of a list, map or set literal, the bytecode counts them in two bytes
+const MAX_ELEMENTS: usize = 65535;
 
 pub struct Parser {
     tokens: Vec<Token>,
     current: usize,
-    statements: Vec<Box<Stmt>>,
-    errors: Vec<Box<Error>>,
+    depth: usize,         // Of declarations, statements and expressions being parsed
+    functions: usize,     // Function bodies being parsed, `yield` is only allowed in one
+    initializer: bool,    // The innermost one is an `init` method, which returns its instance
+    enclosing: Vec<bool>, // Classes whose methods are being parsed, innermost last, whether each has a superclass
+    statements: Vec<Stmt>,
+    errors: Vec<Error>,
+    warnings: Vec<Warning>,
+    assigned: HashSet<Ref<str>>, // Names assigned to anywhere, see check_overrides
+}
+
+// What `override` is checked against before running: the classes a class
+// inherits from or mixes in, by name, and the arity of its own methods
+struct ClassShape {
+    parents: Vec<Ref<str>>, // Mixins, then the superclass, in the order methods are looked up
+    methods: HashMap<Ref<str>, usize>,
 }
 
 /*
@@ -18,70 +38,267 @@ pub struct Parser {
 
     program     -> declaration* EOF ;
 
-    declaration -> class_declaration | fun_declaration | var_declaration | statement ;
+    declaration -> class_declaration | fun_declaration | var_declaration
+                 | import_declaration | statement ;
 
-    class_declaration  -> "class" IDENTIFIER ( "<" IDENTIFIER )? "{" function* "}" ;
+    class_declaration  -> "sealed"? "class" IDENTIFIER ( "<" IDENTIFIER )? ( "with" IDENTIFIER ( "," IDENTIFIER )* )? "{" method* "}" ;
+    method             -> "override"? function ;
     fun_declaration    -> "fun" function ;
-    function           -> IDENTIFIER "(" parameters? ")" block ;
-    parameters         -> IDENTIFIER ( "," IDENTIFIER )* ;
-
-    var_declaration    -> "var" IDENTIFIER ( "=" expression )? ";" ;
+    function           -> IDENTIFIER "(" parameters? ")" ( "->" type )? block ;
+    parameters         -> parameter ( "," parameter )* ","? ;
+    parameter          -> IDENTIFIER ( ":" type )? ;
+    type               -> IDENTIFIER | "nil" ;
+    import_declaration -> "import" IDENTIFIER ";" ;
+
+    var_declaration    -> "var" IDENTIFIER ( ":" type )? ( "=" expression )? ";"
+                        | "var" IDENTIFIER "=" yield_statement
+                        | "var" IDENTIFIER ( "," IDENTIFIER )+ ( "=" expressions )? ";" ;
     statement          -> expression_statement | for_statement | while_statement
-                        | if_statement | print_statement | return_statement | block ;
+                        | if_statement | print_statement | return_statement
+                        | yield_statement | throw_statement | try_statement
+                        | with_statement | block ;
 
     for_statement      -> "for" "(" ( var_declaration | expression_statement | ";" )
                         expression? ";"
-                        expression? ")" statement ;
+                        expression? ")" statement
+                        | "for" "(" "var" IDENTIFIER "in" expression ")" statement ;
     while_statement    -> "while" "(" expression ")" statement ;
     if_statement       -> "if" "(" expression ")" statement ("else" statement)? ;
-    return_statement   -> "return" expression? ;
+    return_statement   -> "return" expressions? ";" ;
+    yield_statement    -> "yield" expressions? ";" ;
+    throw_statement    -> "throw" expression ";" ;
+    try_statement      -> "try" block catch+ ;
+    catch              -> "catch" "(" IDENTIFIER ( ":" IDENTIFIER )? ")" block ;
+    with_statement     -> "with" expression "as" IDENTIFIER block ;
     block              -> "{" declaration* "}" ;
 
     expression_statement    -> expression ";" ;
     print_statement         -> "print" expression ";" ;
 
+    expressions -> expression ( "," expression )* ;
     expression  -> assignment ;
     assignment  -> ( call "." )? IDENTIFIER "=" assignment | logic_or ;
     logic_or    -> logic_and ( "or" logic_and )* ;
     logic_and   -> equality ( "and" equality )* ;
-    equality    -> comparison ( ( "!=" | "==" ) comparison )* ;
-    comparison  -> term ( ( ">" | ">=" | "<" | "<=" ) term )* ;
+    equality    -> comparison ( ( "!=" | "==" | "is" ) comparison )* ;
+    comparison  -> term ( ( ">" | ">=" | "<" | "<=" | "in" ) term )* ;
     term        -> factor ( ( "-" | "+" ) factor )* ;
     factor      -> unary ( ( "/" | "*" ) unary )* ;
-    unary       -> ( "!" | "-" ) unary
+    unary       -> ( "!" | "-" | "await" ) unary
+                | "spawn" call
                 | call ;
-    call        -> primary ( "(" arguments? ")" | "." IDENTIFUER )* ;
-    arguments   -> expression ( "," expression )* ;
-    primary     -> NUMBER | STRING | IDENTIFIER | "true" | "false"
-                | "nil" | "(" expression ")";
+    call        -> primary ( "(" arguments? ")" | ( "." | ".?" ) IDENTIFIER | "[" expression "]" )* ;
+    arguments   -> expression ( "," expression )* ","? ;
+    primary     -> NUMBER | STRING | IDENTIFIER | "true" | "false" | "nil"
+                | "this" | super | "(" expression ")" | list | map | set | match ;
+    super       -> "super" "." IDENTIFIER ;
+    list        -> "[" ( expression ( "," expression )* ","? )? "]" ;
+    set         -> "#{" ( expression ( "," expression )* ","? )? "}" ;
+    map         -> "{" ( expression ":" expression ( "," expression ":" expression )* ","? )? "}" ;
+    match       -> "match" expression "{" arm ( "," arm )* ","? "}" ;
+    arm         -> pattern "=>" expression ;
+    pattern     -> "_" | IDENTIFIER | literal
+                | "[" ( pattern ( "," pattern )* ","? )? "]"
+                | "{" ( literal ":" pattern ( "," literal ":" pattern )* ","? )? "}"
+                | IDENTIFIER "{" ( field ( "," field )* ","? )? "}" ;
+    field       -> IDENTIFIER ( ":" pattern )? ;
+    literal     -> NUMBER | "-" NUMBER | STRING | "true" | "false" | "nil" ;
 */
 
 impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Parser {
+    pub fn new(mut tokens: Vec<Token>) -> Parser {
+        // Parsing stops at EOF, tokens not from a Lexer may not end in one
+        if tokens.last().map(|token| &token.token_type) != Some(&TokenType::EOF) {
+            let span = tokens
+                .last()
+                .map(|token| {
+                    Span::new(
+                        token.span.end,
+                        token.span.end,
+                        token.span.line,
+                        token.span.column,
+                    )
+                })
+                .unwrap_or(Span::new(0, 0, 1, 1));
+            tokens.push(Token::new(TokenType::EOF, Ref::from(""), None, span));
+        }
+
         Parser {
             tokens,
             current: 0,
+            depth: 0,
+            functions: 0,
+            initializer: false,
+            enclosing: Vec::new(),
             statements: Vec::new(),
             errors: Vec::new(),
+            warnings: Vec::new(),
+            assigned: HashSet::new(),
+        }
+    }
+
+    // The statements of a whole program, or every error in it
+    // Warnings are left out, a Parser keeps them
+    pub fn parse_program(tokens: Vec<Token>) -> Result<Vec<Stmt>, Vec<Error>> {
+        let mut parser = Parser::new(tokens);
+        parser.parse();
+        match parser.errors.is_empty() {
+            true => Ok(parser.statements),
+            false => Err(parser.errors),
         }
     }
 
-    pub fn get_parsed_statements(&mut self) -> &mut Vec<Box<Stmt>> {
+    pub fn get_parsed_statements(&mut self) -> &mut Vec<Stmt> {
         &mut self.statements
     }
 
-    pub fn get_errors(&mut self) -> &Vec<Box<Error>> {
+    pub fn get_errors(&mut self) -> &Vec<Error> {
         &self.errors
     }
 
+    pub fn get_warnings(&mut self) -> &Vec<Warning> {
+        &self.warnings
+    }
+
+    // Numbers, strings and nil as conditions rely on truthiness and always pick
+    // the same branch, `0` being false is easy to forget
+    fn check_condition(&mut self, condition: &Expr) {
+        let Expr::Literal(literal) = condition else {
+            return;
+        };
+        let always = match (&literal.token_type, &literal.literal) {
+            (TokenType::Number, Some(LiteralType::NumberType(number))) => *number != 0.0,
+            (TokenType::String, _) => true,
+            (TokenType::Nil, _) => false,
+            _ => return,
+        };
+        self.warnings.push(Warning::new(
+            WarningKind::ImplicitTruthiness,
+            format!(
+                "`{}` used as a condition is always {}",
+                literal.lexeme, always
+            ),
+            literal.span,
+        ));
+    }
+
+    // Parse the tokens and serialize the resulting syntax tree to JSON
+    // The first parse error is returned instead if the program is invalid
+    #[cfg(feature = "serde")]
+    pub fn parse_to_json(&mut self) -> Result<String, Error> {
+        self.parse();
+
+        if let Some(error) = self.errors.first() {
+            return Err(error.clone());
+        }
+
+        serde_json::to_string(&self.statements)
+            .map_err(|error| Error::parser(error.to_string(), self.peek().span))
+    }
+
     pub fn parse(&mut self) {
         // program  -> statement* EOF ;
         while !self.is_at_end() {
+            let start = self.current;
             match self.declaration() {
-                Ok(statement) => self.statements.push(Box::new(statement)),
-                Err(_) => self.synchronize(),
+                Ok(statement) => self.statements.push(statement),
+                Err(_) => {
+                    self.synchronize();
+                    self.statements.push(self.error_statement(start));
+                }
             }
         }
+        self.check_overrides();
+    }
+
+    // A method marked `override` has to replace one the class would otherwise
+    // get from a mixin or its superclass, taking as many parameters. What the
+    // parents are is up to the running program, which checks it when the class
+    // is made. It is only known before when they are classes declared earlier
+    // at the top level, under names nothing else binds or assigns
+    fn check_overrides(&mut self) {
+        // None for a name bound to something else
+        let mut classes: HashMap<Ref<str>, Option<ClassShape>> = HashMap::new();
+        let mut errors = Vec::new();
+        for statement in self.statements.iter() {
+            let (name, shape) = match statement {
+                Stmt::Class(name, superclass, mixins, methods, _) => {
+                    let parents: Vec<Ref<str>> = mixins
+                        .iter()
+                        .chain(superclass.as_deref())
+                        .filter_map(|parent| match parent {
+                            Expr::Variable(name, _) => Some(name.lexeme.clone()),
+                            _ => None,
+                        })
+                        .collect();
+                    let mut arities = HashMap::new();
+                    for method in methods.iter() {
+                        let Stmt::Function(method, parameters, _, signature, _) = method else {
+                            continue;
+                        };
+                        arities.insert(method.lexeme.clone(), parameters.len());
+                        if signature.overriding && !parents.is_empty() {
+                            errors.extend(self.check_override(
+                                &classes,
+                                name,
+                                &parents,
+                                method,
+                                parameters.len(),
+                            ));
+                        }
+                    }
+                    let shape = ClassShape {
+                        parents,
+                        methods: arities,
+                    };
+                    (name, Some(shape))
+                }
+                Stmt::Var(name, ..) | Stmt::Function(name, ..) | Stmt::Import(_, name) => {
+                    (name, None)
+                }
+                Stmt::Unpack(names, _) => {
+                    for name in names.iter() {
+                        classes.insert(name.lexeme.clone(), None);
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+            let known = shape.filter(|_| !self.assigned.contains(&name.lexeme));
+            classes.insert(name.lexeme.clone(), known);
+        }
+        for error in errors {
+            self.errors.push(error);
+        }
+    }
+
+    // The error of a method of `class` marked `override`, none when it is right
+    // or what `parents` are isn't known
+    fn check_override(
+        &self,
+        classes: &HashMap<Ref<str>, Option<ClassShape>>,
+        class: &Token,
+        parents: &[Ref<str>],
+        name: &Token,
+        arity: usize,
+    ) -> Option<Error> {
+        let message = match inherited(classes, parents, &name.lexeme, &mut Vec::new())? {
+            Some((_, inherited)) if inherited == arity => return None,
+            Some((owner, inherited)) => format!(
+                "`{}` overrides the `{}` of `{}`, which takes {} parameter{}, but takes {}",
+                name.lexeme,
+                name.lexeme,
+                owner,
+                inherited,
+                if inherited == 1 { "" } else { "s" },
+                arity
+            ),
+            None => format!(
+                "`{}` is marked `override`, but `{}` inherits no `{}`",
+                name.lexeme, class.lexeme, name.lexeme
+            ),
+        };
+        Some(Error::parser(message, name.span))
     }
 
     // Synchronizing to avoid cacading errors
@@ -89,21 +306,42 @@ impl Parser {
         // Call upon encountering a ParseError
         // Parser will ignore all-tokens till and including ";"
         // or untill encountering start of new statement
+        self.skip_statement(false);
+    }
+
+    // Same as synchronize, inside a block it also stops before the `}` closing it
+    // so the rest of the block is still parsed
+    fn synchronize_block(&mut self) {
+        self.skip_statement(true);
+    }
 
+    fn skip_statement(&mut self, in_block: bool) {
+        // Only a `;` skipped here ends the statement, after a stray `else` the `;`
+        // before it is the one of the statement before
+        let start = self.current;
         while !self.is_at_end() {
-            if self.previous().token_type == TokenType::SemiColon {
+            if self.current > start && self.previous().token_type == TokenType::SemiColon {
+                return;
+            }
+            if in_block && self.check(TokenType::RightBrace) {
                 return;
             }
 
             match self.peek().token_type {
                 TokenType::Class
+                | TokenType::Sealed
                 | TokenType::Fun
                 | TokenType::Var
                 | TokenType::For
                 | TokenType::If
+                | TokenType::Import
                 | TokenType::While
                 | TokenType::Print
-                | TokenType::Return => {
+                | TokenType::Return
+                | TokenType::Throw
+                | TokenType::Try
+                | TokenType::With
+                | TokenType::Yield => {
                     return;
                 }
                 _ => {
@@ -113,22 +351,58 @@ impl Parser {
         }
     }
 
-    // declaration -> class_declaration | fun_declaration | var_declaration | statement ;
+    // The tokens from `start` up to where parsing picks up again
+    fn error_statement(&self, start: usize) -> Stmt {
+        Stmt::Error(self.tokens[start..self.current].to_vec())
+    }
+
+    // Skips the rest of an argument or parameter in error, up to the `,` or `)` after it
+    // Parentheses opened inside it are skipped whole
+    // False when the list can't be recovered, the statement ends first
+    fn synchronize_list(&mut self) -> bool {
+        let mut depth = 0;
+        while !self.is_at_end() {
+            match self.peek().token_type {
+                TokenType::Comma | TokenType::RightParen if depth == 0 => return true,
+                TokenType::LeftParen | TokenType::LeftBracket => depth += 1,
+                TokenType::RightParen | TokenType::RightBracket => depth -= 1,
+                TokenType::SemiColon | TokenType::LeftBrace | TokenType::RightBrace => {
+                    return false
+                }
+                _ => {}
+            }
+            let _ = self.advance();
+        }
+        false
+    }
+
+    // declaration -> class_declaration | fun_declaration | var_declaration
+    //               | import_declaration | statement ;
     // just a special statement
     fn declaration(&mut self) -> Result<Stmt, Error> {
-        if self.match_next(vec![TokenType::Fun]) {
-            self.fun_declaration()
-        } else if self.match_next(vec![TokenType::Class]) {
-            self.class_declaration()
-        } else if self.match_next(vec![TokenType::Var]) {
-            self.var_declaration()
-        } else {
-            self.statement()
-        }
+        self.nested(|parser| {
+            if parser.match_next(vec![TokenType::Fun]) {
+                parser.fun_declaration()
+            } else if parser.match_next(vec![TokenType::Sealed]) {
+                parser.consume(
+                    TokenType::Class,
+                    "Expected keyword `class` after `sealed`".to_string(),
+                )?;
+                parser.class_declaration(true)
+            } else if parser.match_next(vec![TokenType::Class]) {
+                parser.class_declaration(false)
+            } else if parser.match_next(vec![TokenType::Var]) {
+                parser.var_declaration()
+            } else if parser.match_next(vec![TokenType::Import]) {
+                parser.import_declaration()
+            } else {
+                parser.statement()
+            }
+        })
     }
 
-    // class_declaration -> "class" IDENTIFIER ( "<" IDENTIFIER )? "{" function* "}" ;
-    fn class_declaration(&mut self) -> Result<Stmt, Error> {
+    // class_declaration -> "sealed"? "class" IDENTIFIER ( "<" IDENTIFIER )? ( "with" IDENTIFIER ( "," IDENTIFIER )* )? "{" method* "}" ;
+    fn class_declaration(&mut self, sealed: bool) -> Result<Stmt, Error> {
         let class_name = self.consume(
             TokenType::Identifier,
             "Expected the class name Identifier after keyword `class`".to_string(),
@@ -136,31 +410,81 @@ impl Parser {
 
         let mut superclass = None;
         if self.match_next(vec![TokenType::Less]) {
-            self.consume(TokenType::Identifier, "Expect superclass name".to_string())?;
-            superclass = Some(Box::new(Expr::variable(self.previous())));
+            let name = self.consume(TokenType::Identifier, "Expect superclass name".to_string())?;
+            if name.lexeme == class_name.lexeme {
+                self.push_error("A class can't inherit from itself".to_string(), name.span);
+            }
+            superclass = Some(Expr::variable(name));
+        }
+        let mut mixins = Vec::new();
+        if self.match_next(vec![TokenType::With]) {
+            loop {
+                let name = self.consume(TokenType::Identifier, "Expect mixin name".to_string())?;
+                if name.lexeme == class_name.lexeme {
+                    self.push_error("A class can't mix itself in".to_string(), name.span);
+                }
+                mixins.push(Expr::variable(name));
+                if !self.match_next(vec![TokenType::Comma]) {
+                    break;
+                }
+            }
         }
-        let starting_brace = self.consume(
+        self.consume(
             TokenType::LeftBrace,
             "Expected `{` before class body".to_string(),
         )?;
 
-        let mut methods = Box::<Vec<Stmt>>::default();
-        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
-            methods.push(self.function("method".to_string())?)
-        }
+        let mut methods = Vec::new();
+        let parented = superclass.is_some() || !mixins.is_empty();
+        self.enclosing.push(superclass.is_some());
+        let parsed = self.methods(&class_name, parented, &mut methods);
+        self.enclosing.pop();
+        parsed?;
 
         self.consume(
             TokenType::RightBrace,
             "Expected `}` after method body".to_string(),
         )?;
-        Ok(Stmt::class(class_name, superclass, methods))
+        Ok(Stmt::class(class_name, superclass, mixins, methods, sealed))
+    }
+
+    // method  -> "override"? function ;
+    fn methods(
+        &mut self,
+        class_name: &Token,
+        parented: bool,
+        methods: &mut Vec<Stmt>,
+    ) -> Result<(), Error> {
+        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
+            let overriding = self.match_next(vec![TokenType::Override]);
+            let mut method = self.function("method".to_string())?;
+            if let Stmt::Function(name, parameters, _, signature, _) = &mut method {
+                // It runs on its own, when an instance is dropped, nothing gives it arguments
+                if &*name.lexeme == "__drop" && !parameters.is_empty() {
+                    self.push_error("`__drop` can't have parameters".to_string(), name.span);
+                }
+                // What it replaces is only known when the class is made, but
+                // with nothing to inherit from there is nothing to replace
+                if overriding && !parented {
+                    let message = format!(
+                        "`{}` is marked `override`, but `{}` has no superclass or mixin",
+                        name.lexeme, class_name.lexeme
+                    );
+                    self.push_error(message, name.span);
+                }
+                signature.overriding = overriding;
+            }
+            methods.push(method)
+        }
+        Ok(())
     }
 
-    // fun_declaration -> "fun" IDENTIFIER "(" parameters ")" block ;
+    // fun_declaration -> "fun" function ;
     fn fun_declaration(&mut self) -> Result<Stmt, Error> {
         self.function(String::from("function"))
     }
 
+    // function  -> IDENTIFIER "(" parameters? ")" ( "->" type )? block ;
     fn function(&mut self, callable_type: String) -> Result<Stmt, Error> {
         let name = self.consume(
             TokenType::Identifier,
@@ -172,17 +496,29 @@ impl Parser {
         );
 
         let mut parameters = Vec::new();
+        let mut signature = Signature::default();
         if !self.check(TokenType::RightParen) {
             loop {
                 if parameters.len() == 255 {
-                    self.push_error("Too many parameters: 255 parameters allowed".to_string());
+                    self.push_error(
+                        "Too many parameters: 255 parameters allowed".to_string(),
+                        self.peek().span,
+                    );
                 }
 
-                parameters.push(self.consume(
-                    TokenType::Identifier,
-                    "Expected a parameter name".to_string(),
-                )?);
-                if !self.match_next(vec![TokenType::Comma]) {
+                match self.parameter() {
+                    Ok((parameter, annotation)) => {
+                        parameters.push(parameter);
+                        signature.parameters.push(annotation);
+                    }
+                    Err(error) => {
+                        if !self.synchronize_list() {
+                            return Err(error);
+                        }
+                    }
+                }
+                // A trailing comma is allowed
+                if !self.match_next(vec![TokenType::Comma]) || self.check(TokenType::RightParen) {
                     break;
                 }
             }
@@ -191,82 +527,178 @@ impl Parser {
             TokenType::RightParen,
             "Expected a `)` after parameters".to_string(),
         );
+        signature.return_type = self.optional_annotation(TokenType::Arrow)?;
 
         let _ = self.consume(
             TokenType::LeftBrace,
             "Expected `{` in function declaration and define function block".to_string(),
         );
-        let body = self.block()?;
+        let initializer = callable_type == "method" && &*name.lexeme == "init";
+        let enclosing = std::mem::replace(&mut self.initializer, initializer);
+        self.functions += 1;
+        let body = self.block();
+        self.functions -= 1;
+        self.initializer = enclosing;
+        let body = body?;
+
+        Ok(Stmt::function(name, parameters, body, signature))
+    }
+
+    // parameter  -> IDENTIFIER ( ":" type )? ;
+    fn parameter(&mut self) -> Result<(Token, Option<Token>), Error> {
+        let name = self.consume(
+            TokenType::Identifier,
+            "Expected a parameter name".to_string(),
+        )?;
+        Ok((name, self.optional_annotation(TokenType::Colon)?))
+    }
+
+    // type  -> IDENTIFIER | "nil" ;
+    // Only parsed when preceded by `separator` (`:` or `->`), annotations are optional
+    fn optional_annotation(&mut self, separator: TokenType) -> Result<Option<Token>, Error> {
+        if !self.match_next(vec![separator]) {
+            return Ok(None);
+        }
 
-        Ok(Stmt::function(name, Box::new(parameters), Box::new(body)))
+        if self.match_next(vec![TokenType::Identifier, TokenType::Nil]) {
+            return Ok(Some(self.previous()));
+        }
+        Err(self.push_error("Expected a type name".to_string(), self.error_span()))
     }
 
-    // var_declaration -> "var" IDENTIFIER ( "=" expression )? ";" ;
+    // import_declaration -> "import" IDENTIFIER ";" ;
+    fn import_declaration(&mut self) -> Result<Stmt, Error> {
+        let keyword = self.previous();
+        let name = self.consume(
+            TokenType::Identifier,
+            "Expected the name of a module after `import`".to_string(),
+        )?;
+        self.consume(
+            TokenType::SemiColon,
+            "Expected a `;` in the end of a statement".to_string(),
+        )?;
+        Ok(Stmt::import(keyword, name))
+    }
+
+    // var_declaration -> "var" IDENTIFIER ( ":" type )? ( "=" expression )? ";"
+    //                  | "var" IDENTIFIER ( "," IDENTIFIER )+ ( "=" expressions )? ";" ;
     fn var_declaration(&mut self) -> Result<Stmt, Error> {
         let variable_name = self.consume(
             TokenType::Identifier,
             "Expected a variable name".to_string(),
         )?;
+        if self.check(TokenType::Comma) {
+            return self.unpack_declaration(variable_name);
+        }
+        let annotation = self.optional_annotation(TokenType::Colon)?;
+
+        let mut initializer = None;
+        if self.match_next(vec![TokenType::Equal]) {
+            // The variable gets the value the generator is resumed with
+            if self.match_next(vec![TokenType::Yield]) {
+                return self.yield_statement(Some(variable_name));
+            }
+            initializer = Some(self.expression()?);
+        }
+
+        self.consume(TokenType::SemiColon, "Expected `;` in the end".to_string())?;
+        Ok(Stmt::var(variable_name, annotation, initializer))
+    }
+
+    // Several names take the values of a tuple, `var x, y = f();` with `f` returning two
+    fn unpack_declaration(&mut self, first_name: Token) -> Result<Stmt, Error> {
+        let mut names = vec![first_name];
+        while self.match_next(vec![TokenType::Comma]) {
+            if names.len() == 255 {
+                self.push_error(
+                    "Too many variables: 255 variables allowed".to_string(),
+                    self.peek().span,
+                );
+            }
+            names.push(self.consume(
+                TokenType::Identifier,
+                "Expected a variable name".to_string(),
+            )?);
+        }
 
-        let mut initializer: Option<Box<Expr>> = None;
+        let mut initializer = None;
         if self.match_next(vec![TokenType::Equal]) {
-            initializer = Some(Box::new(self.expression()?));
+            initializer = Some(self.expressions()?);
         }
 
         self.consume(TokenType::SemiColon, "Expected `;` in the end".to_string())?;
-        Ok(Stmt::var(variable_name, initializer))
+        Ok(Stmt::unpack(names, initializer))
     }
 
     // statement  -> expression_statement | for_statement | while_statement | if_statement
-    //              | print_statement | return_statement | block ;
+    //              | print_statement | return_statement | yield_statement | throw_statement
+    //              | try_statement | with_statement | block ;
     fn statement(&mut self) -> Result<Stmt, Error> {
-        if self.match_next(vec![TokenType::For]) {
-            self.for_statement()
-        } else if self.match_next(vec![TokenType::While]) {
-            self.while_statement()
-        } else if self.match_next(vec![TokenType::If]) {
-            self.if_statement()
-        } else if self.match_next(vec![TokenType::Print]) {
-            self.print_statement()
-        } else if self.match_next(vec![TokenType::Return]) {
-            self.return_statement()
-        } else if self.match_next(vec![TokenType::LeftBrace]) {
-            Ok(Stmt::block(Box::new(self.block()?)))
-        } else {
-            self.expression_statement()
-        }
+        self.nested(|parser| {
+            if parser.match_next(vec![TokenType::For]) {
+                parser.for_statement()
+            } else if parser.match_next(vec![TokenType::While]) {
+                parser.while_statement()
+            } else if parser.match_next(vec![TokenType::If]) {
+                parser.if_statement()
+            } else if parser.match_next(vec![TokenType::Print]) {
+                parser.print_statement()
+            } else if parser.match_next(vec![TokenType::Return]) {
+                parser.return_statement()
+            } else if parser.match_next(vec![TokenType::Yield]) {
+                parser.yield_statement(None)
+            } else if parser.match_next(vec![TokenType::Throw]) {
+                parser.throw_statement()
+            } else if parser.match_next(vec![TokenType::Try]) {
+                parser.try_statement()
+            } else if parser.match_next(vec![TokenType::With]) {
+                parser.with_statement()
+            } else if parser.match_next(vec![TokenType::LeftBrace]) {
+                Ok(Stmt::block(parser.block()?))
+            } else {
+                parser.expression_statement()
+            }
+        })
     }
 
     // for_statement  -> "for" "(" ( var_declaration | expression_statement | ";" )
     //                    expression? ";"
-    //                    expression? ")" statement ;
+    //                    expression? ")" statement
+    //                  | "for" "(" "var" IDENTIFIER "in" expression ")" statement ;
     fn for_statement(&mut self) -> Result<Stmt, Error> {
         self.consume(TokenType::LeftParen, "Expected `(` after `for`".to_string())?;
+        if self.check(TokenType::Var)
+            && self.check_after(1, TokenType::Identifier)
+            && self.check_after(2, TokenType::In)
+        {
+            return self.for_in_statement();
+        }
 
         // for (var i = 0 ; i < 1 ; i = i + 1) {......}
         //      ^^^         ^^^      ^^^           ^^^
         // initializer   condition   incrementer   loop_body
 
-        let mut initializer: Option<Stmt> = None;
-        if self.match_next(vec![TokenType::SemiColon]) {
-            initializer = None
-        } else if (self.match_next(vec![TokenType::Var])) {
-            initializer = Some(self.var_declaration()?)
+        let initializer = if self.match_next(vec![TokenType::SemiColon]) {
+            None
+        } else if self.match_next(vec![TokenType::Var]) {
+            Some(self.var_declaration()?)
         } else {
-            initializer = Some(self.expression_statement()?)
-        }
+            Some(self.expression_statement()?)
+        };
 
-        let mut condition: Option<Expr> = None;
+        let mut condition = None;
         if !self.check(TokenType::SemiColon) {
-            condition = Some(self.expression()?)
+            let expr = self.expression()?;
+            self.check_condition(&expr);
+            condition = Some(expr)
         }
         self.consume(
             TokenType::SemiColon,
             "Expected `;` after loop condition".to_string(),
         )?;
 
-        let mut incrementer: Option<Expr> = None;
-        if !self.check(TokenType::SemiColon) {
+        let mut incrementer = None;
+        if !self.check(TokenType::RightParen) {
             incrementer = Some(self.expression()?)
         }
         self.consume(
@@ -274,48 +706,38 @@ impl Parser {
             "Expected `)` after for clauses".to_string(),
         )?;
 
-        let mut loop_body = self.statement()?;
-
-        // Desugar for loop into while loop
-        //
-        // this:
-        // for (var i = 0; i < 1; i = i + 1) {...}
-        //
-        // to:
-        // var i = 0;
-        // while (i < 1) {
-        // ...
-        // i = i + 1
-        // }
-
-        if let Some(incrementer_) = incrementer {
-            loop_body = Stmt::block(Box::new(vec![
-                loop_body,
-                Stmt::expression(Box::new(incrementer_)),
-            ]));
-        }
+        let loop_body = self.statement()?;
 
-        if let None = condition {
-            condition = Some(Expr::literal(Token::new(
-                TokenType::True,
-                "true".to_string(),
-                None,
-                1, // Line doesn't matter
-            )))
-        }
-        loop_body = Stmt::whileloop(Box::new(condition.unwrap()), Box::new(loop_body));
+        // The loop is kept as its own node (rather than desugared into a while loop)
+        // so tools like the formatter can reproduce it
+        Ok(Stmt::forloop(
+            initializer,
+            condition,
+            incrementer,
+            loop_body,
+        ))
+    }
 
-        if let Some(initializer_) = initializer {
-            loop_body = Stmt::block(Box::new(vec![initializer_, loop_body]))
-        }
+    // The `for (var name in iterable) body` form, from `var`
+    fn for_in_statement(&mut self) -> Result<Stmt, Error> {
+        self.advance();
+        let name = self.advance();
+        self.advance();
+        let iterable = self.expression()?;
+        self.consume(
+            TokenType::RightParen,
+            "Expected `)` after the iterable of a for loop".to_string(),
+        )?;
 
-        Ok(loop_body)
+        let loop_body = self.statement()?;
+        Ok(Stmt::forin(name, iterable, loop_body))
     }
 
     // while_statement  -> "while" "(" expression ")" statement ;
     fn while_statement(&mut self) -> Result<Stmt, Error> {
         self.consume(TokenType::LeftParen, "Expected `(` after while".to_string())?;
         let condition = self.expression()?;
+        self.check_condition(&condition);
         self.consume(
             TokenType::RightParen,
             "Expected `)` after condition".to_string(),
@@ -323,13 +745,14 @@ impl Parser {
 
         let loop_body = self.statement()?;
 
-        Ok(Stmt::WhileLoop(Box::new(condition), Box::new(loop_body)))
+        Ok(Stmt::whileloop(condition, loop_body))
     }
 
     // if_statement  -> "if" "(" expression ")" statement ("else" statement)? ;
     fn if_statement(&mut self) -> Result<Stmt, Error> {
         self.consume(TokenType::LeftParen, "Expected `(` after if".to_string())?;
         let condition = self.expression()?;
+        self.check_condition(&condition);
         self.consume(
             TokenType::RightParen,
             "Expected `)` after condition".to_string(),
@@ -339,32 +762,32 @@ impl Parser {
 
         if self.match_next(vec![TokenType::Else]) {
             let else_branch = self.statement()?;
-            return Ok(Stmt::IfElse(
-                Box::new(condition),
-                Box::new(then_branch),
-                Some(Box::new(else_branch)),
-            ));
+            return Ok(Stmt::ifelse(condition, then_branch, Some(else_branch)));
         }
 
-        Ok(Stmt::ifelse(
-            Box::new(condition),
-            Box::new(then_branch),
-            None,
-        ))
+        Ok(Stmt::ifelse(condition, then_branch, None))
     }
 
-    // return_statement  -> "return" expression? ;
+    // return_statement  -> "return" expressions? ";" ;
     fn return_statement(&mut self) -> Result<Stmt, Error> {
         let return_keyword = self.previous();
-        let mut return_value = Expr::literal(Token::new(
-            TokenType::Nil,
-            "nil".to_string(),
-            None,
-            return_keyword.line,
-        ));
+        if self.functions == 0 {
+            self.push_error(
+                "Can't return from top-level code".to_string(),
+                return_keyword.span,
+            );
+        }
 
+        // A bare `return;` returns nil, `return a, b;` a tuple
+        let mut return_value = None;
         if !self.check(TokenType::SemiColon) {
-            return_value = self.expression()?
+            if self.initializer {
+                self.push_error(
+                    "Can't return a value from an initializer".to_string(),
+                    return_keyword.span,
+                );
+            }
+            return_value = Some(self.expressions()?)
         }
 
         let _ = self.consume(
@@ -372,24 +795,131 @@ impl Parser {
             "Expected a `;` in the end of a statement".to_string(),
         )?;
 
-        Ok(Stmt::ret(return_keyword, Box::new(return_value)))
+        Ok(Stmt::ret(return_keyword, return_value))
+    }
+
+    // yield_statement  -> "yield" expressions? ";" ;
+    // A function with one is a generator function, `variable` is the one of `var x = yield ...;`
+    fn yield_statement(&mut self, variable: Option<Token>) -> Result<Stmt, Error> {
+        let yield_keyword = self.previous();
+        if self.functions == 0 {
+            self.push_error(
+                "Can't yield outside of a function".to_string(),
+                yield_keyword.span,
+            );
+        } else if self.initializer {
+            self.push_error(
+                "Can't yield from an initializer".to_string(),
+                yield_keyword.span,
+            );
+        }
+
+        let mut value = None;
+        if !self.check(TokenType::SemiColon) {
+            value = Some(self.expressions()?)
+        }
+
+        self.consume(
+            TokenType::SemiColon,
+            "Expected a `;` in the end of a statement".to_string(),
+        )?;
+        Ok(Stmt::Yield(yield_keyword, value.map(Box::new), variable))
+    }
+
+    // throw_statement  -> "throw" expression ";" ;
+    fn throw_statement(&mut self) -> Result<Stmt, Error> {
+        let throw_keyword = self.previous();
+        let value = self.expression()?;
+        self.consume(
+            TokenType::SemiColon,
+            "Expected a `;` in the end of a statement".to_string(),
+        )?;
+        Ok(Stmt::throw(throw_keyword, value))
+    }
+
+    // try_statement  -> "try" block catch+ ;
+    // catch          -> "catch" "(" IDENTIFIER ( ":" IDENTIFIER )? ")" block ;
+    fn try_statement(&mut self) -> Result<Stmt, Error> {
+        self.consume(TokenType::LeftBrace, "Expected `{` after `try`".to_string())?;
+        let body = self.block()?;
+
+        let mut catches = Vec::new();
+        while self.match_next(vec![TokenType::Catch]) {
+            self.consume(
+                TokenType::LeftParen,
+                "Expected `(` after `catch`".to_string(),
+            )?;
+            let name = self.consume(
+                TokenType::Identifier,
+                "Expected the name of the error".to_string(),
+            )?;
+            let mut class = None;
+            if self.match_next(vec![TokenType::Colon]) {
+                let class_name = self.consume(
+                    TokenType::Identifier,
+                    "Expected the class of the errors to catch".to_string(),
+                )?;
+                class = Some(Expr::variable(class_name));
+            }
+            self.consume(
+                TokenType::RightParen,
+                "Expected `)` after the error".to_string(),
+            )?;
+            self.consume(
+                TokenType::LeftBrace,
+                "Expected `{` before the catch body".to_string(),
+            )?;
+            let body = self.block()?;
+            catches.push(Catch { name, class, body });
+        }
+
+        if catches.is_empty() {
+            let span = self.error_span();
+            return Err(self.push_error("Expected `catch` after the try body".to_string(), span));
+        }
+        Ok(Stmt::try_catch(body, catches))
+    }
+
+    // with_statement  -> "with" expression "as" IDENTIFIER block ;
+    fn with_statement(&mut self) -> Result<Stmt, Error> {
+        let keyword = self.previous();
+        let resource = self.expression()?;
+        self.consume(
+            TokenType::As,
+            "Expected `as` after the resource".to_string(),
+        )?;
+        let name = self.consume(
+            TokenType::Identifier,
+            "Expected the name of the resource".to_string(),
+        )?;
+        self.consume(
+            TokenType::LeftBrace,
+            "Expected `{` before the with body".to_string(),
+        )?;
+        let body = self.block()?;
+        Ok(Stmt::with(keyword, resource, name, body))
     }
 
     // block  -> "{" declaration* "}" ;
     fn block(&mut self) -> Result<Vec<Stmt>, Error> {
         let mut statements = Vec::<Stmt>::new();
 
+        // A statement in error becomes an Error statement, the others are still parsed
         while !self.check(TokenType::RightBrace) && !self.is_at_end() {
-            statements.push(self.declaration()?);
+            let start = self.current;
+            match self.declaration() {
+                Ok(statement) => statements.push(statement),
+                Err(_) => {
+                    self.synchronize_block();
+                    statements.push(self.error_statement(start));
+                }
+            }
         }
 
         self.consume(
             TokenType::RightBrace,
             "Expected `}` at the end of block".to_string(),
         )?;
-        for error in &self.errors {
-            error.report()
-        }
         Ok(statements)
     }
 
@@ -399,21 +929,42 @@ impl Parser {
 
         // Expression ends and now at `;`
         self.consume(TokenType::SemiColon, "Expected `;` at the end".to_string())?;
-        Ok(Stmt::print(Box::new(expr)))
+        Ok(Stmt::print(expr))
     }
 
-    // expression_statement  -> expression ;
+    // expression_statement  -> expression ";" ;
     fn expression_statement(&mut self) -> Result<Stmt, Error> {
         let expr = self.expression()?;
 
         // Expression ends and now at `;`
         self.consume(TokenType::SemiColon, "Expected `;` at the end".to_string())?;
-        Ok(Stmt::expression(Box::new(expr)))
+        Ok(Stmt::expression(expr))
+    }
+
+    // expressions  -> expression ( "," expression )* ;
+    // More than one make a tuple
+    fn expressions(&mut self) -> Result<Expr, Error> {
+        let first = self.expression()?;
+        if !self.check(TokenType::Comma) {
+            return Ok(first);
+        }
+
+        let mut values = vec![first];
+        while self.match_next(vec![TokenType::Comma]) {
+            if values.len() == 255 {
+                self.push_error(
+                    "Too many values: 255 values allowed".to_string(),
+                    self.peek().span,
+                );
+            }
+            values.push(self.expression()?);
+        }
+        Ok(Expr::tuple(values))
     }
 
     // expression  -> assignment ;
     fn expression(&mut self) -> Result<Expr, Error> {
-        self.assignment()
+        self.nested(Parser::assignment)
     }
 
     // assignment  -> ( call "." )? IDENTIFIER "=" assignment | logic_or ;
@@ -421,11 +972,13 @@ impl Parser {
         let left_side_identifier = self.logic_or()?;
 
         if self.match_next(vec![TokenType::Equal]) {
-            let equals = self.previous();
-            let right_side_expr = self.assignment()?;
+            let right_side_expr = self.nested(Parser::assignment)?;
 
             match left_side_identifier {
-                Expr::Variable(token) => return Ok(Expr::assign(token, right_side_expr)),
+                Expr::Variable(token, _) => {
+                    self.assigned.insert(token.lexeme.clone());
+                    return Ok(Expr::assign(token, right_side_expr));
+                }
                 Expr::Get(expression, token) => {
                     return Ok(Expr::set(
                         expression.deref().clone(),
@@ -434,7 +987,10 @@ impl Parser {
                     ))
                 }
                 _ => {
-                    return Err(self.push_error("Invalid assignment target".to_string()));
+                    return Err(self.push_error(
+                        "Invalid assignment target".to_string(),
+                        left_side_identifier.span(),
+                    ));
                 }
             }
         }
@@ -443,41 +999,42 @@ impl Parser {
 
     // logic_or  -> logic_and ( "or" logic_and )* ;
     fn logic_or(&mut self) -> Result<Expr, Error> {
-        let left_expr = self.logic_and()?;
+        let mut expr = self.logic_and()?;
 
-        if self.match_next(vec![TokenType::Or]) {
-            let logical_or = self.previous();
-            let right_expr = self.logic_and()?;
-            return Ok(Expr::logical(left_expr, logical_or, right_expr));
+        while self.match_next(vec![TokenType::Or]) {
+            self.deepen()?;
+            expr = Expr::logical(expr, self.previous(), self.logic_and()?);
         }
-
-        Ok(left_expr)
+        Ok(expr)
     }
 
     // logic_and  -> equality ( "and" equality )* ;
     fn logic_and(&mut self) -> Result<Expr, Error> {
-        let left_expr = self.equality()?;
+        let mut expr = self.equality()?;
 
-        if self.match_next(vec![TokenType::And]) {
-            let logical_and = self.previous();
-            let right_expr = self.equality()?;
-            return Ok(Expr::logical(left_expr, logical_and, right_expr));
+        while self.match_next(vec![TokenType::And]) {
+            self.deepen()?;
+            expr = Expr::logical(expr, self.previous(), self.equality()?);
         }
-
-        Ok(left_expr)
+        Ok(expr)
     }
 
-    // equality  -> comparison ( ( "!=" | "==" ) comparison )* ;
+    // equality  -> comparison ( ( "!=" | "==" | "is" ) comparison )* ;
     fn equality(&mut self) -> Result<Expr, Error> {
         let mut expr = self.comparison()?;
 
-        while self.match_next(vec![TokenType::BangEqual, TokenType::EqualEqual]) {
+        while self.match_next(vec![
+            TokenType::BangEqual,
+            TokenType::EqualEqual,
+            TokenType::Is,
+        ]) {
+            self.deepen()?;
             expr = Expr::binary(expr, self.previous(), self.comparison()?);
         }
         Ok(expr)
     }
 
-    // comparison  -> term ( ( ">" | ">=" | "<" | "<=" ) term )* ;
+    // comparison  -> term ( ( ">" | ">=" | "<" | "<=" | "in" ) term )* ;
     fn comparison(&mut self) -> Result<Expr, Error> {
         let mut expr = self.term()?;
 
@@ -486,7 +1043,9 @@ impl Parser {
             TokenType::GreaterEqual,
             TokenType::Less,
             TokenType::LessEqual,
+            TokenType::In,
         ]) {
+            self.deepen()?;
             expr = Expr::binary(expr, self.previous(), self.term()?)
         }
         Ok(expr)
@@ -495,36 +1054,59 @@ impl Parser {
     // term  -> factor ( ( "-" | "+" ) factor )* ;
     fn term(&mut self) -> Result<Expr, Error> {
         let mut expr = self.factor()?;
-        if self.match_next(vec![TokenType::Minus, TokenType::Plus]) {
+
+        while self.match_next(vec![TokenType::Minus, TokenType::Plus]) {
+            self.deepen()?;
             expr = Expr::binary(expr, self.previous(), self.factor()?);
         }
-
         Ok(expr)
     }
 
     // factor  -> unary ( ( "/" | "*" ) unary )* ;
     fn factor(&mut self) -> Result<Expr, Error> {
         let mut expr = self.unary()?;
-        if self.match_next(vec![TokenType::Slash, TokenType::Star]) {
+
+        while self.match_next(vec![TokenType::Slash, TokenType::Star]) {
+            self.deepen()?;
             expr = Expr::binary(expr, self.previous(), self.unary()?);
         }
-
         Ok(expr)
     }
 
-    // unary  -> ( "!" | "-" ) unary  |  call ;
+    // unary  -> ( "!" | "-" | "await" ) unary  |  "spawn" call  |  call ;
     fn unary(&mut self) -> Result<Expr, Error> {
         if self.match_next(vec![TokenType::Bang, TokenType::Minus]) {
-            return Ok(Expr::unary(self.previous(), self.unary()?));
+            return Ok(Expr::unary(self.previous(), self.nested(Parser::unary)?));
+        }
+        if self.match_next(vec![TokenType::Await]) {
+            let keyword = self.previous();
+            return Ok(Expr::await_promise(keyword, self.nested(Parser::unary)?));
+        }
+        if self.match_next(vec![TokenType::Spawn]) {
+            let keyword = self.previous();
+            let call = self.call()?;
+            if !matches!(call, Expr::Call(..)) {
+                return Err(
+                    self.push_error("Expected a call after `spawn`".to_string(), call.span())
+                );
+            }
+            return Ok(Expr::spawn(keyword, call));
         }
         self.call()
     }
 
-    // call  -> primary ( "(" arguments? ")" | "." IDENTIFIER )* ;
+    // call  -> primary ( "(" arguments? ")" | ( "." | ".?" ) IDENTIFIER | "[" expression "]" )* ;
     fn call(&mut self) -> Result<Expr, Error> {
         let mut expression = self.primary()?;
 
         loop {
+            if self.check(TokenType::LeftParen)
+                || self.check(TokenType::Dot)
+                || self.check(TokenType::DotQuestion)
+                || self.check(TokenType::LeftBracket)
+            {
+                self.deepen()?;
+            }
             if self.match_next(vec![TokenType::LeftParen]) {
                 expression = self.finish_call(expression)?;
             } else if self.match_next(vec![TokenType::Dot]) {
@@ -532,7 +1114,22 @@ impl Parser {
                     TokenType::Identifier,
                     "Expeceted property name after `.`".to_string(),
                 )?;
+                self.check_private(&expression, &name);
                 expression = Expr::get(expression, name)
+            } else if self.match_next(vec![TokenType::DotQuestion]) {
+                let name = self.consume(
+                    TokenType::Identifier,
+                    "Expected property name after `.?`".to_string(),
+                )?;
+                self.check_private(&expression, &name);
+                expression = Expr::optional_get(expression, name)
+            } else if self.match_next(vec![TokenType::LeftBracket]) {
+                let index = self.expression()?;
+                let close = self.consume(
+                    TokenType::RightBracket,
+                    "Expected `]` after the index".to_string(),
+                )?;
+                expression = Expr::index(expression, index, close)
             } else {
                 break;
             }
@@ -542,7 +1139,6 @@ impl Parser {
 
     fn finish_call(&mut self, callee: Expr) -> Result<Expr, Error> {
         let mut arguments = Vec::new();
-        let mut error = None;
 
         if !self.check(TokenType::RightParen) {
             loop {
@@ -550,10 +1146,20 @@ impl Parser {
                     let _ = self.push_error(
                         "Too many arguments. (A function can have at max 255 arguments)"
                             .to_string(),
+                        self.peek().span,
                     );
                 }
-                arguments.push(self.expression()?);
-                if !self.match_next(vec![TokenType::Comma]) {
+                // An argument in error is left out, the others are still parsed
+                match self.expression() {
+                    Ok(argument) => arguments.push(argument),
+                    Err(error) => {
+                        if !self.synchronize_list() {
+                            return Err(error);
+                        }
+                    }
+                }
+                // A trailing comma is allowed
+                if !self.match_next(vec![TokenType::Comma]) || self.check(TokenType::RightParen) {
                     break;
                 }
             }
@@ -564,14 +1170,11 @@ impl Parser {
             "Expected `)` after arguments".to_string(),
         )?;
 
-        if let Some(err) = error {
-            return Err(err);
-        }
         Ok(Expr::call(callee, closing_paren, arguments))
     }
 
-    // primary  -> NUMBER | STRING | IDENTIFIER | "true" | "false"
-    //           | "nil"  |  "(" expression ")";
+    // primary  -> NUMBER | STRING | IDENTIFIER | "true" | "false" | "nil"
+    //           | "this" | super | "(" expression ")" | list | map | set | match ;
     fn primary(&mut self) -> Result<Expr, Error> {
         if self.match_next(vec![
             TokenType::Nil,
@@ -584,7 +1187,44 @@ impl Parser {
         }
 
         if self.match_next(vec![TokenType::Identifier]) {
-            return Ok(Expr::Variable(self.previous()));
+            return Ok(Expr::variable(self.previous()));
+        }
+
+        if self.match_next(vec![TokenType::This]) {
+            let keyword = self.previous();
+            if self.enclosing.is_empty() {
+                self.push_error(
+                    "Can't use `this` outside of a class".to_string(),
+                    keyword.span,
+                );
+            }
+            return Ok(Expr::this(keyword));
+        }
+
+        // super  -> "super" "." IDENTIFIER ;
+        if self.match_next(vec![TokenType::Super]) {
+            let keyword = self.previous();
+            match self.enclosing.last() {
+                None => {
+                    self.push_error(
+                        "Can't use `super` outside of a class".to_string(),
+                        keyword.span,
+                    );
+                }
+                Some(false) => {
+                    self.push_error(
+                        "Can't use `super` in a class with no superclass".to_string(),
+                        keyword.span,
+                    );
+                }
+                Some(true) => {}
+            }
+            self.consume(TokenType::Dot, "Expected `.` after `super`".to_string())?;
+            let method = self.consume(
+                TokenType::Identifier,
+                "Expected a superclass method name".to_string(),
+            )?;
+            return Ok(Expr::super_method(keyword, method));
         }
 
         if self.match_next(vec![TokenType::LeftParen]) {
@@ -592,20 +1232,341 @@ impl Parser {
             self.consume(
                 TokenType::RightParen,
                 "Expect ')' after expresion.".to_string(),
-            )
-            .unwrap();
+            )?;
             return Ok(Expr::grouping(expr));
         }
 
-        Err(self.push_error("Unexpected Token".to_string()))
+        if self.match_next(vec![TokenType::LeftBracket]) {
+            return self.list();
+        }
+
+        // A `{` starting a statement is a block, anywhere else in an expression a map
+        if self.match_next(vec![TokenType::LeftBrace]) {
+            return self.map();
+        }
+
+        if self.match_next(vec![TokenType::HashBrace]) {
+            return self.set_literal();
+        }
+
+        if self.match_next(vec![TokenType::Match]) {
+            return self.match_expression();
+        }
+
+        Err(self.push_error("Unexpected Token".to_string(), self.peek().span))
+    }
+
+    // list  -> "[" ( expression ( "," expression )* ","? )? "]" ;
+    fn list(&mut self) -> Result<Expr, Error> {
+        let open = self.previous();
+        let mut elements = Vec::new();
+        while !self.check(TokenType::RightBracket) {
+            if elements.len() == MAX_ELEMENTS {
+                self.push_error(
+                    format!("Too many elements: {} elements allowed", MAX_ELEMENTS),
+                    self.peek().span,
+                );
+            }
+            elements.push(self.expression()?);
+            if !self.match_next(vec![TokenType::Comma]) {
+                break;
+            }
+        }
+
+        let close = self.consume(
+            TokenType::RightBracket,
+            "Expected `]` after the elements of a list".to_string(),
+        )?;
+        Ok(Expr::list(open, elements, close))
+    }
+
+    // map  -> "{" ( expression ":" expression ( "," expression ":" expression )* ","? )? "}" ;
+    fn map(&mut self) -> Result<Expr, Error> {
+        let open = self.previous();
+        let mut entries = Vec::new();
+        while !self.check(TokenType::RightBrace) {
+            if entries.len() == MAX_ELEMENTS {
+                self.push_error(
+                    format!("Too many entries: {} entries allowed", MAX_ELEMENTS),
+                    self.peek().span,
+                );
+            }
+            let key = self.expression()?;
+            self.consume(
+                TokenType::Colon,
+                "Expected `:` after the key of a map entry".to_string(),
+            )?;
+            entries.push((key, self.expression()?));
+            if !self.match_next(vec![TokenType::Comma]) {
+                break;
+            }
+        }
+
+        let close = self.consume(
+            TokenType::RightBrace,
+            "Expected `}` after the entries of a map".to_string(),
+        )?;
+        Ok(Expr::map(open, entries, close))
+    }
+
+    // set  -> "#{" ( expression ( "," expression )* ","? )? "}" ;
+    fn set_literal(&mut self) -> Result<Expr, Error> {
+        let open = self.previous();
+        let mut elements = Vec::new();
+        while !self.check(TokenType::RightBrace) {
+            if elements.len() == MAX_ELEMENTS {
+                self.push_error(
+                    format!("Too many elements: {} elements allowed", MAX_ELEMENTS),
+                    self.peek().span,
+                );
+            }
+            elements.push(self.expression()?);
+            if !self.match_next(vec![TokenType::Comma]) {
+                break;
+            }
+        }
+
+        let close = self.consume(
+            TokenType::RightBrace,
+            "Expected `}` after the elements of a set".to_string(),
+        )?;
+        Ok(Expr::set_literal(open, elements, close))
+    }
+
+    // match  -> "match" expression "{" arm ( "," arm )* ","? "}" ;
+    // arm    -> pattern "=>" expression ;
+    fn match_expression(&mut self) -> Result<Expr, Error> {
+        let keyword = self.previous();
+        let value = self.expression()?;
+        self.consume(
+            TokenType::LeftBrace,
+            "Expected `{` before the arms of a `match`".to_string(),
+        )?;
+
+        let mut arms = Vec::new();
+        while !self.check(TokenType::RightBrace) {
+            let pattern = self.nested(Parser::pattern)?;
+            self.check_bindings(&pattern);
+            self.consume(
+                TokenType::FatArrow,
+                "Expected `=>` after the pattern of an arm".to_string(),
+            )?;
+            let body = self.expression()?;
+            arms.push(Arm { pattern, body });
+            if !self.match_next(vec![TokenType::Comma]) {
+                break;
+            }
+        }
+
+        let close = self.consume(
+            TokenType::RightBrace,
+            "Expected `}` after the arms of a `match`".to_string(),
+        )?;
+        if arms.is_empty() {
+            return Err(self.push_error(
+                "A `match` needs at least one arm".to_string(),
+                keyword.span.to(close.span),
+            ));
+        }
+        Ok(Expr::matching(keyword, value, arms, close))
+    }
+
+    // pattern  -> "_" | IDENTIFIER | literal
+    //           | "[" ( pattern ( "," pattern )* ","? )? "]"
+    //           | "{" ( literal ":" pattern ( "," literal ":" pattern )* ","? )? "}"
+    //           | IDENTIFIER "{" ( field ( "," field )* ","? )? "}" ;
+    fn pattern(&mut self) -> Result<Pattern, Error> {
+        if let Some(literal) = self.pattern_literal() {
+            return Ok(Pattern::Literal(literal));
+        }
+
+        if self.match_next(vec![TokenType::LeftBracket]) {
+            let open = self.previous();
+            let mut elements = Vec::new();
+            while !self.check(TokenType::RightBracket) {
+                elements.push(self.nested(Parser::pattern)?);
+                if !self.match_next(vec![TokenType::Comma]) {
+                    break;
+                }
+            }
+            self.consume(
+                TokenType::RightBracket,
+                "Expected `]` after the elements of a list pattern".to_string(),
+            )?;
+            return Ok(Pattern::List(open, elements));
+        }
+
+        if self.match_next(vec![TokenType::LeftBrace]) {
+            let open = self.previous();
+            let mut entries = Vec::new();
+            while !self.check(TokenType::RightBrace) {
+                let Some(key) = self.pattern_literal() else {
+                    return Err(self.push_error(
+                        "Expected a literal as the key of a map pattern".to_string(),
+                        self.peek().span,
+                    ));
+                };
+                self.consume(
+                    TokenType::Colon,
+                    "Expected `:` after the key of a map pattern".to_string(),
+                )?;
+                entries.push((key, self.nested(Parser::pattern)?));
+                if !self.match_next(vec![TokenType::Comma]) {
+                    break;
+                }
+            }
+            self.consume(
+                TokenType::RightBrace,
+                "Expected `}` after the entries of a map pattern".to_string(),
+            )?;
+            return Ok(Pattern::Map(open, entries));
+        }
+
+        let name = self.consume(TokenType::Identifier, "Expected a pattern".to_string())?;
+        if self.match_next(vec![TokenType::LeftBrace]) {
+            return self.instance_pattern(name);
+        }
+        match &*name.lexeme {
+            "_" => Ok(Pattern::Wildcard(name)),
+            _ => Ok(Pattern::Binding(name)),
+        }
+    }
+
+    // field  -> IDENTIFIER ( ":" pattern )? ;
+    // A field without a pattern binds its value to its own name
+    fn instance_pattern(&mut self, class: Token) -> Result<Pattern, Error> {
+        let mut fields = Vec::new();
+        while !self.check(TokenType::RightBrace) {
+            let field = self.consume(
+                TokenType::Identifier,
+                "Expected a field name in an instance pattern".to_string(),
+            )?;
+            if is_private(&field.lexeme) {
+                let message = format!("`{}` is private, a pattern can't match it", field.lexeme);
+                self.push_error(message, field.span);
+            }
+            let pattern = match self.match_next(vec![TokenType::Colon]) {
+                true => self.nested(Parser::pattern)?,
+                false => Pattern::Binding(field.clone()),
+            };
+            fields.push((field, pattern));
+            if !self.match_next(vec![TokenType::Comma]) {
+                break;
+            }
+        }
+        self.consume(
+            TokenType::RightBrace,
+            "Expected `}` after the fields of an instance pattern".to_string(),
+        )?;
+        Ok(Pattern::Instance(Expr::variable(class), fields))
+    }
+
+    // A private property is only reached through `this`, which only parses
+    // in the methods of a class
+    fn check_private(&mut self, object: &Expr, name: &Token) {
+        if is_private(&name.lexeme) && !matches!(object, Expr::This(..)) {
+            let message = format!(
+                "`{}` is private, only `this.{}` can reach it",
+                name.lexeme, name.lexeme
+            );
+            self.push_error(message, name.span);
+        }
+    }
+
+    // literal  -> NUMBER | "-" NUMBER | STRING | "true" | "false" | "nil" ;
+    // A negative number is one token, as if it was written that way
+    fn pattern_literal(&mut self) -> Option<Token> {
+        if self.match_next(vec![
+            TokenType::Nil,
+            TokenType::True,
+            TokenType::False,
+            TokenType::String,
+            TokenType::Number,
+        ]) {
+            return Some(self.previous());
+        }
+
+        let next = self.tokens.get(self.current + 1)?;
+        if !self.check(TokenType::Minus) || next.token_type != TokenType::Number {
+            return None;
+        }
+        let minus = self.advance();
+        let number = self.advance();
+        let value = match number.literal {
+            Some(LiteralType::NumberType(value)) => -value,
+            _ => 0.0,
+        };
+        Some(Token::new(
+            TokenType::Number,
+            Ref::from(format!("-{}", number.lexeme)),
+            Some(LiteralType::NumberType(value)),
+            minus.span.to(number.span),
+        ))
+    }
+
+    // Every name is bound once in a pattern, `[x, x]` would have to pick one
+    fn check_bindings(&mut self, pattern: &Pattern) {
+        let bindings = pattern.bindings();
+        for (index, name) in bindings.iter().enumerate() {
+            if bindings[..index]
+                .iter()
+                .any(|earlier| earlier.lexeme == name.lexeme)
+            {
+                self.push_error(
+                    format!("`{}` is bound more than once in the pattern", name.lexeme),
+                    name.span,
+                );
+            }
+        }
     }
 
     // Move forward if "current" matches the type else error
+    // A closing bracket missing at the end says which one is left open instead
     fn consume(&mut self, token_type: TokenType, message: String) -> Result<Token, Error> {
         if self.check(token_type.clone()) {
             return Ok(self.advance());
         }
-        Err(self.push_error(message))
+        if token_type == TokenType::Identifier && self.peek().token_type.is_keyword() {
+            let message = format!(
+                "`{}` is a reserved keyword and cannot be used as an identifier",
+                self.peek().lexeme
+            );
+            let error = self.push_error(message, self.peek().span);
+            // Taken as the name, so recovering doesn't start a statement at it
+            self.advance();
+            return Err(error);
+        }
+        if let Some((what, opened)) = self
+            .is_at_end()
+            .then(|| self.unclosed(&token_type))
+            .flatten()
+        {
+            let error = Error::unclosed(ErrorKind::Parse, what, self.error_span(), opened);
+            self.errors.push(error.clone());
+            return Err(error);
+        }
+        Err(self.push_error(message, self.error_span()))
+    }
+
+    // The innermost `(`, `[`, `{` or `#{` before the current token that `closing`
+    // would close, as what is unclosed and where it was opened
+    fn unclosed(&self, closing: &TokenType) -> Option<(String, Span)> {
+        let mut depth = 0;
+        for token in self.tokens[..self.current].iter().rev() {
+            match (&token.token_type, closing) {
+                (token_type, closing) if token_type == closing => depth += 1,
+                (TokenType::LeftParen, TokenType::RightParen)
+                | (TokenType::LeftBracket, TokenType::RightBracket)
+                | (TokenType::LeftBrace | TokenType::HashBrace, TokenType::RightBrace) => {
+                    if depth == 0 {
+                        return Some((format!("`{}`", token.lexeme), token.span));
+                    }
+                    depth -= 1;
+                }
+                _ => {}
+            }
+        }
+        None
     }
 
     // Check if the "current" token is among the specified token types
@@ -620,14 +1581,83 @@ impl Parser {
         false
     }
 
+    // Parses something that can nest, an error instead when nested too deep
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Parser) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let depth = self.depth;
+        self.deepen()?;
+        let result = parse(self);
+        self.depth = depth;
+        result
+    }
+
+    // One level deeper, chains of operators go deeper with every operator
+    // until the `nested` they are in is done
+    fn deepen(&mut self) -> Result<(), Error> {
+        if self.depth >= MAX_NESTING {
+            let error = self.push_error(
+                format!("Nested too deep (at most {} levels)", MAX_NESTING),
+                self.peek().span,
+            );
+            self.skip_nested();
+            return Err(error);
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    // Skips what is nested too deep, up to the `;` ending the statement or the
+    // bracket closing the one it is in, otherwise every level would be an error
+    fn skip_nested(&mut self) {
+        let mut depth = 0;
+        while !self.is_at_end() {
+            match self.peek().token_type {
+                TokenType::LeftParen
+                | TokenType::LeftBrace
+                | TokenType::LeftBracket
+                | TokenType::HashBrace => depth += 1,
+                TokenType::RightParen
+                | TokenType::RightBrace
+                | TokenType::RightBracket
+                | TokenType::SemiColon
+                    if depth == 0 =>
+                {
+                    return
+                }
+                TokenType::RightParen | TokenType::RightBrace | TokenType::RightBracket => {
+                    depth -= 1
+                }
+                _ => {}
+            }
+            self.advance();
+        }
+    }
+
     // Add error to the list
     // Let main handle reporting
-    fn push_error(&mut self, error_message: String) -> Error {
-        let error = Error::parser(error_message, self.previous().line);
-        self.errors.push(Box::new(error.clone()));
+    fn push_error(&mut self, error_message: String, span: Span) -> Error {
+        let error = Error::parser(error_message, span);
+        self.errors.push(error.clone());
         error
     }
 
+    // Missing tokens are reported at the token found instead, unless it is on a later
+    // line than the previous one: a missing `;` is reported at the end of its own line
+    fn error_span(&self) -> Span {
+        let current = self.peek().span;
+        if self.current == 0 {
+            return current;
+        }
+
+        let previous = self.previous().span;
+        match current.line > previous.line {
+            true => previous,
+            false => current,
+        }
+    }
+
     // Check if the "current" token is of the specified token type
     fn check(&self, token_type: TokenType) -> bool {
         if self.is_at_end() {
@@ -637,6 +1667,13 @@ impl Parser {
         self.peek().token_type == token_type
     }
 
+    // Check the token `distance` after the current one, without moving
+    fn check_after(&self, distance: usize, token_type: TokenType) -> bool {
+        self.tokens
+            .get(self.current + distance)
+            .is_some_and(|token| token.token_type == token_type)
+    }
+
     fn advance(&mut self) -> Token {
         if !self.is_at_end() {
             self.current += 1;
@@ -644,10 +1681,12 @@ impl Parser {
         self.previous()
     }
 
+    // The first token before anything is consumed
     fn previous(&self) -> Token {
-        self.tokens[self.current - 1].clone()
+        self.tokens[self.current.saturating_sub(1)].clone()
     }
 
+    // `current` never moves past the EOF that `new` makes sure of
     fn peek(&self) -> Token {
         self.tokens[self.current].clone()
     }
@@ -656,3 +1695,37 @@ impl Parser {
         self.peek().token_type == TokenType::EOF
     }
 }
+
+// The class among `parents` and their own parents whose method `name` is looked
+// up first, and its arity. None when a parent on the way isn't known, Some(None)
+// when none of them has it. The built-in error classes have no methods
+fn inherited(
+    classes: &HashMap<Ref<str>, Option<ClassShape>>,
+    parents: &[Ref<str>],
+    name: &str,
+    visited: &mut Vec<Ref<str>>,
+) -> Option<Option<(Ref<str>, usize)>> {
+    for parent in parents.iter() {
+        if visited.contains(parent) {
+            continue;
+        }
+        visited.push(parent.clone());
+        let shape = match classes.get(parent) {
+            Some(shape) => shape.as_ref()?,
+            None if ErrorClass::ALL
+                .iter()
+                .any(|class| class.name() == &**parent) =>
+            {
+                continue
+            }
+            None => return None,
+        };
+        if let Some(arity) = shape.methods.get(name) {
+            return Some(Some((parent.clone(), *arity)));
+        }
+        if let found @ (None | Some(Some(_))) = inherited(classes, &shape.parents, name, visited) {
+            return found;
+        }
+    }
+    Some(None)
+}
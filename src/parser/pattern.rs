@@ -0,0 +1,141 @@
+use std::fmt;
+
+use super::expr::Expr;
+
+use crate::lexer::token::*;
+
+// What an arm of a `match` compares the value with
+//
+//     match value {
+//         [x, 0] => ...,           // A list of two elements, the second one 0
+//         {"name": name} => ...,   // A map with a "name" key, other keys aside
+//         Point { x, y: 0 } => ..., // An instance of Point or of a subclass of it
+//         0 => ...,
+//         _ => ...,
+//     }
+//
+// A name matches anything and binds it, `_` matches anything without binding it
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Pattern {
+    Wildcard(Token),
+    Literal(Token), // A number, string, `true`, `false` or `nil`
+    Binding(Token),
+    List(Token, Vec<Pattern>),         // `[`, a pattern for every element
+    Map(Token, Vec<(Token, Pattern)>), // `{`, literal keys and the patterns of their values
+    Instance(Expr, Vec<(Token, Pattern)>), // The class, field names and the patterns of their values
+}
+
+// `pattern => body`, the body is evaluated with the names the pattern binds
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Arm {
+    pub pattern: Pattern,
+    pub body: Expr,
+}
+
+impl Pattern {
+    // Names the pattern binds, in the order they are bound: left to right
+    pub fn bindings(&self) -> Vec<&Token> {
+        let mut bindings = Vec::new();
+        self.collect_bindings(&mut bindings);
+        bindings
+    }
+
+    fn collect_bindings<'a>(&'a self, bindings: &mut Vec<&'a Token>) {
+        match self {
+            Pattern::Binding(name) => bindings.push(name),
+            Pattern::List(_, elements) => elements
+                .iter()
+                .for_each(|element| element.collect_bindings(bindings)),
+            Pattern::Map(_, entries) | Pattern::Instance(_, entries) => entries
+                .iter()
+                .for_each(|(_, pattern)| pattern.collect_bindings(bindings)),
+            Pattern::Wildcard(_) | Pattern::Literal(_) => {}
+        }
+    }
+
+    // Classes of the instance patterns in it, outermost first
+    pub fn classes(&self) -> Vec<&Expr> {
+        match self {
+            Pattern::List(_, elements) => elements.iter().flat_map(Pattern::classes).collect(),
+            Pattern::Map(_, entries) => entries
+                .iter()
+                .flat_map(|(_, pattern)| pattern.classes())
+                .collect(),
+            Pattern::Instance(class, fields) => std::iter::once(class)
+                .chain(fields.iter().flat_map(|(_, pattern)| pattern.classes()))
+                .collect(),
+            Pattern::Wildcard(_) | Pattern::Literal(_) | Pattern::Binding(_) => Vec::new(),
+        }
+    }
+
+    // Where the pattern starts, and as far as its tokens tell where it ends
+    pub fn span(&self) -> Span {
+        match self {
+            Pattern::Wildcard(token) | Pattern::Literal(token) | Pattern::Binding(token) => {
+                token.span
+            }
+            Pattern::List(bracket, elements) => match elements.last() {
+                Some(last) => bracket.span.to(last.span()),
+                None => bracket.span,
+            },
+            Pattern::Map(brace, entries) => match entries.last() {
+                Some((_, last)) => brace.span.to(last.span()),
+                None => brace.span,
+            },
+            Pattern::Instance(class, fields) => match fields.last() {
+                Some((_, last)) => class.span().to(last.span()),
+                None => class.span(),
+            },
+        }
+    }
+}
+
+// As it is written, a field binding its own name without `: name`
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let join = |patterns: Vec<String>| patterns.join(", ");
+        match self {
+            Pattern::Wildcard(token) | Pattern::Literal(token) | Pattern::Binding(token) => {
+                write!(f, "{}", token.lexeme)
+            }
+            Pattern::List(_, elements) => {
+                write!(
+                    f,
+                    "[{}]",
+                    join(elements.iter().map(Pattern::to_string).collect())
+                )
+            }
+            Pattern::Map(_, entries) => write!(
+                f,
+                "{{{}}}",
+                join(
+                    entries
+                        .iter()
+                        .map(|(key, pattern)| format!("{}: {}", key.lexeme, pattern))
+                        .collect()
+                )
+            ),
+            Pattern::Instance(class, fields) => {
+                let class = match class {
+                    Expr::Variable(name, _) => name.lexeme.to_string(),
+                    _ => String::new(),
+                };
+                let fields: Vec<String> = fields
+                    .iter()
+                    .map(|(field, pattern)| match pattern {
+                        Pattern::Binding(name) if name.lexeme == field.lexeme => {
+                            field.lexeme.to_string()
+                        }
+                        _ => format!("{}: {}", field.lexeme, pattern),
+                    })
+                    .collect();
+                match fields.is_empty() {
+                    true => write!(f, "{} {{}}", class),
+                    false => write!(f, "{} {{ {} }}", class, join(fields)),
+                }
+            }
+        }
+    }
+}
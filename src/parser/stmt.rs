@@ -1,111 +1,355 @@
-use super::expr::{self, *};
+use super::expr::*;
 
 use crate::lexer::token::*;
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Stmt {
-    Block(Box<Vec<Stmt>>),
-    Class(Token, Option<Box<Expr>>, Box<Vec<Stmt>>),
+    Block(Vec<Stmt>),
+    Class(Token, Option<Box<Expr>>, Vec<Expr>, Vec<Stmt>, bool), // Name, Superclass, Mixins, Methods, Sealed
     Expression(Box<Expr>),
-    Function(Token, Box<Vec<Token>>, Box<Vec<Stmt>>),
+    ForLoop(
+        Option<Box<Stmt>>,
+        Option<Box<Expr>>,
+        Option<Box<Expr>>,
+        Box<Stmt>,
+    ), // Initializer, Condition, Incrementer, Body
+    ForIn(Token, Box<Expr>, Box<Stmt>), // Name, Iterable, Body
+    Function(Token, Vec<Token>, Vec<Stmt>, Box<Signature>, Vec<Slot>), // Name, Parameters, Body, Signature, Slots of the captured variables (filled in by the Resolver)
     IfElse(Box<Expr>, Box<Stmt>, Option<Box<Stmt>>), // Condition, Then_branch, Else_branch
+    Import(Token, Token),                            // Keyword, Name of the module
     Print(Box<Expr>),
-    Return(Token, Box<Expr>),
-    Var(Token, Option<Box<Expr>>),
+    Return(Token, Option<Box<Expr>>),
+    Throw(Token, Box<Expr>),                      // Keyword, Value thrown
+    Try(Vec<Stmt>, Vec<Catch>),                   // Body, Catch clauses in order
+    Var(Token, Option<Token>, Option<Box<Expr>>), // Name, Type annotation, Initializer
+    Unpack(Vec<Token>, Option<Box<Expr>>),        // Names, Initializer giving a tuple
     WhileLoop(Box<Expr>, Box<Stmt>),
+    With(Token, Box<Expr>, Token, Vec<Stmt>), // Keyword, Resource, Name, Body
+    Yield(Token, Option<Box<Expr>>, Option<Token>), // Keyword, Value, Variable of `var x = yield ...;`
+    // A declaration that failed to parse, the tokens it was made of
+    // Kept in its place so tools still see the rest of the program where it is
+    Error(Vec<Token>),
+}
+
+// Optional type annotations of a function declaration
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Signature {
+    pub parameters: Vec<Option<Token>>, // One per parameter
+    pub return_type: Option<Token>,
+    pub overriding: bool, // A method marked `override`
+}
+
+// `catch (name: Class) { body }`, without a class it catches anything
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Catch {
+    pub name: Token,
+    pub class: Option<Expr>,
+    pub body: Vec<Stmt>,
 }
 
 impl Stmt {
-    pub fn block(statements: Box<Vec<Stmt>>) -> Stmt {
+    pub fn block(statements: Vec<Stmt>) -> Stmt {
         Stmt::Block(statements)
     }
 
-    pub fn class(name: Token, super_class: Option<Box<Expr>>, statements: Box<Vec<Stmt>>) -> Stmt {
-        Stmt::Class(name, super_class, statements)
+    pub fn class(
+        name: Token,
+        super_class: Option<Expr>,
+        mixins: Vec<Expr>,
+        statements: Vec<Stmt>,
+        sealed: bool,
+    ) -> Stmt {
+        Stmt::Class(name, super_class.map(Box::new), mixins, statements, sealed)
+    }
+
+    pub fn expression(expr: Expr) -> Stmt {
+        Stmt::Expression(Box::new(expr))
     }
 
-    pub fn expression(expr: Box<Expr>) -> Stmt {
-        Stmt::Expression(expr)
+    pub fn forloop(
+        initializer: Option<Stmt>,
+        condition: Option<Expr>,
+        incrementer: Option<Expr>,
+        body: Stmt,
+    ) -> Stmt {
+        Stmt::ForLoop(
+            initializer.map(Box::new),
+            condition.map(Box::new),
+            incrementer.map(Box::new),
+            Box::new(body),
+        )
     }
 
-    pub fn function(name: Token, parameters: Box<Vec<Token>>, body: Box<Vec<Stmt>>) -> Stmt {
-        Stmt::Function(name, parameters, body)
+    pub fn forin(name: Token, iterable: Expr, body: Stmt) -> Stmt {
+        Stmt::ForIn(name, Box::new(iterable), Box::new(body))
     }
 
-    pub fn ifelse(
-        condition: Box<Expr>,
-        then_branch: Box<Stmt>,
-        else_branch: Option<Box<Stmt>>,
+    pub fn function(
+        name: Token,
+        parameters: Vec<Token>,
+        body: Vec<Stmt>,
+        signature: Signature,
     ) -> Stmt {
-        Stmt::IfElse(condition, then_branch, else_branch)
+        Stmt::Function(name, parameters, body, Box::new(signature), Vec::new())
+    }
+
+    pub fn ifelse(condition: Expr, then_branch: Stmt, else_branch: Option<Stmt>) -> Stmt {
+        Stmt::IfElse(
+            Box::new(condition),
+            Box::new(then_branch),
+            else_branch.map(Box::new),
+        )
+    }
+
+    pub fn import(keyword: Token, name: Token) -> Stmt {
+        Stmt::Import(keyword, name)
+    }
+
+    pub fn print(expr: Expr) -> Stmt {
+        Stmt::Print(Box::new(expr))
+    }
+
+    pub fn ret(keyword: Token, expr: Option<Expr>) -> Stmt {
+        Stmt::Return(keyword, expr.map(Box::new))
+    }
+
+    pub fn throw(keyword: Token, value: Expr) -> Stmt {
+        Stmt::Throw(keyword, Box::new(value))
+    }
+
+    pub fn try_catch(body: Vec<Stmt>, catches: Vec<Catch>) -> Stmt {
+        Stmt::Try(body, catches)
+    }
+
+    pub fn var(variable_name: Token, annotation: Option<Token>, expr: Option<Expr>) -> Stmt {
+        Stmt::Var(variable_name, annotation, expr.map(Box::new))
+    }
+
+    pub fn unpack(names: Vec<Token>, expr: Option<Expr>) -> Stmt {
+        Stmt::Unpack(names, expr.map(Box::new))
+    }
+
+    pub fn whileloop(condition: Expr, statement: Stmt) -> Stmt {
+        Stmt::WhileLoop(Box::new(condition), Box::new(statement))
     }
 
-    pub fn print(expr: Box<Expr>) -> Stmt {
-        Stmt::Print(expr)
+    pub fn with(keyword: Token, resource: Expr, name: Token, body: Vec<Stmt>) -> Stmt {
+        Stmt::With(keyword, Box::new(resource), name, body)
     }
 
-    pub fn ret(keyword: Token, expr: Box<Expr>) -> Stmt {
-        Stmt::Return(keyword, expr)
+    // Line the statement starts on, as far as its tokens tell
+    // Empty blocks and `for (;;) {}` have none
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            Stmt::Block(statements) => statements.first().and_then(Stmt::line),
+            Stmt::Try(body, catches) => body
+                .first()
+                .and_then(Stmt::line)
+                .or_else(|| catches.first().map(|catch| catch.name.span.line)),
+            Stmt::Class(name, ..)
+            | Stmt::Function(name, ..)
+            | Stmt::Var(name, _, _)
+            | Stmt::ForIn(name, _, _)
+            | Stmt::Import(_, name) => Some(name.span.line),
+            Stmt::Expression(expr) | Stmt::Print(expr) => Some(expr.span().line),
+            Stmt::ForLoop(initializer, condition, incrementer, body) => initializer
+                .as_ref()
+                .and_then(|initializer| initializer.line())
+                .or_else(|| condition.as_ref().map(|condition| condition.span().line))
+                .or_else(|| {
+                    incrementer
+                        .as_ref()
+                        .map(|incrementer| incrementer.span().line)
+                })
+                .or_else(|| body.line()),
+            Stmt::IfElse(condition, _, _) | Stmt::WhileLoop(condition, _) => {
+                Some(condition.span().line)
+            }
+            Stmt::Return(keyword, _)
+            | Stmt::Throw(keyword, _)
+            | Stmt::With(keyword, ..)
+            | Stmt::Yield(keyword, ..) => Some(keyword.span.line),
+            Stmt::Unpack(names, _) | Stmt::Error(names) => names.first().map(|name| name.span.line),
+        }
     }
-    pub fn var(variable_name: Token, expr: Option<Box<Expr>>) -> Stmt {
-        Stmt::Var(variable_name, expr)
+
+    // Whether running it can yield, a `yield` in a function declared inside doesn't count
+    // A function whose body yields is a generator function
+    pub fn yields(&self) -> bool {
+        match self {
+            Stmt::Yield(..) => true,
+            Stmt::Block(statements) | Stmt::With(_, _, _, statements) => {
+                statements.iter().any(Stmt::yields)
+            }
+            Stmt::Try(body, catches) => {
+                body.iter().any(Stmt::yields)
+                    || catches
+                        .iter()
+                        .any(|catch| catch.body.iter().any(Stmt::yields))
+            }
+            Stmt::ForLoop(_, _, _, body) | Stmt::ForIn(_, _, body) | Stmt::WhileLoop(_, body) => {
+                body.yields()
+            }
+            Stmt::IfElse(_, then_branch, else_branch) => {
+                then_branch.yields() || else_branch.as_ref().is_some_and(|branch| branch.yields())
+            }
+            Stmt::Class(..)
+            | Stmt::Expression(_)
+            | Stmt::Function(..)
+            | Stmt::Import(..)
+            | Stmt::Print(_)
+            | Stmt::Return(..)
+            | Stmt::Throw(..)
+            | Stmt::Var(..)
+            | Stmt::Unpack(..)
+            | Stmt::Error(_) => false,
+        }
     }
 
-    pub fn whileloop(condition: Box<Expr>, statement: Box<Stmt>) -> Stmt {
-        Stmt::WhileLoop(condition, statement)
+    // Whether running it can go on to the statement after it, rather than always
+    // returning or throwing. A loop only ever ends through its condition, one
+    // that is `true` or left out never does
+    pub fn can_complete(&self) -> bool {
+        match self {
+            Stmt::Return(..) | Stmt::Throw(..) => false,
+            Stmt::Block(statements) | Stmt::With(_, _, _, statements) => {
+                statements.iter().all(Stmt::can_complete)
+            }
+            Stmt::IfElse(_, then_branch, Some(else_branch)) => {
+                then_branch.can_complete() || else_branch.can_complete()
+            }
+            Stmt::Try(body, catches) => {
+                body.iter().all(Stmt::can_complete)
+                    || catches
+                        .iter()
+                        .any(|catch| catch.body.iter().all(Stmt::can_complete))
+            }
+            Stmt::WhileLoop(condition, _) => !is_true(condition),
+            Stmt::ForLoop(_, condition, _, _) => condition
+                .as_ref()
+                .is_some_and(|condition| !is_true(condition)),
+            Stmt::IfElse(_, _, None)
+            | Stmt::Class(..)
+            | Stmt::Expression(_)
+            | Stmt::ForIn(..)
+            | Stmt::Function(..)
+            | Stmt::Import(..)
+            | Stmt::Print(_)
+            | Stmt::Var(..)
+            | Stmt::Unpack(..)
+            | Stmt::Yield(..)
+            | Stmt::Error(_) => true,
+        }
+    }
+
+    // The expression giving the statement a value, like the last one of a script
+    // or one typed into the REPL. An assignment is made for its effect, it gives none
+    pub fn value(&self) -> Option<&Expr> {
+        match self {
+            Stmt::Expression(expr) if !matches!(**expr, Expr::Assign(..) | Expr::Set(..)) => {
+                Some(expr)
+            }
+            _ => None,
+        }
     }
 }
 
+fn is_true(condition: &Expr) -> bool {
+    matches!(condition, Expr::Literal(token) if token.token_type == TokenType::True)
+}
+
 pub trait Visitable<T> {
-    fn accept(&mut self, visitor: &mut impl Visitor<T>) -> T;
+    fn accept(&self, visitor: &mut impl Visitor<T>) -> T;
 }
 
 impl<T> Visitable<T> for Stmt {
-    fn accept(&mut self, visitor: &mut impl Visitor<T>) -> T {
+    fn accept(&self, visitor: &mut impl Visitor<T>) -> T {
         match self {
             Stmt::Block(statements) => visitor.visit_block(statements),
-            Stmt::Class(name, superclass, statements) => {
-                visitor.visit_class(name, superclass, statements)
+            Stmt::Class(name, superclass, mixins, statements, sealed) => {
+                visitor.visit_class(name, superclass.as_deref(), mixins, statements, *sealed)
             }
             Stmt::Expression(expr) => visitor.visit_expression(expr),
-            Stmt::Function(name, parameters, body) => {
-                visitor.visit_function(name, parameters, body)
+            Stmt::ForLoop(initializer, condition, incrementer, body) => visitor.visit_forloop(
+                initializer.as_deref(),
+                condition.as_deref(),
+                incrementer.as_deref(),
+                body,
+            ),
+            Stmt::ForIn(name, iterable, body) => visitor.visit_forin(name, iterable, body),
+            Stmt::Function(name, parameters, body, signature, captures) => {
+                visitor.visit_function(name, parameters, body, signature, captures)
             }
             Stmt::IfElse(condition, then_branch, else_branch) => {
-                visitor.visit_ifelse(condition, then_branch, else_branch)
+                visitor.visit_ifelse(condition, then_branch, else_branch.as_deref())
             }
+            Stmt::Import(keyword, name) => visitor.visit_import(keyword, name),
             Stmt::Print(expr) => visitor.visit_print(expr),
-            Stmt::Return(token, expr) => visitor.visit_return(&token, &expr),
-            Stmt::Var(token, expr) => visitor.visit_var(&token, &expr),
+            Stmt::Return(token, expr) => visitor.visit_return(token, expr.as_deref()),
+            Stmt::Throw(keyword, value) => visitor.visit_throw(keyword, value),
+            Stmt::Try(body, catches) => visitor.visit_try(body, catches),
+            Stmt::Var(token, annotation, expr) => {
+                visitor.visit_var(token, annotation, expr.as_deref())
+            }
+            Stmt::Unpack(names, expr) => visitor.visit_unpack(names, expr.as_deref()),
             Stmt::WhileLoop(condition, statement) => visitor.visit_whileloop(condition, statement),
+            Stmt::With(keyword, resource, name, body) => {
+                visitor.visit_with(keyword, resource, name, body)
+            }
+            Stmt::Yield(keyword, expr, variable) => {
+                visitor.visit_yield(keyword, expr.as_deref(), variable)
+            }
+            Stmt::Error(tokens) => visitor.visit_error(tokens),
         }
     }
 }
 
 // Any Visitor class to Stmt must implement Visitor trait
 pub trait Visitor<T> {
-    fn visit_block(&mut self, statements: &mut Box<Vec<Stmt>>) -> T;
+    fn visit_block(&mut self, statements: &[Stmt]) -> T;
     fn visit_class(
         &mut self,
         name: &Token,
-        superclass: &mut Option<Box<Expr>>,
-        statements: &mut Box<Vec<Stmt>>,
+        superclass: Option<&Expr>,
+        mixins: &[Expr],
+        statements: &[Stmt],
+        sealed: bool,
+    ) -> T;
+    fn visit_expression(&mut self, expr: &Expr) -> T;
+    fn visit_forloop(
+        &mut self,
+        initializer: Option<&Stmt>,
+        condition: Option<&Expr>,
+        incrementer: Option<&Expr>,
+        body: &Stmt,
     ) -> T;
-    fn visit_expression(&mut self, expr: &Box<Expr>) -> T;
+    fn visit_forin(&mut self, name: &Token, iterable: &Expr, body: &Stmt) -> T;
     fn visit_ifelse(
         &mut self,
-        condition: &Box<Expr>,
-        then_branch: &Box<Stmt>,
-        else_branch: &Option<Box<Stmt>>,
+        condition: &Expr,
+        then_branch: &Stmt,
+        else_branch: Option<&Stmt>,
     ) -> T;
-    fn visit_print(&mut self, expr: &Box<Expr>) -> T;
-    fn visit_return(&mut self, token: &Token, expr: &Box<Expr>) -> T;
-    fn visit_var(&mut self, token: &Token, expr: &Option<Box<Expr>>) -> T;
-    fn visit_whileloop(&mut self, condition: &Box<Expr>, statement: &mut Box<Stmt>) -> T;
+    fn visit_import(&mut self, keyword: &Token, name: &Token) -> T;
+    fn visit_print(&mut self, expr: &Expr) -> T;
+    fn visit_return(&mut self, token: &Token, expr: Option<&Expr>) -> T;
+    fn visit_throw(&mut self, keyword: &Token, value: &Expr) -> T;
+    fn visit_try(&mut self, body: &[Stmt], catches: &[Catch]) -> T;
+    fn visit_var(&mut self, token: &Token, annotation: &Option<Token>, expr: Option<&Expr>) -> T;
+    fn visit_unpack(&mut self, names: &[Token], expr: Option<&Expr>) -> T;
+    fn visit_whileloop(&mut self, condition: &Expr, statement: &Stmt) -> T;
+    fn visit_with(&mut self, keyword: &Token, resource: &Expr, name: &Token, body: &[Stmt]) -> T;
+    fn visit_yield(&mut self, keyword: &Token, expr: Option<&Expr>, variable: &Option<Token>) -> T;
     fn visit_function(
         &mut self,
         name: &Token,
-        parameters: &Box<Vec<Token>>,
-        body: &mut Box<Vec<Stmt>>,
+        parameters: &[Token],
+        body: &[Stmt],
+        signature: &Signature,
+        captures: &[Slot],
     ) -> T;
+    fn visit_error(&mut self, tokens: &[Token]) -> T;
 }
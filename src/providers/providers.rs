@@ -0,0 +1,110 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::shared::Sendable;
+
+// Where the natives that differ from run to run get their values
+//
+// The interpreter and the VM ask their providers for every call of `clock()`,
+// `now()` and `random()`, so a run can be made reproducible by giving them
+// `Deterministic` ones, or any others
+pub trait Providers: Sendable {
+    fn clock(&mut self) -> f64; // Seconds since the Unix epoch
+    fn now(&mut self) -> f64; // Milliseconds since the Unix epoch
+    fn random(&mut self) -> f64; // From 0 up to, not including, 1
+}
+
+// The time of the system, random numbers seeded by it
+pub struct System {
+    random: Random,
+}
+
+impl System {
+    pub fn new() -> System {
+        let seed = since_the_epoch().to_bits() ^ u64::from(std::process::id());
+        System {
+            random: Random::new(seed),
+        }
+    }
+}
+
+impl Default for System {
+    fn default() -> System {
+        System::new()
+    }
+}
+
+impl Providers for System {
+    fn clock(&mut self) -> f64 {
+        since_the_epoch()
+    }
+
+    fn now(&mut self) -> f64 {
+        (since_the_epoch() * 1000.0).floor()
+    }
+
+    fn random(&mut self) -> f64 {
+        self.random.next()
+    }
+}
+
+// Time starts at the Unix epoch and every reading moves it a millisecond on,
+// random numbers are the same for the same seed
+pub struct Deterministic {
+    milliseconds: u64, // Since the Unix epoch, counted so it doesn't drift
+    random: Random,
+}
+
+impl Deterministic {
+    pub fn new(seed: u64) -> Deterministic {
+        Deterministic {
+            milliseconds: 0,
+            random: Random::new(seed),
+        }
+    }
+
+    fn tick(&mut self) -> f64 {
+        self.milliseconds += 1;
+        (self.milliseconds - 1) as f64
+    }
+}
+
+impl Providers for Deterministic {
+    fn clock(&mut self) -> f64 {
+        self.tick() / 1000.0
+    }
+
+    fn now(&mut self) -> f64 {
+        self.tick()
+    }
+
+    fn random(&mut self) -> f64 {
+        self.random.next()
+    }
+}
+
+fn since_the_epoch() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |duration| duration.as_secs_f64())
+}
+
+// xorshift64*, good enough for scripts and the same everywhere
+struct Random {
+    state: u64, // Never 0, it would stay 0
+}
+
+impl Random {
+    fn new(seed: u64) -> Random {
+        Random {
+            state: (seed ^ 0x9e37_79b9_7f4a_7c15).max(1),
+        }
+    }
+
+    fn next(&mut self) -> f64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        let bits = self.state.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 11;
+        bits as f64 / (1u64 << 53) as f64
+    }
+}
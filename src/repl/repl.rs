@@ -0,0 +1,97 @@
+use rustyline::{
+    completion::Completer, highlight::Highlighter, hint::Hinter, validate::Validator, Context,
+    Helper,
+};
+
+use crate::{
+    interpreter::{environment::Globals, types::Type},
+    lexer::lexer::KEYWORDS,
+    shared::{Ref, Shared},
+};
+
+// Line editing for the REPL: history (arrows, Ctrl-R) comes with the editor,
+// completion of keywords, globals and properties is done here
+//
+// Completions are read from the live global environment, so everything
+// defined by earlier inputs is offered
+pub struct ReplHelper {
+    globals: Shared<Globals>,
+}
+
+impl ReplHelper {
+    pub fn new(globals: Shared<Globals>) -> ReplHelper {
+        ReplHelper { globals }
+    }
+
+    // `point.x` completes the fields and methods of the instance in the global `point`,
+    // `math.s` the members of an imported module
+    fn properties(&self, object: &str) -> Vec<Ref<str>> {
+        match self.globals.borrow().value(object) {
+            Some(Type::Instance(instance)) => instance.borrow().property_names(),
+            Some(Type::Module(module)) => module
+                .members
+                .keys()
+                .map(|member| Ref::from(member.as_str()))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn names(&self) -> Vec<Ref<str>> {
+        let mut names = self.globals.borrow().names();
+        names.extend(KEYWORDS.iter().map(|(keyword, _)| Ref::from(*keyword)));
+        names
+    }
+}
+
+fn is_identifier(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+// Start of the identifier ending at `end`
+fn word_start(line: &str, end: usize) -> usize {
+    line[..end]
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| is_identifier(*c))
+        .last()
+        .map_or(end, |(index, _)| index)
+}
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = word_start(line, pos);
+        let prefix = &line[start..pos];
+
+        let names = match line[..start].strip_suffix('.') {
+            Some(before) => self.properties(&before[word_start(before, before.len())..]),
+            None => self.names(),
+        };
+
+        let mut candidates: Vec<String> = names
+            .iter()
+            .filter(|name| name.starts_with(prefix))
+            .map(|name| name.to_string())
+            .collect();
+        candidates.sort();
+        candidates.dedup();
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
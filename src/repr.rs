@@ -0,0 +1,153 @@
+// How values holding other values print, the same for the interpreter and the VM
+//
+// A value can end up holding itself, like an instance stored in one of its own
+// fields, so printing one goes no deeper than a value already being printed: the
+// inner one shows as `[...]` or `{...}`. Nesting deeper than a limit is cut off
+// the same way, so a long chain of lists doesn't run out of stack
+use std::cell::RefCell;
+
+// Nesting `print` shows, debug_repr is given its own
+pub const MAX_DEPTH: usize = 100;
+
+thread_local! {
+    // Addresses of the values being printed, outermost first
+    static PRINTING: RefCell<Vec<*const ()>> = const { RefCell::new(Vec::new()) };
+}
+
+// Runs `print` for the value at `address`, none when that value is already
+// being printed or when `max_depth` values are
+pub fn nested<T>(address: *const (), max_depth: usize, print: impl FnOnce() -> T) -> Option<T> {
+    let entered = PRINTING.with_borrow_mut(|printing| {
+        if printing.len() >= max_depth || printing.contains(&address) {
+            return false;
+        }
+        printing.push(address);
+        true
+    });
+    if !entered {
+        return None;
+    }
+
+    // Left even when `print` panics, a later print mustn't see it as being printed
+    struct Leave;
+    impl Drop for Leave {
+        fn drop(&mut self) {
+            PRINTING.with_borrow_mut(|printing| printing.pop());
+        }
+    }
+    let _leave = Leave;
+    Some(print())
+}
+
+// What a list, map, set or instance holds, for debug_repr
+pub struct Contents<T> {
+    pub address: *const (),
+    pub open: String,
+    pub close: &'static str,
+    pub items: Vec<(Option<String>, T)>, // With the key of a map entry or the name of a field
+}
+
+pub trait Nested: Sized {
+    // None for a value holding nothing, it is shown `inline`
+    fn contents(&self) -> Option<Contents<Self>>;
+
+    // On one line, as inside a list
+    fn inline(&self) -> String;
+}
+
+// The value with every element on a line of its own, indented by its depth
+// Values nested deeper than `max_depth` show as `[...]`
+pub fn debug_repr<T: Nested>(value: &T, max_depth: usize) -> String {
+    let mut text = String::new();
+    write_indented(value, max_depth, 0, &mut text);
+    text
+}
+
+fn write_indented<T: Nested>(value: &T, max_depth: usize, level: usize, text: &mut String) {
+    let Some(contents) = value.contents() else {
+        text.push_str(&value.inline());
+        return;
+    };
+    text.push_str(&contents.open);
+    if contents.items.is_empty() {
+        text.push_str(contents.close);
+        return;
+    }
+
+    let count = contents.items.len();
+    let written = nested(contents.address, max_depth, || {
+        for (index, (key, item)) in contents.items.iter().enumerate() {
+            text.push('\n');
+            text.push_str(&"  ".repeat(level + 1));
+            if let Some(key) = key {
+                text.push_str(key);
+                text.push_str(": ");
+            }
+            write_indented(item, max_depth, level + 1, text);
+            if index + 1 < count {
+                text.push(',');
+            }
+        }
+        text.push('\n');
+        text.push_str(&"  ".repeat(level));
+    });
+    if written.is_none() {
+        text.push_str("...");
+    }
+    text.push_str(contents.close);
+}
+
+// A cell of `print_table`, numbers are aligned to the right
+pub struct Cell {
+    pub column: String,
+    pub text: String,
+    pub number: bool,
+}
+
+// The rows as a table of aligned columns, framed in ASCII. Columns are in the
+// order they first appear, a row without one has an empty cell. None when no
+// row has a cell, there is nothing to frame
+pub fn table(rows: &[Vec<Cell>]) -> Option<String> {
+    let mut columns: Vec<(&str, usize)> = Vec::new(); // With their widths
+    for cell in rows.iter().flatten() {
+        let width = cell.text.chars().count();
+        match columns
+            .iter_mut()
+            .find(|(column, _)| *column == cell.column)
+        {
+            Some((_, widest)) => *widest = (*widest).max(width),
+            None => columns.push((&cell.column, width.max(cell.column.chars().count()))),
+        }
+    }
+    if columns.is_empty() {
+        return None;
+    }
+
+    let rule: Vec<String> = columns
+        .iter()
+        .map(|(_, width)| "-".repeat(width + 2))
+        .collect();
+    let rule = format!("+{}+", rule.join("+"));
+    let line = |cells: Vec<String>| format!("| {} |", cells.join(" | "));
+    let header = columns
+        .iter()
+        .map(|(column, width)| format!("{:<width$}", column))
+        .collect();
+
+    let mut lines = vec![rule.clone(), line(header), rule.clone()];
+    for row in rows {
+        let cells = columns
+            .iter()
+            .map(
+                |(column, width)| match row.iter().find(|cell| cell.column == *column) {
+                    Some(cell) if cell.number => format!("{:>width$}", cell.text),
+                    Some(cell) => format!("{:<width$}", cell.text),
+                    None => " ".repeat(*width),
+                },
+            )
+            .collect();
+        lines.push(line(cells));
+    }
+    lines.push(rule);
+    Some(lines.join("\n"))
+}
@@ -0,0 +1,517 @@
+use super::symbols::*;
+
+use crate::{
+    error::{Warning, WarningKind},
+    interpreter::environment::GlobalNames,
+    lexer::token::{Span, Token},
+    parser::{
+        expr::{Expr, Location, Slot},
+        formatter::Formatter,
+        pattern::Pattern,
+        stmt::Stmt,
+    },
+    shared::Ref,
+};
+
+// Works out where every local variable lives before the program runs
+//
+// Each block, for loop and function call gets its own environment at runtime,
+// the Resolver mirrors them with scopes and numbers the locals of each scope
+// in declaration order. Every variable read and assignment is then given the
+// Slot of the declaration it refers to, the interpreter indexes straight into it.
+// Names not declared in any enclosing scope are globals, given the index of their
+// name in the globals.
+//
+// A function does not keep the environment it is declared in. It runs in one
+// holding only the variables it captures, in front of the globals: the locals
+// of enclosing scopes its body uses, a function in between capturing them too.
+// They are boxes shared with the environment declaring them, so a function sees
+// the assignments made after it is declared and the ones it makes are seen
+// outside. The Resolver gives every function a scope for them, filled as its
+// body uses them, and records the Slots they are captured from in its statement.
+//
+// Runs after the Optimizer, which can remove declarations
+//
+// Created `with_symbols`, it also records a SymbolTable of the declarations
+// and what every variable refers to, for editors. Created `with_globals`, the
+// indices of globals are the ones of the interpreter that runs the program
+#[derive(Default)]
+pub struct Resolver {
+    scopes: Vec<Vec<(Ref<str>, Option<usize>)>>, // Innermost last, names in slot order and their Symbol
+    functions: Vec<(usize, Vec<Slot>)>, // Enclosing functions: the index of their scope of captures, and where those come from
+    initializing: Option<Ref<str>>,     // Local whose initializer is being resolved
+    warnings: Vec<Warning>,
+    symbols: Option<SymbolTable>,
+    globals: Vec<(Ref<str>, usize)>, // Symbols of global declarations, in order
+    global_references: Vec<(Ref<str>, Span)>, // Matched once every global is declared
+    names: GlobalNames,              // Numbering the globals
+}
+
+impl Resolver {
+    pub fn new() -> Resolver {
+        Resolver {
+            scopes: Vec::new(),
+            functions: Vec::new(),
+            initializing: None,
+            warnings: Vec::new(),
+            symbols: None,
+            globals: Vec::new(),
+            global_references: Vec::new(),
+            names: GlobalNames::default(),
+        }
+    }
+
+    pub fn with_globals(names: GlobalNames) -> Resolver {
+        Resolver {
+            names,
+            ..Resolver::new()
+        }
+    }
+
+    pub fn with_symbols() -> Resolver {
+        Resolver {
+            symbols: Some(SymbolTable::default()),
+            ..Resolver::new()
+        }
+    }
+
+    pub fn resolve(&mut self, statements: &mut [Stmt]) -> Vec<Warning> {
+        for statement in statements.iter_mut() {
+            self.resolve_statement(statement);
+        }
+        self.resolve_global_references();
+        std::mem::take(&mut self.warnings)
+    }
+
+    // Empty unless created `with_symbols`
+    pub fn take_symbols(&mut self) -> SymbolTable {
+        self.symbols.take().unwrap_or_default()
+    }
+
+    // Functions can use globals declared after them, so globals are matched last
+    // A reference is to the closest declaration before it, or the first one after it
+    fn resolve_global_references(&mut self) {
+        let Some(symbols) = self.symbols.as_mut() else {
+            return;
+        };
+        for (name, span) in self.global_references.drain(..) {
+            let declarations: Vec<usize> = self
+                .globals
+                .iter()
+                .filter(|(global, _)| *global == name)
+                .map(|(_, symbol)| *symbol)
+                .collect();
+            let symbol = declarations
+                .iter()
+                .rev()
+                .find(|symbol| symbols.symbols[**symbol].span.start < span.start)
+                .or(declarations.first());
+            if let Some(symbol) = symbol {
+                symbols.references.push(Reference {
+                    span,
+                    symbol: *symbol,
+                });
+            }
+        }
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(Vec::new());
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    // Redeclaring a name in the same scope takes a new slot, as the environment does
+    fn declare(&mut self, name: &Token, kind: SymbolKind, detail: String) {
+        let symbol = self.symbols.as_mut().map(|symbols| {
+            symbols.symbols.push(Symbol {
+                name: name.lexeme.clone(),
+                kind,
+                span: name.span,
+                detail,
+            });
+            symbols.symbols.len() - 1
+        });
+
+        match (self.scopes.last_mut(), symbol) {
+            (Some(scope), _) => scope.push((name.lexeme.clone(), symbol)),
+            (None, Some(symbol)) => self.globals.push((name.lexeme.clone(), symbol)),
+            (None, None) => {}
+        }
+    }
+
+    // A local the language declares, like `this`, with no symbol
+    fn declare_hidden(&mut self, name: &str) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.push((Ref::from(name), None));
+        }
+    }
+
+    // A local of a scope outside the current function is captured by every
+    // function in between, outermost first, each from the captures of the last
+    fn slot(&mut self, name: &Token) -> Option<Slot> {
+        let (mut scope, mut index) =
+            self.scopes
+                .iter()
+                .enumerate()
+                .rev()
+                .find_map(|(scope, locals)| {
+                    locals
+                        .iter()
+                        .rposition(|(local, _)| *local == name.lexeme)
+                        .map(|index| (scope, index))
+                })?;
+
+        for (captures_scope, captures) in self.functions.iter_mut() {
+            if *captures_scope <= scope {
+                continue;
+            }
+            // Declared in the scope right before its captures
+            captures.push(Slot {
+                depth: *captures_scope - 1 - scope,
+                index,
+            });
+            let local = self.scopes[scope][index].clone();
+            self.scopes[*captures_scope].push(local);
+            (scope, index) = (*captures_scope, self.scopes[*captures_scope].len() - 1);
+        }
+
+        Some(Slot {
+            depth: self.scopes.len() - 1 - scope,
+            index,
+        })
+    }
+
+    // Resolves `name` to its Slot, recording what it refers to when keeping symbols
+    fn resolve_name(&mut self, name: &Token) -> Location {
+        let slot = self.slot(name);
+        if let Some(symbols) = self.symbols.as_mut() {
+            match slot {
+                Some(Slot { depth, index }) => {
+                    let scope = &self.scopes[self.scopes.len() - 1 - depth];
+                    if let Some(symbol) = scope[index].1 {
+                        symbols.references.push(Reference {
+                            span: name.span,
+                            symbol,
+                        });
+                    }
+                }
+                None => self
+                    .global_references
+                    .push((name.lexeme.clone(), name.span)),
+            }
+        }
+        match slot {
+            Some(slot) => Location::Local(slot),
+            None => Location::Global(self.names.index(&name.lexeme)),
+        }
+    }
+
+    // The first line of the declaration as the formatter writes it, without its body
+    // Only worked out when recording symbols
+    fn declaration(&self, stmt: &Stmt) -> String {
+        if self.symbols.is_none() {
+            return String::new();
+        }
+        let formatted = Formatter::new().format_statement(stmt);
+        let first_line = formatted.lines().next().unwrap_or_default();
+        first_line
+            .trim_end_matches(" {}")
+            .trim_end_matches(" {")
+            .to_string()
+    }
+
+    fn resolve_block(&mut self, statements: &mut [Stmt]) {
+        for statement in statements.iter_mut() {
+            self.resolve_statement(statement);
+        }
+    }
+
+    // Parameters and the body share the scope of the call, which comes after
+    // the scope of what the function captures, and for a method the one of `this`
+    fn resolve_function(
+        &mut self,
+        parameters: &[Token],
+        body: &mut [Stmt],
+        captures: &mut Vec<Slot>,
+        method: bool,
+    ) {
+        self.begin_scope();
+        self.functions.push((self.scopes.len() - 1, Vec::new()));
+        if method {
+            self.begin_scope();
+            self.declare_hidden("this");
+        }
+        self.begin_scope();
+        for parameter in parameters {
+            self.declare(
+                parameter,
+                SymbolKind::Parameter,
+                parameter.lexeme.to_string(),
+            );
+        }
+        self.resolve_block(body);
+        self.end_scope();
+        if method {
+            self.end_scope();
+        }
+        if let Some((_, function_captures)) = self.functions.pop() {
+            *captures = function_captures;
+        }
+        self.end_scope();
+    }
+
+    fn resolve_statement(&mut self, statement: &mut Stmt) {
+        let declaration = match statement {
+            Stmt::Class(..) | Stmt::Function(..) | Stmt::Var(..) | Stmt::Unpack(..) => {
+                self.declaration(statement)
+            }
+            _ => String::new(),
+        };
+        match statement {
+            Stmt::Block(statements) => {
+                self.begin_scope();
+                self.resolve_block(statements);
+                self.end_scope();
+            }
+            // The superclass and mixins are evaluated before the class name is defined
+            Stmt::Class(name, superclass, mixins, methods, _) => {
+                if let Some(superclass) = superclass {
+                    self.resolve_expression(superclass);
+                }
+                for mixin in mixins.iter_mut() {
+                    self.resolve_expression(mixin);
+                }
+                self.declare(name, SymbolKind::Class, declaration);
+                // The class environment holds the superclass, for `super`
+                if superclass.is_some() {
+                    self.begin_scope();
+                    self.declare_hidden("super");
+                }
+                for method in methods.iter_mut() {
+                    if let Stmt::Function(_, parameters, body, _, captures) = method {
+                        self.resolve_function(parameters, body, captures, true);
+                    }
+                }
+                if superclass.is_some() {
+                    self.end_scope();
+                }
+            }
+            Stmt::Expression(expr) | Stmt::Print(expr) => self.resolve_expression(expr),
+            Stmt::ForLoop(initializer, condition, incrementer, body) => {
+                self.begin_scope();
+                if let Some(initializer) = initializer {
+                    self.resolve_statement(initializer);
+                }
+                if let Some(condition) = condition {
+                    self.resolve_expression(condition);
+                }
+                if let Some(incrementer) = incrementer {
+                    self.resolve_expression(incrementer);
+                }
+                self.resolve_statement(body);
+                self.end_scope();
+            }
+            // Every element gets an environment of its own, holding the variable
+            Stmt::ForIn(name, iterable, body) => {
+                self.resolve_expression(iterable);
+                self.begin_scope();
+                self.declare(name, SymbolKind::Variable, format!("var {}", name.lexeme));
+                self.resolve_statement(body);
+                self.end_scope();
+            }
+            // Declared before its body, so it can call itself
+            Stmt::Function(name, parameters, body, _, captures) => {
+                self.declare(name, SymbolKind::Function, declaration);
+                self.resolve_function(parameters, body, captures, false);
+            }
+            Stmt::IfElse(condition, then_branch, else_branch) => {
+                self.resolve_expression(condition);
+                self.resolve_statement(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.resolve_statement(else_branch);
+                }
+            }
+            Stmt::Return(_, expr) => {
+                if let Some(expr) = expr {
+                    self.resolve_expression(expr);
+                }
+            }
+            Stmt::Throw(_, value) => self.resolve_expression(value),
+            // The error caught is the first local of the catch body's scope
+            Stmt::Try(body, catches) => {
+                self.begin_scope();
+                self.resolve_block(body);
+                self.end_scope();
+                for catch in catches.iter_mut() {
+                    if let Some(class) = &mut catch.class {
+                        self.resolve_expression(class);
+                    }
+                    self.begin_scope();
+                    let name = &catch.name;
+                    self.declare(
+                        name,
+                        SymbolKind::Variable,
+                        format!("catch ({})", name.lexeme),
+                    );
+                    self.resolve_block(&mut catch.body);
+                    self.end_scope();
+                }
+            }
+            Stmt::Yield(_, expr, variable) => {
+                if let Some(expr) = expr {
+                    self.resolve_expression(expr);
+                }
+                if let Some(variable) = variable {
+                    self.declare(
+                        variable,
+                        SymbolKind::Variable,
+                        format!("var {}", variable.lexeme),
+                    );
+                }
+            }
+            // The initializer still sees any outer variable with the same name
+            Stmt::Var(name, _, initializer) => {
+                if let Some(initializer) = initializer {
+                    if !self.scopes.is_empty() {
+                        self.initializing = Some(name.lexeme.clone());
+                    }
+                    self.resolve_expression(initializer);
+                    self.initializing = None;
+                }
+                self.declare(name, SymbolKind::Variable, declaration);
+            }
+            Stmt::Import(_, name) => self.declare(name, SymbolKind::Variable, declaration),
+            // Declared in order, the slots follow the values of the tuple
+            Stmt::Unpack(names, initializer) => {
+                if let Some(initializer) = initializer {
+                    self.resolve_expression(initializer);
+                }
+                for name in names.iter() {
+                    self.declare(name, SymbolKind::Variable, declaration.clone());
+                }
+            }
+            Stmt::WhileLoop(condition, body) => {
+                self.resolve_expression(condition);
+                self.resolve_statement(body);
+            }
+            // The resource is the first local of the body's scope
+            Stmt::With(_, resource, name, body) => {
+                self.resolve_expression(resource);
+                self.begin_scope();
+                self.declare(
+                    name,
+                    SymbolKind::Variable,
+                    format!("with ... as {}", name.lexeme),
+                );
+                self.resolve_block(body);
+                self.end_scope();
+            }
+            Stmt::Error(_) => {}
+        }
+    }
+
+    fn resolve_expression(&mut self, expr: &mut Expr) {
+        match expr {
+            Expr::Binary(left, _, right) | Expr::Logical(left, _, right) => {
+                self.resolve_expression(left);
+                self.resolve_expression(right);
+            }
+            Expr::Call(callee, _, arguments) => {
+                self.resolve_expression(callee);
+                for argument in arguments.iter_mut() {
+                    self.resolve_expression(argument);
+                }
+            }
+            Expr::Get(object, _) | Expr::OptionalGet(object, _) => self.resolve_expression(object),
+            Expr::Index(object, index, _) => {
+                self.resolve_expression(object);
+                self.resolve_expression(index);
+            }
+            Expr::Set(object, _, value) => {
+                self.resolve_expression(object);
+                self.resolve_expression(value);
+            }
+            Expr::Grouping(expr)
+            | Expr::Unary(_, expr)
+            | Expr::Spawn(_, expr)
+            | Expr::Await(_, expr) => self.resolve_expression(expr),
+            Expr::Literal(_) => {}
+            Expr::Variable(name, location) => {
+                if self.initializing.as_ref() == Some(&name.lexeme) {
+                    self.warnings.push(Warning::new(
+                        WarningKind::SelfInitializer,
+                        format!(
+                            "`{}` in its own initializer reads the enclosing `{}`, not the one being declared",
+                            name.lexeme, name.lexeme
+                        ),
+                        name.span,
+                    ));
+                }
+                *location = Some(self.resolve_name(name))
+            }
+            Expr::Assign(name, value, location) => {
+                self.resolve_expression(value);
+                *location = Some(self.resolve_name(name));
+            }
+            Expr::This(keyword, location) => *location = Some(self.resolve_name(keyword)),
+            Expr::Super(keyword, _, superclass, this) => {
+                *superclass = Some(self.resolve_name(keyword));
+                let this_keyword = Token {
+                    lexeme: Ref::from("this"),
+                    ..keyword.clone()
+                };
+                *this = Some(self.resolve_name(&this_keyword));
+            }
+            Expr::Tuple(values) | Expr::List(_, values, _) | Expr::SetLiteral(_, values, _) => {
+                for value in values.iter_mut() {
+                    self.resolve_expression(value);
+                }
+            }
+            Expr::Map(_, entries, _) => {
+                for (key, value) in entries.iter_mut() {
+                    self.resolve_expression(key);
+                    self.resolve_expression(value);
+                }
+            }
+            // Every arm runs in an environment of its own, holding what its pattern binds
+            Expr::Match(_, value, arms, _) => {
+                self.resolve_expression(value);
+                for arm in arms.iter_mut() {
+                    self.resolve_classes(&mut arm.pattern);
+                    self.begin_scope();
+                    for name in arm.pattern.bindings() {
+                        self.declare(name, SymbolKind::Variable, name.lexeme.to_string());
+                    }
+                    self.resolve_expression(&mut arm.body);
+                    self.end_scope();
+                }
+            }
+        }
+    }
+
+    // Classes of instance patterns are looked up where the `match` is
+    fn resolve_classes(&mut self, pattern: &mut Pattern) {
+        match pattern {
+            Pattern::List(_, elements) => {
+                for element in elements.iter_mut() {
+                    self.resolve_classes(element);
+                }
+            }
+            Pattern::Map(_, entries) => {
+                for (_, pattern) in entries.iter_mut() {
+                    self.resolve_classes(pattern);
+                }
+            }
+            Pattern::Instance(class, fields) => {
+                self.resolve_expression(class);
+                for (_, pattern) in fields.iter_mut() {
+                    self.resolve_classes(pattern);
+                }
+            }
+            Pattern::Wildcard(_) | Pattern::Literal(_) | Pattern::Binding(_) => {}
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use crate::{lexer::token::Span, shared::Ref};
+
+// Every declaration of a program and the variables referring to them,
+// filled in by the Resolver for editor features like go-to-definition and hover
+#[derive(Debug, Default)]
+pub struct SymbolTable {
+    pub symbols: Vec<Symbol>,
+    pub references: Vec<Reference>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymbolKind {
+    Variable,
+    Parameter,
+    Function,
+    Class,
+}
+
+impl SymbolKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SymbolKind::Variable => "variable",
+            SymbolKind::Parameter => "parameter",
+            SymbolKind::Function => "function",
+            SymbolKind::Class => "class",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Symbol {
+    pub name: Ref<str>,
+    pub kind: SymbolKind,
+    pub span: Span,     // Of the name in the declaration
+    pub detail: String, // The declaration as written, like `fun add(a, b)`, or the parameter
+}
+
+// A variable read or assignment and the index of the Symbol it resolved to
+#[derive(Debug)]
+pub struct Reference {
+    pub span: Span,
+    pub symbol: usize,
+}
+
+impl SymbolTable {
+    // Index of the symbol declared or referenced at a character offset,
+    // the end of a name still counts, that's where the cursor is after typing it
+    pub fn at(&self, offset: usize) -> Option<usize> {
+        let contains = |span: &Span| span.start <= offset && offset <= span.end;
+        self.references
+            .iter()
+            .find(|reference| contains(&reference.span))
+            .map(|reference| reference.symbol)
+            .or_else(|| {
+                self.symbols
+                    .iter()
+                    .position(|symbol| contains(&symbol.span))
+            })
+    }
+}
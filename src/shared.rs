@@ -0,0 +1,59 @@
+// How the interpreter shares values and changes shared ones
+//
+// By default these are Rc and RefCell, and the interpreter stays on the thread
+// that made it. With the `sync` feature they are Arc and an RwLock with the
+// same `borrow()` and `borrow_mut()`, which makes the Interpreter Send, so a host
+// can hand it to another thread, for the cost of atomic counts and locking.
+// Borrowing mutably what is borrowed is a bug either way: a RefCell panics, the
+// lock never returns
+#[cfg(not(feature = "sync"))]
+pub use std::{
+    cell::RefCell as Lock,
+    rc::{Rc as Ref, Weak as WeakRef},
+};
+
+#[cfg(feature = "sync")]
+pub use std::sync::{Arc as Ref, Weak as WeakRef};
+
+pub type Shared<T> = Ref<Lock<T>>;
+
+#[cfg(feature = "sync")]
+#[derive(Debug, Default)]
+pub struct Lock<T>(std::sync::RwLock<T>);
+
+#[cfg(feature = "sync")]
+impl<T> Lock<T> {
+    pub fn new(value: T) -> Lock<T> {
+        Lock(std::sync::RwLock::new(value))
+    }
+
+    // A lock is only poisoned by a panic, which already ended the run
+    pub fn borrow(&self) -> std::sync::RwLockReadGuard<'_, T> {
+        self.0.read().unwrap()
+    }
+
+    pub fn borrow_mut(&self) -> std::sync::RwLockWriteGuard<'_, T> {
+        self.0.write().unwrap()
+    }
+
+    // Fails while it is borrowed mutably
+    pub fn try_borrow(&self) -> std::sync::TryLockResult<std::sync::RwLockReadGuard<'_, T>> {
+        self.0.try_read()
+    }
+}
+
+// What the interpreter keeps of its host, its output and providers: Send with
+// `sync`, so they don't keep it on one thread, anything without
+#[cfg(feature = "sync")]
+pub trait Sendable: Send {}
+#[cfg(feature = "sync")]
+impl<T: Send + ?Sized> Sendable for T {}
+
+#[cfg(not(feature = "sync"))]
+pub trait Sendable {}
+#[cfg(not(feature = "sync"))]
+impl<T: ?Sized> Sendable for T {}
+
+// Where the interpreter writes
+pub trait Output: std::io::Write + Sendable {}
+impl<T: std::io::Write + Sendable + ?Sized> Output for T {}
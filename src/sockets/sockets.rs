@@ -0,0 +1,204 @@
+use std::{
+    fmt,
+    net::{TcpListener, TcpStream, UdpSocket},
+    sync::{Arc, Mutex},
+};
+#[cfg(feature = "sockets")]
+use std::{
+    io::{Read, Write},
+    net::Shutdown,
+};
+
+// A socket of the `net` module, the same for the interpreter and the VM
+//
+// Scripts get one from `net.connect`, `net.listen` or `net.bind` and use it
+// through its methods, which the runtimes give with the socket bound to them.
+// Copies share the socket, closing it closes it for all of them. Text goes
+// over it as UTF-8, what isn't is read as U+FFFD
+#[derive(Debug, Clone)]
+pub struct Socket {
+    kind: SocketKind, // Kept once closed, for its methods and how it shows
+    inner: Arc<Mutex<Option<Inner>>>, // None once closed
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SocketKind {
+    Connection, // TCP, from `net.connect` or `accept`
+    Listener,   // TCP, from `net.listen`
+    Datagram,   // UDP, from `net.bind`
+}
+
+// Only ever made with the `sockets` feature, values can hold a Socket either way
+#[derive(Debug)]
+#[cfg_attr(not(feature = "sockets"), allow(dead_code))]
+enum Inner {
+    Connection(TcpStream),
+    Listener(TcpListener),
+    Datagram(UdpSocket),
+}
+
+// Bytes read at most by one `recv`
+#[cfg(feature = "sockets")]
+const CHUNK: usize = 64 * 1024;
+
+#[cfg(feature = "sockets")]
+const CLOSED: &str = "The socket is closed";
+
+impl Socket {
+    pub fn kind(&self) -> SocketKind {
+        self.kind
+    }
+
+    // Whether both share the same socket
+    pub fn is(&self, other: &Socket) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner)
+    }
+
+    // The local address, with the port the system picked for port 0
+    pub fn address(&self) -> Result<String, String> {
+        let address = match &*self.inner.lock().unwrap() {
+            Some(Inner::Connection(stream)) => stream.local_addr(),
+            Some(Inner::Listener(listener)) => listener.local_addr(),
+            Some(Inner::Datagram(socket)) => socket.local_addr(),
+            None => return Err("The socket is closed".to_string()),
+        };
+        address
+            .map(|address| address.to_string())
+            .map_err(|error| format!("Can't get the address: {error}"))
+    }
+}
+
+#[cfg(feature = "sockets")]
+impl Socket {
+    fn new(inner: Inner) -> Socket {
+        let kind = match inner {
+            Inner::Connection(_) => SocketKind::Connection,
+            Inner::Listener(_) => SocketKind::Listener,
+            Inner::Datagram(_) => SocketKind::Datagram,
+        };
+        Socket {
+            kind,
+            inner: Arc::new(Mutex::new(Some(inner))),
+        }
+    }
+
+    // A TCP connection to `address`, like "example.com:80"
+    pub fn connect(address: &str) -> Result<Socket, String> {
+        TcpStream::connect(address)
+            .map(|stream| Socket::new(Inner::Connection(stream)))
+            .map_err(|error| format!("Can't connect to \"{address}\": {error}"))
+    }
+
+    // A TCP listener on `address`, port 0 lets the system pick a free one
+    pub fn listen(address: &str) -> Result<Socket, String> {
+        TcpListener::bind(address)
+            .map(|listener| Socket::new(Inner::Listener(listener)))
+            .map_err(|error| format!("Can't listen on \"{address}\": {error}"))
+    }
+
+    // A UDP socket on `address`
+    pub fn bind(address: &str) -> Result<Socket, String> {
+        UdpSocket::bind(address)
+            .map(|socket| Socket::new(Inner::Datagram(socket)))
+            .map_err(|error| format!("Can't bind \"{address}\": {error}"))
+    }
+
+    // Waits for the next connection to the listener
+    pub fn accept(&self) -> Result<Socket, String> {
+        match &*self.inner.lock().unwrap() {
+            Some(Inner::Listener(listener)) => listener
+                .accept()
+                .map(|(stream, _)| Socket::new(Inner::Connection(stream)))
+                .map_err(|error| format!("Can't accept a connection: {error}")),
+            _ => Err(CLOSED.to_string()),
+        }
+    }
+
+    // Sends all of the text over a connection
+    pub fn send(&self, text: &str) -> Result<(), String> {
+        match &mut *self.inner.lock().unwrap() {
+            Some(Inner::Connection(stream)) => stream
+                .write_all(text.as_bytes())
+                .map_err(|error| format!("Can't send: {error}")),
+            _ => Err(CLOSED.to_string()),
+        }
+    }
+
+    // Sends the text as one datagram to `address`
+    pub fn send_to(&self, text: &str, address: &str) -> Result<(), String> {
+        match &*self.inner.lock().unwrap() {
+            Some(Inner::Datagram(socket)) => socket
+                .send_to(text.as_bytes(), address)
+                .map(|_| ())
+                .map_err(|error| format!("Can't send to \"{address}\": {error}")),
+            _ => Err(CLOSED.to_string()),
+        }
+    }
+
+    // Waits for what comes next over a connection, None once the other end
+    // has closed it
+    pub fn recv(&self) -> Result<Option<String>, String> {
+        match &mut *self.inner.lock().unwrap() {
+            Some(Inner::Connection(stream)) => {
+                let mut buffer = vec![0; CHUNK];
+                let read = stream
+                    .read(&mut buffer)
+                    .map_err(|error| format!("Can't receive: {error}"))?;
+                Ok((read > 0).then(|| String::from_utf8_lossy(&buffer[..read]).into_owned()))
+            }
+            _ => Err(CLOSED.to_string()),
+        }
+    }
+
+    // Waits for the next datagram, with the address it came from
+    pub fn recv_from(&self) -> Result<(String, String), String> {
+        match &*self.inner.lock().unwrap() {
+            Some(Inner::Datagram(socket)) => {
+                let mut buffer = vec![0; CHUNK];
+                let (read, from) = socket
+                    .recv_from(&mut buffer)
+                    .map_err(|error| format!("Can't receive: {error}"))?;
+                let text = String::from_utf8_lossy(&buffer[..read]).into_owned();
+                Ok((text, from.to_string()))
+            }
+            _ => Err(CLOSED.to_string()),
+        }
+    }
+
+    // The address of the other end of a connection
+    pub fn peer(&self) -> Result<String, String> {
+        match &*self.inner.lock().unwrap() {
+            Some(Inner::Connection(stream)) => stream
+                .peer_addr()
+                .map(|address| address.to_string())
+                .map_err(|error| format!("Can't get the address: {error}")),
+            _ => Err(CLOSED.to_string()),
+        }
+    }
+
+    // Closing again does nothing
+    pub fn close(&self) {
+        if let Some(Inner::Connection(stream)) = self.inner.lock().unwrap().take() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+impl fmt::Display for SocketKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SocketKind::Connection => write!(f, "Connection"),
+            SocketKind::Listener => write!(f, "Listener"),
+            SocketKind::Datagram => write!(f, "Datagram"),
+        }
+    }
+}
+
+impl fmt::Display for Socket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.address() {
+            Ok(address) => write!(f, "{} <{}>", self.kind(), address),
+            Err(_) => write!(f, "{} <closed>", self.kind()),
+        }
+    }
+}
@@ -0,0 +1,181 @@
+// Operations on strings, the same for the interpreter, the VM and the optimizer
+use std::iter::Peekable;
+
+use crate::number;
+
+// Longest string repetition makes, in bytes, past it the run fails rather
+// than the process running out of memory
+pub const MAX_LENGTH: usize = 1 << 30;
+
+// `text * times`, an error message unless `times` is a whole number from 0
+pub fn repeat(text: &str, times: f64) -> Result<String, String> {
+    if times.fract() != 0.0 || times < 0.0 {
+        return Err(format!(
+            "A string can only be repeated a whole number of times, got {}",
+            number::format(times)
+        ));
+    }
+    match text.len().checked_mul(times as usize) {
+        Some(length) if length <= MAX_LENGTH => Ok(text.repeat(times as usize)),
+        _ => Err(format!(
+            "Repeating a string {} times makes it too long",
+            number::format(times)
+        )),
+    }
+}
+
+// A value given to `format`, numbers are the only ones with a precision
+pub enum Argument {
+    Number(f64),
+    Text(String), // As `print` shows it
+}
+
+// How a field of `format` shows its value,
+// `{:[[fill]align][width][,][.precision][e]}`
+struct Spec {
+    fill: char,
+    align: Option<char>, // `<`, `>` or `^`, numbers go right and the rest left unless given
+    zero: bool,          // A width starting with 0, numbers are padded with zeros after the sign
+    width: usize,
+    grouped: bool, // `,`, the whole part of a number in groups of three digits
+    precision: Option<usize>, // Digits after the point of a number, characters of the rest
+    scientific: bool, // `e`, a number as a mantissa and an exponent, like 1.5e3
+}
+
+// `template` with each `{}` replaced by the next argument, `{2}` by the third.
+// `{{` and `}}` are braces, arguments left over are ignored
+pub fn format(template: &str, arguments: &[Argument]) -> Result<String, String> {
+    let mut text = String::with_capacity(template.len());
+    let mut characters = template.chars().enumerate().peekable();
+    let mut next = 0;
+    while let Some((index, character)) = characters.next() {
+        match character {
+            '{' if characters.next_if(|(_, next)| *next == '{').is_some() => text.push('{'),
+            '}' if characters.next_if(|(_, next)| *next == '}').is_some() => text.push('}'),
+            '}' => return Err(format!("Unmatched `}}` at {}", index)),
+            '{' => {
+                let field =
+                    field(&mut characters).ok_or_else(|| format!("Unmatched `{{` at {}", index))?;
+                let invalid = || format!("Invalid field `{{{}}}` at {}", field, index);
+                let (position, spec) = field.split_once(':').unwrap_or((&field, ""));
+                let position = match position {
+                    "" => {
+                        next += 1;
+                        next - 1
+                    }
+                    position => digits(position).ok_or_else(invalid)?,
+                };
+                let spec = Spec::parse(spec).ok_or_else(invalid)?;
+                let Some(argument) = arguments.get(position) else {
+                    return Err(format!(
+                        "No argument {} for `{{{}}}` at {}, got {}",
+                        position,
+                        field,
+                        index,
+                        arguments.len()
+                    ));
+                };
+                text.push_str(&spec.show(argument));
+            }
+            _ => text.push(character),
+        }
+    }
+    Ok(text)
+}
+
+// What is between the braces of a field, none when it isn't closed
+fn field(characters: &mut Peekable<impl Iterator<Item = (usize, char)>>) -> Option<String> {
+    let mut field = String::new();
+    loop {
+        match characters.next()? {
+            (_, '}') => return Some(field),
+            (_, character) => field.push(character),
+        }
+    }
+}
+
+// A number written only with digits, no sign
+fn digits(text: &str) -> Option<usize> {
+    match !text.is_empty() && text.bytes().all(|byte| byte.is_ascii_digit()) {
+        true => text.parse().ok(),
+        false => None,
+    }
+}
+
+impl Spec {
+    fn parse(spec: &str) -> Option<Spec> {
+        let characters: Vec<char> = spec.chars().collect();
+        let aligns = |character: &char| matches!(character, '<' | '>' | '^');
+        let (fill, align, rest) = match characters.as_slice() {
+            [fill, align, rest @ ..] if aligns(align) => (*fill, Some(*align), rest),
+            [align, rest @ ..] if aligns(align) => (' ', Some(*align), rest),
+            rest => (' ', None, rest),
+        };
+        let rest: String = rest.iter().collect();
+        let (rest, scientific) = match rest.strip_suffix('e') {
+            Some(rest) => (rest, true),
+            None => (rest.as_str(), false),
+        };
+        let (width, precision) = match rest.split_once('.') {
+            Some((width, precision)) => (width, Some(digits(precision)?)),
+            None => (rest, None),
+        };
+        let (width, grouped) = match width.strip_suffix(',') {
+            Some(width) => (width, true),
+            None => (width, false),
+        };
+        let spec = Spec {
+            fill,
+            align,
+            zero: align.is_none() && width.len() > 1 && width.starts_with('0'),
+            width: if width.is_empty() { 0 } else { digits(width)? },
+            grouped,
+            precision,
+            scientific,
+        };
+        // Past it the field would be longer than any string can be
+        match spec.width.max(spec.precision.unwrap_or(0)) <= MAX_LENGTH {
+            true => Some(spec),
+            false => None,
+        }
+    }
+
+    fn show(&self, argument: &Argument) -> String {
+        let (text, number) = match argument {
+            Argument::Number(value) => {
+                let text = match (self.precision, self.scientific) {
+                    _ if !value.is_finite() => number::format(*value),
+                    (Some(precision), true) => format!("{:.*e}", precision, value),
+                    (None, true) => format!("{:e}", value),
+                    (Some(precision), false) => format!("{:.*}", precision, value),
+                    (None, false) => number::format(*value),
+                };
+                match self.grouped {
+                    true => (number::grouped(&text), true),
+                    false => (text, true),
+                }
+            }
+            Argument::Text(text) => match self.precision {
+                Some(precision) => (text.chars().take(precision).collect(), false),
+                None => (text.clone(), false),
+            },
+        };
+        let padding = self.width.saturating_sub(text.chars().count());
+        if padding == 0 {
+            return text;
+        }
+        if self.zero && number {
+            let (sign, digits) = match text.strip_prefix('-') {
+                Some(digits) => ("-", digits),
+                None => ("", text.as_str()),
+            };
+            return format!("{}{}{}", sign, "0".repeat(padding), digits);
+        }
+        let fill = |count: usize| self.fill.to_string().repeat(count);
+        match self.align.unwrap_or(if number { '>' } else { '<' }) {
+            '<' => text + &fill(padding),
+            '>' => fill(padding) + &text,
+            _ => fill(padding / 2) + &text + &fill(padding - padding / 2),
+        }
+    }
+}
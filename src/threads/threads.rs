@@ -0,0 +1,95 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Condvar, Mutex},
+};
+
+// What scripts running on different threads share
+//
+// `spawn_thread(f)` runs `f` in an interpreter of its own on another thread,
+// with one end of a Channel as its argument, and gives the caller the other end.
+// Nothing else is shared: `send()` copies the value into a Message, all the way
+// down, and `recv()` makes a value of the receiving interpreter from it
+#[derive(Debug, Clone)]
+pub enum Message {
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    String(String),
+    Bytes(Vec<u8>),
+    Tuple(Vec<Message>),
+    List(Vec<Message>),
+    Map(Vec<(Message, Message)>),
+    Set(Vec<Message>),
+    Channel(Channel),
+}
+
+// One end of a two way channel between threads: what one end sends, the other
+// one receives, in order
+#[derive(Debug, Clone)]
+pub struct Channel {
+    inbox: Arc<Queue>,
+    outbox: Arc<Queue>,
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    state: Mutex<QueueState>,
+    ready: Condvar, // Notified when a message comes or the sender is done
+}
+
+#[derive(Debug, Default)]
+struct QueueState {
+    messages: VecDeque<Message>,
+    closed: Option<Result<(), String>>, // How the thread sending on it ended
+}
+
+impl Channel {
+    // The two ends of a new channel
+    pub fn pair() -> (Channel, Channel) {
+        let (first, second) = (Arc::new(Queue::default()), Arc::new(Queue::default()));
+        (
+            Channel {
+                inbox: Arc::clone(&first),
+                outbox: Arc::clone(&second),
+            },
+            Channel {
+                inbox: second,
+                outbox: first,
+            },
+        )
+    }
+
+    pub fn send(&self, message: Message) {
+        let mut state = self.outbox.state.lock().unwrap();
+        state.messages.push_back(message);
+        self.outbox.ready.notify_one();
+    }
+
+    // Waits for the next message, fails once the other end's thread has ended
+    // and everything it sent has been received
+    pub fn recv(&self) -> Result<Message, String> {
+        let mut state = self.inbox.state.lock().unwrap();
+        loop {
+            if let Some(message) = state.messages.pop_front() {
+                return Ok(message);
+            }
+            match &state.closed {
+                Some(Ok(())) => return Err("The thread has ended".to_string()),
+                Some(Err(message)) => return Err(format!("The thread failed: {}", message)),
+                None => state = self.inbox.ready.wait(state).unwrap(),
+            }
+        }
+    }
+
+    // Called by the thread of this end when it ends, with its error if it failed
+    pub fn close(&self, result: Result<(), String>) {
+        let mut state = self.outbox.state.lock().unwrap();
+        state.closed = Some(result);
+        self.outbox.ready.notify_all();
+    }
+
+    // Whether both are the same end of the same channel
+    pub fn is(&self, other: &Channel) -> bool {
+        Arc::ptr_eq(&self.inbox, &other.inbox)
+    }
+}
@@ -0,0 +1,973 @@
+use std::{collections::HashMap, fmt};
+
+use crate::{
+    error::{Error, ErrorClass},
+    lexer::token::*,
+    parser::{
+        expr::{Visitable as ExpressionVisitable, Visitor as ExpressionVisitor, *},
+        pattern::Arm,
+        stmt::{Visitable as StatementVisitable, Visitor as StatementVisitor, *},
+    },
+    shared::Ref,
+};
+
+// Optional static type checking, run before the program is executed
+//
+// var x: number = 1;
+// fun f(a: string) -> number { ... }
+//
+// Only annotated code is checked, anything without an annotation is `any`
+// and is accepted everywhere, so untyped programs always pass
+pub struct TypeChecker {
+    scopes: Vec<HashMap<Ref<str>, StaticType>>,
+    classes: HashMap<String, ClassType>,
+    return_types: Vec<StaticType>, // Declared return types of the enclosing functions
+    errors: Vec<Error>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StaticType {
+    Any,
+    Number,
+    String,
+    Boolean,
+    Nil,
+    Function(Box<FunctionType>),
+    Class(String),
+    Instance(String),
+    Tuple(Vec<StaticType>), // Of `return a, b;`, only ever unpacked
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionType {
+    pub parameters: Vec<StaticType>,
+    pub return_type: StaticType,
+}
+
+struct ClassType {
+    superclass: Option<String>,
+    methods: HashMap<String, FunctionType>,
+}
+
+impl fmt::Display for StaticType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StaticType::Any => write!(f, "any"),
+            StaticType::Number => write!(f, "number"),
+            StaticType::String => write!(f, "string"),
+            StaticType::Boolean => write!(f, "bool"),
+            StaticType::Nil => write!(f, "nil"),
+            StaticType::Function(function) => {
+                let parameters = function
+                    .parameters
+                    .iter()
+                    .map(|parameter| parameter.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                write!(f, "fun({}) -> {}", parameters, function.return_type)
+            }
+            StaticType::Class(name) => write!(f, "class {}", name),
+            StaticType::Instance(name) => write!(f, "{}", name),
+            StaticType::Tuple(values) => {
+                let values = values
+                    .iter()
+                    .map(|value| value.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                write!(f, "({})", values)
+            }
+        }
+    }
+}
+
+impl Default for TypeChecker {
+    fn default() -> TypeChecker {
+        TypeChecker::new()
+    }
+}
+
+impl TypeChecker {
+    pub fn new() -> TypeChecker {
+        // Natives defined by the interpreter
+        let mut globals = HashMap::new();
+        let natives = [
+            ("clock", Vec::new(), StaticType::Number),
+            ("now", Vec::new(), StaticType::Number),
+            ("random", Vec::new(), StaticType::Number),
+            ("is_nan", vec![StaticType::Number], StaticType::Boolean),
+            ("gc", Vec::new(), StaticType::Number),
+            ("arg_count", Vec::new(), StaticType::Number),
+            ("arg", vec![StaticType::Number], StaticType::Any), // A string or nil
+            ("args", Vec::new(), StaticType::Any),              // A list of strings
+            (
+                "union",
+                vec![StaticType::Any, StaticType::Any],
+                StaticType::Any,
+            ), // A set or nil
+            (
+                "intersection",
+                vec![StaticType::Any, StaticType::Any],
+                StaticType::Any,
+            ),
+            (
+                "resume",
+                vec![StaticType::Any, StaticType::Any],
+                StaticType::Any,
+            ),
+            ("status", vec![StaticType::Any], StaticType::String),
+            ("run", Vec::new(), StaticType::Nil),
+            ("sleep_async", vec![StaticType::Number], StaticType::Any),
+            ("read_file_async", vec![StaticType::String], StaticType::Any),
+            ("spawn_thread", vec![StaticType::Any], StaticType::Any),
+            (
+                "send",
+                vec![StaticType::Any, StaticType::Any],
+                StaticType::Nil,
+            ),
+            ("recv", vec![StaticType::Any], StaticType::Any),
+            ("string_builder", Vec::new(), StaticType::Any),
+            (
+                "append",
+                vec![StaticType::Any, StaticType::Any],
+                StaticType::Nil,
+            ),
+            ("to_string", vec![StaticType::Any], StaticType::String),
+            (
+                "debug_repr",
+                vec![StaticType::Any, StaticType::Number],
+                StaticType::String,
+            ),
+            ("print_table", vec![StaticType::Any], StaticType::Nil),
+            ("parse_number", vec![StaticType::String], StaticType::Any), // A number or nil
+            (
+                "encode",
+                vec![StaticType::String, StaticType::String],
+                StaticType::Any,
+            ), // Bytes
+            ("modules", Vec::new(), StaticType::Any),                    // A list of strings
+            ("locals", Vec::new(), StaticType::Any),                     // A map
+            ("globals", Vec::new(), StaticType::Any),                    // A map
+            ("freeze", vec![StaticType::Any], StaticType::Any),          // The instance
+            ("seal", vec![StaticType::Any], StaticType::Any),            // The instance
+            (
+                "has",
+                vec![StaticType::Any, StaticType::String],
+                StaticType::Boolean,
+            ),
+        ];
+        // `format` takes any number of arguments, it stays `any` and its calls aren't checked
+        for (native, parameters, return_type) in natives {
+            globals.insert(
+                Ref::from(native),
+                StaticType::Function(Box::new(FunctionType {
+                    parameters,
+                    return_type,
+                })),
+            );
+        }
+
+        // The built-in error classes, with no methods
+        let mut classes = HashMap::new();
+        for class in ErrorClass::ALL {
+            globals.insert(
+                Ref::from(class.name()),
+                StaticType::Class(class.name().to_string()),
+            );
+            classes.insert(
+                class.name().to_string(),
+                ClassType {
+                    superclass: class.superclass().map(|class| class.name().to_string()),
+                    methods: HashMap::new(),
+                },
+            );
+        }
+
+        TypeChecker {
+            scopes: vec![globals],
+            classes,
+            return_types: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn check(&mut self, statements: &[Stmt]) -> Vec<Error> {
+        for statement in statements.iter() {
+            statement.accept(self);
+        }
+        std::mem::take(&mut self.errors)
+    }
+
+    fn error(&mut self, message: String, span: Span) {
+        self.errors.push(Error::type_checker(message, span));
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn declare(&mut self, name: &Token, static_type: StaticType) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.lexeme.clone(), static_type);
+        }
+    }
+
+    // Undeclared variables are left for the interpreter to report
+    fn lookup(&self, name: &Token) -> StaticType {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&name.lexeme))
+            .cloned()
+            .unwrap_or(StaticType::Any)
+    }
+
+    // number, string, bool, nil, any or the name of a class
+    fn resolve_annotation(&mut self, annotation: &Option<Token>) -> StaticType {
+        let Some(annotation) = annotation else {
+            return StaticType::Any;
+        };
+
+        match &*annotation.lexeme {
+            "any" => StaticType::Any,
+            "number" => StaticType::Number,
+            "string" => StaticType::String,
+            "bool" => StaticType::Boolean,
+            "nil" => StaticType::Nil,
+            name if self.classes.contains_key(name) => StaticType::Instance(name.to_string()),
+            name => {
+                self.error(format!("Unknown type `{}`", name), annotation.span);
+                StaticType::Any
+            }
+        }
+    }
+
+    // Calling a generator function gives a generator, whatever its return type says,
+    // so it returns `any` and what it returns and yields isn't checked
+    fn resolve_signature(
+        &mut self,
+        parameters: &[Token],
+        body: &[Stmt],
+        signature: &Signature,
+    ) -> FunctionType {
+        let parameters = (0..parameters.len())
+            .map(|index| {
+                let annotation = signature.parameters.get(index).cloned().flatten();
+                self.resolve_annotation(&annotation)
+            })
+            .collect();
+        FunctionType {
+            parameters,
+            return_type: match body.iter().any(Stmt::yields) {
+                true => StaticType::Any,
+                false => self.resolve_annotation(&signature.return_type),
+            },
+        }
+    }
+
+    fn is_subclass(&self, class: &str, ancestor: &str) -> bool {
+        let mut class = Some(class.to_string());
+        while let Some(name) = class {
+            if name == ancestor {
+                return true;
+            }
+            class = self
+                .classes
+                .get(&name)
+                .and_then(|class| class.superclass.clone());
+        }
+        false
+    }
+
+    // Whether a value of type `actual` can be used where `expected` is declared
+    fn is_assignable(&self, expected: &StaticType, actual: &StaticType) -> bool {
+        match (expected, actual) {
+            (StaticType::Any, _) | (_, StaticType::Any) => true,
+            (StaticType::Instance(expected), StaticType::Instance(actual)) => {
+                self.is_subclass(actual, expected)
+            }
+            (StaticType::Function(expected), StaticType::Function(actual)) => {
+                expected.parameters.len() == actual.parameters.len()
+                    && expected
+                        .parameters
+                        .iter()
+                        .zip(actual.parameters.iter())
+                        .all(|(expected, actual)| self.is_assignable(actual, expected))
+                    && self.is_assignable(&expected.return_type, &actual.return_type)
+            }
+            (StaticType::Tuple(expected), StaticType::Tuple(actual)) => {
+                expected.len() == actual.len()
+                    && expected
+                        .iter()
+                        .zip(actual.iter())
+                        .all(|(expected, actual)| self.is_assignable(expected, actual))
+            }
+            (expected, actual) => expected == actual,
+        }
+    }
+
+    fn expect(&mut self, expected: &StaticType, actual: &StaticType, context: &str, span: Span) {
+        if !self.is_assignable(expected, actual) {
+            self.error(
+                format!("Expected {} {}, got {}", expected, context, actual),
+                span,
+            );
+        }
+    }
+
+    fn expect_number(&mut self, operand: &StaticType, operator: &Token) {
+        let context = format!("operand for `{}`", operator.lexeme);
+        self.expect(&StaticType::Number, operand, &context, operator.span);
+    }
+
+    fn find_method(&self, class: &str, name: &str) -> Option<FunctionType> {
+        let mut class = self.classes.get(class);
+        while let Some(current) = class {
+            if let Some(method) = current.methods.get(name) {
+                return Some(method.clone());
+            }
+            class = current
+                .superclass
+                .as_ref()
+                .and_then(|superclass| self.classes.get(superclass));
+        }
+        None
+    }
+
+    // Every method of the class, inherited ones included, the closest one wins
+    fn methods(&self, class: &str) -> Vec<(String, FunctionType)> {
+        let mut methods: Vec<(String, FunctionType)> = Vec::new();
+        let mut class = self.classes.get(class);
+        while let Some(current) = class {
+            for (name, method) in current.methods.iter() {
+                if !methods.iter().any(|(known, _)| known == name) {
+                    methods.push((name.clone(), method.clone()));
+                }
+            }
+            class = current
+                .superclass
+                .as_ref()
+                .and_then(|superclass| self.classes.get(superclass));
+        }
+        methods
+    }
+
+    fn check_function(&mut self, parameters: &[Token], body: &[Stmt], function: &FunctionType) {
+        self.begin_scope();
+        for (parameter, static_type) in parameters.iter().zip(function.parameters.iter()) {
+            self.declare(parameter, static_type.clone());
+        }
+        self.return_types.push(function.return_type.clone());
+        for statement in body.iter() {
+            statement.accept(self);
+        }
+        self.return_types.pop();
+        self.end_scope();
+    }
+
+    // Reaching the end of its body, a function returns nil
+    fn check_end(&mut self, name: &Token, body: &[Stmt], function: &FunctionType) {
+        if self.is_assignable(&function.return_type, &StaticType::Nil)
+            || !body.iter().all(Stmt::can_complete)
+        {
+            return;
+        }
+        self.error(
+            format!(
+                "`{}` returns {}, but can reach the end of its body without a `return`",
+                name.lexeme, function.return_type
+            ),
+            name.span,
+        );
+    }
+
+    fn check_arguments(
+        &mut self,
+        function: &FunctionType,
+        arguments: &[StaticType],
+        closing_paren: &Token,
+    ) {
+        if function.parameters.len() != arguments.len() {
+            self.error(
+                format!(
+                    "Expected {} arguments but got {}",
+                    function.parameters.len(),
+                    arguments.len()
+                ),
+                closing_paren.span,
+            );
+            return;
+        }
+
+        for (index, (parameter, argument)) in
+            function.parameters.iter().zip(arguments.iter()).enumerate()
+        {
+            let context = format!("for argument {}", index + 1);
+            self.expect(parameter, argument, &context, closing_paren.span);
+        }
+    }
+}
+
+impl ExpressionVisitor<StaticType> for TypeChecker {
+    fn visit_binary(
+        &mut self,
+        left_expr: &Expr,
+        operator: &Token,
+        right_expr: &Expr,
+    ) -> StaticType {
+        let left = left_expr.accept(self);
+        let right = right_expr.accept(self);
+
+        match operator.token_type {
+            TokenType::Minus | TokenType::Slash => {
+                self.expect_number(&left, operator);
+                self.expect_number(&right, operator);
+                StaticType::Number
+            }
+
+            // Numbers are multiplied, a string is repeated
+            TokenType::Star => {
+                self.expect_number(&right, operator);
+                match left {
+                    StaticType::String => StaticType::String,
+                    StaticType::Any => StaticType::Any,
+                    _ => {
+                        self.expect_number(&left, operator);
+                        StaticType::Number
+                    }
+                }
+            }
+
+            // Numbers are added, anything else is concatenated as a string
+            TokenType::Plus => match (&left, &right) {
+                (StaticType::Any, StaticType::Any) => StaticType::Any,
+                (StaticType::Number, other) | (other, StaticType::Number) => {
+                    self.expect_number(other, operator);
+                    StaticType::Number
+                }
+                _ => StaticType::String,
+            },
+
+            TokenType::Greater
+            | TokenType::GreaterEqual
+            | TokenType::Less
+            | TokenType::LessEqual => {
+                self.expect_number(&left, operator);
+                self.expect_number(&right, operator);
+                StaticType::Boolean
+            }
+
+            _ => StaticType::Boolean, // ==, != and in
+        }
+    }
+
+    fn visit_call(
+        &mut self,
+        callee: &Expr,
+        closing_paren: &Token,
+        arguments: &[Expr],
+    ) -> StaticType {
+        let callee = callee.accept(self);
+        let arguments = arguments
+            .iter()
+            .map(|argument| argument.accept(self))
+            .collect::<Vec<StaticType>>();
+
+        match callee {
+            StaticType::Function(function) => {
+                self.check_arguments(&function, &arguments, closing_paren);
+                function.return_type
+            }
+            StaticType::Class(name) => {
+                if let Some(init) = self.find_method(&name, "init") {
+                    self.check_arguments(&init, &arguments, closing_paren);
+                }
+                StaticType::Instance(name)
+            }
+            StaticType::Any => StaticType::Any,
+            other => {
+                self.error(
+                    format!("Can only call functions and classes, not {}", other),
+                    closing_paren.span,
+                );
+                StaticType::Any
+            }
+        }
+    }
+
+    fn visit_grouping(&mut self, grouping_expr: &Expr) -> StaticType {
+        grouping_expr.accept(self)
+    }
+
+    // The call is checked as if it was made, the coroutine it makes is a value of its own
+    fn visit_spawn(&mut self, _keyword: &Token, call: &Expr) -> StaticType {
+        call.accept(self);
+        StaticType::Any
+    }
+
+    // What a promise settles with is only known once it has
+    fn visit_await(&mut self, _keyword: &Token, promise: &Expr) -> StaticType {
+        promise.accept(self);
+        StaticType::Any
+    }
+
+    fn visit_unary(&mut self, operator: &Token, unary_expr: &Expr) -> StaticType {
+        let operand = unary_expr.accept(self);
+        match operator.token_type {
+            TokenType::Minus => {
+                self.expect_number(&operand, operator);
+                StaticType::Number
+            }
+            _ => StaticType::Boolean, // !
+        }
+    }
+
+    fn visit_literal(&mut self, lit: &Token) -> StaticType {
+        match lit.token_type {
+            TokenType::Number => StaticType::Number,
+            TokenType::String => StaticType::String,
+            TokenType::True | TokenType::False => StaticType::Boolean,
+            TokenType::Nil => StaticType::Nil,
+            _ => StaticType::Any,
+        }
+    }
+
+    // `and` and `or` evaluate to one of their operands
+    fn visit_logical(
+        &mut self,
+        left_expr: &Expr,
+        _logical_and_or: &Token,
+        right_expr: &Expr,
+    ) -> StaticType {
+        let left = left_expr.accept(self);
+        let right = right_expr.accept(self);
+        if left == right {
+            left
+        } else {
+            StaticType::Any
+        }
+    }
+
+    fn visit_variable(&mut self, variable: &Token, _location: Option<Location>) -> StaticType {
+        self.lookup(variable)
+    }
+
+    // Not worked out, like the types of fields
+    fn visit_this(&mut self, _keyword: &Token, _location: Option<Location>) -> StaticType {
+        StaticType::Any
+    }
+
+    fn visit_super(
+        &mut self,
+        _keyword: &Token,
+        _method: &Token,
+        _superclass: Option<Location>,
+        _this: Option<Location>,
+    ) -> StaticType {
+        StaticType::Any
+    }
+
+    fn visit_assign(
+        &mut self,
+        variable: &Token,
+        expr: &Expr,
+        _location: Option<Location>,
+    ) -> StaticType {
+        let value = expr.accept(self);
+        let declared = self.lookup(variable);
+        let context = format!("for `{}`", variable.lexeme);
+        self.expect(&declared, &value, &context, variable.span);
+        value
+    }
+
+    // Fields are not declared, only methods have a known type
+    fn visit_get(&mut self, expr: &Expr, name: &Token) -> StaticType {
+        match expr.accept(self) {
+            StaticType::Instance(class) => match self.find_method(&class, &name.lexeme) {
+                Some(method) => StaticType::Function(Box::new(method)),
+                None => StaticType::Any,
+            },
+            StaticType::Any => StaticType::Any,
+            other => {
+                self.error(
+                    format!("Only instances have properties, not {}", other),
+                    name.span,
+                );
+                StaticType::Any
+            }
+        }
+    }
+
+    // Any value can be asked, what it gives may be nil
+    fn visit_optional_get(&mut self, expr: &Expr, _name: &Token) -> StaticType {
+        expr.accept(self);
+        StaticType::Any
+    }
+
+    // Lists and maps hold values of any type, a string gives strings
+    fn visit_index(&mut self, object: &Expr, index: &Expr, close: &Token) -> StaticType {
+        let object = object.accept(self);
+        let index_type = index.accept(self);
+        match object {
+            StaticType::String => {
+                self.expect(&StaticType::Number, &index_type, "index", index.span());
+                StaticType::String
+            }
+            StaticType::Any => StaticType::Any,
+            other => {
+                self.error(
+                    format!(
+                        "Only lists, maps, strings and bytes can be indexed, not {}",
+                        other
+                    ),
+                    close.span,
+                );
+                StaticType::Any
+            }
+        }
+    }
+
+    fn visit_set(&mut self, expr: &Expr, name: &Token, value: &Expr) -> StaticType {
+        let object = expr.accept(self);
+        let value = value.accept(self);
+        match object {
+            StaticType::Instance(_) | StaticType::Any => {}
+            other => self.error(
+                format!("Only instances have fields, not {}", other),
+                name.span,
+            ),
+        }
+        value
+    }
+
+    fn visit_tuple(&mut self, values: &[Expr]) -> StaticType {
+        StaticType::Tuple(values.iter().map(|value| value.accept(self)).collect())
+    }
+
+    // Lists and maps have no type of their own to annotate with
+    fn visit_list(&mut self, elements: &[Expr]) -> StaticType {
+        for element in elements.iter() {
+            element.accept(self);
+        }
+        StaticType::Any
+    }
+
+    fn visit_set_literal(&mut self, elements: &[Expr]) -> StaticType {
+        for element in elements.iter() {
+            element.accept(self);
+        }
+        StaticType::Any
+    }
+
+    fn visit_map(&mut self, entries: &[(Expr, Expr)]) -> StaticType {
+        for (key, value) in entries.iter() {
+            key.accept(self);
+            value.accept(self);
+        }
+        StaticType::Any
+    }
+
+    // The type every arm has, `any` when they differ
+    // What a pattern binds could be anything
+    fn visit_match(&mut self, _keyword: &Token, value: &Expr, arms: &[Arm]) -> StaticType {
+        value.accept(self);
+        let mut result: Option<StaticType> = None;
+        for arm in arms.iter() {
+            for class in arm.pattern.classes() {
+                class.accept(self);
+            }
+            self.begin_scope();
+            for name in arm.pattern.bindings() {
+                self.declare(name, StaticType::Any);
+            }
+            let body = arm.body.accept(self);
+            self.end_scope();
+
+            result = match result {
+                Some(previous) if previous != body => Some(StaticType::Any),
+                _ => Some(body),
+            };
+        }
+        result.unwrap_or(StaticType::Any)
+    }
+}
+
+impl StatementVisitor<()> for TypeChecker {
+    fn visit_block(&mut self, statements: &[Stmt]) {
+        self.begin_scope();
+        for statement in statements.iter() {
+            statement.accept(self);
+        }
+        self.end_scope();
+    }
+
+    fn visit_class(
+        &mut self,
+        name: &Token,
+        superclass: Option<&Expr>,
+        mixins: &[Expr],
+        statements: &[Stmt],
+        _sealed: bool,
+    ) {
+        let superclass = match superclass {
+            Some(Expr::Variable(superclass, _))
+                if self.classes.contains_key(&*superclass.lexeme) =>
+            {
+                // Classes are known by name, so a class declared again below one of
+                // its subclasses would close a loop
+                if self.is_subclass(&superclass.lexeme, &name.lexeme) {
+                    self.error(
+                        format!(
+                            "`{}` can't inherit from `{}`, which inherits from `{}`",
+                            name.lexeme, superclass.lexeme, name.lexeme
+                        ),
+                        superclass.span,
+                    );
+                    None
+                } else {
+                    Some(superclass.lexeme.to_string())
+                }
+            }
+            _ => None,
+        };
+
+        // Registered before the methods, so they can take and return instances of the class
+        self.classes.insert(
+            name.lexeme.to_string(),
+            ClassType {
+                superclass,
+                methods: HashMap::new(),
+            },
+        );
+        self.declare(name, StaticType::Class(name.lexeme.to_string()));
+
+        let mut methods = Vec::new();
+        for method in statements.iter() {
+            if let Stmt::Function(method_name, parameters, body, signature, _) = method {
+                let method_type = self.resolve_signature(parameters, body, signature);
+                methods.push((method_name.lexeme.to_string(), method_type));
+            }
+        }
+        // Mixed in after its own methods, which override theirs
+        for mixin in mixins.iter() {
+            if let Expr::Variable(mixin, _) = mixin {
+                for (method_name, method_type) in self.methods(&mixin.lexeme) {
+                    if !methods.iter().any(|(name, _)| *name == method_name) {
+                        methods.push((method_name, method_type));
+                    }
+                }
+            }
+        }
+        if let Some(class) = self.classes.get_mut(&*name.lexeme) {
+            class.methods.extend(methods);
+        }
+
+        for method in statements.iter() {
+            if let Stmt::Function(method_name, parameters, body, ..) = method {
+                if let Some(method_type) = self.find_method(&name.lexeme, &method_name.lexeme) {
+                    self.check_function(parameters, body, &method_type);
+                    // An initializer gives its instance when it ends
+                    if &*method_name.lexeme != "init" {
+                        self.check_end(method_name, body, &method_type);
+                    }
+                }
+            }
+        }
+    }
+
+    fn visit_expression(&mut self, expr: &Expr) {
+        expr.accept(self);
+    }
+
+    fn visit_forloop(
+        &mut self,
+        initializer: Option<&Stmt>,
+        condition: Option<&Expr>,
+        incrementer: Option<&Expr>,
+        body: &Stmt,
+    ) {
+        self.begin_scope();
+        if let Some(initializer) = initializer {
+            initializer.accept(self);
+        }
+        if let Some(condition) = condition {
+            condition.accept(self);
+        }
+        if let Some(incrementer) = incrementer {
+            incrementer.accept(self);
+        }
+        body.accept(self);
+        self.end_scope();
+    }
+
+    fn visit_forin(&mut self, name: &Token, iterable: &Expr, body: &Stmt) {
+        iterable.accept(self);
+        self.begin_scope();
+        self.declare(name, StaticType::Any);
+        body.accept(self);
+        self.end_scope();
+    }
+
+    fn visit_ifelse(&mut self, condition: &Expr, then_branch: &Stmt, else_branch: Option<&Stmt>) {
+        condition.accept(self);
+        then_branch.accept(self);
+        if let Some(else_branch) = else_branch {
+            else_branch.accept(self);
+        }
+    }
+
+    // Hosts can add modules, what one holds isn't known
+    fn visit_import(&mut self, _keyword: &Token, name: &Token) {
+        self.declare(name, StaticType::Any);
+    }
+
+    fn visit_print(&mut self, expr: &Expr) {
+        expr.accept(self);
+    }
+
+    fn visit_return(&mut self, token: &Token, expr: Option<&Expr>) {
+        let value = match expr {
+            Some(expr) => expr.accept(self),
+            None => StaticType::Nil,
+        };
+
+        if let Some(return_type) = self.return_types.last().cloned() {
+            self.expect(&return_type, &value, "as the return value", token.span);
+        }
+    }
+
+    fn visit_throw(&mut self, _keyword: &Token, value: &Expr) {
+        let thrown = value.accept(self);
+        let is_error = match &thrown {
+            StaticType::Any => true,
+            StaticType::Instance(class) => self.is_subclass(class, "Error"),
+            _ => false,
+        };
+        if !is_error {
+            self.error(
+                format!(
+                    "Only instances of Error and its subclasses can be thrown, got {}",
+                    thrown
+                ),
+                value.span(),
+            );
+        }
+    }
+
+    // The error caught is an instance of the class a catch names
+    fn visit_try(&mut self, body: &[Stmt], catches: &[Catch]) {
+        self.visit_block(body);
+        for catch in catches.iter() {
+            let caught = match &catch.class {
+                Some(class) => match class.accept(self) {
+                    StaticType::Class(name) => StaticType::Instance(name),
+                    _ => StaticType::Any,
+                },
+                None => StaticType::Any,
+            };
+            self.begin_scope();
+            self.declare(&catch.name, caught);
+            for statement in catch.body.iter() {
+                statement.accept(self);
+            }
+            self.end_scope();
+        }
+    }
+
+    // What a generator is resumed with isn't known
+    fn visit_yield(&mut self, _keyword: &Token, expr: Option<&Expr>, variable: &Option<Token>) {
+        if let Some(expr) = expr {
+            expr.accept(self);
+        }
+        if let Some(variable) = variable {
+            self.declare(variable, StaticType::Any);
+        }
+    }
+
+    fn visit_error(&mut self, _tokens: &[Token]) {}
+
+    fn visit_var(&mut self, token: &Token, annotation: &Option<Token>, expr: Option<&Expr>) {
+        let declared = self.resolve_annotation(annotation);
+        if let Some(initializer) = expr {
+            let value = initializer.accept(self);
+            let context = format!("for `{}`", token.lexeme);
+            self.expect(&declared, &value, &context, token.span);
+        }
+        self.declare(token, declared);
+    }
+
+    fn visit_unpack(&mut self, names: &[Token], expr: Option<&Expr>) {
+        let values = match expr {
+            Some(initializer) => match initializer.accept(self) {
+                StaticType::Tuple(values) if values.len() == names.len() => values,
+                StaticType::Any => vec![StaticType::Any; names.len()],
+                value => {
+                    let count = match value {
+                        StaticType::Tuple(values) => values.len(),
+                        _ => 1,
+                    };
+                    self.error(
+                        format!("Expected {} values to unpack, got {}", names.len(), count),
+                        initializer.span(),
+                    );
+                    vec![StaticType::Any; names.len()]
+                }
+            },
+            None => vec![StaticType::Any; names.len()],
+        };
+        for (name, value) in names.iter().zip(values) {
+            self.declare(name, value);
+        }
+    }
+
+    fn visit_whileloop(&mut self, condition: &Expr, statement: &Stmt) {
+        condition.accept(self);
+        statement.accept(self);
+    }
+
+    // Only an instance of a class with a method to close it can be a resource
+    fn visit_with(&mut self, _keyword: &Token, resource: &Expr, name: &Token, body: &[Stmt]) {
+        let resource_type = resource.accept(self);
+        let closes = match &resource_type {
+            StaticType::Any => true,
+            StaticType::Instance(class) => {
+                self.find_method(class, "__exit").is_some()
+                    || self.find_method(class, "close").is_some()
+            }
+            _ => false,
+        };
+        if !closes {
+            self.error(
+                format!(
+                    "Only instances with a `close` or `__exit` method can be used in `with`, got {}",
+                    resource_type
+                ),
+                resource.span(),
+            );
+        }
+        self.begin_scope();
+        self.declare(name, resource_type);
+        for statement in body.iter() {
+            statement.accept(self);
+        }
+        self.end_scope();
+    }
+
+    // Declared before the body is checked, so the function can call itself
+    fn visit_function(
+        &mut self,
+        name: &Token,
+        parameters: &[Token],
+        body: &[Stmt],
+        signature: &Signature,
+        _captures: &[Slot],
+    ) {
+        let function = self.resolve_signature(parameters, body, signature);
+        self.declare(name, StaticType::Function(Box::new(function.clone())));
+        self.check_function(parameters, body, &function);
+        self.check_end(name, body, &function);
+    }
+}
@@ -0,0 +1,270 @@
+use std::{
+    cell::RefCell,
+    rc::{Rc, Weak},
+};
+
+use super::value::{Class, Closure, Value};
+use crate::lexer::token::Span;
+
+// Instructions of the VM, one byte each
+// followed by their operands, if any
+//
+// u16 operands are indices into the constant table or jump offsets,
+// u8 operands are stack slots, upvalue indices or argument counts
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub enum OpCode {
+    Constant, // u16 constant
+    Nil,
+    True,
+    False,
+    Pop,
+    GetLocal,     // u8 slot
+    SetLocal,     // u8 slot
+    GetGlobal,    // u16 name
+    DefineGlobal, // u16 name
+    SetGlobal,    // u16 name
+    GetUpvalue,   // u8 upvalue
+    SetUpvalue,   // u8 upvalue
+    GetProperty,  // u16 name, u16 inline cache
+    GetOptional,  // u16 name, like GetProperty but nil when there is no such property
+    SetProperty,  // u16 name
+    GetSuper,     // u16 name, pops the superclass and the instance, pushes the method bound to it
+    Index,        // Pops the index and the list, map or string, pushes the element
+    Equal,
+    Is, // Pops two values, pushes whether they are the very same list, map or set
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    In,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Not,
+    Negate,
+    Print,
+    Jump,        // u16 forward offset
+    JumpIfFalse, // u16 forward offset, leaves the condition on the stack
+    Loop,        // u16 backward offset
+    Call,        // u8 argument count
+    Closure,     // u16 function, then (is_local: u8, index: u8) per upvalue
+    CloseUpvalue,
+    Return,
+    Class,    // u16 name
+    Seal,     // Makes the class on top sealed, its instances are sealed once initialized
+    Inherit,  // Pops the class, its superclass under it stays as `super`
+    Method,   // u16 name, pops the method
+    Mix,      // u8 count, pops that many mixins and adds their methods to the class under them
+    Override, // u16 name, checks the method of the class on top replaces an inherited one
+    Tuple,    // u8 count, pops that many values
+    Unpack,   // u8 count, pops a tuple of that many values and pushes them
+    List,     // u16 count, pops that many elements
+    Map,      // u16 count, pops that many keys and values, each key under its value
+    Set,      // u16 count, pops that many elements
+    // u16 pattern, pops the value and the classes of the pattern's instance patterns,
+    // pushes what the pattern binds and true when the value matches, false when not
+    Match,
+    NoMatch, // Pops the value no arm matched and fails
+    // First in a generator function, the frame of the call becomes a suspended
+    // Generator, which the caller gets
+    Generator,
+    Yield,   // Suspends the generator running, its caller gets the value
+    Iterate, // Pops what a for-in loop goes through, pushes it and the index of its first element
+    Next,    // u16 forward offset, pushes the next element or jumps when there is none
+    Spawn,   // u8 argument count, like Call but pushes the call as a suspended coroutine
+    // u8 1 for a statement of its own, pops a promise, waits for it to settle and
+    // pushes its value
+    Await,
+    // u16 forward offset to the catches, an error until the matching EndTry unwinds
+    // the stack to where it is now, pushes the error and jumps there
+    Try,
+    EndTry,
+    Throw,   // Pops an error and fails with it
+    Catches, // Pops a class and an error, pushes whether the error is an instance of the class
+    Rethrow, // Pops the error no catch took and fails with it again
+    Closer,  // Pops the resource of a `with`, pushes the method closing it
+    Import,  // u16 name, pushes the module
+}
+
+impl OpCode {
+    const ALL: [OpCode; 64] = [
+        OpCode::Constant,
+        OpCode::Nil,
+        OpCode::True,
+        OpCode::False,
+        OpCode::Pop,
+        OpCode::GetLocal,
+        OpCode::SetLocal,
+        OpCode::GetGlobal,
+        OpCode::DefineGlobal,
+        OpCode::SetGlobal,
+        OpCode::GetUpvalue,
+        OpCode::SetUpvalue,
+        OpCode::GetProperty,
+        OpCode::GetOptional,
+        OpCode::SetProperty,
+        OpCode::GetSuper,
+        OpCode::Index,
+        OpCode::Equal,
+        OpCode::Is,
+        OpCode::Greater,
+        OpCode::GreaterEqual,
+        OpCode::Less,
+        OpCode::LessEqual,
+        OpCode::In,
+        OpCode::Add,
+        OpCode::Subtract,
+        OpCode::Multiply,
+        OpCode::Divide,
+        OpCode::Not,
+        OpCode::Negate,
+        OpCode::Print,
+        OpCode::Jump,
+        OpCode::JumpIfFalse,
+        OpCode::Loop,
+        OpCode::Call,
+        OpCode::Closure,
+        OpCode::CloseUpvalue,
+        OpCode::Return,
+        OpCode::Class,
+        OpCode::Seal,
+        OpCode::Inherit,
+        OpCode::Method,
+        OpCode::Mix,
+        OpCode::Override,
+        OpCode::Tuple,
+        OpCode::Unpack,
+        OpCode::List,
+        OpCode::Map,
+        OpCode::Set,
+        OpCode::Match,
+        OpCode::NoMatch,
+        OpCode::Generator,
+        OpCode::Yield,
+        OpCode::Iterate,
+        OpCode::Next,
+        OpCode::Spawn,
+        OpCode::Await,
+        OpCode::Try,
+        OpCode::EndTry,
+        OpCode::Throw,
+        OpCode::Catches,
+        OpCode::Rethrow,
+        OpCode::Closer,
+        OpCode::Import,
+    ];
+
+    pub fn from_byte(byte: u8) -> Option<OpCode> {
+        OpCode::ALL.get(byte as usize).copied()
+    }
+}
+
+// A compiled function body
+#[derive(Debug, Clone, Default)]
+pub struct Chunk {
+    pub code: Vec<u8>,
+    pub constants: Vec<Value>,
+    pub spans: Vec<Span>, // Source span of every byte in `code`
+    pub caches: Vec<RefCell<Option<MethodCache>>>, // One per GetProperty, filled in by the VM
+    pub locals: Vec<LocalName>, // Of the named locals, for `locals()`
+}
+
+// A named local, in its slot while the code from `start` up to, not including,
+// `end` runs
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocalName {
+    pub name: String,
+    pub slot: u8,
+    pub start: usize,
+    pub end: usize,
+}
+
+// The method a GetProperty found the last time it read one, good for the next
+// instance of the same class as long as the class hasn't changed since
+#[derive(Debug, Clone)]
+pub struct MethodCache {
+    class: Weak<RefCell<Class>>, // Weak, the class' methods keep this chunk alive
+    version: usize,
+    pub method: Rc<Closure>,
+}
+
+impl MethodCache {
+    pub fn new(class: &Rc<RefCell<Class>>, method: Rc<Closure>) -> MethodCache {
+        MethodCache {
+            class: Rc::downgrade(class),
+            version: class.borrow().version,
+            method,
+        }
+    }
+
+    pub fn is_for(&self, class: &Rc<RefCell<Class>>) -> bool {
+        std::ptr::eq(self.class.as_ptr(), Rc::as_ptr(class))
+            && self.version == class.borrow().version
+    }
+}
+
+impl Chunk {
+    pub fn new() -> Chunk {
+        Chunk {
+            code: Vec::new(),
+            constants: Vec::new(),
+            spans: Vec::new(),
+            caches: Vec::new(),
+            locals: Vec::new(),
+        }
+    }
+
+    pub fn write(&mut self, byte: u8, span: Span) {
+        self.code.push(byte);
+        self.spans.push(span);
+    }
+
+    pub fn write_op(&mut self, op: OpCode, span: Span) {
+        self.write(op as u8, span);
+    }
+
+    pub fn write_u16(&mut self, value: u16, span: Span) {
+        let [high, low] = value.to_be_bytes();
+        self.write(high, span);
+        self.write(low, span);
+    }
+
+    pub fn read_u16(&self, offset: usize) -> u16 {
+        u16::from_be_bytes([self.code[offset], self.code[offset + 1]])
+    }
+
+    // Overwrites the u16 operand at `offset`, used to patch jumps
+    pub fn patch_u16(&mut self, offset: usize, value: u16) {
+        let [high, low] = value.to_be_bytes();
+        self.code[offset] = high;
+        self.code[offset + 1] = low;
+    }
+
+    // An empty inline cache, returns its index
+    pub fn add_cache(&mut self) -> usize {
+        self.caches.push(RefCell::default());
+        self.caches.len() - 1
+    }
+
+    // Equal strings and numbers share one constant
+    pub fn add_constant(&mut self, value: Value) -> usize {
+        let existing = self
+            .constants
+            .iter()
+            .position(|constant| match (constant, &value) {
+                (Value::Number(a), Value::Number(b)) => a.to_bits() == b.to_bits(),
+                (Value::String(a), Value::String(b)) => a == b,
+                _ => false,
+            });
+
+        match existing {
+            Some(index) => index,
+            None => {
+                self.constants.push(value);
+                self.constants.len() - 1
+            }
+        }
+    }
+}
@@ -0,0 +1,279 @@
+// Finds the instances and captured variables of the VM that only refer to each
+// other, for `gc()`
+//
+// Values are reference counted, which frees one as soon as nothing refers to it,
+// but not a cycle, like an instance stored in one of its own fields or a local
+// function capturing the variable it is stored in. A value can only be put
+// somewhere after it was made in the fields of an instance or in a closed
+// variable, so every cycle goes through one of them, and they are the ones
+// tracked. Collecting works out, like the tree-walker's Heap, how many references
+// to each object come from the others; ones with more than that are used from
+// outside (the stack, the globals, a frame), and so is everything they reach.
+// The rest is garbage, emptying it breaks the cycles
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    rc::{Rc, Weak},
+};
+
+use super::value::{BoundMethod, Class, Closure, Generator, Instance, Upvalue, Value};
+use crate::{
+    interpreter::heap::{mark, PRUNE_MIN},
+    modules::Module,
+};
+
+pub struct Collector {
+    tracked: Vec<Tracked>,
+    prune_at: usize, // Number tracked at which the ones gone are left out
+}
+
+enum Tracked {
+    Instance(Weak<RefCell<Instance>>),
+    Upvalue(Weak<RefCell<Upvalue>>),
+}
+
+impl Default for Collector {
+    fn default() -> Collector {
+        Collector {
+            tracked: Vec::new(),
+            prune_at: PRUNE_MIN,
+        }
+    }
+}
+
+impl Collector {
+    pub fn instance(&mut self, instance: &Rc<RefCell<Instance>>) {
+        self.track(Tracked::Instance(Rc::downgrade(instance)));
+    }
+
+    pub fn upvalue(&mut self, upvalue: &Rc<RefCell<Upvalue>>) {
+        self.track(Tracked::Upvalue(Rc::downgrade(upvalue)));
+    }
+
+    fn track(&mut self, tracked: Tracked) {
+        if self.tracked.len() >= self.prune_at {
+            self.tracked.retain(|tracked| tracked.upgrade().is_some());
+            self.prune_at = PRUNE_MIN.max(self.tracked.len() * 2);
+        }
+        self.tracked.push(tracked);
+    }
+
+    // Returns the number of instances and variables emptied. The `__drop` of an
+    // instance let go of here runs on it, so the instance and what it refers to
+    // are kept for it, until a later collection
+    pub fn collect(&mut self) -> usize {
+        let mut objects = Objects::default();
+        self.tracked.retain(|tracked| match tracked.upgrade() {
+            Some(object) => {
+                objects.add(object);
+                true
+            }
+            None => false,
+        });
+        // Tracing an object can find more of them
+        let mut references: Vec<Vec<usize>> = Vec::new();
+        while references.len() < objects.list.len() {
+            let object = objects.list[references.len()].clone();
+            // Only happens while one is being updated, try again next time
+            let Some(traced) = object.references() else {
+                return 0;
+            };
+            let nodes = traced
+                .into_iter()
+                .map(|object| objects.add(object))
+                .collect();
+            references.push(nodes);
+        }
+
+        // Objects minus the reference `objects` holds
+        let mut outside: Vec<usize> = objects
+            .list
+            .iter()
+            .map(|object| object.strong_count() - 1)
+            .collect();
+        for reference in references.iter().flatten() {
+            outside[*reference] = outside[*reference].saturating_sub(1);
+        }
+        let mut reachable = vec![false; outside.len()];
+        let roots = (0..outside.len()).filter(|&index| outside[index] > 0);
+        mark(roots.collect(), &references, &mut reachable);
+        let mut roots = Vec::new();
+        for (index, object) in objects.list.iter().enumerate() {
+            if let (Object::Instance(instance), false) = (object, reachable[index]) {
+                if Instance::finalize(instance) {
+                    roots.push(index);
+                }
+            }
+        }
+        mark(roots, &references, &mut reachable);
+
+        // Dropped once they are all out, they may hold each other
+        let mut garbage = Vec::new();
+        let mut emptied = 0;
+        for (object, reachable) in objects.list.iter().zip(reachable) {
+            match (object, reachable) {
+                (Object::Instance(instance), false) => {
+                    let fields = std::mem::take(&mut instance.borrow_mut().fields);
+                    garbage.extend(fields.into_values());
+                    emptied += 1;
+                }
+                (Object::Upvalue(upvalue), false) => {
+                    if let Upvalue::Closed(value) = upvalue.replace(Upvalue::Closed(Value::Nil)) {
+                        garbage.push(value);
+                    }
+                    emptied += 1;
+                }
+                _ => {}
+            }
+        }
+        emptied
+    }
+}
+
+impl Tracked {
+    fn upgrade(&self) -> Option<Object> {
+        match self {
+            Tracked::Instance(instance) => instance.upgrade().map(Object::Instance),
+            Tracked::Upvalue(upvalue) => upvalue.upgrade().map(Object::Upvalue),
+        }
+    }
+}
+
+// A value shared by reference the collector follows
+#[derive(Clone)]
+enum Object {
+    Instance(Rc<RefCell<Instance>>),
+    Upvalue(Rc<RefCell<Upvalue>>),
+    Closure(Rc<Closure>),
+    Class(Rc<RefCell<Class>>),
+    BoundMethod(Rc<BoundMethod>),
+    Tuple(Rc<[Value]>),
+    Elements(Rc<RefCell<Vec<Value>>>), // Of a list or a set
+    Map(Rc<RefCell<Vec<(Value, Value)>>>),
+    Generator(Rc<RefCell<Generator>>),
+    Module(Rc<Module<Value>>),
+}
+
+impl Object {
+    // None for a value holding nothing the collector follows
+    fn of(value: &Value) -> Option<Object> {
+        let object = match value {
+            Value::Instance(instance) => Object::Instance(Rc::clone(instance)),
+            Value::Closure(closure) => Object::Closure(Rc::clone(closure)),
+            Value::Class(class) => Object::Class(Rc::clone(class)),
+            Value::BoundMethod(bound) => Object::BoundMethod(Rc::clone(bound)),
+            Value::Tuple(elements) => Object::Tuple(Rc::clone(elements)),
+            Value::List(elements) | Value::Set(elements) => Object::Elements(Rc::clone(elements)),
+            Value::Map(entries) => Object::Map(Rc::clone(entries)),
+            Value::Generator(generator) => Object::Generator(Rc::clone(generator)),
+            Value::Module(module) => Object::Module(Rc::clone(module)),
+            _ => return None,
+        };
+        Some(object)
+    }
+
+    fn address(&self) -> *const () {
+        match self {
+            Object::Instance(instance) => Rc::as_ptr(instance).cast(),
+            Object::Upvalue(upvalue) => Rc::as_ptr(upvalue).cast(),
+            Object::Closure(closure) => Rc::as_ptr(closure).cast(),
+            Object::Class(class) => Rc::as_ptr(class).cast(),
+            Object::BoundMethod(bound) => Rc::as_ptr(bound).cast(),
+            Object::Tuple(elements) => Rc::as_ptr(elements).cast(),
+            Object::Elements(elements) => Rc::as_ptr(elements).cast(),
+            Object::Map(entries) => Rc::as_ptr(entries).cast(),
+            Object::Generator(generator) => Rc::as_ptr(generator).cast(),
+            Object::Module(module) => Rc::as_ptr(module).cast(),
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Object::Instance(instance) => Rc::strong_count(instance),
+            Object::Upvalue(upvalue) => Rc::strong_count(upvalue),
+            Object::Closure(closure) => Rc::strong_count(closure),
+            Object::Class(class) => Rc::strong_count(class),
+            Object::BoundMethod(bound) => Rc::strong_count(bound),
+            Object::Tuple(elements) => Rc::strong_count(elements),
+            Object::Elements(elements) => Rc::strong_count(elements),
+            Object::Map(entries) => Rc::strong_count(entries),
+            Object::Generator(generator) => Rc::strong_count(generator),
+            Object::Module(module) => Rc::strong_count(module),
+        }
+    }
+
+    // What it holds, none while it is borrowed mutably
+    fn references(&self) -> Option<Vec<Object>> {
+        let mut references = Vec::new();
+        match self {
+            Object::Instance(instance) => {
+                let instance = instance.try_borrow().ok()?;
+                references.push(Object::Class(Rc::clone(&instance.class)));
+                references.extend(instance.fields.values().filter_map(Object::of));
+            }
+            Object::Upvalue(upvalue) => {
+                if let Upvalue::Closed(value) = &*upvalue.try_borrow().ok()? {
+                    references.extend(Object::of(value));
+                }
+            }
+            Object::Closure(closure) => {
+                let upvalues = closure.upvalues.iter().map(Rc::clone);
+                references.extend(upvalues.map(Object::Upvalue));
+            }
+            Object::Class(class) => {
+                let class = class.try_borrow().ok()?;
+                let methods = class.methods.values().map(Rc::clone);
+                references.extend(methods.map(Object::Closure));
+                let classes = class.superclass.iter().chain(&class.mixins).map(Rc::clone);
+                references.extend(classes.map(Object::Class));
+            }
+            Object::BoundMethod(bound) => {
+                references.extend(Object::of(&bound.receiver));
+                references.push(Object::Closure(Rc::clone(&bound.method)));
+            }
+            Object::Tuple(elements) => {
+                references.extend(elements.iter().filter_map(Object::of));
+            }
+            Object::Elements(elements) => {
+                let elements = elements.try_borrow().ok()?;
+                references.extend(elements.iter().filter_map(Object::of));
+            }
+            Object::Map(entries) => {
+                let entries = entries.try_borrow().ok()?;
+                let values = entries.iter().flat_map(|(key, value)| [key, value]);
+                references.extend(values.filter_map(Object::of));
+            }
+            Object::Generator(generator) => {
+                let generator = generator.try_borrow().ok()?;
+                references.push(Object::Closure(Rc::clone(&generator.closure)));
+                references.extend(generator.stack.iter().filter_map(Object::of));
+                let upvalues = generator
+                    .upvalues
+                    .iter()
+                    .map(|(_, upvalue)| Rc::clone(upvalue));
+                references.extend(upvalues.map(Object::Upvalue));
+            }
+            Object::Module(module) => {
+                references.extend(module.members.values().filter_map(Object::of));
+            }
+        }
+        Some(references)
+    }
+}
+
+// The objects found while collecting, each with its node
+#[derive(Default)]
+struct Objects {
+    list: Vec<Object>,
+    nodes: HashMap<*const (), usize>,
+}
+
+impl Objects {
+    fn add(&mut self, object: Object) -> usize {
+        let list = &mut self.list;
+        *self.nodes.entry(object.address()).or_insert_with(|| {
+            list.push(object);
+            list.len() - 1
+        })
+    }
+}
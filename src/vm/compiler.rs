@@ -0,0 +1,1189 @@
+use std::rc::Rc;
+
+use super::{
+    chunk::{Chunk, LocalName, OpCode},
+    value::{FunctionProto, PatternProto, Value},
+};
+
+use crate::{
+    error::Error,
+    lexer::token::*,
+    parser::{
+        expr::{Visitable as ExpressionVisitable, Visitor as ExpressionVisitor, *},
+        pattern::{Arm, Pattern},
+        stmt::{Visitable as StatementVisitable, Visitor as StatementVisitor, *},
+    },
+};
+
+const MAX_LOCALS: usize = 256;
+const MAX_UPVALUES: usize = 256;
+const MAX_ARGUMENTS: usize = 255;
+
+// Compiles the syntax tree into bytecode for the VM
+//
+// Top-level variables are globals, looked up by name at runtime,
+// everything declared inside a block or a function lives in a stack slot
+pub struct Compiler {
+    functions: Vec<FunctionState>, // Function being compiled, and the ones enclosing it
+    span: Span,                    // Last token seen, for the emitted code
+    errors: Vec<Error>,
+}
+
+#[derive(PartialEq)]
+enum FunctionKind {
+    Script,
+    Function,
+    Method,
+    Initializer,
+}
+
+struct FunctionState {
+    function: FunctionProto,
+    kind: FunctionKind,
+    locals: Vec<Local>,
+    upvalues: Vec<UpvalueSlot>,
+    scope_depth: usize,
+    // Of the try and with bodies being compiled, innermost last: none for a try,
+    // the slot of the method closing the resource for a with
+    handlers: Vec<Option<u8>>,
+}
+
+struct Local {
+    name: String,
+    depth: usize,
+    is_captured: bool, // Captured locals are moved off the stack when they go out of scope
+    start: usize,      // Where its scope starts in the code
+}
+
+#[derive(PartialEq)]
+struct UpvalueSlot {
+    index: u8,
+    is_local: bool, // A local of the enclosing function, or one of its upvalues
+}
+
+impl FunctionState {
+    // `span` is where its name is, like the tree-walker shows functions
+    fn new(name: String, span: Span, kind: FunctionKind) -> FunctionState {
+        FunctionState {
+            function: FunctionProto {
+                name,
+                line: span.line,
+                column: span.column,
+                ..Default::default()
+            },
+            // Slot 0 holds the called function, or the instance for methods
+            locals: vec![Local {
+                name: match kind {
+                    FunctionKind::Method | FunctionKind::Initializer => "this".to_string(),
+                    _ => String::new(),
+                },
+                depth: 0,
+                is_captured: false,
+                start: 0,
+            }],
+            upvalues: Vec::new(),
+            handlers: Vec::new(),
+            scope_depth: match kind {
+                FunctionKind::Script => 0,
+                _ => 1,
+            },
+            kind,
+        }
+    }
+
+    // The local leaves the compiler, its name goes in the chunk for `locals()`
+    // with where its scope ended, here
+    fn retire(&mut self, slot: usize, local: Local) {
+        if local.name.is_empty() {
+            return;
+        }
+        let chunk = &mut self.function.chunk;
+        chunk.locals.push(LocalName {
+            name: local.name,
+            slot: slot as u8,
+            start: local.start,
+            end: chunk.code.len(),
+        });
+    }
+
+    // The function, once its code is all emitted
+    fn finish(&mut self) -> FunctionProto {
+        while let Some(local) = self.locals.pop() {
+            self.retire(self.locals.len(), local);
+        }
+        std::mem::take(&mut self.function)
+    }
+}
+
+impl Default for Compiler {
+    fn default() -> Compiler {
+        Compiler::new()
+    }
+}
+
+impl Compiler {
+    pub fn new() -> Compiler {
+        Compiler {
+            functions: Vec::new(),
+            span: Span::new(0, 0, 1, 1),
+            errors: Vec::new(),
+        }
+    }
+
+    // The whole program becomes the body of an implicit, argumentless function
+    pub fn compile(&mut self, statements: &[Stmt]) -> Result<Rc<FunctionProto>, Vec<Error>> {
+        self.functions.push(FunctionState::new(
+            "script".to_string(),
+            Span::default(),
+            FunctionKind::Script,
+        ));
+
+        // A last expression statement is what the script returns, see Stmt::value
+        let value = statements.last().and_then(Stmt::value);
+        let body = match value {
+            Some(_) => &statements[..statements.len() - 1],
+            None => statements,
+        };
+        for statement in body {
+            statement.accept(self);
+        }
+        match value {
+            Some(expr) => {
+                expr.accept(self);
+                self.emit_op(OpCode::Return);
+            }
+            None => self.emit_return(),
+        }
+
+        let function = self.functions.pop().unwrap().finish();
+        if !self.errors.is_empty() {
+            return Err(std::mem::take(&mut self.errors));
+        }
+        Ok(Rc::new(function))
+    }
+
+    fn error(&mut self, message: &str) {
+        self.errors
+            .push(Error::compiler(message.to_string(), self.span));
+    }
+
+    fn current(&mut self) -> &mut FunctionState {
+        self.functions.last_mut().unwrap()
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.current().function.chunk
+    }
+
+    fn emit_op(&mut self, op: OpCode) {
+        let span = self.span;
+        self.chunk().write_op(op, span);
+    }
+
+    fn emit_byte(&mut self, byte: u8) {
+        let span = self.span;
+        self.chunk().write(byte, span);
+    }
+
+    fn emit_u16(&mut self, value: u16) {
+        let span = self.span;
+        self.chunk().write_u16(value, span);
+    }
+
+    // Leaves the try and with bodies the code is in before it returns, the innermost
+    // first, so closing a resource doesn't go to the catches of a try inside the with
+    fn emit_exits(&mut self) {
+        let handlers = self.current().handlers.clone();
+        for handler in handlers.into_iter().rev() {
+            self.emit_op(OpCode::EndTry);
+            if let Some(closer) = handler {
+                self.emit_close(closer);
+            }
+        }
+    }
+
+    fn emit_close(&mut self, closer: u8) {
+        self.emit_op(OpCode::GetLocal);
+        self.emit_byte(closer);
+        self.emit_op(OpCode::Call);
+        self.emit_byte(0);
+        self.emit_op(OpCode::Pop);
+    }
+
+    // Functions return nil when they reach their end, initializers the new instance
+    fn emit_return(&mut self) {
+        if self.current().kind == FunctionKind::Initializer {
+            self.emit_op(OpCode::GetLocal);
+            self.emit_byte(0);
+        } else {
+            self.emit_op(OpCode::Nil);
+        }
+        self.emit_op(OpCode::Return);
+    }
+
+    fn make_constant(&mut self, value: Value) -> u16 {
+        let index = self.chunk().add_constant(value);
+        match u16::try_from(index) {
+            Ok(index) => index,
+            Err(_) => {
+                self.error("Too many constants in one chunk");
+                0
+            }
+        }
+    }
+
+    fn make_cache(&mut self) -> u16 {
+        let index = self.chunk().add_cache();
+        match u16::try_from(index) {
+            Ok(index) => index,
+            Err(_) => {
+                self.error("Too many property reads in one chunk");
+                0
+            }
+        }
+    }
+
+    fn emit_constant(&mut self, value: Value) {
+        let constant = self.make_constant(value);
+        self.emit_op(OpCode::Constant);
+        self.emit_u16(constant);
+    }
+
+    fn identifier_constant(&mut self, name: &Token) -> u16 {
+        self.make_constant(Value::String(Rc::from(&*name.lexeme)))
+    }
+
+    // `statement` is for `await promise;` and `var x = await promise;`, which can
+    // stop a coroutine rather than wait
+    fn emit_await(&mut self, keyword: &Token, promise: &Expr, statement: bool) {
+        promise.accept(self);
+        self.span = keyword.span;
+        self.emit_op(OpCode::Await);
+        self.emit_byte(statement as u8);
+    }
+
+    // Emits a jump with a placeholder offset, returns where the offset is to be patched
+    fn emit_jump(&mut self, op: OpCode) -> usize {
+        self.emit_op(op);
+        self.emit_u16(u16::MAX);
+        self.chunk().code.len() - 2
+    }
+
+    // Makes the jump at `offset` land on the next instruction
+    fn patch_jump(&mut self, offset: usize) {
+        let jump = self.chunk().code.len() - offset - 2;
+        match u16::try_from(jump) {
+            Ok(jump) => self.chunk().patch_u16(offset, jump),
+            Err(_) => self.error("Too much code to jump over"),
+        }
+    }
+
+    fn emit_loop(&mut self, loop_start: usize) {
+        self.emit_op(OpCode::Loop);
+        let offset = self.chunk().code.len() - loop_start + 2;
+        match u16::try_from(offset) {
+            Ok(offset) => self.emit_u16(offset),
+            Err(_) => {
+                self.error("Loop body too large");
+                self.emit_u16(0);
+            }
+        }
+    }
+
+    fn begin_scope(&mut self) {
+        self.current().scope_depth += 1;
+    }
+
+    // Locals of the scope are popped, the captured ones are moved to the heap first
+    fn end_scope(&mut self) {
+        self.current().scope_depth -= 1;
+
+        loop {
+            let state = self.current();
+            let captured = match state.locals.last() {
+                Some(local) if local.depth > state.scope_depth => local.is_captured,
+                _ => break,
+            };
+            let local = state.locals.pop().unwrap();
+            state.retire(state.locals.len(), local);
+
+            if captured {
+                self.emit_op(OpCode::CloseUpvalue);
+            } else {
+                self.emit_op(OpCode::Pop);
+            }
+        }
+    }
+
+    // The value on top of the stack becomes the variable
+    fn define_variable(&mut self, name: &Token) {
+        if self.current().scope_depth == 0 {
+            let constant = self.identifier_constant(name);
+            self.emit_op(OpCode::DefineGlobal);
+            self.emit_u16(constant);
+            return;
+        }
+
+        if self.current().locals.len() == MAX_LOCALS {
+            self.error("Too many local variables in function");
+            return;
+        }
+
+        let state = self.current();
+        let depth = state.scope_depth;
+        let start = state.function.chunk.code.len();
+        state.locals.push(Local {
+            name: name.lexeme.to_string(),
+            depth,
+            is_captured: false,
+            start,
+        });
+    }
+
+    // A slot no name resolves to, for values the compiled code keeps on the stack
+    fn hidden_local(&mut self) {
+        if self.current().locals.len() == MAX_LOCALS {
+            self.error("Too many local variables in function");
+            return;
+        }
+
+        let state = self.current();
+        let depth = state.scope_depth;
+        state.locals.push(Local {
+            name: String::new(),
+            depth,
+            is_captured: false,
+            start: 0,
+        });
+    }
+
+    fn resolve_local(&self, function: usize, name: &str) -> Option<u8> {
+        self.functions[function]
+            .locals
+            .iter()
+            .rposition(|local| !local.name.is_empty() && local.name == name)
+            .map(|slot| slot as u8)
+    }
+
+    // Looks for the variable in the enclosing functions,
+    // threading it through the upvalues of every function in between
+    fn resolve_upvalue(&mut self, function: usize, name: &str) -> Option<u8> {
+        if function == 0 {
+            return None;
+        }
+
+        if let Some(slot) = self.resolve_local(function - 1, name) {
+            self.functions[function - 1].locals[slot as usize].is_captured = true;
+            return self.add_upvalue(function, slot, true, name);
+        }
+
+        let index = self.resolve_upvalue(function - 1, name)?;
+        self.add_upvalue(function, index, false, name)
+    }
+
+    fn add_upvalue(
+        &mut self,
+        function: usize,
+        index: u8,
+        is_local: bool,
+        name: &str,
+    ) -> Option<u8> {
+        let upvalue = UpvalueSlot { index, is_local };
+        let state = &mut self.functions[function];
+        if let Some(existing) = state.upvalues.iter().position(|slot| *slot == upvalue) {
+            return Some(existing as u8);
+        }
+
+        if state.upvalues.len() == MAX_UPVALUES {
+            self.error("Too many closure variables in function");
+            return Some(0);
+        }
+        state.upvalues.push(upvalue);
+        state.function.upvalues.push(name.to_string());
+        Some((state.upvalues.len() - 1) as u8)
+    }
+
+    fn get_variable(&mut self, name: &Token) {
+        self.span = name.span;
+        let function = self.functions.len() - 1;
+        if let Some(slot) = self.resolve_local(function, &name.lexeme) {
+            self.emit_op(OpCode::GetLocal);
+            self.emit_byte(slot);
+        } else if let Some(index) = self.resolve_upvalue(function, &name.lexeme) {
+            self.emit_op(OpCode::GetUpvalue);
+            self.emit_byte(index);
+        } else {
+            let constant = self.identifier_constant(name);
+            self.emit_op(OpCode::GetGlobal);
+            self.emit_u16(constant);
+        }
+    }
+
+    fn set_variable(&mut self, name: &Token) {
+        self.span = name.span;
+        let function = self.functions.len() - 1;
+        if let Some(slot) = self.resolve_local(function, &name.lexeme) {
+            self.emit_op(OpCode::SetLocal);
+            self.emit_byte(slot);
+        } else if let Some(index) = self.resolve_upvalue(function, &name.lexeme) {
+            self.emit_op(OpCode::SetUpvalue);
+            self.emit_byte(index);
+        } else {
+            let constant = self.identifier_constant(name);
+            self.emit_op(OpCode::SetGlobal);
+            self.emit_u16(constant);
+        }
+    }
+
+    // Compiles the body into its own chunk,
+    // and emits the code creating a closure of it in the current one
+    fn function(&mut self, name: &Token, parameters: &[Token], body: &[Stmt], kind: FunctionKind) {
+        self.span = name.span;
+        let mut state = FunctionState::new(name.lexeme.to_string(), name.span, kind);
+        state.function.arity = parameters.len();
+        self.functions.push(state);
+
+        if parameters.len() > MAX_ARGUMENTS {
+            self.error("Can't have more than 255 parameters");
+        }
+        for parameter in parameters {
+            self.define_variable(parameter);
+        }
+        // A call gets the generator instead of running the body
+        if body.iter().any(Stmt::yields) {
+            self.emit_op(OpCode::Generator);
+        }
+
+        for statement in body.iter() {
+            statement.accept(self);
+        }
+        self.emit_return();
+        self.emit_closure();
+    }
+
+    // Ends the function being compiled, and emits the code creating a closure of it
+    fn emit_closure(&mut self) {
+        let mut state = self.functions.pop().unwrap();
+        let function = state.finish();
+        let constant = self.make_constant(Value::Function(Rc::new(function)));
+        self.emit_op(OpCode::Closure);
+        self.emit_u16(constant);
+        for upvalue in state.upvalues {
+            self.emit_byte(upvalue.is_local as u8);
+            self.emit_byte(upvalue.index);
+        }
+    }
+
+    // Literals of patterns are compared with, so they are values rather than code
+    fn literal_value(literal: &Token) -> Value {
+        match (&literal.token_type, &literal.literal) {
+            (TokenType::Number, Some(LiteralType::NumberType(number))) => Value::Number(*number),
+            (TokenType::String, Some(LiteralType::StringType(string))) => {
+                Value::String(Rc::from(string.as_str()))
+            }
+            (TokenType::True, _) => Value::Boolean(true),
+            (TokenType::False, _) => Value::Boolean(false),
+            _ => Value::Nil,
+        }
+    }
+
+    fn pattern(pattern: &Pattern) -> PatternProto {
+        match pattern {
+            Pattern::Wildcard(_) => PatternProto::Wildcard,
+            Pattern::Binding(_) => PatternProto::Binding,
+            Pattern::Literal(literal) => PatternProto::Literal(Self::literal_value(literal)),
+            Pattern::List(_, elements) => {
+                PatternProto::List(elements.iter().map(Self::pattern).collect())
+            }
+            Pattern::Map(_, entries) => PatternProto::Map(
+                entries
+                    .iter()
+                    .map(|(key, pattern)| (Self::literal_value(key), Self::pattern(pattern)))
+                    .collect(),
+            ),
+            Pattern::Instance(_, fields) => PatternProto::Instance(
+                fields
+                    .iter()
+                    .map(|(field, pattern)| (Rc::from(&*field.lexeme), Self::pattern(pattern)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl ExpressionVisitor<()> for Compiler {
+    fn visit_binary(&mut self, left_expr: &Expr, operator: &Token, right_expr: &Expr) {
+        left_expr.accept(self);
+        right_expr.accept(self);
+
+        self.span = operator.span;
+        match operator.token_type {
+            TokenType::Plus => self.emit_op(OpCode::Add),
+            TokenType::Minus => self.emit_op(OpCode::Subtract),
+            TokenType::Star => self.emit_op(OpCode::Multiply),
+            TokenType::Slash => self.emit_op(OpCode::Divide),
+            TokenType::Greater => self.emit_op(OpCode::Greater),
+            TokenType::GreaterEqual => self.emit_op(OpCode::GreaterEqual),
+            TokenType::Less => self.emit_op(OpCode::Less),
+            TokenType::LessEqual => self.emit_op(OpCode::LessEqual),
+            TokenType::In => self.emit_op(OpCode::In),
+            TokenType::EqualEqual => self.emit_op(OpCode::Equal),
+            TokenType::Is => self.emit_op(OpCode::Is),
+            TokenType::BangEqual => {
+                self.emit_op(OpCode::Equal);
+                self.emit_op(OpCode::Not);
+            }
+            _ => self.error("Unexpected Operator"),
+        }
+    }
+
+    fn visit_call(&mut self, callee: &Expr, closing_paren: &Token, arguments: &[Expr]) {
+        callee.accept(self);
+        for argument in arguments.iter() {
+            argument.accept(self);
+        }
+
+        self.span = closing_paren.span;
+        if arguments.len() > MAX_ARGUMENTS {
+            self.error("Can't have more than 255 arguments");
+        }
+        self.emit_op(OpCode::Call);
+        self.emit_byte(arguments.len() as u8);
+    }
+
+    fn visit_await(&mut self, keyword: &Token, promise: &Expr) {
+        self.emit_await(keyword, promise, false);
+    }
+
+    fn visit_spawn(&mut self, _keyword: &Token, call: &Expr) {
+        let Expr::Call(callee, closing_paren, arguments) = call else {
+            unreachable!("the parser only spawns calls")
+        };
+        callee.accept(self);
+        for argument in arguments.iter() {
+            argument.accept(self);
+        }
+
+        self.span = closing_paren.span;
+        if arguments.len() > MAX_ARGUMENTS {
+            self.error("Can't have more than 255 arguments");
+        }
+        self.emit_op(OpCode::Spawn);
+        self.emit_byte(arguments.len() as u8);
+    }
+
+    fn visit_tuple(&mut self, values: &[Expr]) {
+        for value in values.iter() {
+            value.accept(self);
+        }
+
+        self.span = values.first().map_or(self.span, |first| first.span());
+        if values.len() > MAX_ARGUMENTS {
+            self.error("Can't have more than 255 values");
+        }
+        self.emit_op(OpCode::Tuple);
+        self.emit_byte(values.len() as u8);
+    }
+
+    fn visit_list(&mut self, elements: &[Expr]) {
+        for element in elements.iter() {
+            element.accept(self);
+        }
+        self.emit_op(OpCode::List);
+        self.emit_u16(elements.len() as u16);
+    }
+
+    // Elements are checked all at once, a bad one is reported at the first element
+    fn visit_set_literal(&mut self, elements: &[Expr]) {
+        for element in elements.iter() {
+            element.accept(self);
+        }
+        self.span = elements.first().map_or(self.span, |first| first.span());
+        self.emit_op(OpCode::Set);
+        self.emit_u16(elements.len() as u16);
+    }
+
+    // Keys are checked all at once, a bad one is reported at the first key
+    fn visit_map(&mut self, entries: &[(Expr, Expr)]) {
+        for (key, value) in entries.iter() {
+            key.accept(self);
+            value.accept(self);
+        }
+        self.span = entries.first().map_or(self.span, |(key, _)| key.span());
+        self.emit_op(OpCode::Map);
+        self.emit_u16(entries.len() as u16);
+    }
+
+    // Compiled as a function called with the value right away: what an arm binds
+    // are locals of its own frame, whatever else is on the stack around the `match`
+    //
+    //     GetLocal 1, classes, Match p   JumpIfFalse next   Pop   body   Return
+    //     next: Pop   ...the next arms...   GetLocal 1   NoMatch
+    fn visit_match(&mut self, keyword: &Token, value: &Expr, arms: &[Arm]) {
+        self.span = keyword.span;
+        let mut state =
+            FunctionState::new("match".to_string(), keyword.span, FunctionKind::Function);
+        state.function.arity = 1;
+        // Without a name, the arms can't refer to it
+        state.locals.push(Local {
+            name: String::new(),
+            depth: 1,
+            is_captured: false,
+            start: 0,
+        });
+        self.functions.push(state);
+
+        for arm in arms.iter() {
+            self.span = arm.pattern.span();
+            self.emit_op(OpCode::GetLocal);
+            self.emit_byte(1);
+            for class in arm.pattern.classes() {
+                class.accept(self);
+            }
+            self.span = arm.pattern.span();
+            let pattern = Value::Pattern(Rc::new(Self::pattern(&arm.pattern)));
+            let constant = self.make_constant(pattern);
+            self.emit_op(OpCode::Match);
+            self.emit_u16(constant);
+            let next_arm = self.emit_jump(OpCode::JumpIfFalse);
+            self.emit_op(OpCode::Pop);
+
+            self.begin_scope();
+            for name in arm.pattern.bindings() {
+                self.define_variable(name);
+            }
+            arm.body.accept(self);
+            self.emit_op(OpCode::Return);
+
+            // Nothing runs after the return, the locals only leave the compiler
+            let state = self.current();
+            state.scope_depth -= 1;
+            let depth = state.scope_depth;
+            while state.locals.last().is_some_and(|local| local.depth > depth) {
+                let local = state.locals.pop().unwrap();
+                state.retire(state.locals.len(), local);
+            }
+
+            self.patch_jump(next_arm);
+            self.emit_op(OpCode::Pop);
+        }
+        self.span = keyword.span;
+        self.emit_op(OpCode::GetLocal);
+        self.emit_byte(1);
+        self.emit_op(OpCode::NoMatch);
+        self.emit_closure();
+
+        value.accept(self);
+        self.span = keyword.span;
+        self.emit_op(OpCode::Call);
+        self.emit_byte(1);
+    }
+
+    fn visit_grouping(&mut self, grouping_expr: &Expr) {
+        grouping_expr.accept(self);
+    }
+
+    fn visit_unary(&mut self, operator: &Token, unary_expr: &Expr) {
+        unary_expr.accept(self);
+
+        self.span = operator.span;
+        match operator.token_type {
+            TokenType::Minus => self.emit_op(OpCode::Negate),
+            _ => self.emit_op(OpCode::Not),
+        }
+    }
+
+    fn visit_literal(&mut self, lit: &Token) {
+        self.span = lit.span;
+        match (&lit.token_type, &lit.literal) {
+            (TokenType::Number, Some(LiteralType::NumberType(number))) => {
+                self.emit_constant(Value::Number(*number))
+            }
+            (TokenType::String, Some(LiteralType::StringType(string))) => {
+                self.emit_constant(Value::String(Rc::from(string.as_str())))
+            }
+            (TokenType::True, _) => self.emit_op(OpCode::True),
+            (TokenType::False, _) => self.emit_op(OpCode::False),
+            (TokenType::Nil, _) => self.emit_op(OpCode::Nil),
+            _ => self.error("Invalid literal"),
+        }
+    }
+
+    // The right operand is skipped when the left one decides the result
+    fn visit_logical(&mut self, left_expr: &Expr, logical_and_or: &Token, right_expr: &Expr) {
+        left_expr.accept(self);
+        self.span = logical_and_or.span;
+
+        if logical_and_or.token_type == TokenType::And {
+            let end_jump = self.emit_jump(OpCode::JumpIfFalse);
+            self.emit_op(OpCode::Pop);
+            right_expr.accept(self);
+            self.patch_jump(end_jump);
+        } else {
+            let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+            let end_jump = self.emit_jump(OpCode::Jump);
+            self.patch_jump(else_jump);
+            self.emit_op(OpCode::Pop);
+            right_expr.accept(self);
+            self.patch_jump(end_jump);
+        }
+    }
+
+    fn visit_variable(&mut self, variable: &Token, _location: Option<Location>) {
+        self.get_variable(variable);
+    }
+
+    fn visit_this(&mut self, keyword: &Token, _location: Option<Location>) {
+        self.get_variable(keyword);
+    }
+
+    fn visit_super(
+        &mut self,
+        keyword: &Token,
+        method: &Token,
+        _superclass: Option<Location>,
+        _this: Option<Location>,
+    ) {
+        let this = Token {
+            lexeme: "this".into(),
+            ..keyword.clone()
+        };
+        self.get_variable(&this);
+        self.get_variable(keyword);
+        self.span = method.span;
+        let constant = self.identifier_constant(method);
+        self.emit_op(OpCode::GetSuper);
+        self.emit_u16(constant);
+    }
+
+    fn visit_assign(&mut self, variable: &Token, expr: &Expr, _location: Option<Location>) {
+        expr.accept(self);
+        self.set_variable(variable);
+    }
+
+    fn visit_get(&mut self, expr: &Expr, name: &Token) {
+        expr.accept(self);
+        self.span = name.span;
+        let constant = self.identifier_constant(name);
+        let cache = self.make_cache();
+        self.emit_op(OpCode::GetProperty);
+        self.emit_u16(constant);
+        self.emit_u16(cache);
+    }
+
+    fn visit_optional_get(&mut self, expr: &Expr, name: &Token) {
+        expr.accept(self);
+        self.span = name.span;
+        let constant = self.identifier_constant(name);
+        self.emit_op(OpCode::GetOptional);
+        self.emit_u16(constant);
+    }
+
+    fn visit_index(&mut self, object: &Expr, index: &Expr, close: &Token) {
+        object.accept(self);
+        index.accept(self);
+        self.span = close.span;
+        self.emit_op(OpCode::Index);
+    }
+
+    fn visit_set(&mut self, expr: &Expr, name: &Token, value: &Expr) {
+        expr.accept(self);
+        value.accept(self);
+        self.span = name.span;
+        let constant = self.identifier_constant(name);
+        self.emit_op(OpCode::SetProperty);
+        self.emit_u16(constant);
+    }
+}
+
+impl StatementVisitor<()> for Compiler {
+    fn visit_block(&mut self, statements: &[Stmt]) {
+        self.begin_scope();
+        for statement in statements.iter() {
+            statement.accept(self);
+        }
+        self.end_scope();
+    }
+
+    fn visit_class(
+        &mut self,
+        name: &Token,
+        superclass: Option<&Expr>,
+        mixins: &[Expr],
+        statements: &[Stmt],
+        sealed: bool,
+    ) {
+        self.span = name.span;
+        let constant = self.identifier_constant(name);
+        self.emit_op(OpCode::Class);
+        self.emit_u16(constant);
+        if sealed {
+            self.emit_op(OpCode::Seal);
+        }
+        self.define_variable(name);
+
+        // The superclass stays on the stack as `super`, a local the methods capture
+        if let Some(superclass) = superclass {
+            if matches!(superclass, Expr::Variable(parent, _) if parent.lexeme == name.lexeme) {
+                self.error("A class can't inherit from itself");
+            }
+            superclass.accept(self);
+            self.begin_scope();
+            let keyword = Token {
+                lexeme: "super".into(),
+                ..name.clone()
+            };
+            self.define_variable(&keyword);
+            self.get_variable(name);
+            self.emit_op(OpCode::Inherit);
+        }
+
+        // The class stays on the stack while its methods are attached
+        self.get_variable(name);
+        let mut overriding = Vec::new();
+        for method in statements.iter() {
+            if let Stmt::Function(method_name, parameters, body, signature, _) = method {
+                if signature.overriding {
+                    overriding.push(method_name);
+                }
+                let kind = if &*method_name.lexeme == "init" {
+                    FunctionKind::Initializer
+                } else {
+                    FunctionKind::Method
+                };
+                self.function(method_name, parameters, body, kind);
+
+                let constant = self.identifier_constant(method_name);
+                self.emit_op(OpCode::Method);
+                self.emit_u16(constant);
+            }
+        }
+        // Mixed in once the class has its own methods, which override theirs
+        if let (Some(first), Some(last)) = (mixins.first(), mixins.last()) {
+            for mixin in mixins.iter() {
+                mixin.accept(self);
+            }
+            self.span = first.span().to(last.span());
+            if mixins.len() > MAX_ARGUMENTS {
+                self.error("Can't have more than 255 mixins");
+            }
+            self.emit_op(OpCode::Mix);
+            self.emit_byte(mixins.len() as u8);
+        }
+        // Checked once everything the class inherits is there
+        for method_name in overriding {
+            self.span = method_name.span;
+            let constant = self.identifier_constant(method_name);
+            self.emit_op(OpCode::Override);
+            self.emit_u16(constant);
+        }
+        self.emit_op(OpCode::Pop);
+        if superclass.is_some() {
+            self.end_scope();
+        }
+    }
+
+    fn visit_expression(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Await(keyword, promise) => self.emit_await(keyword, promise, true),
+            _ => expr.accept(self),
+        }
+        self.emit_op(OpCode::Pop);
+    }
+
+    fn visit_forloop(
+        &mut self,
+        initializer: Option<&Stmt>,
+        condition: Option<&Expr>,
+        incrementer: Option<&Expr>,
+        body: &Stmt,
+    ) {
+        self.begin_scope();
+        if let Some(initializer) = initializer {
+            initializer.accept(self);
+        }
+
+        let loop_start = self.chunk().code.len();
+        let exit_jump = match condition {
+            Some(condition) => {
+                condition.accept(self);
+                let exit_jump = self.emit_jump(OpCode::JumpIfFalse);
+                self.emit_op(OpCode::Pop);
+                Some(exit_jump)
+            }
+            None => None, // Loops forever
+        };
+
+        body.accept(self);
+        if let Some(incrementer) = incrementer {
+            incrementer.accept(self);
+            self.emit_op(OpCode::Pop);
+        }
+        self.emit_loop(loop_start);
+
+        if let Some(exit_jump) = exit_jump {
+            self.patch_jump(exit_jump);
+            self.emit_op(OpCode::Pop);
+        }
+        self.end_scope();
+    }
+
+    // What is looped over and the index of its next element stay on the stack,
+    // the variable is a new local for every element, so closures keep theirs
+    fn visit_forin(&mut self, name: &Token, iterable: &Expr, body: &Stmt) {
+        self.begin_scope();
+        iterable.accept(self);
+        self.span = iterable.span();
+        self.emit_op(OpCode::Iterate);
+        self.hidden_local();
+        self.hidden_local();
+
+        let loop_start = self.chunk().code.len();
+        let exit_jump = self.emit_jump(OpCode::Next);
+        self.begin_scope();
+        self.define_variable(name);
+        body.accept(self);
+        self.end_scope();
+        self.emit_loop(loop_start);
+
+        self.patch_jump(exit_jump);
+        self.end_scope();
+    }
+
+    fn visit_ifelse(&mut self, condition: &Expr, then_branch: &Stmt, else_branch: Option<&Stmt>) {
+        condition.accept(self);
+        let then_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit_op(OpCode::Pop);
+        then_branch.accept(self);
+
+        let else_jump = self.emit_jump(OpCode::Jump);
+        self.patch_jump(then_jump);
+        self.emit_op(OpCode::Pop);
+        if let Some(else_branch) = else_branch {
+            else_branch.accept(self);
+        }
+        self.patch_jump(else_jump);
+    }
+
+    fn visit_import(&mut self, _keyword: &Token, name: &Token) {
+        self.span = name.span;
+        let constant = self.identifier_constant(name);
+        self.emit_op(OpCode::Import);
+        self.emit_u16(constant);
+        self.define_variable(name);
+    }
+
+    fn visit_print(&mut self, expr: &Expr) {
+        expr.accept(self);
+        self.emit_op(OpCode::Print);
+    }
+
+    fn visit_return(&mut self, token: &Token, expr: Option<&Expr>) {
+        self.span = token.span;
+        match expr {
+            Some(_) if self.current().kind == FunctionKind::Initializer => {
+                self.error("Can't return a value from an initializer")
+            }
+            Some(expr) => {
+                expr.accept(self);
+                self.emit_exits();
+                self.emit_op(OpCode::Return);
+            }
+            None => {
+                self.emit_exits();
+                self.emit_return();
+            }
+        }
+    }
+
+    fn visit_throw(&mut self, keyword: &Token, value: &Expr) {
+        value.accept(self);
+        self.span = keyword.span;
+        self.emit_op(OpCode::Throw);
+    }
+
+    // An error in the body unwinds to the catches with the error on the stack, a
+    // hidden local each catch tries its class against. None taking it throws it again
+    //
+    //     Try catches   body   EndTry   Jump end
+    //     catches: GetLocal e, class, Catches   JumpIfFalse next   Pop   body   Jump done
+    //     next: Pop   ...the next catches...   GetLocal e   Rethrow
+    //     done: Pop   end:
+    fn visit_try(&mut self, body: &[Stmt], catches: &[Catch]) {
+        let try_jump = self.emit_jump(OpCode::Try);
+        self.current().handlers.push(None);
+        self.visit_block(body);
+        self.current().handlers.pop();
+        self.emit_op(OpCode::EndTry);
+        let end_jump = self.emit_jump(OpCode::Jump);
+        self.patch_jump(try_jump);
+
+        self.begin_scope();
+        self.hidden_local();
+        let error = (self.current().locals.len() - 1) as u8;
+        let mut done_jumps = Vec::new();
+        for catch in catches.iter() {
+            self.span = catch.name.span;
+            let next_catch = catch.class.as_ref().map(|class| {
+                self.emit_op(OpCode::GetLocal);
+                self.emit_byte(error);
+                class.accept(self);
+                self.span = class.span();
+                self.emit_op(OpCode::Catches);
+                let next_catch = self.emit_jump(OpCode::JumpIfFalse);
+                self.emit_op(OpCode::Pop);
+                next_catch
+            });
+
+            self.begin_scope();
+            self.emit_op(OpCode::GetLocal);
+            self.emit_byte(error);
+            self.define_variable(&catch.name);
+            for statement in catch.body.iter() {
+                statement.accept(self);
+            }
+            self.end_scope();
+            done_jumps.push(self.emit_jump(OpCode::Jump));
+
+            if let Some(next_catch) = next_catch {
+                self.patch_jump(next_catch);
+                self.emit_op(OpCode::Pop);
+            }
+        }
+        self.emit_op(OpCode::GetLocal);
+        self.emit_byte(error);
+        self.emit_op(OpCode::Rethrow);
+
+        for done_jump in done_jumps {
+            self.patch_jump(done_jump);
+        }
+        self.end_scope();
+        self.patch_jump(end_jump);
+    }
+
+    // The resource and the method closing it are locals, the body is in a try
+    // that closes it and throws the error again
+    //
+    //     resource   GetLocal r, Closer   Try error   body   EndTry   close   Jump end
+    //     error: close   GetLocal e   Rethrow
+    //     end:
+    fn visit_with(&mut self, keyword: &Token, resource: &Expr, name: &Token, body: &[Stmt]) {
+        self.begin_scope();
+        resource.accept(self);
+        self.define_variable(name);
+        let slot = (self.current().locals.len() - 1) as u8;
+        self.span = resource.span();
+        self.emit_op(OpCode::GetLocal);
+        self.emit_byte(slot);
+        self.emit_op(OpCode::Closer);
+        self.hidden_local();
+        let closer = slot + 1;
+
+        let try_jump = self.emit_jump(OpCode::Try);
+        self.current().handlers.push(Some(closer));
+        self.visit_block(body);
+        self.current().handlers.pop();
+        self.span = keyword.span;
+        self.emit_op(OpCode::EndTry);
+        self.emit_close(closer);
+        let end_jump = self.emit_jump(OpCode::Jump);
+
+        // The error is on the stack above the closer, only this path has it
+        self.patch_jump(try_jump);
+        self.emit_close(closer);
+        self.emit_op(OpCode::GetLocal);
+        self.emit_byte(closer + 1);
+        self.emit_op(OpCode::Rethrow);
+
+        self.patch_jump(end_jump);
+        self.end_scope();
+    }
+
+    // Once resumed, the value it is resumed with is on the stack
+    fn visit_yield(&mut self, keyword: &Token, expr: Option<&Expr>, variable: &Option<Token>) {
+        self.span = keyword.span;
+        if self.current().kind == FunctionKind::Initializer {
+            self.error("Can't yield from an initializer");
+        }
+        match expr {
+            Some(expr) => expr.accept(self),
+            None => self.emit_op(OpCode::Nil),
+        }
+        self.emit_op(OpCode::Yield);
+        match variable {
+            Some(variable) => self.define_variable(variable),
+            None => self.emit_op(OpCode::Pop),
+        }
+    }
+
+    fn visit_error(&mut self, tokens: &[Token]) {
+        self.span = tokens.first().map(|token| token.span).unwrap_or_default();
+        self.error("Can't compile a statement that failed to parse");
+    }
+
+    fn visit_var(&mut self, token: &Token, _annotation: &Option<Token>, expr: Option<&Expr>) {
+        match expr {
+            Some(Expr::Await(keyword, promise)) => self.emit_await(keyword, promise, true),
+            Some(initializer) => initializer.accept(self),
+            None => self.emit_op(OpCode::Nil),
+        }
+        self.define_variable(token);
+    }
+
+    // The values go on the stack in order, where locals take them as they are
+    // Globals are defined from the top of the stack, the last one first
+    fn visit_unpack(&mut self, names: &[Token], expr: Option<&Expr>) {
+        match expr {
+            Some(initializer) => {
+                initializer.accept(self);
+                self.span = initializer.span();
+                if names.len() > MAX_ARGUMENTS {
+                    self.error("Can't unpack more than 255 values");
+                }
+                self.emit_op(OpCode::Unpack);
+                self.emit_byte(names.len() as u8);
+            }
+            None => names.iter().for_each(|_| self.emit_op(OpCode::Nil)),
+        }
+
+        if self.current().scope_depth == 0 {
+            names
+                .iter()
+                .rev()
+                .for_each(|name| self.define_variable(name));
+        } else {
+            names.iter().for_each(|name| self.define_variable(name));
+        }
+    }
+
+    fn visit_whileloop(&mut self, condition: &Expr, statement: &Stmt) {
+        let loop_start = self.chunk().code.len();
+        condition.accept(self);
+
+        let exit_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit_op(OpCode::Pop);
+        statement.accept(self);
+        self.emit_loop(loop_start);
+
+        self.patch_jump(exit_jump);
+        self.emit_op(OpCode::Pop);
+    }
+
+    // A local function is declared before its body is compiled, so it can call itself
+    fn visit_function(
+        &mut self,
+        name: &Token,
+        parameters: &[Token],
+        body: &[Stmt],
+        _signature: &Signature,
+        _captures: &[Slot],
+    ) {
+        if self.current().scope_depth > 0 {
+            self.define_variable(name);
+            self.function(name, parameters, body, FunctionKind::Function);
+        } else {
+            self.function(name, parameters, body, FunctionKind::Function);
+            self.define_variable(name);
+        }
+    }
+}
@@ -0,0 +1,6 @@
+pub mod chunk;
+pub mod collector;
+pub mod compiler;
+pub mod serialize;
+pub mod value;
+pub mod vm;
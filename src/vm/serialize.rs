@@ -0,0 +1,666 @@
+use std::{cell::RefCell, rc::Rc};
+
+use sha2::{Digest, Sha256};
+
+use super::{
+    chunk::{Chunk, LocalName, OpCode},
+    value::{FunctionProto, PatternProto, Value},
+};
+use crate::lexer::token::Span;
+
+// Compiled programs (.lostc files)
+//
+// "LOSTC" magic, u16 format version, checksum, then the script function:
+//
+// checksum   the first 8 bytes of the SHA-256 of everything after it
+//
+// function   name, line, column, arity, upvalue names, code, spans, constants,
+//            number of inline caches, locals
+// locals     (name, slot, start, end) of every named local, u8 slot
+// spans      run-length encoded (start, end, line, column, count) tuples
+// constant   u8 tag (0 number, 1 string, 2 function, 3 pattern) followed by its data,
+//            numbers are f64
+// pattern    u8 kind (0 wildcard, 1 binding, 2 literal, 3 list, 4 map, 5 instance),
+//            then the literal, the element patterns, the literal keys and their
+//            patterns, or the field names and their patterns
+// literal    u8 kind (0 nil, 1 false, 2 true, 3 number, 4 string) followed by its data
+//
+// Integers are little endian, strings and lists are prefixed with their u32 length
+//
+// Decoding checks the code of every function before the VM gets it, see verify
+const MAGIC: &[u8; 5] = b"LOSTC";
+
+// Bumped whenever the instruction set or this layout changes
+pub const FORMAT_VERSION: u16 = 24;
+
+const NUMBER_TAG: u8 = 0;
+const STRING_TAG: u8 = 1;
+const FUNCTION_TAG: u8 = 2;
+const PATTERN_TAG: u8 = 3;
+
+pub fn is_compiled(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+pub fn encode(function: &FunctionProto) -> Vec<u8> {
+    let mut body = Vec::new();
+    encode_function(&mut body, function);
+
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend(FORMAT_VERSION.to_le_bytes());
+    bytes.extend(checksum(&body));
+    bytes.extend(body);
+    bytes
+}
+
+fn checksum(body: &[u8]) -> [u8; 8] {
+    let mut checksum = [0; 8];
+    checksum.copy_from_slice(&Sha256::digest(body)[..8]);
+    checksum
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Rc<FunctionProto>, String> {
+    if !is_compiled(bytes) {
+        return Err("Not a compiled Lost program".to_string());
+    }
+
+    let mut reader = Reader {
+        bytes,
+        position: MAGIC.len(),
+    };
+    let version = reader.u16()?;
+    if version != FORMAT_VERSION {
+        return Err(format!(
+            "Compiled with format version {}, expected {}, recompile the script",
+            version, FORMAT_VERSION
+        ));
+    }
+
+    let expected = reader.take(8)?.to_vec();
+    if expected != checksum(&bytes[reader.position..]) {
+        return Err("The compiled program is corrupted, recompile the script".to_string());
+    }
+
+    let function = reader.function()?;
+    if reader.position != bytes.len() {
+        return Err("Unexpected data after the program".to_string());
+    }
+    Ok(Rc::new(function))
+}
+
+fn encode_u32(bytes: &mut Vec<u8>, value: usize) {
+    bytes.extend((value as u32).to_le_bytes());
+}
+
+fn encode_string(bytes: &mut Vec<u8>, string: &str) {
+    encode_u32(bytes, string.len());
+    bytes.extend(string.as_bytes());
+}
+
+fn encode_function(bytes: &mut Vec<u8>, function: &FunctionProto) {
+    encode_string(bytes, &function.name);
+    encode_u32(bytes, function.line);
+    encode_u32(bytes, function.column);
+    encode_u32(bytes, function.arity);
+    encode_u32(bytes, function.upvalues.len());
+    for name in function.upvalues.iter() {
+        encode_string(bytes, name);
+    }
+
+    let chunk = &function.chunk;
+    encode_u32(bytes, chunk.code.len());
+    bytes.extend(&chunk.code);
+
+    let mut runs: Vec<(Span, usize)> = Vec::new();
+    for span in chunk.spans.iter() {
+        match runs.last_mut() {
+            Some((run_span, count)) if run_span == span => *count += 1,
+            _ => runs.push((*span, 1)),
+        }
+    }
+    encode_u32(bytes, runs.len());
+    for (span, count) in runs {
+        encode_u32(bytes, span.start);
+        encode_u32(bytes, span.end);
+        encode_u32(bytes, span.line);
+        encode_u32(bytes, span.column);
+        encode_u32(bytes, count);
+    }
+
+    encode_u32(bytes, chunk.constants.len());
+    for constant in chunk.constants.iter() {
+        match constant {
+            Value::Number(number) => {
+                bytes.push(NUMBER_TAG);
+                bytes.extend(number.to_le_bytes());
+            }
+            Value::String(string) => {
+                bytes.push(STRING_TAG);
+                encode_string(bytes, string);
+            }
+            Value::Function(function) => {
+                bytes.push(FUNCTION_TAG);
+                encode_function(bytes, function);
+            }
+            Value::Pattern(pattern) => {
+                bytes.push(PATTERN_TAG);
+                encode_pattern(bytes, pattern);
+            }
+            _ => unreachable!("Only numbers, strings, functions and patterns are constants"),
+        }
+    }
+
+    encode_u32(bytes, chunk.caches.len());
+
+    encode_u32(bytes, chunk.locals.len());
+    for local in chunk.locals.iter() {
+        encode_string(bytes, &local.name);
+        bytes.push(local.slot);
+        encode_u32(bytes, local.start);
+        encode_u32(bytes, local.end);
+    }
+}
+
+fn encode_pattern(bytes: &mut Vec<u8>, pattern: &PatternProto) {
+    match pattern {
+        PatternProto::Wildcard => bytes.push(0),
+        PatternProto::Binding => bytes.push(1),
+        PatternProto::Literal(literal) => {
+            bytes.push(2);
+            encode_literal(bytes, literal);
+        }
+        PatternProto::List(elements) => {
+            bytes.push(3);
+            encode_u32(bytes, elements.len());
+            elements
+                .iter()
+                .for_each(|element| encode_pattern(bytes, element));
+        }
+        PatternProto::Map(entries) => {
+            bytes.push(4);
+            encode_u32(bytes, entries.len());
+            for (key, pattern) in entries.iter() {
+                encode_literal(bytes, key);
+                encode_pattern(bytes, pattern);
+            }
+        }
+        PatternProto::Instance(fields) => {
+            bytes.push(5);
+            encode_u32(bytes, fields.len());
+            for (name, pattern) in fields.iter() {
+                encode_string(bytes, name);
+                encode_pattern(bytes, pattern);
+            }
+        }
+    }
+}
+
+fn encode_literal(bytes: &mut Vec<u8>, literal: &Value) {
+    match literal {
+        Value::Boolean(false) => bytes.push(1),
+        Value::Boolean(true) => bytes.push(2),
+        Value::Number(number) => {
+            bytes.push(3);
+            bytes.extend(number.to_le_bytes());
+        }
+        Value::String(string) => {
+            bytes.push(4);
+            encode_string(bytes, string);
+        }
+        _ => bytes.push(0),
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, length: usize) -> Result<&[u8], String> {
+        let end = self
+            .position
+            .checked_add(length)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or("Truncated compiled program")?;
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<usize, String> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let length = self.u32()?;
+        let bytes = self.take(length)?.to_vec();
+        String::from_utf8(bytes).map_err(|_| "Invalid string in compiled program".to_string())
+    }
+
+    fn number(&mut self) -> Result<f64, String> {
+        let mut number = [0; 8];
+        number.copy_from_slice(self.take(8)?);
+        Ok(f64::from_le_bytes(number))
+    }
+
+    fn pattern(&mut self) -> Result<PatternProto, String> {
+        let pattern = match self.u8()? {
+            0 => PatternProto::Wildcard,
+            1 => PatternProto::Binding,
+            2 => PatternProto::Literal(self.literal()?),
+            3 => {
+                let count = self.u32()?;
+                let mut elements = Vec::new();
+                for _ in 0..count {
+                    elements.push(self.pattern()?);
+                }
+                PatternProto::List(elements)
+            }
+            4 => {
+                let count = self.u32()?;
+                let mut entries = Vec::new();
+                for _ in 0..count {
+                    entries.push((self.literal()?, self.pattern()?));
+                }
+                PatternProto::Map(entries)
+            }
+            5 => {
+                let count = self.u32()?;
+                let mut fields = Vec::new();
+                for _ in 0..count {
+                    fields.push((Rc::from(self.string()?), self.pattern()?));
+                }
+                PatternProto::Instance(fields)
+            }
+            kind => return Err(format!("Unknown pattern kind {}", kind)),
+        };
+        Ok(pattern)
+    }
+
+    fn literal(&mut self) -> Result<Value, String> {
+        let literal = match self.u8()? {
+            0 => Value::Nil,
+            1 => Value::Boolean(false),
+            2 => Value::Boolean(true),
+            3 => Value::Number(self.number()?),
+            4 => Value::String(Rc::from(self.string()?)),
+            kind => return Err(format!("Unknown literal kind {}", kind)),
+        };
+        Ok(literal)
+    }
+
+    fn function(&mut self) -> Result<FunctionProto, String> {
+        let name = self.string()?;
+        let line = self.u32()?;
+        let column = self.u32()?;
+        let arity = self.u32()?;
+        let upvalue_count = self.u32()?;
+        let upvalues = (0..upvalue_count)
+            .map(|_| self.string())
+            .collect::<Result<Vec<String>, String>>()?;
+
+        let mut chunk = Chunk::new();
+        let code_length = self.u32()?;
+        chunk.code = self.take(code_length)?.to_vec();
+
+        let runs = self.u32()?;
+        for _ in 0..runs {
+            let span = Span::new(self.u32()?, self.u32()?, self.u32()?, self.u32()?);
+            let count = self.u32()?;
+            if chunk.spans.len() + count > code_length {
+                return Err("Span table does not match the code".to_string());
+            }
+            chunk.spans.extend(std::iter::repeat_n(span, count));
+        }
+        if chunk.spans.len() != code_length {
+            return Err("Span table does not match the code".to_string());
+        }
+
+        let constants = self.u32()?;
+        for _ in 0..constants {
+            let constant = match self.u8()? {
+                NUMBER_TAG => Value::Number(self.number()?),
+                STRING_TAG => Value::String(Rc::from(self.string()?)),
+                FUNCTION_TAG => Value::Function(Rc::new(self.function()?)),
+                PATTERN_TAG => Value::Pattern(Rc::new(self.pattern()?)),
+                tag => return Err(format!("Unknown constant tag {}", tag)),
+            };
+            chunk.constants.push(constant);
+        }
+
+        // Every cache is read by a GetProperty, which takes more than a byte
+        let caches = self.u32()?;
+        if caches > code_length {
+            return Err("Cache table does not match the code".to_string());
+        }
+        chunk.caches.resize_with(caches, RefCell::default);
+
+        let locals = self.u32()?;
+        for _ in 0..locals {
+            let local = LocalName {
+                name: self.string()?,
+                slot: self.u8()?,
+                start: self.u32()?,
+                end: self.u32()?,
+            };
+            if local.start > local.end || local.end > code_length {
+                return Err("Local table does not match the code".to_string());
+            }
+            chunk.locals.push(local);
+        }
+
+        let function = FunctionProto {
+            name,
+            line,
+            column,
+            arity,
+            upvalues,
+            chunk,
+        };
+        verify(&function)?;
+        Ok(function)
+    }
+}
+
+// The VM trusts its code, so the code of a file is checked here before it runs:
+// every instruction is known and has its operands, constants are of the kind the
+// instruction reads, and jumps land on an instruction. The code ends with one
+// that doesn't go on to the next. verify_stack checks the stack heights and local
+// slots. What is left trusted is the kind of the values the compiler puts where
+// an instruction takes them, like the index a `for` loop keeps under its element
+fn verify(function: &FunctionProto) -> Result<(), String> {
+    let chunk = &function.chunk;
+    let invalid = |what: &str, offset: usize| {
+        Err(format!(
+            "Invalid {} at byte {} of `{}`",
+            what, offset, function.name
+        ))
+    };
+    let byte = |offset: usize| chunk.code.get(offset).copied();
+    let u16_at = |offset: usize| match (byte(offset), byte(offset + 1)) {
+        (Some(high), Some(low)) => Some(u16::from_be_bytes([high, low]) as usize),
+        _ => None,
+    };
+
+    let mut starts = vec![false; chunk.code.len()];
+    let mut next = vec![0; chunk.code.len()]; // Where the instruction at an offset ends
+    let mut jumps = Vec::new();
+    let mut last = None;
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        starts[offset] = true;
+        let Some(op) = OpCode::from_byte(chunk.code[offset]) else {
+            return invalid("instruction", offset);
+        };
+        let operands = offset + 1;
+        // The constant at the u16 operand, when there is one
+        let constant = || u16_at(operands).and_then(|index| chunk.constants.get(index));
+        let width = match op {
+            OpCode::GetLocal
+            | OpCode::SetLocal
+            | OpCode::Call
+            | OpCode::Mix
+            | OpCode::Tuple
+            | OpCode::Unpack
+            | OpCode::Spawn
+            | OpCode::Await => 1,
+            OpCode::GetUpvalue | OpCode::SetUpvalue => {
+                match byte(operands) {
+                    Some(index) if (index as usize) < function.upvalues.len() => {}
+                    _ => return invalid("upvalue", offset),
+                }
+                1
+            }
+            OpCode::Constant | OpCode::List | OpCode::Map | OpCode::Set => {
+                if u16_at(operands).is_none() || op == OpCode::Constant && constant().is_none() {
+                    return invalid("constant", offset);
+                }
+                2
+            }
+            OpCode::GetGlobal
+            | OpCode::DefineGlobal
+            | OpCode::SetGlobal
+            | OpCode::GetOptional
+            | OpCode::SetProperty
+            | OpCode::GetSuper
+            | OpCode::Class
+            | OpCode::Method
+            | OpCode::Override
+            | OpCode::Import => {
+                let Some(Value::String(_)) = constant() else {
+                    return invalid("name", offset);
+                };
+                2
+            }
+            OpCode::GetProperty => {
+                let Some(Value::String(_)) = constant() else {
+                    return invalid("name", offset);
+                };
+                match u16_at(operands + 2) {
+                    Some(cache) if cache < chunk.caches.len() => {}
+                    _ => return invalid("inline cache", offset),
+                }
+                4
+            }
+            OpCode::Match => {
+                let Some(Value::Pattern(_)) = constant() else {
+                    return invalid("pattern", offset);
+                };
+                2
+            }
+            OpCode::Closure => {
+                let Some(Value::Function(inner)) = constant() else {
+                    return invalid("function", offset);
+                };
+                for capture in 0..inner.upvalues.len() {
+                    let at = operands + 2 + capture * 2;
+                    match (byte(at), byte(at + 1)) {
+                        (Some(1), Some(_)) => {}
+                        (Some(0), Some(index)) if (index as usize) < function.upvalues.len() => {}
+                        _ => return invalid("upvalue", offset),
+                    }
+                }
+                2 + inner.upvalues.len() * 2
+            }
+            OpCode::Jump | OpCode::JumpIfFalse | OpCode::Next | OpCode::Try => {
+                let Some(distance) = u16_at(operands) else {
+                    return invalid("jump", offset);
+                };
+                jumps.push((offset, operands + 2 + distance));
+                2
+            }
+            OpCode::Loop => {
+                let Some(target) =
+                    u16_at(operands).and_then(|distance| (operands + 2).checked_sub(distance))
+                else {
+                    return invalid("jump", offset);
+                };
+                jumps.push((offset, target));
+                2
+            }
+            _ => 0,
+        };
+        if operands + width > chunk.code.len() {
+            return invalid("operand", offset);
+        }
+        last = Some(op);
+        next[offset] = operands + width;
+        offset = operands + width;
+    }
+
+    for (offset, target) in jumps {
+        if !starts.get(target).copied().unwrap_or(false) {
+            return invalid("jump", offset);
+        }
+    }
+    match last {
+        Some(
+            OpCode::Return
+            | OpCode::Jump
+            | OpCode::Loop
+            | OpCode::Throw
+            | OpCode::Rethrow
+            | OpCode::NoMatch,
+        ) => verify_stack(function, &next),
+        _ => invalid("end of code", chunk.code.len()),
+    }
+}
+
+// Follows every path through the code with the height of the frame's part of
+// the stack, which starts with the function and its arguments. No instruction
+// takes more values than there are, reads or captures a local slot above the top,
+// and wherever paths meet, they do at the same height. A match that fails pushes
+// none of its bindings, so it is followed together with the JumpIfFalse after it
+fn verify_stack(function: &FunctionProto, next: &[usize]) -> Result<(), String> {
+    let chunk = &function.chunk;
+    let invalid = |offset: usize| {
+        Err(format!(
+            "Invalid stack at byte {} of `{}`",
+            offset, function.name
+        ))
+    };
+    let byte = |offset: usize| chunk.code[offset] as usize;
+    let u16_at =
+        |offset: usize| u16::from_be_bytes([chunk.code[offset], chunk.code[offset + 1]]) as usize;
+    let constant = |offset: usize| &chunk.constants[u16_at(offset)];
+
+    let mut heights: Vec<Option<usize>> = vec![None; chunk.code.len()];
+    let mut paths = vec![(0, function.arity + 1)];
+    while let Some((offset, height)) = paths.pop() {
+        match heights[offset] {
+            Some(known) if known == height => continue,
+            Some(_) => return invalid(offset),
+            None => heights[offset] = Some(height),
+        }
+        let Some(op) = OpCode::from_byte(chunk.code[offset]) else {
+            unreachable!("verify checked the instructions")
+        };
+        let operands = offset + 1;
+        let after = next[offset];
+        // Values taken, values left, and where the code goes on
+        let (taken, left, successors) = match op {
+            OpCode::Constant
+            | OpCode::Nil
+            | OpCode::True
+            | OpCode::False
+            | OpCode::GetGlobal
+            | OpCode::GetUpvalue
+            | OpCode::Class
+            | OpCode::Import => (0, 1, vec![after]),
+            OpCode::GetLocal | OpCode::SetLocal => {
+                if byte(operands) >= height {
+                    return invalid(offset);
+                }
+                match op {
+                    OpCode::GetLocal => (0, 1, vec![after]),
+                    _ => (1, 1, vec![after]),
+                }
+            }
+            OpCode::Closure => {
+                let Value::Function(inner) = constant(operands) else {
+                    unreachable!("verify checked the constant")
+                };
+                // A local function captures itself, in the slot the closure goes to
+                for capture in 0..inner.upvalues.len() {
+                    let at = operands + 2 + capture * 2;
+                    if byte(at) == 1 && byte(at + 1) > height {
+                        return invalid(offset);
+                    }
+                }
+                (0, 1, vec![after])
+            }
+            OpCode::Pop
+            | OpCode::DefineGlobal
+            | OpCode::Print
+            | OpCode::CloseUpvalue
+            | OpCode::EndTry => match op {
+                OpCode::EndTry => (0, 0, vec![after]),
+                _ => (1, 0, vec![after]),
+            },
+            OpCode::SetGlobal
+            | OpCode::SetUpvalue
+            | OpCode::GetProperty
+            | OpCode::GetOptional
+            | OpCode::Not
+            | OpCode::Negate
+            | OpCode::Seal
+            | OpCode::Override
+            | OpCode::Yield
+            | OpCode::Await
+            | OpCode::Closer => (1, 1, vec![after]),
+            OpCode::Generator => (0, 0, vec![after]),
+            OpCode::SetProperty
+            | OpCode::GetSuper
+            | OpCode::Index
+            | OpCode::Equal
+            | OpCode::Is
+            | OpCode::Greater
+            | OpCode::GreaterEqual
+            | OpCode::Less
+            | OpCode::LessEqual
+            | OpCode::In
+            | OpCode::Add
+            | OpCode::Subtract
+            | OpCode::Multiply
+            | OpCode::Divide
+            | OpCode::Catches => (2, 1, vec![after]),
+            OpCode::Inherit | OpCode::Method => (2, 1, vec![after]),
+            OpCode::Mix => (byte(operands) + 1, 1, vec![after]),
+            OpCode::Call | OpCode::Spawn => (byte(operands) + 1, 1, vec![after]),
+            OpCode::Tuple => (byte(operands), 1, vec![after]),
+            OpCode::Unpack => (1, byte(operands), vec![after]),
+            OpCode::List | OpCode::Set => (u16_at(operands), 1, vec![after]),
+            OpCode::Map => (2 * u16_at(operands), 1, vec![after]),
+            OpCode::Jump => (0, 0, vec![operands + 2 + u16_at(operands)]),
+            OpCode::Loop => (0, 0, vec![operands + 2 - u16_at(operands)]),
+            OpCode::JumpIfFalse => (1, 1, vec![after, operands + 2 + u16_at(operands)]),
+            OpCode::Return | OpCode::Throw | OpCode::Rethrow | OpCode::NoMatch => (1, 0, vec![]),
+            OpCode::Iterate => (1, 2, vec![after]),
+            // Goes on with the element on top, or to the end of the loop without it
+            OpCode::Next => {
+                if height < 2 {
+                    return invalid(offset);
+                }
+                paths.push((operands + 2 + u16_at(operands), height));
+                (0, 1, vec![after])
+            }
+            // The catches start with the error on top
+            OpCode::Try => {
+                paths.push((operands + 2 + u16_at(operands), height + 1));
+                (0, 0, vec![after])
+            }
+            OpCode::Match => {
+                let Value::Pattern(pattern) = constant(operands) else {
+                    unreachable!("verify checked the pattern")
+                };
+                let taken = pattern.class_count() + 1;
+                if height < taken || chunk.code.get(after) != Some(&(OpCode::JumpIfFalse as u8)) {
+                    return invalid(offset);
+                }
+                let jump = after + 1;
+                let failed = height - taken + 1;
+                heights[after] = Some(failed + pattern.binding_count());
+                paths.push((jump + 2 + u16_at(jump), failed));
+                (taken, pattern.binding_count() + 1, vec![next[after]])
+            }
+        };
+        if height < taken {
+            return invalid(offset);
+        }
+        for successor in successors {
+            paths.push((successor, height - taken + left));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,662 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt,
+    rc::{Rc, Weak},
+};
+
+use super::{chunk::Chunk, vm::VM};
+use crate::{
+    bytes,
+    equality::{self, Comparing, Key},
+    error::{arity_message, variadic_arity_message, Error},
+    eventloop::eventloop::Promise,
+    lexer::token::is_private,
+    modules::Module,
+    mutability::Mutability,
+    number,
+    repr::{self, Contents, Nested},
+    shared::{Ref, Shared},
+    sockets::sockets::Socket,
+    threads::threads::{Channel, Message},
+};
+
+// Runtime values of the VM
+//
+// Strings and objects are reference counted,
+// so copying a value onto the stack is cheap
+#[derive(Debug, Clone)]
+pub enum Value {
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    String(Rc<str>),
+    Bytes(Rc<[u8]>), // Immutable like strings, its methods are read as properties
+    Function(Rc<FunctionProto>), // Only as a constant, wrapped into a Closure at runtime
+    Closure(Rc<Closure>),
+    NativeFunction(Rc<NativeFunction>),
+    Class(Rc<RefCell<Class>>),
+    Instance(Rc<RefCell<Instance>>),
+    BoundMethod(Rc<BoundMethod>),
+    Tuple(Rc<[Value]>), // Returned by `return a, b;`, unpacked by `var a, b = ...;`
+    List(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<Vec<(Value, Value)>>>), // Entries in the order their keys were first set
+    Set(Rc<RefCell<Vec<Value>>>),          // Elements in the order they were first added
+    Pattern(Rc<PatternProto>),             // Only as a constant, of a `match`
+    Generator(Rc<RefCell<Generator>>),
+    Promise(Shared<Promise>),     // Of an async native, settled by `await`
+    Channel(Channel),             // An end of the channel to or from a thread
+    Builder(Rc<RefCell<String>>), // Of `string_builder()`, `append` adds to it in place
+    Module(Rc<Module<Value>>),    // Of `import`, its members are read as properties
+    Socket(Socket),               // Of the `net` module, its methods are read as properties
+}
+
+impl Value {
+    // nil, false and 0 are false, like in the tree-walking interpreter
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Value::Nil => false,
+            Value::Boolean(value) => *value,
+            Value::Number(value) => *value != 0.0 && !value.is_nan(),
+            _ => true,
+        }
+    }
+
+    // `==`, lists, maps and sets holding equal values are equal, see equality
+    // Instances, closures and the like are only equal to themselves
+    pub fn is_equal(&self, other: &Value) -> bool {
+        self.equal_in(other, &mut Vec::new())
+    }
+
+    fn equal_in(&self, other: &Value, comparing: &mut Comparing) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Bytes(a), Value::Bytes(b)) => a == b,
+            (Value::Function(a), Value::Function(b)) => Rc::ptr_eq(a, b),
+            (Value::Closure(a), Value::Closure(b)) => Rc::ptr_eq(a, b),
+            (Value::NativeFunction(a), Value::NativeFunction(b)) => Rc::ptr_eq(a, b),
+            (Value::Class(a), Value::Class(b)) => Rc::ptr_eq(a, b),
+            (Value::Instance(a), Value::Instance(b)) => Rc::ptr_eq(a, b),
+            // The same method of the same instance, read twice
+            (Value::BoundMethod(a), Value::BoundMethod(b)) => {
+                Rc::ptr_eq(&a.method, &b.method) && a.receiver.equal_in(&b.receiver, comparing)
+            }
+            (Value::Tuple(a), Value::Tuple(b)) => {
+                a.len() == b.len()
+                    && a.iter()
+                        .zip(b.iter())
+                        .all(|(a, b)| a.equal_in(b, comparing))
+            }
+            (Value::List(a), Value::List(b)) => equality::deep(
+                Rc::as_ptr(a).cast(),
+                Rc::as_ptr(b).cast(),
+                comparing,
+                |comparing| {
+                    let (a, b) = (a.borrow(), b.borrow());
+                    a.len() == b.len()
+                        && a.iter()
+                            .zip(b.iter())
+                            .all(|(a, b)| a.equal_in(b, comparing))
+                },
+            ),
+            // Keys of a map and elements of a set appear once
+            (Value::Map(a), Value::Map(b)) => equality::deep(
+                Rc::as_ptr(a).cast(),
+                Rc::as_ptr(b).cast(),
+                comparing,
+                |comparing| {
+                    let (a, b) = (a.borrow(), b.borrow());
+                    a.len() == b.len()
+                        && a.iter().all(|(key, value)| {
+                            b.iter().any(|(other_key, other_value)| {
+                                key.equal_in(other_key, comparing)
+                                    && value.equal_in(other_value, comparing)
+                            })
+                        })
+                },
+            ),
+            (Value::Set(a), Value::Set(b)) => equality::deep(
+                Rc::as_ptr(a).cast(),
+                Rc::as_ptr(b).cast(),
+                comparing,
+                |comparing| {
+                    let (a, b) = (a.borrow(), b.borrow());
+                    a.len() == b.len()
+                        && a.iter()
+                            .all(|element| b.iter().any(|other| element.equal_in(other, comparing)))
+                },
+            ),
+            (Value::Generator(a), Value::Generator(b)) => Rc::ptr_eq(a, b),
+            (Value::Promise(a), Value::Promise(b)) => Ref::ptr_eq(a, b),
+            (Value::Channel(a), Value::Channel(b)) => a.is(b),
+            (Value::Builder(a), Value::Builder(b)) => Rc::ptr_eq(a, b),
+            (Value::Module(a), Value::Module(b)) => Rc::ptr_eq(a, b),
+            (Value::Socket(a), Value::Socket(b)) => a.is(b),
+            _ => false,
+        }
+    }
+
+    // None for a value that can't be a map key or a set element
+    pub fn key(&self) -> Option<Key<'_>> {
+        match self {
+            Value::Nil => Some(Key::Nil),
+            Value::Boolean(boolean) => Some(Key::Boolean(*boolean)),
+            Value::Number(number) => Some(Key::number(*number)),
+            Value::String(text) => Some(Key::String(text)),
+            _ => None,
+        }
+    }
+
+    // `is`, the very same list, map or set rather than an equal one
+    // Anything else is compared as by `==`
+    pub fn is(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::List(a), Value::List(b)) | (Value::Set(a), Value::Set(b)) => Rc::ptr_eq(a, b),
+            (Value::Map(a), Value::Map(b)) => Rc::ptr_eq(a, b),
+            (Value::Tuple(a), Value::Tuple(b)) => {
+                a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| a.is(b))
+            }
+            _ => self.is_equal(other),
+        }
+    }
+
+    // A copy that can go to another thread, none for values made of functions,
+    // classes, instances, generators or promises
+    pub fn to_message(&self) -> Option<Message> {
+        let all = |values: &[Value]| values.iter().map(Value::to_message).collect::<Option<_>>();
+        Some(match self {
+            Value::Nil => Message::Nil,
+            Value::Boolean(value) => Message::Boolean(*value),
+            Value::Number(value) => Message::Number(*value),
+            Value::String(value) => Message::String(value.to_string()),
+            Value::Bytes(value) => Message::Bytes(value.to_vec()),
+            Value::Tuple(values) => Message::Tuple(all(values)?),
+            Value::List(elements) => Message::List(all(&elements.borrow())?),
+            Value::Set(elements) => Message::Set(all(&elements.borrow())?),
+            Value::Map(entries) => Message::Map(
+                entries
+                    .borrow()
+                    .iter()
+                    .map(|(key, value)| Some((key.to_message()?, value.to_message()?)))
+                    .collect::<Option<_>>()?,
+            ),
+            Value::Channel(channel) => Message::Channel(channel.clone()),
+            _ => return None,
+        })
+    }
+
+    pub fn from_message(message: Message) -> Value {
+        let all = |messages: Vec<Message>| {
+            messages
+                .into_iter()
+                .map(Value::from_message)
+                .collect::<Vec<_>>()
+        };
+        match message {
+            Message::Nil => Value::Nil,
+            Message::Boolean(value) => Value::Boolean(value),
+            Message::Number(value) => Value::Number(value),
+            Message::String(value) => Value::String(Rc::from(value)),
+            Message::Bytes(value) => Value::Bytes(Rc::from(value)),
+            Message::Tuple(messages) => Value::Tuple(all(messages).into()),
+            Message::List(messages) => Value::List(Rc::new(RefCell::new(all(messages)))),
+            Message::Set(messages) => Value::Set(Rc::new(RefCell::new(all(messages)))),
+            Message::Map(entries) => Value::Map(Rc::new(RefCell::new(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (Value::from_message(key), Value::from_message(value)))
+                    .collect(),
+            ))),
+            Message::Channel(channel) => Value::Channel(channel),
+        }
+    }
+
+    // The same as one of `values`, for sets and `in`
+    pub fn is_in(&self, values: &[Value]) -> bool {
+        values.iter().any(|value| value.is_equal(self))
+    }
+
+    // Strings quoted, to tell "1" from 1 in lists, maps and errors
+    pub fn repr(&self) -> String {
+        match self {
+            Value::String(value) => format!("{:?}", value),
+            Value::Tuple(values) => {
+                let values: Vec<String> = values.iter().map(Value::repr).collect();
+                format!("({})", values.join(", "))
+            }
+            _ => self.to_string(),
+        }
+    }
+
+    // Text used when the value is concatenated to a string
+    pub fn value(&self) -> String {
+        match self {
+            Value::Function(function) => function.to_string(),
+            Value::Closure(closure) => closure.function.to_string(),
+            Value::NativeFunction(native) => native.name.clone(),
+            Value::Class(class) => class.borrow().name.clone(),
+            Value::Instance(instance) => instance.borrow().class.borrow().name.clone(),
+            Value::BoundMethod(method) => method.method.function.to_string(),
+            _ => self.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Boolean(value) => write!(f, "{}", value),
+            Value::Number(value) => write!(f, "{}", number::format(*value)),
+            Value::String(value) => write!(f, "{}", value),
+            Value::Function(function) => write!(f, "Function <{}>", function),
+            Value::Closure(closure) => write!(f, "Function <{}>", closure.function),
+            Value::NativeFunction(native) => write!(f, "Native Function <{}>", native.name),
+            Value::Class(class) => write!(f, "Class <{}>", class.borrow().name),
+            Value::Instance(instance) => {
+                write!(f, "Instance of <{}>", instance.borrow().class.borrow().name)
+            }
+            Value::BoundMethod(method) => write!(f, "Function <{}>", method.method.function),
+            Value::Tuple(values) => {
+                let values: Vec<String> = values.iter().map(Value::to_string).collect();
+                write!(f, "({})", values.join(", "))
+            }
+            Value::List(elements) => {
+                let elements = repr::nested(Rc::as_ptr(elements).cast(), repr::MAX_DEPTH, || {
+                    let elements: Vec<String> = elements.borrow().iter().map(Value::repr).collect();
+                    elements.join(", ")
+                });
+                write!(f, "[{}]", elements.as_deref().unwrap_or("..."))
+            }
+            Value::Map(entries) => {
+                let entries = repr::nested(Rc::as_ptr(entries).cast(), repr::MAX_DEPTH, || {
+                    let entries: Vec<String> = entries
+                        .borrow()
+                        .iter()
+                        .map(|(key, value)| format!("{}: {}", key.repr(), value.repr()))
+                        .collect();
+                    entries.join(", ")
+                });
+                write!(f, "{{{}}}", entries.as_deref().unwrap_or("..."))
+            }
+            Value::Set(elements) => {
+                let elements: Vec<String> = elements.borrow().iter().map(Value::repr).collect();
+                write!(f, "#{{{}}}", elements.join(", "))
+            }
+            Value::Pattern(_) => write!(f, "Pattern"),
+            Value::Generator(generator) => {
+                let generator = generator.borrow();
+                match generator.spawned {
+                    true => write!(f, "Coroutine <{}>", generator.closure.function),
+                    false => write!(f, "Generator <{}>", generator.closure.function),
+                }
+            }
+            Value::Promise(promise) => write!(f, "{}", promise.borrow()),
+            Value::Channel(_) => write!(f, "Channel"),
+            Value::Builder(_) => write!(f, "String builder"),
+            Value::Module(module) => write!(f, "Module <{}>", module.name),
+            Value::Socket(socket) => write!(f, "{}", socket),
+            Value::Bytes(bytes) => bytes::format(bytes, f),
+        }
+    }
+}
+
+impl Nested for Value {
+    fn contents(&self) -> Option<Contents<Value>> {
+        let (address, open, close, items) = match self {
+            Value::List(elements) => (
+                Rc::as_ptr(elements).cast(),
+                "[".to_string(),
+                "]",
+                elements
+                    .borrow()
+                    .iter()
+                    .map(|element| (None, element.clone()))
+                    .collect(),
+            ),
+            Value::Set(elements) => (
+                Rc::as_ptr(elements).cast(),
+                "#{".to_string(),
+                "}",
+                elements
+                    .borrow()
+                    .iter()
+                    .map(|element| (None, element.clone()))
+                    .collect(),
+            ),
+            Value::Map(entries) => (
+                Rc::as_ptr(entries).cast(),
+                "{".to_string(),
+                "}",
+                entries
+                    .borrow()
+                    .iter()
+                    .map(|(key, value)| (Some(key.repr()), value.clone()))
+                    .collect(),
+            ),
+            // Public fields by name, the order they were set in isn't kept
+            Value::Instance(instance) => {
+                let borrowed = instance.borrow();
+                let mut fields: Vec<(Option<String>, Value)> = borrowed
+                    .fields
+                    .iter()
+                    .filter(|(name, _)| !is_private(name))
+                    .map(|(name, value)| (Some(name.clone()), value.clone()))
+                    .collect();
+                if fields.is_empty() {
+                    return None;
+                }
+                fields.sort_by(|(a, _), (b, _)| a.cmp(b));
+                (
+                    Rc::as_ptr(instance).cast(),
+                    format!("Instance of <{}> {{", borrowed.class.borrow().name),
+                    "}",
+                    fields,
+                )
+            }
+            _ => return None,
+        };
+        Some(Contents {
+            address,
+            open,
+            close,
+            items,
+        })
+    }
+
+    fn inline(&self) -> String {
+        self.repr()
+    }
+}
+
+// A function as compiled, shared by all the closures created from it
+#[derive(Debug, Clone, Default)]
+pub struct FunctionProto {
+    pub name: String,
+    pub line: usize,
+    pub column: usize,
+    pub arity: usize,
+    pub upvalues: Vec<String>, // Names of the variables it captures, by index
+    pub chunk: Chunk,
+}
+
+impl fmt::Display for FunctionProto {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at line {}, column {}",
+            self.name, self.line, self.column
+        )
+    }
+}
+
+// A variable captured by a closure
+// Open while the variable still lives on the stack, closed once it went out of scope
+#[derive(Debug)]
+pub enum Upvalue {
+    Open(usize), // Stack slot
+    Closed(Value),
+}
+
+#[derive(Debug)]
+pub struct Closure {
+    pub function: Rc<FunctionProto>,
+    pub upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    // The class declaring it as a method, or declaring the method it was made
+    // in, whose instances' private members it reaches
+    pub owner: Option<Weak<RefCell<Class>>>,
+}
+
+// A call of a generator function, run a piece at a time
+// While suspended, its part of the stack is kept here, and the variables of it that
+// closures captured are closed. Resuming puts the part back where the stack is then
+#[derive(Debug)]
+pub struct Generator {
+    pub closure: Rc<Closure>,
+    pub ip: usize,
+    pub stack: Vec<Value>, // From the slot of the called function up
+    pub upvalues: Vec<(usize, Rc<RefCell<Upvalue>>)>, // Captured slots of `stack`
+    pub handlers: Vec<Handler>, // Of the tries it stopped in
+    pub state: State,
+    pub started: bool, // Stopped at a `yield`, which takes the value it is resumed with
+    pub spawned: bool, // A coroutine made by `spawn`, rather than by calling a generator function
+    pub awaited: Option<Shared<Promise>>, // Of the `await` a coroutine stopped at, run again once resumed
+}
+
+impl Generator {
+    // The pending promise of the `await` the coroutine stopped at, if it did
+    pub fn awaiting(&self) -> Option<&Shared<Promise>> {
+        self.awaited
+            .as_ref()
+            .filter(|promise| matches!(*promise.borrow(), Promise::Pending))
+    }
+}
+
+// Where a try of a frame goes when its body fails: the code of its catches, and
+// how high the frame's part of the stack was when the body started
+#[derive(Debug, Clone, Copy)]
+pub struct Handler {
+    pub ip: usize,
+    pub stack: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum State {
+    Suspended,
+    Running,
+    Done,
+}
+
+#[derive(Debug)]
+pub struct NativeFunction {
+    pub name: String,
+    pub arity: usize,
+    pub function: fn(&mut VM, &[Value]) -> Result<Value, Error>,
+    pub receiver: Option<Value>, // Given before the arguments, for the method of a socket
+    pub variadic: bool,          // `arity` is the least it takes, more are given too
+}
+
+impl NativeFunction {
+    // Why a call with `count` arguments can't be made, none when it can
+    pub fn arity_error(&self, count: usize) -> Option<String> {
+        match self.variadic {
+            true if count < self.arity => {
+                Some(variadic_arity_message(&self.name, self.arity, count))
+            }
+            false if count != self.arity => Some(arity_message(&self.name, self.arity, count)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Class {
+    pub name: String,
+    pub superclass: Option<Rc<RefCell<Class>>>, // Its methods are copied down, this is for `match`
+    pub methods: HashMap<String, Rc<Closure>>,
+    pub mixins: Vec<Rc<RefCell<Class>>>, // In the order they were mixed in, for `override`
+    pub version: usize, // Bumped whenever its methods change, for the inline caches
+    pub dropped: Option<Rc<RefCell<Vec<BoundMethod>>>>, // Where its instances leave their `__drop` method
+    pub sealed: bool,                                   // Declared `sealed class`
+}
+
+impl Class {
+    // The class itself, or one it inherits from
+    pub fn is_or_inherits(&self, class: &Rc<RefCell<Class>>) -> bool {
+        if std::ptr::eq(self, class.as_ptr()) {
+            return true;
+        }
+        match &self.superclass {
+            Some(superclass) => superclass.borrow().is_or_inherits(class),
+            None => false,
+        }
+    }
+
+    // A method of `owner` reaches the private members of the class' instances
+    // when `owner` is the class or one of its superclasses, not a mixin
+    pub fn is_owner(&self, owner: &Option<Weak<RefCell<Class>>>) -> bool {
+        owner
+            .as_ref()
+            .and_then(Weak::upgrade)
+            .is_some_and(|owner| self.is_or_inherits(&owner))
+    }
+
+    // Declared `sealed class`, or inheriting from one, its instances are
+    // sealed once their initializer has run
+    pub fn is_sealed(&self) -> bool {
+        self.sealed
+            || self
+                .superclass
+                .as_ref()
+                .is_some_and(|superclass| superclass.borrow().is_sealed())
+    }
+
+    // Whether the class defines the method itself, rather than having it
+    // copied down from its superclass
+    pub fn defines(&self, name: &str) -> bool {
+        let Some(method) = self.methods.get(name) else {
+            return false;
+        };
+        match &self.superclass {
+            Some(superclass) => match superclass.borrow().methods.get(name) {
+                Some(inherited) => !Rc::ptr_eq(method, inherited),
+                None => true,
+            },
+            None => true,
+        }
+    }
+
+    // The closest class up the chain of `class` defining the method `name`
+    // itself, by name, and the method's arity
+    pub fn defining(class: &Rc<RefCell<Class>>, name: &str) -> Option<(String, usize)> {
+        let mut class = Rc::clone(class);
+        loop {
+            let superclass = {
+                let class = class.borrow();
+                if class.defines(name) {
+                    return Some((class.name.clone(), class.methods[name].function.arity));
+                }
+                class.superclass.clone()?
+            };
+            class = superclass;
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Instance {
+    pub class: Rc<RefCell<Class>>,
+    pub fields: HashMap<String, Value>,
+    pub mutability: Mutability,
+    pub finalized: bool, // Its `__drop` method was left to run
+}
+
+impl Instance {
+    pub fn new(class: Rc<RefCell<Class>>, fields: HashMap<String, Value>) -> Instance {
+        Instance {
+            class,
+            fields,
+            mutability: Mutability::Open,
+            finalized: false,
+        }
+    }
+
+    // Leaves its `__drop` method, bound to it, to run once, see Class::dropped
+    // False when it has none, or it was already left
+    pub fn finalize(instance: &Rc<RefCell<Instance>>) -> bool {
+        let class = Rc::clone(&instance.borrow().class);
+        let class = class.borrow();
+        let (Some(dropped), Some(method), false) = (
+            &class.dropped,
+            class.methods.get("__drop"),
+            instance.borrow().finalized,
+        ) else {
+            return false;
+        };
+        instance.borrow_mut().finalized = true;
+        dropped.borrow_mut().push(BoundMethod {
+            receiver: Value::Instance(Rc::clone(instance)),
+            method: Rc::clone(method),
+        });
+        true
+    }
+}
+
+// The `__drop` method of an instance no longer reachable waits for the VM to
+// be between instructions, see VM::finalize. It is bound to an instance taking
+// over the fields, which is dropped without running it again
+impl Drop for Instance {
+    fn drop(&mut self) {
+        let class = self.class.borrow();
+        if self.finalized || class.dropped.is_none() || !class.methods.contains_key("__drop") {
+            return;
+        }
+        drop(class);
+        let alive = Instance {
+            class: Rc::clone(&self.class),
+            fields: std::mem::take(&mut self.fields),
+            mutability: self.mutability,
+            finalized: false,
+        };
+        Instance::finalize(&Rc::new(RefCell::new(alive)));
+    }
+}
+
+// A method read from an instance, remembers the instance it was read from
+#[derive(Debug)]
+pub struct BoundMethod {
+    pub receiver: Value,
+    pub method: Rc<Closure>,
+}
+
+// A pattern of a `match` as compiled
+// Its instance patterns only name their class, the classes are runtime values
+// pushed in the order of Pattern::classes before matching
+#[derive(Debug)]
+pub enum PatternProto {
+    Wildcard,
+    Binding,
+    Literal(Value), // nil, a boolean, a number or a string
+    List(Vec<PatternProto>),
+    Map(Vec<(Value, PatternProto)>),
+    Instance(Vec<(Rc<str>, PatternProto)>),
+}
+
+impl PatternProto {
+    pub fn class_count(&self) -> usize {
+        match self {
+            PatternProto::List(elements) => elements.iter().map(PatternProto::class_count).sum(),
+            PatternProto::Map(entries) => entries
+                .iter()
+                .map(|(_, pattern)| pattern.class_count())
+                .sum(),
+            PatternProto::Instance(fields) => {
+                1 + fields
+                    .iter()
+                    .map(|(_, pattern)| pattern.class_count())
+                    .sum::<usize>()
+            }
+            PatternProto::Wildcard | PatternProto::Binding | PatternProto::Literal(_) => 0,
+        }
+    }
+
+    // The values a match pushes when it matches
+    pub fn binding_count(&self) -> usize {
+        match self {
+            PatternProto::Binding => 1,
+            PatternProto::List(elements) => elements.iter().map(PatternProto::binding_count).sum(),
+            PatternProto::Map(entries) => entries
+                .iter()
+                .map(|(_, pattern)| pattern.binding_count())
+                .sum(),
+            PatternProto::Instance(fields) => fields
+                .iter()
+                .map(|(_, pattern)| pattern.binding_count())
+                .sum(),
+            PatternProto::Wildcard | PatternProto::Literal(_) => 0,
+        }
+    }
+}
@@ -0,0 +1,2819 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    io::{self, Write},
+    rc::Rc,
+    thread,
+    time::Duration,
+};
+
+use super::{
+    chunk::{MethodCache, OpCode},
+    collector::Collector,
+    compiler::Compiler,
+    serialize,
+    value::{
+        BoundMethod, Class, Closure, FunctionProto, Generator, Handler, Instance, NativeFunction,
+        PatternProto, State, Upvalue, Value,
+    },
+};
+
+#[cfg(feature = "sockets")]
+use crate::sockets::sockets::{Socket, SocketKind};
+use crate::{
+    bytes, codec, equality,
+    error::{arity_message, Error, ErrorClass},
+    eventloop::eventloop::{EventLoop, Executor, Operation, Outcome, Promise},
+    files,
+    interpreter::{interpreter::CALLS_MAX, scheduler::Scheduler},
+    lexer::token::{is_private, Span},
+    modules::{self, Load, Module, ModuleRegistry},
+    mutability::Mutability,
+    number,
+    providers::providers::{Providers, System},
+    repr,
+    strings::{self, Argument},
+    threads::threads::Channel,
+};
+
+// Stack based virtual machine running the compiler's bytecode
+//
+// Produces the same output and errors as the tree-walking interpreter,
+// which stays the reference implementation
+pub struct VM {
+    stack: Vec<Value>,
+    frames: Vec<CallFrame>,
+    globals: HashMap<Rc<str>, Value>,
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>, // Captured variables still on the stack
+    arguments: Vec<Rc<str>>,                  // Given to the script on the command line
+    output: Box<dyn Write>,                   // Where `print` writes, stdout unless set
+    providers: RefCell<Box<dyn Providers>>,   // Of `clock()`, `now()` and `random()`
+    scheduler: Scheduler<Rc<RefCell<Generator>>>, // Spawned coroutines, for `run()`
+    event_loop: EventLoop,                    // Of the promises of async natives
+    error_classes: Vec<Rc<RefCell<Class>>>,   // Built-in, in the order of ErrorClass::ALL
+    thrown: Option<Value>,                    // What the last `throw` threw, until it is caught
+    modules: ModuleRegistry<Value>,           // What `import` can import
+    dropped: Rc<RefCell<Vec<BoundMethod>>>,   // `__drop` methods of unreachable instances, to run
+    collector: Collector,                     // Of the cycles counting references can't free
+}
+
+struct CallFrame {
+    closure: Rc<Closure>,
+    ip: usize,
+    base: usize, // Stack slot of the called function, its locals follow
+    // The generator running in the frame, and where its caller's loop ends,
+    // none when `resume()` runs it
+    generator: Option<(Rc<RefCell<Generator>>, Option<usize>)>,
+    handlers: Vec<Handler>, // Of the tries the frame is in, innermost last
+    // The error its catches are given, for Rethrow
+    // The frame's own, a call made before the Rethrow can catch errors of its own
+    caught: Option<Error>,
+    seals: bool, // The initializer of a sealed class' call, its instance is sealed when it returns
+    calls: usize, // Under way up to this one, the script's frame and those of `match` aside
+}
+
+impl Default for VM {
+    fn default() -> VM {
+        VM::new()
+    }
+}
+
+impl VM {
+    pub fn new() -> VM {
+        let mut vm = VM {
+            stack: Vec::new(),
+            frames: Vec::new(),
+            globals: HashMap::new(),
+            open_upvalues: Vec::new(),
+            arguments: Vec::new(),
+            output: Box::new(io::stdout()),
+            providers: RefCell::new(Box::new(System::new())),
+            scheduler: Scheduler::new(),
+            event_loop: EventLoop::new(),
+            error_classes: Vec::new(),
+            thrown: None,
+            modules: ModuleRegistry::new(),
+            dropped: Rc::default(),
+            collector: Collector::default(),
+        };
+
+        // Built-in error classes, superclasses first
+        for class in ErrorClass::ALL {
+            let superclass = class
+                .superclass()
+                .map(|superclass| Rc::clone(&vm.error_classes[superclass as usize]));
+            let class = Rc::new(RefCell::new(Class {
+                name: class.name().to_string(),
+                superclass,
+                methods: HashMap::new(),
+                mixins: Vec::new(),
+                version: 0,
+                dropped: None,
+                sealed: false,
+            }));
+            let name = Rc::from(class.borrow().name.as_str());
+            vm.globals.insert(name, Value::Class(Rc::clone(&class)));
+            vm.error_classes.push(class);
+        }
+
+        // Native Functions
+        // Seconds since the Unix epoch, to time code
+        fn clock(vm: &mut VM, _arguments: &[Value]) -> Result<Value, Error> {
+            Ok(Value::Number(vm.providers.borrow_mut().clock()))
+        }
+
+        // Milliseconds since the Unix epoch
+        fn now(vm: &mut VM, _arguments: &[Value]) -> Result<Value, Error> {
+            Ok(Value::Number(vm.providers.borrow_mut().now()))
+        }
+
+        // A number from 0 up to, not including, 1
+        fn random(vm: &mut VM, _arguments: &[Value]) -> Result<Value, Error> {
+            Ok(Value::Number(vm.providers.borrow_mut().random()))
+        }
+
+        // Frees the instances that only refer to each other, see Collector
+        fn gc(vm: &mut VM, _arguments: &[Value]) -> Result<Value, Error> {
+            Ok(Value::Number(vm.collector.collect() as f64))
+        }
+
+        // NaN is the only number not equal to itself, this tells it apart
+        fn is_nan(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+            match arguments {
+                [Value::Number(number)] => Ok(Value::Boolean(number.is_nan())),
+                [value] => Err(vm.error_as(
+                    ErrorClass::TypeError,
+                    format!("Expected Number, got {}", value.repr()),
+                )),
+                _ => unreachable!("is_nan takes one argument"),
+            }
+        }
+
+        // Strings are immutable, so `text = text + part` copies all of `text`
+        // every time. A builder is appended to in place instead
+        fn string_builder(_vm: &mut VM, _arguments: &[Value]) -> Result<Value, Error> {
+            Ok(Value::Builder(Rc::new(RefCell::new(String::new()))))
+        }
+
+        // Adds the value, as print shows it, to the end of the builder
+        fn append(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+            match arguments {
+                [Value::Builder(builder), value] => {
+                    match value {
+                        Value::String(text) => builder.borrow_mut().push_str(text),
+                        _ => builder.borrow_mut().push_str(&value.to_string()),
+                    }
+                    Ok(Value::Nil)
+                }
+                [value, _] => Err(vm.error_as(
+                    ErrorClass::TypeError,
+                    format!(
+                        "Only string builders can be appended to, got {}",
+                        value.repr()
+                    ),
+                )),
+                _ => unreachable!("append takes two arguments"),
+            }
+        }
+
+        // The text of a builder so far, or any other value as print shows it
+        fn to_string(_vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+            match arguments {
+                [Value::Builder(builder)] => Ok(Value::String(Rc::from(builder.borrow().as_str()))),
+                [Value::String(text)] => Ok(Value::String(Rc::clone(text))),
+                [value] => Ok(Value::String(Rc::from(value.to_string()))),
+                _ => unreachable!("to_string takes one argument"),
+            }
+        }
+
+        // The list of maps as an aligned table, a map a row, see repr::table
+        fn print_table(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+            let Value::List(rows) = &arguments[0] else {
+                return Err(vm.error_as(
+                    ErrorClass::TypeError,
+                    format!("Expected a list of maps, got {}", arguments[0].repr()),
+                ));
+            };
+            let mut table = Vec::new();
+            for (index, row) in rows.borrow().iter().enumerate() {
+                let Value::Map(entries) = row else {
+                    return Err(vm.error_as(
+                        ErrorClass::TypeError,
+                        format!("Row {} is not a map, got {}", index, row.repr()),
+                    ));
+                };
+                let cells = entries
+                    .borrow()
+                    .iter()
+                    .map(|(key, value)| repr::Cell {
+                        column: key.to_string(),
+                        text: value.to_string(),
+                        number: matches!(value, Value::Number(_)),
+                    })
+                    .collect();
+                table.push(cells);
+            }
+            if let Some(table) = repr::table(&table) {
+                if let Err(error) = writeln!(vm.output, "{}", table) {
+                    return Err(vm.error(format!("Could not print: {}", error)));
+                }
+            }
+            Ok(Value::Nil)
+        }
+
+        // The template with its fields replaced by the values after it, see
+        // strings::format
+        fn format(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+            let Value::String(template) = &arguments[0] else {
+                return Err(vm.error_as(
+                    ErrorClass::TypeError,
+                    format!("Expected String, got {}", arguments[0].repr()),
+                ));
+            };
+            let values: Vec<Argument> = arguments[1..]
+                .iter()
+                .map(|value| match value {
+                    Value::Number(number) => Argument::Number(*number),
+                    _ => Argument::Text(value.to_string()),
+                })
+                .collect();
+            strings::format(template, &values)
+                .map(|text| Value::String(Rc::from(text)))
+                .map_err(|message| vm.error(message))
+        }
+
+        // The number written in the text, nil when it isn't one, see number::parse
+        fn parse_number(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+            match &arguments[0] {
+                Value::String(text) => Ok(number::parse(text).map_or(Value::Nil, Value::Number)),
+                value => Err(vm.error_as(
+                    ErrorClass::TypeError,
+                    format!("Expected String, got {}", value.repr()),
+                )),
+            }
+        }
+
+        // The text as bytes in the encoding, see bytes
+        fn encode(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+            match arguments {
+                [Value::String(text), Value::String(encoding)] => bytes::encode(text, encoding)
+                    .map(|bytes| Value::Bytes(Rc::from(bytes)))
+                    .map_err(|message| vm.error(message)),
+                [Value::String(_), value] | [value, _] => Err(vm.error_as(
+                    ErrorClass::TypeError,
+                    format!("Expected String, got {}", value.repr()),
+                )),
+                _ => unreachable!("encode takes two arguments"),
+            }
+        }
+
+        // Every element on a line of its own, indented, nesting deeper than the
+        // depth and values holding themselves shown as `[...]`
+        fn debug_repr(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+            match arguments {
+                [value, Value::Number(depth)] if depth.fract() == 0.0 && *depth >= 0.0 => Ok(
+                    Value::String(Rc::from(repr::debug_repr(value, *depth as usize))),
+                ),
+                [_, depth] => Err(vm.error_as(
+                    ErrorClass::TypeError,
+                    format!("Expected a whole number of levels, got {}", depth.repr()),
+                )),
+                _ => unreachable!("debug_repr takes two arguments"),
+            }
+        }
+
+        // The names of the modules imported so far, in the order they were loaded
+        fn modules(vm: &mut VM, _arguments: &[Value]) -> Result<Value, Error> {
+            let names = vm
+                .modules
+                .names()
+                .into_iter()
+                .map(|name| Value::String(Rc::from(name)))
+                .collect();
+            Ok(Value::List(Rc::new(RefCell::new(names))))
+        }
+
+        // The instance, from now on none of its fields can be set
+        fn freeze(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+            vm.restrict(&arguments[0], Mutability::Frozen)
+        }
+
+        // The instance, from now on it can't get fields it doesn't have
+        fn seal(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+            vm.restrict(&arguments[0], Mutability::Sealed)
+        }
+
+        // Whether `value.name` would give something rather than raise
+        fn has(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+            match &arguments[1] {
+                Value::String(name) => Ok(Value::Boolean(
+                    !is_private(name) && property_of(&arguments[0], name).is_some(),
+                )),
+                value => Err(vm.error_as(
+                    ErrorClass::TypeError,
+                    format!("Expected String, got {}", value.repr()),
+                )),
+            }
+        }
+
+        // The variables the running code sees, other than globals, by name: the
+        // function's own and the ones it captured. Empty at the top level
+        fn locals(vm: &mut VM, _arguments: &[Value]) -> Result<Value, Error> {
+            let frame = vm.frames.last().expect("Natives are called from a frame");
+            let function = &frame.closure.function;
+            let mut bindings: Vec<(Rc<str>, Value)> = Vec::new();
+            for (name, upvalue) in function.upvalues.iter().zip(frame.closure.upvalues.iter()) {
+                let value = match &*upvalue.borrow() {
+                    Upvalue::Open(slot) => vm.stack[*slot].clone(),
+                    Upvalue::Closed(value) => value.clone(),
+                };
+                bindings.push((Rc::from(name.as_str()), value));
+            }
+            // The call is behind the ip, in the scopes it was made in
+            let mut scoped: Vec<_> = function
+                .chunk
+                .locals
+                .iter()
+                .filter(|local| local.start < frame.ip && frame.ip <= local.end)
+                .collect();
+            scoped.sort_by_key(|local| local.slot);
+            for local in scoped {
+                let value = vm.stack[frame.base + local.slot as usize].clone();
+                match bindings.iter_mut().find(|(name, _)| **name == *local.name) {
+                    Some((_, bound)) => *bound = value,
+                    None => bindings.push((Rc::from(local.name.as_str()), value)),
+                }
+            }
+            Ok(bindings_map(bindings))
+        }
+
+        // The globals, by name, but for the natives and error classes every script has
+        fn globals(vm: &mut VM, _arguments: &[Value]) -> Result<Value, Error> {
+            let bindings = vm
+                .globals
+                .iter()
+                .filter(|(name, value)| !vm.is_built_in(name, value))
+                .map(|(name, value)| (Rc::clone(name), value.clone()))
+                .collect();
+            Ok(bindings_map(bindings))
+        }
+
+        // Number of arguments given to the script
+        fn arg_count(vm: &mut VM, _arguments: &[Value]) -> Result<Value, Error> {
+            Ok(Value::Number(vm.arguments.len() as f64))
+        }
+
+        // The argument at that index, nil when there is none
+        fn arg(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+            Ok(match arguments {
+                [Value::Number(index)] if index.fract() == 0.0 && *index >= 0.0 => vm
+                    .arguments
+                    .get(*index as usize)
+                    .map_or(Value::Nil, |argument| Value::String(Rc::clone(argument))),
+                _ => Value::Nil,
+            })
+        }
+
+        // All of the arguments, a new list every call
+        fn args(vm: &mut VM, _arguments: &[Value]) -> Result<Value, Error> {
+            let arguments = vm
+                .arguments
+                .iter()
+                .map(|argument| Value::String(Rc::clone(argument)))
+                .collect();
+            Ok(Value::List(Rc::new(RefCell::new(arguments))))
+        }
+
+        // Elements of either set, those of the first one first, nil unless both are sets
+        fn union(_vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+            let [Value::Set(first), Value::Set(second)] = arguments else {
+                return Ok(Value::Nil);
+            };
+            let mut elements = first.borrow().clone();
+            for element in second.borrow().iter() {
+                if !element.is_in(&elements) {
+                    elements.push(element.clone());
+                }
+            }
+            Ok(Value::Set(Rc::new(RefCell::new(elements))))
+        }
+
+        // Elements of the first set that are in the second one, nil unless both are sets
+        fn intersection(_vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+            let [Value::Set(first), Value::Set(second)] = arguments else {
+                return Ok(Value::Nil);
+            };
+            let elements = first
+                .borrow()
+                .iter()
+                .filter(|element| element.is_in(&second.borrow()))
+                .cloned()
+                .collect();
+            Ok(Value::Set(Rc::new(RefCell::new(elements))))
+        }
+
+        // What the coroutine yields when resumed with the value, or returns at its end
+        fn resume(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+            let [coroutine, value] = arguments else {
+                unreachable!("resume takes two arguments")
+            };
+            let Value::Generator(coroutine) = coroutine else {
+                return Err(vm.error_as(
+                    ErrorClass::TypeError,
+                    format!("Only coroutines can be resumed, got {}", coroutine.repr()),
+                ));
+            };
+            let state = coroutine.borrow().state;
+            match state {
+                State::Done => Err(vm.error("Can't resume a coroutine that is done".to_string())),
+                State::Running => Err(vm.error("Generator is already running".to_string())),
+                State::Suspended => {
+                    let depth = vm.frames.len();
+                    vm.resume(Rc::clone(coroutine), value.clone(), None)?;
+                    vm.run(depth)?;
+                    Ok(vm.pop())
+                }
+            }
+        }
+
+        // "suspended", "running" or "done"
+        fn status(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+            match arguments {
+                [Value::Generator(coroutine)] => {
+                    Ok(Value::String(Rc::from(match coroutine.borrow().state {
+                        State::Suspended => "suspended",
+                        State::Running => "running",
+                        State::Done => "done",
+                    })))
+                }
+                [value] => Err(vm.error_as(
+                    ErrorClass::TypeError,
+                    format!("Only coroutines have a status, got {}", value.repr()),
+                )),
+                _ => unreachable!("status takes one argument"),
+            }
+        }
+
+        // Runs the spawned coroutines in turns until they are all done. One stopped
+        // at an `await` gets no turn until its promise settles, and when all of
+        // them are waiting, the event loop waits for the first to settle
+        fn run(vm: &mut VM, _arguments: &[Value]) -> Result<Value, Error> {
+            let mut waiting: Vec<Rc<RefCell<Generator>>> = Vec::new();
+            loop {
+                while let Some(coroutine) = vm.scheduler.take_turn() {
+                    let state = coroutine.borrow().state;
+                    match state {
+                        State::Done => continue,
+                        State::Running => {
+                            return Err(vm.error("Generator is already running".to_string()))
+                        }
+                        State::Suspended => {}
+                    }
+                    let depth = vm.frames.len();
+                    vm.resume(Rc::clone(&coroutine), Value::Nil, None)?;
+                    vm.run(depth)?;
+                    vm.pop();
+                    if coroutine.borrow().awaiting().is_some() {
+                        waiting.push(coroutine);
+                    } else if coroutine.borrow().state != State::Done {
+                        vm.scheduler.spawn(coroutine);
+                    }
+                }
+                if waiting.is_empty() {
+                    return Ok(Value::Nil);
+                }
+
+                vm.event_loop.wait_any();
+                for coroutine in std::mem::take(&mut waiting) {
+                    if coroutine.borrow().awaiting().is_some() {
+                        waiting.push(coroutine);
+                    } else {
+                        vm.scheduler.spawn(coroutine);
+                    }
+                }
+            }
+        }
+
+        // A promise settled with nil after that many milliseconds
+        fn sleep_async(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+            match arguments {
+                [Value::Number(milliseconds)]
+                    if *milliseconds >= 0.0 && milliseconds.is_finite() =>
+                {
+                    let duration = Duration::from_secs_f64(milliseconds / 1000.0);
+                    Ok(Value::Promise(
+                        vm.event_loop.start(Operation::Sleep(duration)),
+                    ))
+                }
+                [value] => Err(vm.error_as(
+                    ErrorClass::TypeError,
+                    format!("Expected a number of milliseconds, got {}", value.repr()),
+                )),
+                _ => unreachable!("sleep_async takes one argument"),
+            }
+        }
+
+        // A promise settled with the text of the file at that path
+        fn read_file_async(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+            match arguments {
+                [Value::String(path)] => Ok(Value::Promise(
+                    vm.event_loop.start(Operation::ReadFile(path.to_string())),
+                )),
+                [value] => Err(vm.error_as(
+                    ErrorClass::TypeError,
+                    format!("Expected a path, got {}", value.repr()),
+                )),
+                _ => unreachable!("read_file_async takes one argument"),
+            }
+        }
+
+        // Runs the function in a VM of its own on another thread, with its end of a
+        // new channel, the caller gets the other end
+        fn spawn_thread(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+            let [Value::Closure(closure)] = arguments else {
+                return Err(vm.error_as(
+                    ErrorClass::TypeError,
+                    format!(
+                        "Only functions can run on a thread, got {}",
+                        arguments[0].repr()
+                    ),
+                ));
+            };
+            if !closure.upvalues.is_empty() {
+                return Err(
+                    vm.error("Functions that capture variables can't run on a thread".to_string())
+                );
+            }
+            if closure.function.arity != 1 {
+                return Err(vm.error(
+                    "A function run on a thread takes one parameter, its channel".to_string(),
+                ));
+            }
+
+            // The other VM gets the function compiled, like a .lostc file
+            let bytes = serialize::encode(&closure.function);
+            let (ours, theirs) = Channel::pair();
+            thread::spawn(move || {
+                let function = serialize::decode(&bytes).expect("Encoded by this build");
+                let result =
+                    VM::new().call_function(function, vec![Value::Channel(theirs.clone())]);
+                theirs.close(
+                    result
+                        .map(|_| ())
+                        .map_err(|error| error.message().to_string()),
+                );
+            });
+            Ok(Value::Channel(ours))
+        }
+
+        fn send(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+            let [channel, value] = arguments else {
+                unreachable!("send takes two arguments")
+            };
+            let Value::Channel(channel) = channel else {
+                return Err(vm.error_as(
+                    ErrorClass::TypeError,
+                    format!("Only channels can be sent on, got {}", channel.repr()),
+                ));
+            };
+            let Some(message) = value.to_message() else {
+                return Err(vm.error_as(ErrorClass::TypeError, format!(
+                    "Only nil, booleans, numbers, strings, bytes, tuples, lists, maps, sets and channels can be sent, got {}",
+                    value.repr()
+                )));
+            };
+            channel.send(message);
+            Ok(Value::Nil)
+        }
+
+        // Waits for the next value sent from the other end
+        fn recv(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+            match arguments {
+                [Value::Channel(channel)] => match channel.recv() {
+                    Ok(message) => Ok(Value::from_message(message)),
+                    Err(message) => Err(vm.error(message)),
+                },
+                [value] => Err(vm.error_as(
+                    ErrorClass::TypeError,
+                    format!("Only channels can be received from, got {}", value.repr()),
+                )),
+                _ => unreachable!("recv takes one argument"),
+            }
+        }
+
+        vm.define_native("clock", 0, clock);
+        vm.define_native("now", 0, now);
+        vm.define_native("random", 0, random);
+        vm.define_native("is_nan", 1, is_nan);
+        vm.define_native("gc", 0, gc);
+        vm.define_native("arg_count", 0, arg_count);
+        vm.define_native("arg", 1, arg);
+        vm.define_native("args", 0, args);
+        vm.define_native("union", 2, union);
+        vm.define_native("intersection", 2, intersection);
+        vm.define_native("resume", 2, resume);
+        vm.define_native("status", 1, status);
+        vm.define_native("run", 0, run);
+        vm.define_native("sleep_async", 1, sleep_async);
+        vm.define_native("read_file_async", 1, read_file_async);
+        vm.define_native("spawn_thread", 1, spawn_thread);
+        vm.define_native("send", 2, send);
+        vm.define_native("recv", 1, recv);
+        vm.define_native("string_builder", 0, string_builder);
+        vm.define_native("append", 2, append);
+        vm.define_native("to_string", 1, to_string);
+        vm.define_native("debug_repr", 2, debug_repr);
+        vm.define_native("print_table", 1, print_table);
+        vm.define_native("parse_number", 1, parse_number);
+        vm.define_native("encode", 2, encode);
+        // The values of its fields come after the template, as many as there are
+        vm.define_variadic_native("format", 1, format);
+        vm.define_native("modules", 0, modules);
+        vm.define_native("locals", 0, locals);
+        vm.define_native("globals", 0, globals);
+        vm.define_native("freeze", 1, freeze);
+        vm.define_native("seal", 1, seal);
+        vm.define_native("has", 2, has);
+        vm.modules.register("math", math);
+        vm.modules.register("fs", fs);
+        vm.modules.register("hash", hash);
+        vm.modules.register("codec", codec);
+        #[cfg(feature = "sockets")]
+        vm.modules.register("net", net);
+        vm
+    }
+
+    // The modules scripts can import, a host adds its own here
+    pub fn modules(&mut self) -> &mut ModuleRegistry<Value> {
+        &mut self.modules
+    }
+
+    // What `arg_count()` and `arg(index)` give the script
+    pub fn set_arguments(&mut self, arguments: &[String]) {
+        self.arguments = arguments
+            .iter()
+            .map(|argument| Rc::from(argument.as_str()))
+            .collect();
+    }
+
+    // Where `print` writes instead of stdout, to capture it
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = output;
+    }
+
+    // Where `clock()`, `now()` and `random()` get their values, the system's unless set
+    pub fn set_providers(&mut self, providers: Box<dyn Providers>) {
+        self.providers = RefCell::new(providers);
+    }
+
+    // What runs the operations of async natives, a thread for each unless set
+    pub fn set_executor(&mut self, executor: Box<dyn Executor>) {
+        self.event_loop.set_executor(executor);
+    }
+
+    fn define_native(
+        &mut self,
+        name: &str,
+        arity: usize,
+        function: fn(&mut VM, &[Value]) -> Result<Value, Error>,
+    ) {
+        let native = NativeFunction {
+            name: name.to_string(),
+            arity,
+            function,
+            receiver: None,
+            variadic: false,
+        };
+        self.globals
+            .insert(Rc::from(name), Value::NativeFunction(Rc::new(native)));
+    }
+
+    // `least` is the fewest arguments the native takes, more are given too
+    fn define_variadic_native(
+        &mut self,
+        name: &str,
+        least: usize,
+        function: fn(&mut VM, &[Value]) -> Result<Value, Error>,
+    ) {
+        let native = NativeFunction {
+            name: name.to_string(),
+            arity: least,
+            function,
+            receiver: None,
+            variadic: true,
+        };
+        self.globals
+            .insert(Rc::from(name), Value::NativeFunction(Rc::new(native)));
+    }
+
+    // The instance after `freeze` or `seal`, sealing a frozen one leaves it frozen
+    fn restrict(&mut self, value: &Value, mutability: Mutability) -> Result<Value, Error> {
+        let Value::Instance(instance) = value else {
+            let message = match mutability {
+                Mutability::Frozen => format!("Only instances can be frozen, got {}", value.repr()),
+                _ => format!("Only instances can be sealed, got {}", value.repr()),
+            };
+            return Err(self.error_as(ErrorClass::TypeError, message));
+        };
+        instance.borrow_mut().mutability.restrict(mutability);
+        Ok(value.clone())
+    }
+
+    // A global still holding the native or error class defined under its name
+    fn is_built_in(&self, name: &str, value: &Value) -> bool {
+        match value {
+            Value::NativeFunction(native) => native.name == name,
+            Value::Class(class) => {
+                class.borrow().name == name
+                    && self
+                        .error_classes
+                        .iter()
+                        .any(|error_class| Rc::ptr_eq(error_class, class))
+            }
+            _ => false,
+        }
+    }
+
+    // Returns what the script returns, the value of its last expression statement
+    pub fn interpret(&mut self, function: Rc<FunctionProto>) -> Result<Value, Error> {
+        self.call_function(function, Vec::new())
+    }
+
+    // Runs a function that captures nothing as the first frame, like the script
+    fn call_function(
+        &mut self,
+        function: Rc<FunctionProto>,
+        arguments: Vec<Value>,
+    ) -> Result<Value, Error> {
+        let closure = Rc::new(Closure {
+            function,
+            upvalues: Vec::new(),
+            owner: None,
+        });
+        self.stack.push(Value::Closure(Rc::clone(&closure)));
+        let argument_count = arguments.len();
+        self.stack.extend(arguments);
+
+        let result = self
+            .call(closure, argument_count)
+            .and_then(|_| self.run(0))
+            .map(|_| self.stack.pop().unwrap_or(Value::Nil));
+        self.stack.clear();
+        if result.is_err() {
+            for frame in self.frames.iter() {
+                if let Some((generator, _)) = &frame.generator {
+                    generator.borrow_mut().state = State::Done;
+                }
+            }
+            self.frames.clear();
+            self.open_upvalues.clear();
+        }
+        result
+    }
+
+    // Runs the `__drop` methods of the instances that became unreachable, after
+    // the instruction that dropped them. An error they raise is raised there
+    //
+    // Only the instructions dropping what a statement or a scope leaves call it,
+    // so they run where the interpreter runs them, after the statement
+    fn finalize(&mut self) -> Result<(), Error> {
+        while !self.dropped.borrow().is_empty() {
+            let dropped = std::mem::take(&mut *self.dropped.borrow_mut());
+            for method in dropped {
+                let depth = self.frames.len();
+                self.stack.push(method.receiver);
+                self.call(method.method, 0)?;
+                self.run(depth)?;
+                self.pop();
+            }
+        }
+        Ok(())
+    }
+
+    fn frame(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().unwrap()
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let frame = self.frame();
+        let byte = frame.closure.function.chunk.code[frame.ip];
+        frame.ip += 1;
+        byte
+    }
+
+    fn read_u16(&mut self) -> u16 {
+        let frame = self.frame();
+        let value = frame.closure.function.chunk.read_u16(frame.ip);
+        frame.ip += 2;
+        value
+    }
+
+    fn read_constant(&mut self) -> Value {
+        let index = self.read_u16() as usize;
+        self.frame().closure.function.chunk.constants[index].clone()
+    }
+
+    fn read_string(&mut self) -> Rc<str> {
+        match self.read_constant() {
+            Value::String(string) => string,
+            _ => unreachable!("Names are always string constants"),
+        }
+    }
+
+    // The method `name` of `class`, for the GetProperty with inline cache `cache`
+    // The cache has it when the last instance read there was of the same class
+    fn find_method(
+        &self,
+        class: &Rc<RefCell<Class>>,
+        name: &str,
+        cache: usize,
+    ) -> Option<Rc<Closure>> {
+        let frame = self.frames.last().unwrap();
+        let Some(cache) = frame.closure.function.chunk.caches.get(cache) else {
+            return class.borrow().methods.get(name).cloned();
+        };
+        if let Some(cached) = &*cache.borrow() {
+            if cached.is_for(class) {
+                return Some(Rc::clone(&cached.method));
+            }
+        }
+
+        let method = class.borrow().methods.get(name).cloned()?;
+        *cache.borrow_mut() = Some(MethodCache::new(class, Rc::clone(&method)));
+        Some(method)
+    }
+
+    // A field of the instance, or else a method bound to it
+    fn property(
+        &mut self,
+        instance: Rc<RefCell<Instance>>,
+        name: &str,
+        cache: usize,
+    ) -> Result<Value, Error> {
+        if let Some(field) = instance.borrow().fields.get(name) {
+            return Ok(field.clone());
+        }
+        let Some(method) = self.find_method(&instance.borrow().class, name, cache) else {
+            return Err(self.error_as(ErrorClass::NameError, "Property does not exist".to_string()));
+        };
+        let bound = BoundMethod {
+            receiver: Value::Instance(instance),
+            method,
+        };
+        Ok(Value::BoundMethod(Rc::new(bound)))
+    }
+
+    // `this._x` only reaches `_x` from the methods of the instance's class and its
+    // superclasses, and the functions made in them, not from mixed in ones
+    fn check_private(&mut self, instance: &Rc<RefCell<Instance>>, name: &str) -> Result<(), Error> {
+        if !is_private(name) {
+            return Ok(());
+        }
+        let class = Rc::clone(&instance.borrow().class);
+        if class.borrow().is_owner(&self.frame().closure.owner) {
+            return Ok(());
+        }
+        let message = format!(
+            "`{}` is private to the methods of `{}` and its superclasses",
+            name,
+            class.borrow().name
+        );
+        Err(self.error_as(ErrorClass::NameError, message))
+    }
+
+    // Runtime errors are reported at the code of the failing instruction
+    // Of the instruction running
+    // Nowhere before the first frame, when the script itself can't be called
+    fn span(&mut self) -> Span {
+        match self.frames.last() {
+            Some(frame) => frame.closure.function.chunk.spans[frame.ip.saturating_sub(1)],
+            None => Span::default(),
+        }
+    }
+
+    fn error(&mut self, message: String) -> Error {
+        Error::interpreter(message, self.span())
+    }
+
+    // Raised as an instance of `class`, for `catch`
+    fn error_as(&mut self, class: ErrorClass, message: String) -> Error {
+        self.error(message).raised_as(class)
+    }
+
+    // Where `index` is in something `length` long
+    fn position(&mut self, index: &Value, length: usize, kind: &str) -> Result<usize, Error> {
+        let Value::Number(number) = index else {
+            return Err(self.error_as(
+                ErrorClass::IndexError,
+                format!("Index must be a whole number, got {}", index.repr()),
+            ));
+        };
+        if number.fract() != 0.0 {
+            return Err(self.error_as(
+                ErrorClass::IndexError,
+                format!("Index must be a whole number, got {}", number),
+            ));
+        }
+        if *number < 0.0 || *number >= length as f64 {
+            return Err(self.error_as(
+                ErrorClass::IndexError,
+                format!(
+                    "Index {} is out of range for a {} of length {}",
+                    number, kind, length
+                ),
+            ));
+        }
+        Ok(*number as usize)
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().unwrap_or(Value::Nil)
+    }
+
+    fn peek(&self, distance: usize) -> &Value {
+        &self.stack[self.stack.len() - 1 - distance]
+    }
+
+    fn number(&mut self, value: &Value) -> Result<f64, Error> {
+        match value {
+            Value::Number(number) => Ok(*number),
+            _ => Err(self.error_as(
+                ErrorClass::TypeError,
+                format!("Expected Number, got {}", value),
+            )),
+        }
+    }
+
+    // Both operands have to be numbers, the left one is checked first
+    fn numbers(&mut self) -> Result<(f64, f64), Error> {
+        let right = self.pop();
+        let left = self.pop();
+        Ok((self.number(&left)?, self.number(&right)?))
+    }
+
+    // Runs until the frames are back to `depth`, an error goes to the innermost
+    // try of the frames above it, when there is one
+    fn run(&mut self, depth: usize) -> Result<(), Error> {
+        loop {
+            match self.execute(depth) {
+                Ok(()) => return Ok(()),
+                Err(error) => self.catch(error, depth)?,
+            }
+        }
+    }
+
+    // Unwinds to the innermost try of the frames above `depth` and goes on with its
+    // catches, given the error as a value. The error is back when there is no try
+    fn catch(&mut self, error: Error, depth: usize) -> Result<(), Error> {
+        let Some(index) = (depth..self.frames.len())
+            .rev()
+            .find(|index| !self.frames[*index].handlers.is_empty())
+        else {
+            return Err(error);
+        };
+        let value = match &error {
+            Error::Thrown(..) => self.thrown.take().unwrap_or(Value::Nil),
+            Error::InterpretError(message, _, class) => {
+                let instance = Instance::new(
+                    Rc::clone(&self.error_classes[*class as usize]),
+                    HashMap::from([(
+                        "message".to_string(),
+                        Value::String(Rc::from(message.as_str())),
+                    )]),
+                );
+                let instance = Rc::new(RefCell::new(instance));
+                self.collector.instance(&instance);
+                Value::Instance(instance)
+            }
+            _ => return Err(error),
+        };
+
+        for frame in self.frames.drain(index + 1..) {
+            if let Some((generator, _)) = frame.generator {
+                generator.borrow_mut().state = State::Done;
+            }
+        }
+        let frame = self.frame();
+        let handler = frame.handlers.pop().unwrap();
+        frame.ip = handler.ip;
+        let top = frame.base + handler.stack;
+        self.close_upvalues(top);
+        self.stack.truncate(top);
+        self.stack.push(value);
+        self.frame().caught = Some(error);
+        Ok(())
+    }
+
+    // Runs until the frames are back to `depth`, or an instruction fails
+    fn execute(&mut self, depth: usize) -> Result<(), Error> {
+        loop {
+            let byte = self.read_byte();
+            let Some(op) = OpCode::from_byte(byte) else {
+                return Err(self.error(format!("Unknown instruction {}", byte)));
+            };
+
+            match op {
+                OpCode::Constant => {
+                    let constant = self.read_constant();
+                    self.stack.push(constant);
+                }
+                OpCode::Nil => self.stack.push(Value::Nil),
+                OpCode::True => self.stack.push(Value::Boolean(true)),
+                OpCode::False => self.stack.push(Value::Boolean(false)),
+                OpCode::Pop => {
+                    self.pop();
+                    self.finalize()?;
+                }
+
+                OpCode::GetLocal => {
+                    let slot = self.read_byte() as usize + self.frame().base;
+                    self.stack.push(self.stack[slot].clone());
+                }
+                OpCode::SetLocal => {
+                    let slot = self.read_byte() as usize + self.frame().base;
+                    self.stack[slot] = self.peek(0).clone();
+                }
+                OpCode::GetGlobal => {
+                    let name = self.read_string();
+                    match self.globals.get(&name) {
+                        Some(value) => self.stack.push(value.clone()),
+                        None => {
+                            return Err(self.error_as(
+                                ErrorClass::NameError,
+                                format!("Undefined Variable {}", name),
+                            ))
+                        }
+                    }
+                }
+                OpCode::DefineGlobal => {
+                    let name = self.read_string();
+                    let value = self.pop();
+                    self.globals.insert(name, value);
+                    self.finalize()?;
+                }
+                OpCode::SetGlobal => {
+                    let name = self.read_string();
+                    if !self.globals.contains_key(&name) {
+                        return Err(self.error_as(
+                            ErrorClass::NameError,
+                            format!("Undefined Variable {}", name),
+                        ));
+                    }
+                    let value = self.peek(0).clone();
+                    self.globals.insert(name, value);
+                }
+                OpCode::GetUpvalue => {
+                    let index = self.read_byte() as usize;
+                    let upvalue = Rc::clone(&self.frame().closure.upvalues[index]);
+                    let value = match &*upvalue.borrow() {
+                        Upvalue::Open(slot) => self.stack[*slot].clone(),
+                        Upvalue::Closed(value) => value.clone(),
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::SetUpvalue => {
+                    let index = self.read_byte() as usize;
+                    let upvalue = Rc::clone(&self.frame().closure.upvalues[index]);
+                    let value = self.peek(0).clone();
+                    let mut upvalue = upvalue.borrow_mut();
+                    match &mut *upvalue {
+                        Upvalue::Open(slot) => self.stack[*slot] = value,
+                        Upvalue::Closed(closed) => *closed = value,
+                    }
+                }
+
+                OpCode::GetProperty => {
+                    let name = self.read_string();
+                    let cache = self.read_u16() as usize;
+                    let property = match self.pop() {
+                        Value::Instance(instance) => {
+                            self.check_private(&instance, &name)?;
+                            self.property(instance, &name, cache)?
+                        }
+                        Value::Module(module) => match module.members.get(&*name) {
+                            Some(member) => member.clone(),
+                            None => {
+                                let message = format!("Module `{}` has no `{}`", module.name, name);
+                                return Err(self.error_as(ErrorClass::NameError, message));
+                            }
+                        },
+                        Value::Bytes(bytes) => match bytes_method(&bytes, &name) {
+                            Some(method) => Value::NativeFunction(Rc::new(method)),
+                            None => {
+                                let message = format!("Bytes have no method `{}`", name);
+                                return Err(self.error_as(ErrorClass::NameError, message));
+                            }
+                        },
+                        #[cfg(feature = "sockets")]
+                        Value::Socket(socket) => match socket_method(&socket, &name) {
+                            Some(method) => Value::NativeFunction(Rc::new(method)),
+                            None => {
+                                let message = format!("{} has no method `{}`", socket.kind(), name);
+                                return Err(self.error_as(ErrorClass::NameError, message));
+                            }
+                        },
+                        _ => {
+                            return Err(self.error_as(
+                                ErrorClass::TypeError,
+                                "Only instances and modules have properties".to_string(),
+                            ))
+                        }
+                    };
+                    self.stack.push(property);
+                }
+                OpCode::GetOptional => {
+                    let name = self.read_string();
+                    let object = self.pop();
+                    if let Value::Instance(instance) = &object {
+                        self.check_private(instance, &name)?;
+                    }
+                    self.stack
+                        .push(property_of(&object, &name).unwrap_or(Value::Nil));
+                }
+                OpCode::SetProperty => {
+                    let name = self.read_string();
+                    let value = self.pop();
+                    let Value::Instance(instance) = self.pop() else {
+                        return Err(self.error_as(
+                            ErrorClass::TypeError,
+                            "Only instances have fields".to_string(),
+                        ));
+                    };
+                    self.check_private(&instance, &name)?;
+
+                    let mut instance = instance.borrow_mut();
+                    if instance.mutability != Mutability::Open {
+                        let exists = instance.fields.contains_key(&*name);
+                        let class = instance.class.borrow().name.clone();
+                        if let Some(message) = instance.mutability.set_error(&class, &name, exists)
+                        {
+                            return Err(self.error_as(ErrorClass::TypeError, message));
+                        }
+                    }
+                    instance.fields.insert(name.to_string(), value.clone());
+                    drop(instance);
+                    self.stack.push(value);
+                }
+                OpCode::GetSuper => {
+                    let name = self.read_string();
+                    let Value::Class(superclass) = self.pop() else {
+                        return Err(self.error_as(
+                            ErrorClass::TypeError,
+                            "Superclass must be a class".to_string(),
+                        ));
+                    };
+                    let receiver = self.pop();
+                    let method = superclass.borrow().methods.get(&*name).cloned();
+                    let Some(method) = method else {
+                        let message = format!(
+                            "Superclass `{}` has no method `{}`",
+                            superclass.borrow().name,
+                            name
+                        );
+                        return Err(self.error_as(ErrorClass::NameError, message));
+                    };
+                    let bound = BoundMethod { receiver, method };
+                    self.stack.push(Value::BoundMethod(Rc::new(bound)));
+                }
+                OpCode::Index => {
+                    let index = self.pop();
+                    let object = self.pop();
+                    let element = match &object {
+                        Value::List(elements) => {
+                            let elements = elements.borrow();
+                            let position = self.position(&index, elements.len(), "list")?;
+                            elements[position].clone()
+                        }
+                        Value::String(text) => {
+                            let length = text.chars().count();
+                            let position = self.position(&index, length, "string")?;
+                            Value::String(Rc::from(text.chars().nth(position).unwrap().to_string()))
+                        }
+                        Value::Bytes(bytes) => {
+                            let position = self.position(&index, bytes.len(), "buffer")?;
+                            Value::Number(f64::from(bytes[position]))
+                        }
+                        Value::Map(entries) => {
+                            let value = entries
+                                .borrow()
+                                .iter()
+                                .find(|(key, _)| key.is_equal(&index))
+                                .map(|(_, value)| value.clone());
+                            match value {
+                                Some(value) => value,
+                                None => {
+                                    return Err(self.error_as(
+                                        ErrorClass::IndexError,
+                                        format!("Key {} is not in the map", index.repr()),
+                                    ))
+                                }
+                            }
+                        }
+                        _ => {
+                            return Err(self.error_as(
+                                ErrorClass::TypeError,
+                                format!(
+                                    "Only lists, maps, strings and bytes can be indexed, got {}",
+                                    object.repr()
+                                ),
+                            ))
+                        }
+                    };
+                    self.stack.push(element);
+                }
+
+                OpCode::Equal => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    self.stack.push(Value::Boolean(left.is_equal(&right)));
+                }
+                OpCode::Is => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    self.stack.push(Value::Boolean(left.is(&right)));
+                }
+                OpCode::Greater => {
+                    let (left, right) = self.numbers()?;
+                    self.stack.push(Value::Boolean(left > right));
+                }
+                OpCode::GreaterEqual => {
+                    let (left, right) = self.numbers()?;
+                    self.stack.push(Value::Boolean(left >= right));
+                }
+                OpCode::Less => {
+                    let (left, right) = self.numbers()?;
+                    self.stack.push(Value::Boolean(left < right));
+                }
+                OpCode::LessEqual => {
+                    let (left, right) = self.numbers()?;
+                    self.stack.push(Value::Boolean(left <= right));
+                }
+                // An element of a set or list, a key of a map, or a string inside a string
+                OpCode::In => {
+                    let collection = self.pop();
+                    let element = self.pop();
+                    let is_in = match (&collection, &element) {
+                        (Value::Set(elements) | Value::List(elements), _) => {
+                            element.is_in(&elements.borrow())
+                        }
+                        (Value::Map(entries), _) => entries
+                            .borrow()
+                            .iter()
+                            .any(|(key, _)| key.is_equal(&element)),
+                        (Value::String(text), Value::String(part)) => text.contains(&**part),
+                        (Value::String(_), _) => {
+                            return Err(self.error_as(
+                                ErrorClass::TypeError,
+                                format!("Only a string can be in a string, got {}", element.repr()),
+                            ))
+                        }
+                        _ => {
+                            return Err(self.error_as(
+                                ErrorClass::TypeError,
+                                format!(
+                                    "`in` needs a set, list, map or string, got {}",
+                                    collection.repr()
+                                ),
+                            ))
+                        }
+                    };
+                    self.stack.push(Value::Boolean(is_in));
+                }
+
+                // Numbers are added, anything else but a number is concatenated
+                OpCode::Add => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    let value = match (&left, &right) {
+                        (Value::Number(left), Value::Number(right)) => Value::Number(left + right),
+                        (Value::Number(_), _) => {
+                            return Err(self.error_as(
+                                ErrorClass::TypeError,
+                                format!("Expected Number, got {}", right),
+                            ))
+                        }
+                        (_, Value::Number(_)) => {
+                            return Err(self.error_as(
+                                ErrorClass::TypeError,
+                                format!("Expected String, got {}", right),
+                            ))
+                        }
+                        // Anything else is joined as text, collections only with a string
+                        _ if !matches!(left, Value::String(_))
+                            && !matches!(right, Value::String(_))
+                            && (is_collection(&left) || is_collection(&right)) =>
+                        {
+                            let collection = match is_collection(&left) {
+                                true => &left,
+                                false => &right,
+                            };
+                            return Err(self.error_as(
+                                ErrorClass::TypeError,
+                                format!("Expected Number or String, got {}", collection.repr()),
+                            ));
+                        }
+                        _ => Value::String(Rc::from(left.value() + &right.value())),
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::Subtract => {
+                    let (left, right) = self.numbers()?;
+                    self.stack.push(Value::Number(left - right));
+                }
+                // A string times a number repeats the string
+                OpCode::Multiply => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    let value = match (&left, &right) {
+                        (Value::String(text), Value::Number(times)) => {
+                            match strings::repeat(text, *times) {
+                                Ok(repeated) => Value::String(Rc::from(repeated)),
+                                Err(message) => return Err(self.error(message)),
+                            }
+                        }
+                        _ => Value::Number(self.number(&left)? * self.number(&right)?),
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::Divide => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    let right = self.number(&right)?;
+                    let left = self.number(&left)?;
+                    self.stack.push(Value::Number(left / right));
+                }
+                OpCode::Not => {
+                    let value = self.pop();
+                    self.stack.push(Value::Boolean(!value.is_truthy()));
+                }
+                OpCode::Negate => {
+                    let value = self.pop();
+                    let number = self.number(&value)?;
+                    self.stack.push(Value::Number(-number));
+                }
+                OpCode::Print => {
+                    let value = self.pop();
+                    if let Err(error) = writeln!(self.output, "{}", value) {
+                        return Err(self.error(format!("Could not print: {}", error)));
+                    }
+                    drop(value);
+                    self.finalize()?;
+                }
+
+                OpCode::Jump => {
+                    let offset = self.read_u16() as usize;
+                    self.frame().ip += offset;
+                }
+                OpCode::JumpIfFalse => {
+                    let offset = self.read_u16() as usize;
+                    if !self.peek(0).is_truthy() {
+                        self.frame().ip += offset;
+                    }
+                }
+                OpCode::Loop => {
+                    let offset = self.read_u16() as usize;
+                    self.frame().ip -= offset;
+                }
+
+                OpCode::Call => {
+                    let argument_count = self.read_byte() as usize;
+                    let callee = self.peek(argument_count).clone();
+                    self.call_value(callee, argument_count)?;
+                }
+                OpCode::Closure => {
+                    let Value::Function(function) = self.read_constant() else {
+                        unreachable!("Closures are always made from function constants")
+                    };
+
+                    let mut upvalues = Vec::with_capacity(function.upvalues.len());
+                    for _ in 0..function.upvalues.len() {
+                        let is_local = self.read_byte() == 1;
+                        let index = self.read_byte() as usize;
+                        if is_local {
+                            let slot = self.frame().base + index;
+                            upvalues.push(self.capture_upvalue(slot));
+                        } else {
+                            upvalues.push(Rc::clone(&self.frame().closure.upvalues[index]));
+                        }
+                    }
+
+                    let owner = self.frame().closure.owner.clone();
+                    let closure = Closure {
+                        function,
+                        upvalues,
+                        owner,
+                    };
+                    self.stack.push(Value::Closure(Rc::new(closure)));
+                }
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                    self.finalize()?;
+                }
+                // A generator that returns is done, its value is dropped
+                // and its caller's loop ends
+                OpCode::Return => {
+                    let result = self.pop();
+                    let base = self.frame().base;
+                    self.close_upvalues(base);
+                    let frame = self.frames.pop().unwrap();
+                    if let (true, Value::Instance(instance)) = (frame.seals, &result) {
+                        instance
+                            .borrow_mut()
+                            .mutability
+                            .restrict(Mutability::Sealed);
+                    }
+
+                    self.stack.truncate(base);
+                    match frame.generator {
+                        Some((generator, Some(exit))) => {
+                            generator.borrow_mut().state = State::Done;
+                            self.frame().ip = exit;
+                        }
+                        Some((generator, None)) => {
+                            generator.borrow_mut().state = State::Done;
+                            self.stack.push(result);
+                        }
+                        None => self.stack.push(result),
+                    }
+                    self.finalize()?;
+                    if self.frames.len() == depth {
+                        return Ok(());
+                    }
+                }
+
+                OpCode::Class => {
+                    let name = self.read_string();
+                    let class = Class {
+                        name: name.to_string(),
+                        superclass: None,
+                        methods: HashMap::new(),
+                        mixins: Vec::new(),
+                        version: 0,
+                        dropped: Some(Rc::clone(&self.dropped)),
+                        sealed: false,
+                    };
+                    self.stack.push(Value::Class(Rc::new(RefCell::new(class))));
+                }
+                OpCode::Seal => {
+                    if let Value::Class(class) = self.peek(0) {
+                        class.borrow_mut().sealed = true;
+                    }
+                }
+                // Methods are copied down, the subclass' own ones are added afterwards
+                OpCode::Inherit => {
+                    let class = self.pop();
+                    let superclass = self.peek(0).clone();
+                    match (superclass, class) {
+                        (Value::Class(superclass), Value::Class(class)) => {
+                            let methods = superclass.borrow().methods.clone();
+                            let mut class = class.borrow_mut();
+                            class.methods.extend(methods);
+                            class.superclass = Some(superclass);
+                            class.version += 1;
+                        }
+                        _ => {
+                            return Err(self.error_as(
+                                ErrorClass::TypeError,
+                                "Superclass must be a class".to_string(),
+                            ))
+                        }
+                    }
+                }
+                OpCode::Method => {
+                    let name = self.read_string();
+                    let method = self.pop();
+                    if let (Value::Class(class), Value::Closure(method)) = (self.peek(0), method) {
+                        let method = Rc::new(Closure {
+                            function: Rc::clone(&method.function),
+                            upvalues: method.upvalues.clone(),
+                            owner: Some(Rc::downgrade(class)),
+                        });
+                        let mut class = class.borrow_mut();
+                        class.methods.insert(name.to_string(), method);
+                        class.version += 1;
+                    }
+                }
+                // Two mixins can't both give a method the class doesn't define
+                // itself, unless it is the same method
+                OpCode::Mix => {
+                    let count = self.read_byte() as usize;
+                    let mixins: Vec<Value> = self.stack.drain(self.stack.len() - count..).collect();
+                    let Value::Class(class) = self.peek(0).clone() else {
+                        unreachable!("Mixins are added to the class under them");
+                    };
+                    let mut mixed: Vec<(String, Rc<Closure>, String)> = Vec::new(); // Name, method, mixin
+                    let mut classes = Vec::with_capacity(count);
+                    for mixin in mixins {
+                        let Value::Class(mixin) = mixin else {
+                            return Err(self.error_as(
+                                ErrorClass::TypeError,
+                                "Mixin must be a class".to_string(),
+                            ));
+                        };
+                        classes.push(Rc::clone(&mixin));
+                        let mixin = mixin.borrow();
+                        let mut names: Vec<&String> = mixin.methods.keys().collect();
+                        names.sort();
+                        for name in names {
+                            if class.borrow().defines(name) {
+                                continue;
+                            }
+                            let method = &mixin.methods[name];
+                            match mixed.iter().find(|(known, ..)| known == name) {
+                                Some((_, known, first)) if !Rc::ptr_eq(known, method) => {
+                                    let message = format!(
+                                        "Mixins `{}` and `{}` both define `{}`, `{}` has to define it itself",
+                                        first, mixin.name, name, class.borrow().name
+                                    );
+                                    return Err(self.error_as(ErrorClass::NameError, message));
+                                }
+                                Some(_) => {}
+                                None => mixed.push((
+                                    name.clone(),
+                                    Rc::clone(method),
+                                    mixin.name.clone(),
+                                )),
+                            }
+                        }
+                    }
+                    let mut class = class.borrow_mut();
+                    for (name, method, _) in mixed {
+                        class.methods.insert(name, method);
+                    }
+                    class.mixins = classes;
+                    class.version += 1;
+                }
+                // A method marked `override` has to replace one the class would
+                // otherwise get from a mixin or its superclass, taking as many
+                // parameters
+                OpCode::Override => {
+                    let name = self.read_string();
+                    let Value::Class(class) = self.peek(0).clone() else {
+                        unreachable!("Overrides are checked on the class under them");
+                    };
+                    let class = class.borrow();
+                    let Some(method) = class.methods.get(&*name) else {
+                        unreachable!("Overrides are checked once the method is added");
+                    };
+                    let arity = method.function.arity;
+                    let inherited = class
+                        .mixins
+                        .iter()
+                        .chain(&class.superclass)
+                        .find_map(|parent| Class::defining(parent, &name));
+                    let error = match inherited {
+                        Some((_, inherited)) if inherited == arity => None,
+                        Some((owner, inherited)) => Some(self.error_as(
+                            ErrorClass::TypeError,
+                            format!(
+                                "`{}` overrides the `{}` of `{}`, which takes {} parameter{}, but takes {}",
+                                name,
+                                name,
+                                owner,
+                                inherited,
+                                if inherited == 1 { "" } else { "s" },
+                                arity
+                            ),
+                        )),
+                        None => Some(self.error_as(
+                            ErrorClass::NameError,
+                            format!(
+                                "`{}` is marked `override`, but `{}` inherits no `{}`",
+                                name, class.name, name
+                            ),
+                        )),
+                    };
+                    if let Some(error) = error {
+                        return Err(error);
+                    }
+                }
+                OpCode::Tuple => {
+                    let count = self.read_byte() as usize;
+                    let values: Rc<[Value]> =
+                        self.stack.drain(self.stack.len() - count..).collect();
+                    self.stack.push(Value::Tuple(values));
+                }
+                OpCode::Unpack => {
+                    let count = self.read_byte() as usize;
+                    match self.pop() {
+                        Value::Tuple(values) if values.len() == count => {
+                            self.stack.extend(values.iter().cloned())
+                        }
+                        value => {
+                            let got = match value {
+                                Value::Tuple(values) => values.len(),
+                                _ => 1,
+                            };
+                            return Err(self.error(format!(
+                                "Expected {} values to unpack, got {}",
+                                count, got
+                            )));
+                        }
+                    }
+                }
+                OpCode::List => {
+                    let count = self.read_u16() as usize;
+                    let elements = self.stack.split_off(self.stack.len() - count);
+                    self.stack
+                        .push(Value::List(Rc::new(RefCell::new(elements))));
+                }
+                // An element given twice is only in the set once, where it was first given
+                OpCode::Set => {
+                    let count = self.read_u16() as usize;
+                    let values = self.stack.split_off(self.stack.len() - count);
+                    if let Some(value) = values.iter().find(|value| value.key().is_none()) {
+                        return Err(self.error_as(
+                            ErrorClass::TypeError,
+                            format!(
+                                "Set elements must be strings, numbers, booleans or nil, got {}",
+                                value.repr()
+                            ),
+                        ));
+                    }
+                    let elements = equality::unique_elements(&values, Value::key);
+                    self.stack.push(Value::Set(Rc::new(RefCell::new(elements))));
+                }
+                // A key set twice keeps its first place and its last value
+                OpCode::Map => {
+                    let count = self.read_u16() as usize;
+                    let values = self.stack.split_off(self.stack.len() - 2 * count);
+                    let mut entries: Vec<(Value, Value)> = Vec::with_capacity(count);
+                    for pair in values.chunks(2) {
+                        let (key, value) = (pair[0].clone(), pair[1].clone());
+                        if key.key().is_none() {
+                            return Err(self.error_as(
+                                ErrorClass::TypeError,
+                                format!(
+                                    "Map keys must be strings, numbers, booleans or nil, got {}",
+                                    key.repr()
+                                ),
+                            ));
+                        }
+                        entries.push((key, value));
+                    }
+                    let entries = equality::unique_entries(&entries, Value::key);
+                    self.stack.push(Value::Map(Rc::new(RefCell::new(entries))));
+                }
+                OpCode::Match => {
+                    let Value::Pattern(pattern) = self.read_constant() else {
+                        unreachable!("Matches are always of pattern constants")
+                    };
+                    let classes = self
+                        .stack
+                        .split_off(self.stack.len() - pattern.class_count());
+                    if !classes.iter().all(|class| matches!(class, Value::Class(_))) {
+                        return Err(self.error_as(
+                            ErrorClass::TypeError,
+                            "Only classes can be matched with fields".to_string(),
+                        ));
+                    }
+                    let value = self.pop();
+
+                    let mut bound = Vec::new();
+                    let matched = matches(&pattern, &value, &mut classes.into_iter(), &mut bound);
+                    if matched {
+                        self.stack.extend(bound);
+                    }
+                    self.stack.push(Value::Boolean(matched));
+                }
+                OpCode::NoMatch => {
+                    let value = self.pop();
+                    return Err(self.error(format!("No arm matches {}", value.repr())));
+                }
+
+                OpCode::Generator => {
+                    let generator = self.suspended_call(false);
+                    self.stack
+                        .push(Value::Generator(Rc::new(RefCell::new(generator))));
+                }
+                OpCode::Yield => {
+                    let value = self.pop();
+                    let Some((generator, _)) = self.frame().generator.clone() else {
+                        unreachable!("Only generator functions yield")
+                    };
+                    self.suspend(&generator);
+                    self.stack.push(value);
+                    if self.frames.len() == depth {
+                        return Ok(());
+                    }
+                }
+                // Lists, sets and maps are looped over as they are when the loop
+                // starts, maps by their keys
+                OpCode::Iterate => {
+                    let iterable = match self.pop() {
+                        Value::List(elements) | Value::Set(elements) => {
+                            Value::Tuple(elements.borrow().iter().cloned().collect())
+                        }
+                        Value::Map(entries) => Value::Tuple(
+                            entries
+                                .borrow()
+                                .iter()
+                                .map(|(key, _)| key.clone())
+                                .collect(),
+                        ),
+                        Value::Generator(generator) => Value::Generator(generator),
+                        value => {
+                            return Err(self.error_as(
+                                ErrorClass::TypeError,
+                                format!(
+                                "Only generators, lists, sets and maps can be looped over, got {}",
+                                value.repr()
+                            ),
+                            ))
+                        }
+                    };
+                    self.stack.push(iterable);
+                    self.stack.push(Value::Number(0.0));
+                }
+                // Under the index on the stack is what is looped over
+                OpCode::Next => {
+                    let offset = self.read_u16() as usize;
+                    let exit = self.frame().ip + offset;
+                    let Value::Number(index) = *self.peek(0) else {
+                        unreachable!("Iterate pushes the index")
+                    };
+                    match self.peek(1).clone() {
+                        Value::Tuple(elements) => match elements.get(index as usize) {
+                            Some(element) => {
+                                let last = self.stack.len() - 1;
+                                self.stack[last] = Value::Number(index + 1.0);
+                                self.stack.push(element.clone());
+                            }
+                            None => self.frame().ip = exit,
+                        },
+                        Value::Generator(generator) => {
+                            let state = generator.borrow().state;
+                            match state {
+                                State::Done => self.frame().ip = exit,
+                                State::Running => {
+                                    return Err(
+                                        self.error("Generator is already running".to_string())
+                                    )
+                                }
+                                State::Suspended => {
+                                    self.resume(generator, Value::Nil, Some(exit))?
+                                }
+                            }
+                        }
+                        _ => unreachable!("Iterate pushes a tuple or a generator"),
+                    }
+                }
+                // An `await` of a statement of its own in a coroutine's body stops the
+                // coroutine while the promise is pending, and is run again once resumed
+                OpCode::Await => {
+                    let statement = self.read_byte() == 1;
+                    let promise = match self.pop() {
+                        Value::Promise(promise) => promise,
+                        value => {
+                            return Err(self.error_as(
+                                ErrorClass::TypeError,
+                                format!("Only promises can be awaited, got {}", value.repr()),
+                            ))
+                        }
+                    };
+                    let coroutine = match &self.frame().generator {
+                        Some((generator, _)) if statement && generator.borrow().spawned => {
+                            Some(Rc::clone(generator))
+                        }
+                        _ => None,
+                    };
+                    if coroutine.is_some() {
+                        self.event_loop.poll();
+                    }
+                    match coroutine {
+                        Some(coroutine) if matches!(*promise.borrow(), Promise::Pending) => {
+                            self.frame().ip -= 2;
+                            self.stack.push(Value::Promise(promise.clone()));
+                            self.suspend(&coroutine);
+                            coroutine.borrow_mut().awaited = Some(promise.clone());
+                            self.stack.push(Value::Promise(promise));
+                            if self.frames.len() == depth {
+                                return Ok(());
+                            }
+                        }
+                        _ => {
+                            self.event_loop.wait(&promise);
+                            let value = match &*promise.borrow() {
+                                Promise::Resolved(Outcome::Nil) => Value::Nil,
+                                Promise::Resolved(Outcome::String(text)) => {
+                                    Value::String(Rc::from(text.as_str()))
+                                }
+                                Promise::Rejected(message) => {
+                                    return Err(self.error(message.clone()))
+                                }
+                                Promise::Pending => unreachable!("Waiting settles the promise"),
+                            };
+                            self.stack.push(value);
+                        }
+                    }
+                }
+                // The frame of the call is made into a coroutine before any of the body
+                // runs, that of a generator function skips its Generator instruction
+                OpCode::Spawn => {
+                    let argument_count = self.read_byte() as usize;
+                    let callee = self.peek(argument_count).clone();
+                    if !matches!(callee, Value::Closure(_) | Value::BoundMethod(_)) {
+                        return Err(self.error_as(
+                            ErrorClass::TypeError,
+                            format!("Only functions can be spawned, got {}", callee.repr()),
+                        ));
+                    }
+                    self.call_value(callee, argument_count)?;
+                    let frame = self.frame();
+                    if frame.closure.function.chunk.code.first() == Some(&(OpCode::Generator as u8))
+                    {
+                        frame.ip = 1;
+                    }
+
+                    let coroutine = Rc::new(RefCell::new(self.suspended_call(true)));
+                    self.scheduler.spawn(Rc::clone(&coroutine));
+                    self.stack.push(Value::Generator(coroutine));
+                }
+
+                OpCode::Try => {
+                    let offset = self.read_u16() as usize;
+                    let stack = self.stack.len() - self.frame().base;
+                    let frame = self.frame();
+                    let ip = frame.ip + offset;
+                    frame.handlers.push(Handler { ip, stack });
+                }
+                OpCode::EndTry => {
+                    self.frame().handlers.pop();
+                }
+                // Only errors can be thrown, they are reported with their class and
+                // message when nothing catches them
+                OpCode::Throw => {
+                    let thrown = self.pop();
+                    let message = match &thrown {
+                        Value::Instance(instance)
+                            if instance.borrow().class.borrow().is_or_inherits(
+                                &self.error_classes[ErrorClass::Error as usize],
+                            ) =>
+                        {
+                            let instance = instance.borrow();
+                            let class = instance.class.borrow().name.clone();
+                            match instance.fields.get("message") {
+                                None | Some(Value::Nil) => class,
+                                Some(message) => format!("{}: {}", class, message),
+                            }
+                        }
+                        _ => {
+                            let message = format!(
+                                "Only instances of Error and its subclasses can be thrown, got {}",
+                                thrown.repr()
+                            );
+                            return Err(self.error_as(ErrorClass::TypeError, message));
+                        }
+                    };
+                    self.thrown = Some(thrown);
+                    return Err(Error::thrown(message, self.span()));
+                }
+                OpCode::Catches => {
+                    let class = self.pop();
+                    let error = self.pop();
+                    let Value::Class(class) = class else {
+                        return Err(self.error_as(
+                            ErrorClass::TypeError,
+                            format!("Only classes can be caught, got {}", class.repr()),
+                        ));
+                    };
+                    let caught = match &error {
+                        Value::Instance(instance) => {
+                            instance.borrow().class.borrow().is_or_inherits(&class)
+                        }
+                        _ => false,
+                    };
+                    self.stack.push(Value::Boolean(caught));
+                }
+                OpCode::Rethrow => {
+                    let error = self.pop();
+                    let caught = self
+                        .frame()
+                        .caught
+                        .take()
+                        .expect("Catches run with the error caught");
+                    if let Error::Thrown(..) = caught {
+                        self.thrown = Some(error);
+                    }
+                    return Err(caught);
+                }
+                // Loaded the first time it is imported
+                OpCode::Import => {
+                    let name = self.read_string();
+                    let module = self
+                        .modules
+                        .import(&name, |module| Value::Module(Rc::new(module)));
+                    let module = match module {
+                        Some(module) => module,
+                        None => self.load(&name)?,
+                    };
+                    self.stack.push(module);
+                }
+                // Bound, so the resource is closed even when its variable is assigned
+                OpCode::Closer => {
+                    let resource = self.pop();
+                    let method = match &resource {
+                        Value::Instance(instance) => {
+                            let class = instance.borrow().class.clone();
+                            let methods = &class.borrow().methods;
+                            methods
+                                .get("__exit")
+                                .or_else(|| methods.get("close"))
+                                .cloned()
+                        }
+                        _ => None,
+                    };
+                    let Some(method) = method else {
+                        let message = format!(
+                            "Only instances with a `close` or `__exit` method can be used in `with`, got {}",
+                            resource.repr()
+                        );
+                        return Err(self.error_as(ErrorClass::TypeError, message));
+                    };
+                    let bound = BoundMethod {
+                        receiver: resource,
+                        method,
+                    };
+                    self.stack.push(Value::BoundMethod(Rc::new(bound)));
+                }
+            }
+        }
+    }
+
+    // Compiles and runs the file of a module written in Lost, in the globals
+    // wherever it is imported
+    fn load(&mut self, name: &str) -> Result<Value, Error> {
+        let span = self.span();
+        let (path, statements) = match self.modules.load(name, span)? {
+            Load::Loaded(module) => return Ok(module),
+            Load::Run(path, statements) => (path, statements),
+        };
+        let function = match Compiler::new().compile(&statements) {
+            Ok(function) => function,
+            Err(errors) => {
+                self.modules.loaded(None);
+                let error = Error::interpreter(errors[0].message().to_string(), errors[0].span());
+                return Err(modules::raised_in(&path, error, span));
+            }
+        };
+
+        let closure = Rc::new(Closure {
+            function,
+            upvalues: Vec::new(),
+            owner: None,
+        });
+        let depth = self.frames.len();
+        self.stack.push(Value::Closure(Rc::clone(&closure)));
+        if let Err(error) = self.call(closure, 0).and_then(|_| self.run(depth)) {
+            self.modules.loaded(None);
+            return Err(modules::raised_in(&path, error, span));
+        }
+        self.pop();
+
+        let members = modules::declared(&statements)
+            .into_iter()
+            .filter_map(|member| {
+                let value = self.globals.get(member.as_str())?.clone();
+                Some((member, value))
+            })
+            .collect();
+        let module = Value::Module(Rc::new(Module {
+            name: name.to_string(),
+            members,
+        }));
+        self.modules.loaded(Some(module.clone()));
+        Ok(module)
+    }
+
+    fn call_value(&mut self, callee: Value, argument_count: usize) -> Result<(), Error> {
+        let callee_slot = self.stack.len() - argument_count - 1;
+        match callee {
+            Value::Closure(closure) => self.call(closure, argument_count),
+            Value::BoundMethod(bound) => {
+                self.stack[callee_slot] = bound.receiver.clone();
+                self.call(Rc::clone(&bound.method), argument_count)
+            }
+            Value::Class(class) => {
+                let instance = Rc::new(RefCell::new(Instance::new(
+                    Rc::clone(&class),
+                    HashMap::new(),
+                )));
+                self.collector.instance(&instance);
+                self.stack[callee_slot] = Value::Instance(instance);
+
+                // Named after the class rather than `init`
+                let initializer = class.borrow().methods.get("init").cloned();
+                // Errors without one take their message, or leave it nil
+                let error = initializer.is_none()
+                    && class
+                        .borrow()
+                        .is_or_inherits(&self.error_classes[ErrorClass::Error as usize]);
+                let arity = initializer.as_ref().map_or(0, |init| init.function.arity);
+                if arity != argument_count && !(error && argument_count == 1) {
+                    let name = class.borrow().name.clone();
+                    let expected = if error { 1 } else { arity };
+                    return Err(self.arity_error(&name, expected, argument_count));
+                }
+                let sealed = class.borrow().is_sealed();
+                if let Some(initializer) = initializer {
+                    self.call(initializer, argument_count)?;
+                    // Sealed once the initializer returns, see Return
+                    self.frame().seals = sealed;
+                    return Ok(());
+                }
+                let message = match argument_count {
+                    1 => self.pop(),
+                    _ => Value::Nil,
+                };
+                if let Value::Instance(instance) = &self.stack[callee_slot] {
+                    let mut instance = instance.borrow_mut();
+                    if error {
+                        instance.fields.insert("message".to_string(), message);
+                    }
+                    if sealed {
+                        instance.mutability.restrict(Mutability::Sealed);
+                    }
+                }
+                Ok(())
+            }
+            Value::NativeFunction(native) => {
+                if let Some(message) = native.arity_error(argument_count) {
+                    return Err(self.error_as(ErrorClass::TypeError, message));
+                }
+                let mut arguments = self.stack.split_off(callee_slot + 1);
+                if let Some(receiver) = &native.receiver {
+                    arguments.insert(0, receiver.clone());
+                }
+                let result = (native.function)(self, &arguments)?;
+                self.stack.truncate(callee_slot);
+                self.stack.push(result);
+                Ok(())
+            }
+            _ => Err(self.error_as(ErrorClass::TypeError, "Not a function".to_string())),
+        }
+    }
+
+    fn call(&mut self, closure: Rc<Closure>, argument_count: usize) -> Result<(), Error> {
+        if closure.function.arity != argument_count {
+            let function = &closure.function;
+            return Err(self.arity_error(&function.name, function.arity, argument_count));
+        }
+        // A `match` is compiled as a call, but isn't one to the script
+        let calls = match self.frames.last() {
+            None => 0,
+            Some(frame) if closure.function.name == "match" => frame.calls,
+            Some(frame) => frame.calls + 1,
+        };
+        if calls > CALLS_MAX {
+            return Err(self.error("Stack overflow".to_string()));
+        }
+
+        self.frames.push(CallFrame {
+            closure,
+            ip: 0,
+            base: self.stack.len() - argument_count - 1,
+            generator: None,
+            handlers: Vec::new(),
+            caught: None,
+            seals: false,
+            calls,
+        });
+        Ok(())
+    }
+
+    // Makes the frame of the call just made into a suspended generator
+    fn suspended_call(&mut self, spawned: bool) -> Generator {
+        let frame = self.frames.pop().unwrap();
+        Generator {
+            stack: self.stack.split_off(frame.base),
+            closure: frame.closure,
+            ip: frame.ip,
+            upvalues: Vec::new(),
+            handlers: frame.handlers,
+            state: State::Suspended,
+            started: false,
+            spawned,
+            awaited: None,
+        }
+    }
+
+    // Puts the generator's part of the stack back on top, and runs it in a new frame
+    // until it yields, or returns to `exit` in the caller's code. The `yield` it
+    // stopped at gets `value`
+    fn resume(
+        &mut self,
+        generator: Rc<RefCell<Generator>>,
+        value: Value,
+        exit: Option<usize>,
+    ) -> Result<(), Error> {
+        let calls = self.frames.last().map_or(0, |frame| frame.calls) + 1;
+        if calls > CALLS_MAX {
+            return Err(self.error("Stack overflow".to_string()));
+        }
+
+        let base = self.stack.len();
+        let (closure, ip, handlers) = {
+            let mut suspended = generator.borrow_mut();
+            suspended.state = State::Running;
+            self.stack.append(&mut suspended.stack);
+            for (offset, upvalue) in suspended.upvalues.drain(..) {
+                if let Upvalue::Closed(value) = &*upvalue.borrow() {
+                    self.stack[base + offset] = value.clone();
+                }
+                *upvalue.borrow_mut() = Upvalue::Open(base + offset);
+                self.open_upvalues.push(upvalue);
+            }
+            // An `await` it stopped at takes no value, it is run again
+            if suspended.started && suspended.awaited.take().is_none() {
+                self.stack.push(value);
+            }
+            suspended.started = true;
+            (
+                Rc::clone(&suspended.closure),
+                suspended.ip,
+                std::mem::take(&mut suspended.handlers),
+            )
+        };
+
+        self.frames.push(CallFrame {
+            closure,
+            ip,
+            base,
+            generator: Some((generator, exit)),
+            handlers,
+            caught: None,
+            seals: false,
+            calls,
+        });
+        Ok(())
+    }
+
+    // Takes the frame's part of the stack off it, into the generator
+    // Captured variables of that part are closed, and reopened when it is resumed
+    fn suspend(&mut self, generator: &Rc<RefCell<Generator>>) {
+        let frame = self.frames.pop().unwrap();
+        let mut suspended = generator.borrow_mut();
+        let stack = &self.stack;
+        self.open_upvalues.retain(|upvalue| {
+            let slot = match *upvalue.borrow() {
+                Upvalue::Open(slot) if slot >= frame.base => slot,
+                _ => return true,
+            };
+            *upvalue.borrow_mut() = Upvalue::Closed(stack[slot].clone());
+            suspended
+                .upvalues
+                .push((slot - frame.base, Rc::clone(upvalue)));
+            false
+        });
+        suspended.stack = self.stack.split_off(frame.base);
+        suspended.ip = frame.ip;
+        suspended.handlers = frame.handlers;
+        suspended.state = State::Suspended;
+    }
+
+    fn arity_error(&mut self, name: &str, expected: usize, got: usize) -> Error {
+        self.error_as(ErrorClass::TypeError, arity_message(name, expected, got))
+    }
+
+    // Closures capturing the same variable share one upvalue
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        for upvalue in self.open_upvalues.iter() {
+            if matches!(*upvalue.borrow(), Upvalue::Open(open) if open == slot) {
+                return Rc::clone(upvalue);
+            }
+        }
+
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+        self.open_upvalues.push(Rc::clone(&upvalue));
+        self.collector.upvalue(&upvalue);
+        upvalue
+    }
+
+    // Moves the variables from `first_slot` up off the stack, into their upvalues
+    fn close_upvalues(&mut self, first_slot: usize) {
+        let stack = &self.stack;
+        self.open_upvalues.retain(|upvalue| {
+            let slot = match *upvalue.borrow() {
+                Upvalue::Open(slot) if slot >= first_slot => slot,
+                _ => return true,
+            };
+            *upvalue.borrow_mut() = Upvalue::Closed(stack[slot].clone());
+            false
+        });
+    }
+}
+
+// `math`, numbers in and out
+fn math() -> Vec<(String, Value)> {
+    fn number(vm: &mut VM, value: &Value) -> Result<f64, Error> {
+        match value {
+            Value::Number(number) => Ok(*number),
+            _ => Err(vm.error_as(
+                ErrorClass::TypeError,
+                format!("Expected Number, got {}", value.repr()),
+            )),
+        }
+    }
+
+    fn sqrt(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+        Ok(Value::Number(number(vm, &arguments[0])?.sqrt()))
+    }
+
+    fn floor(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+        Ok(Value::Number(number(vm, &arguments[0])?.floor()))
+    }
+
+    fn ceil(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+        Ok(Value::Number(number(vm, &arguments[0])?.ceil()))
+    }
+
+    fn abs(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+        Ok(Value::Number(number(vm, &arguments[0])?.abs()))
+    }
+
+    fn pow(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+        let base = number(vm, &arguments[0])?;
+        Ok(Value::Number(base.powf(number(vm, &arguments[1])?)))
+    }
+
+    fn min(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+        let a = number(vm, &arguments[0])?;
+        Ok(Value::Number(a.min(number(vm, &arguments[1])?)))
+    }
+
+    fn max(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+        let a = number(vm, &arguments[0])?;
+        Ok(Value::Number(a.max(number(vm, &arguments[1])?)))
+    }
+
+    type Native = fn(&mut VM, &[Value]) -> Result<Value, Error>;
+    let functions: [(&str, usize, Native); 7] = [
+        ("sqrt", 1, sqrt),
+        ("floor", 1, floor),
+        ("ceil", 1, ceil),
+        ("abs", 1, abs),
+        ("pow", 2, pow),
+        ("min", 2, min),
+        ("max", 2, max),
+    ];
+    let mut members: Vec<(String, Value)> = functions
+        .into_iter()
+        .map(|(name, arity, function)| {
+            let native = NativeFunction {
+                name: name.to_string(),
+                arity,
+                function,
+                receiver: None,
+                variadic: false,
+            };
+            (name.to_string(), Value::NativeFunction(Rc::new(native)))
+        })
+        .collect();
+    members.push(("pi".to_string(), Value::Number(std::f64::consts::PI)));
+    members.push(("e".to_string(), Value::Number(std::f64::consts::E)));
+    members
+}
+
+// `fs`, directories and what's in them, see files
+fn fs() -> Vec<(String, Value)> {
+    fn path<'v>(vm: &mut VM, value: &'v Value) -> Result<&'v str, Error> {
+        match value {
+            Value::String(path) => Ok(path),
+            _ => Err(vm.error_as(
+                ErrorClass::TypeError,
+                format!("Expected a path, got {}", value.repr()),
+            )),
+        }
+    }
+
+    fn done(vm: &mut VM, result: Result<(), String>) -> Result<Value, Error> {
+        result
+            .map(|_| Value::Nil)
+            .map_err(|message| vm.error(message))
+    }
+
+    fn list_dir(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+        let names =
+            files::list_dir(path(vm, &arguments[0])?).map_err(|message| vm.error(message))?;
+        let names = names
+            .into_iter()
+            .map(|name| Value::String(Rc::from(name)))
+            .collect();
+        Ok(Value::List(Rc::new(RefCell::new(names))))
+    }
+
+    fn mkdir(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+        let result = files::mkdir(path(vm, &arguments[0])?);
+        done(vm, result)
+    }
+
+    fn remove(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+        let result = files::remove(path(vm, &arguments[0])?);
+        done(vm, result)
+    }
+
+    fn remove_all(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+        let result = files::remove_all(path(vm, &arguments[0])?);
+        done(vm, result)
+    }
+
+    fn rename(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+        let from = path(vm, &arguments[0])?;
+        let result = files::rename(from, path(vm, &arguments[1])?);
+        done(vm, result)
+    }
+
+    fn read_bytes(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+        files::read_bytes(path(vm, &arguments[0])?)
+            .map(|bytes| Value::Bytes(Rc::from(bytes)))
+            .map_err(|message| vm.error(message))
+    }
+
+    fn metadata(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+        let metadata =
+            files::metadata(path(vm, &arguments[0])?).map_err(|message| vm.error(message))?;
+        let entries = vec![
+            (
+                Value::String(Rc::from("size")),
+                Value::Number(metadata.size as f64),
+            ),
+            (
+                Value::String(Rc::from("modified")),
+                Value::Number(metadata.modified),
+            ),
+            (
+                Value::String(Rc::from("directory")),
+                Value::Boolean(metadata.directory),
+            ),
+        ];
+        Ok(Value::Map(Rc::new(RefCell::new(entries))))
+    }
+
+    type Native = fn(&mut VM, &[Value]) -> Result<Value, Error>;
+    let functions: [(&str, usize, Native); 7] = [
+        ("read_bytes", 1, read_bytes),
+        ("list_dir", 1, list_dir),
+        ("mkdir", 1, mkdir),
+        ("remove", 1, remove),
+        ("remove_all", 1, remove_all),
+        ("rename", 2, rename),
+        ("metadata", 1, metadata),
+    ];
+    functions
+        .into_iter()
+        .map(|(name, arity, function)| {
+            let native = NativeFunction {
+                name: name.to_string(),
+                arity,
+                function,
+                receiver: None,
+                variadic: false,
+            };
+            (name.to_string(), Value::NativeFunction(Rc::new(native)))
+        })
+        .collect()
+}
+
+// `hash`, digests of strings and bytes as hex, see codec
+fn hash() -> Vec<(String, Value)> {
+    fn md5(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+        let digest = codec::md5(data(vm, &arguments[0])?);
+        Ok(Value::String(Rc::from(digest)))
+    }
+
+    fn sha256(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+        let digest = codec::sha256(data(vm, &arguments[0])?);
+        Ok(Value::String(Rc::from(digest)))
+    }
+
+    type Native = fn(&mut VM, &[Value]) -> Result<Value, Error>;
+    let functions: [(&str, usize, Native); 2] = [("md5", 1, md5), ("sha256", 1, sha256)];
+    functions
+        .into_iter()
+        .map(|(name, arity, function)| {
+            let native = NativeFunction {
+                name: name.to_string(),
+                arity,
+                function,
+                receiver: None,
+                variadic: false,
+            };
+            (name.to_string(), Value::NativeFunction(Rc::new(native)))
+        })
+        .collect()
+}
+
+// `codec`, strings and bytes as text and back, see codec
+fn codec() -> Vec<(String, Value)> {
+    fn text<'v>(vm: &mut VM, value: &'v Value) -> Result<&'v str, Error> {
+        match value {
+            Value::String(text) => Ok(text),
+            _ => Err(vm.error_as(
+                ErrorClass::TypeError,
+                format!("Expected String, got {}", value.repr()),
+            )),
+        }
+    }
+
+    fn encoded(text: String) -> Result<Value, Error> {
+        Ok(Value::String(Rc::from(text)))
+    }
+
+    fn decoded(vm: &mut VM, result: Result<Vec<u8>, String>) -> Result<Value, Error> {
+        result
+            .map(|bytes| Value::Bytes(Rc::from(bytes)))
+            .map_err(|message| vm.error(message))
+    }
+
+    fn base64_encode(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+        encoded(codec::base64_encode(data(vm, &arguments[0])?))
+    }
+
+    fn base64_decode(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+        let result = codec::base64_decode(text(vm, &arguments[0])?);
+        decoded(vm, result)
+    }
+
+    fn hex_encode(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+        encoded(codec::hex_encode(data(vm, &arguments[0])?))
+    }
+
+    fn hex_decode(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+        let result = codec::hex_decode(text(vm, &arguments[0])?);
+        decoded(vm, result)
+    }
+
+    fn url_encode(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+        encoded(codec::url_encode(data(vm, &arguments[0])?))
+    }
+
+    fn url_decode(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+        codec::url_decode(text(vm, &arguments[0])?)
+            .map(|text| Value::String(Rc::from(text)))
+            .map_err(|message| vm.error(message))
+    }
+
+    type Native = fn(&mut VM, &[Value]) -> Result<Value, Error>;
+    let functions: [(&str, usize, Native); 6] = [
+        ("base64_encode", 1, base64_encode),
+        ("base64_decode", 1, base64_decode),
+        ("hex_encode", 1, hex_encode),
+        ("hex_decode", 1, hex_decode),
+        ("url_encode", 1, url_encode),
+        ("url_decode", 1, url_decode),
+    ];
+    functions
+        .into_iter()
+        .map(|(name, arity, function)| {
+            let native = NativeFunction {
+                name: name.to_string(),
+                arity,
+                function,
+                receiver: None,
+                variadic: false,
+            };
+            (name.to_string(), Value::NativeFunction(Rc::new(native)))
+        })
+        .collect()
+}
+
+// Bindings as a map from their names, sorted so both runtimes list them the same
+fn bindings_map(mut bindings: Vec<(Rc<str>, Value)>) -> Value {
+    bindings.sort_by(|(first, _), (second, _)| first.cmp(second));
+    let entries = bindings
+        .into_iter()
+        .map(|(name, value)| (Value::String(name), value))
+        .collect();
+    Value::Map(Rc::new(RefCell::new(entries)))
+}
+
+// What `+` joins as text only with a string, `[1] + [2]` is not "[1][2]"
+fn is_collection(value: &Value) -> bool {
+    matches!(
+        value,
+        Value::List(_) | Value::Map(_) | Value::Set(_) | Value::Tuple(_)
+    )
+}
+
+// What `hash` and `codec` work on, the UTF-8 of a string or bytes as they are
+fn data<'v>(vm: &mut VM, value: &'v Value) -> Result<&'v [u8], Error> {
+    match value {
+        Value::String(text) => Ok(text.as_bytes()),
+        Value::Bytes(bytes) => Ok(bytes),
+        _ => Err(vm.error_as(
+            ErrorClass::TypeError,
+            format!("Expected String or Bytes, got {}", value.repr()),
+        )),
+    }
+}
+
+// What `value.?name` gives and `has` looks for, none when the value has no
+// such property or no properties at all
+fn property_of(value: &Value, name: &str) -> Option<Value> {
+    match value {
+        Value::Instance(instance) => {
+            let borrowed = instance.borrow();
+            if let Some(field) = borrowed.fields.get(name) {
+                return Some(field.clone());
+            }
+            let method = borrowed.class.borrow().methods.get(name).cloned()?;
+            let bound = BoundMethod {
+                receiver: value.clone(),
+                method,
+            };
+            Some(Value::BoundMethod(Rc::new(bound)))
+        }
+        Value::Module(module) => module.members.get(name).cloned(),
+        Value::Bytes(bytes) => {
+            bytes_method(bytes, name).map(|method| Value::NativeFunction(Rc::new(method)))
+        }
+        #[cfg(feature = "sockets")]
+        Value::Socket(socket) => {
+            socket_method(socket, name).map(|method| Value::NativeFunction(Rc::new(method)))
+        }
+        _ => None,
+    }
+}
+
+// `bytes.name`, the method bound to them
+fn bytes_method(bytes: &Rc<[u8]>, name: &str) -> Option<NativeFunction> {
+    // The bytes the method is bound to, its first argument
+    fn receiver(arguments: &[Value]) -> &[u8] {
+        match &arguments[0] {
+            Value::Bytes(bytes) => bytes,
+            _ => unreachable!("Methods of bytes are bound to bytes"),
+        }
+    }
+
+    fn text<'v>(vm: &mut VM, value: &'v Value) -> Result<&'v str, Error> {
+        match value {
+            Value::String(text) => Ok(text),
+            _ => Err(vm.error_as(
+                ErrorClass::TypeError,
+                format!("Expected String, got {}", value.repr()),
+            )),
+        }
+    }
+
+    fn bound(vm: &mut VM, value: &Value) -> Result<f64, Error> {
+        match value {
+            Value::Number(number) => Ok(*number),
+            _ => Err(vm.error_as(
+                ErrorClass::TypeError,
+                format!("Expected Number, got {}", value.repr()),
+            )),
+        }
+    }
+
+    fn length(_vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+        Ok(Value::Number(receiver(arguments).len() as f64))
+    }
+
+    fn slice(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+        let (start, end) = (bound(vm, &arguments[1])?, bound(vm, &arguments[2])?);
+        bytes::slice(receiver(arguments), start, end)
+            .map(|bytes| Value::Bytes(Rc::from(bytes)))
+            .map_err(|message| vm.error_as(ErrorClass::IndexError, message))
+    }
+
+    fn decode(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+        let encoding = text(vm, &arguments[1])?;
+        bytes::decode(receiver(arguments), encoding)
+            .map(|text| Value::String(Rc::from(text)))
+            .map_err(|message| vm.error(message))
+    }
+
+    type Native = fn(&mut VM, &[Value]) -> Result<Value, Error>;
+    let methods: [(&str, usize, Native); 3] = [
+        ("length", 0, length),
+        ("slice", 2, slice),
+        ("decode", 1, decode),
+    ];
+    let (name, arity, function) = methods.into_iter().find(|(method, ..)| *method == name)?;
+    Some(NativeFunction {
+        name: name.to_string(),
+        arity,
+        function,
+        receiver: Some(Value::Bytes(Rc::clone(bytes))),
+        variadic: false,
+    })
+}
+
+// `net`, TCP and UDP sockets, see Socket
+#[cfg(feature = "sockets")]
+fn net() -> Vec<(String, Value)> {
+    fn open(
+        vm: &mut VM,
+        arguments: &[Value],
+        open: fn(&str) -> Result<Socket, String>,
+    ) -> Result<Value, Error> {
+        let address = socket_text(vm, &arguments[0])?;
+        open(address)
+            .map(Value::Socket)
+            .map_err(|message| vm.error(message))
+    }
+
+    fn connect(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+        open(vm, arguments, Socket::connect)
+    }
+
+    fn listen(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+        open(vm, arguments, Socket::listen)
+    }
+
+    fn bind(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+        open(vm, arguments, Socket::bind)
+    }
+
+    type Native = fn(&mut VM, &[Value]) -> Result<Value, Error>;
+    let functions: [(&str, usize, Native); 3] = [
+        ("connect", 1, connect),
+        ("listen", 1, listen),
+        ("bind", 1, bind),
+    ];
+    functions
+        .into_iter()
+        .map(|(name, arity, function)| {
+            let native = NativeFunction {
+                name: name.to_string(),
+                arity,
+                function,
+                receiver: None,
+                variadic: false,
+            };
+            (name.to_string(), Value::NativeFunction(Rc::new(native)))
+        })
+        .collect()
+}
+
+// `socket.name`, the method of that kind of socket bound to it
+#[cfg(feature = "sockets")]
+fn socket_method(socket: &Socket, name: &str) -> Option<NativeFunction> {
+    // The socket the method is bound to, its first argument
+    fn receiver(arguments: &[Value]) -> &Socket {
+        match &arguments[0] {
+            Value::Socket(socket) => socket,
+            _ => unreachable!("Methods of sockets are bound to a socket"),
+        }
+    }
+
+    fn accept(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+        receiver(arguments)
+            .accept()
+            .map(Value::Socket)
+            .map_err(|message| vm.error(message))
+    }
+
+    fn send(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+        let text = socket_text(vm, &arguments[1])?;
+        receiver(arguments)
+            .send(text)
+            .map(|_| Value::Nil)
+            .map_err(|message| vm.error(message))
+    }
+
+    fn send_to(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+        let text = socket_text(vm, &arguments[1])?;
+        let address = socket_text(vm, &arguments[2])?;
+        receiver(arguments)
+            .send_to(text, address)
+            .map(|_| Value::Nil)
+            .map_err(|message| vm.error(message))
+    }
+
+    fn recv(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+        match receiver(arguments).recv() {
+            Ok(Some(text)) => Ok(Value::String(Rc::from(text))),
+            Ok(None) => Ok(Value::Nil),
+            Err(message) => Err(vm.error(message)),
+        }
+    }
+
+    fn recv_from(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+        let (text, from) = receiver(arguments)
+            .recv_from()
+            .map_err(|message| vm.error(message))?;
+        Ok(Value::Tuple(Rc::from([
+            Value::String(Rc::from(text)),
+            Value::String(Rc::from(from)),
+        ])))
+    }
+
+    fn address(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+        receiver(arguments)
+            .address()
+            .map(|address| Value::String(Rc::from(address)))
+            .map_err(|message| vm.error(message))
+    }
+
+    fn peer(vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+        receiver(arguments)
+            .peer()
+            .map(|address| Value::String(Rc::from(address)))
+            .map_err(|message| vm.error(message))
+    }
+
+    fn close(_vm: &mut VM, arguments: &[Value]) -> Result<Value, Error> {
+        receiver(arguments).close();
+        Ok(Value::Nil)
+    }
+
+    type Native = fn(&mut VM, &[Value]) -> Result<Value, Error>;
+    let methods: &[(&str, usize, Native)] = match socket.kind() {
+        SocketKind::Connection => &[
+            ("send", 1, send),
+            ("recv", 0, recv),
+            ("address", 0, address),
+            ("peer", 0, peer),
+            ("close", 0, close),
+        ],
+        SocketKind::Listener => &[
+            ("accept", 0, accept),
+            ("address", 0, address),
+            ("close", 0, close),
+        ],
+        SocketKind::Datagram => &[
+            ("send", 2, send_to),
+            ("recv", 0, recv_from),
+            ("address", 0, address),
+            ("close", 0, close),
+        ],
+    };
+    let (name, arity, function) = methods.iter().find(|(method, ..)| *method == name)?;
+    Some(NativeFunction {
+        name: name.to_string(),
+        arity: *arity,
+        function: *function,
+        receiver: Some(Value::Socket(socket.clone())),
+        variadic: false,
+    })
+}
+
+// The text argument of a `net` native, an address or what is sent
+#[cfg(feature = "sockets")]
+fn socket_text<'v>(vm: &mut VM, value: &'v Value) -> Result<&'v str, Error> {
+    match value {
+        Value::String(text) => Ok(text),
+        _ => Err(vm.error_as(
+            ErrorClass::TypeError,
+            format!("Expected String, got {}", value.repr()),
+        )),
+    }
+}
+
+// Whether the value matches the pattern, the values of the names it binds are added
+// to `bound` in order. `classes` are those of its instance patterns, in order
+fn matches(
+    pattern: &PatternProto,
+    value: &Value,
+    classes: &mut impl Iterator<Item = Value>,
+    bound: &mut Vec<Value>,
+) -> bool {
+    match pattern {
+        PatternProto::Wildcard => true,
+        PatternProto::Binding => {
+            bound.push(value.clone());
+            true
+        }
+        PatternProto::Literal(literal) => literal.is_equal(value),
+        PatternProto::List(patterns) => {
+            let Value::List(elements) = value else {
+                return false;
+            };
+            let elements = elements.borrow().clone();
+            elements.len() == patterns.len()
+                && patterns
+                    .iter()
+                    .zip(elements.iter())
+                    .all(|(pattern, element)| matches(pattern, element, classes, bound))
+        }
+        PatternProto::Map(patterns) => {
+            let Value::Map(entries) = value else {
+                return false;
+            };
+            let entries = entries.borrow().clone();
+            patterns.iter().all(|(key, pattern)| {
+                entries
+                    .iter()
+                    .find(|(entry_key, _)| entry_key.is_equal(key))
+                    .is_some_and(|(_, entry_value)| matches(pattern, entry_value, classes, bound))
+            })
+        }
+        PatternProto::Instance(fields) => {
+            let Some(Value::Class(class)) = classes.next() else {
+                return false;
+            };
+            let Value::Instance(instance) = value else {
+                return false;
+            };
+            if !instance.borrow().class.borrow().is_or_inherits(&class) {
+                return false;
+            }
+            fields.iter().all(|(name, pattern)| {
+                let field = instance.borrow().fields.get(&**name).cloned();
+                field.is_some_and(|field| matches(pattern, &field, classes, bound))
+            })
+        }
+    }
+}
@@ -0,0 +1,501 @@
+// Runs every script in tests/cases/ with the `lost` binary and checks what it
+// writes against comments in the script, like the test suite of the Lox book:
+//
+//     print 1 + 2;  // expect: 3
+//     print nope;   // expect runtime error: Undefined Variable nope
+//
+// - `// expect: <line>` is the next line of stdout, trailing spaces aside, and
+//   spaces past the one after the colon part of it, for indented output
+// - `// expect error: <message>` is an error on that line found before running
+// - `// expect runtime error: <message>` is the error on that line the run fails with
+// - `// expect warning: <message>` is a warning on that line
+// - `// expect stderr: <line>` is a line of stderr, in order with the others
+// - `// expect exit: <status>` when it isn't the one the errors imply (65, 70 or 0)
+// - `// flags: <flags>` go before the script, like `--vm`
+// - `// args: <arguments>` go after it, to the script
+// - `// env: <name>=<value>` sets a variable of its environment, LOST_PATH is unset otherwise
+// - `// requires: <feature>` skips the script unless lost is built with the cargo feature
+// - `// repl` types the script into the REPL instead, errors there have no line
+// - `// backend: interpreter` or `// backend: vm` runs it on that runtime only
+//
+// A script is run by the tree-walking interpreter, then again with `--vm`,
+// and has to pass on both. Scripts run with `--vm` in their flags, typed into
+// the REPL or given to a mode that runs nothing, like `--ast`, have one run
+//
+// A line can have several, `print ""; // expect: // expect warning: ...`.
+// Every error and warning has to be expected, other stderr is only checked with
+// `expect stderr`. `cargo test --test golden -- <filter>` runs the scripts whose
+// path contains the filter. Scripts in a `lost_modules/` folder are modules the
+// others import, not cases
+
+use std::{
+    env, fs,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    process::{self, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+const CASES: &str = "tests/cases";
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// Flags of modes only the tree-walking interpreter has, or that run nothing
+const INTERPRETER_MODES: [&str; 6] = [
+    "--tokens",
+    "--ast",
+    "--lint",
+    "--check-types",
+    "--check",
+    "fmt",
+];
+
+#[derive(Debug, Default)]
+struct Expectations {
+    stdout: Vec<String>,
+    stderr: Vec<String>,
+    diagnostics: Vec<Diagnostic>,
+    exit: Option<i32>,
+    flags: Vec<String>,
+    arguments: Vec<String>,
+    environment: Vec<(String, String)>,
+    features: Vec<String>,
+    repl: bool,
+    backend: Option<Backend>, // The only one it runs on
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Backend {
+    Interpreter,
+    Vm,
+}
+
+// An error or a warning, as the header of its report
+#[derive(Debug, Clone, PartialEq)]
+struct Diagnostic {
+    kind: Kind,
+    message: String,
+    line: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Error, // Found before running
+    RuntimeError,
+    Warning,
+}
+
+struct Output {
+    stdout: String,
+    stderr: String,
+    status: Option<i32>, // None when it was killed
+}
+
+fn main() {
+    let filters: Vec<String> = env::args()
+        .skip(1)
+        .filter(|arg| !arg.starts_with('-'))
+        .collect();
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+
+    let mut cases = Vec::new();
+    collect_cases(&root.join(CASES), &mut cases);
+    cases.retain(|case| {
+        let path = case.to_string_lossy();
+        filters.is_empty() || filters.iter().any(|filter| path.contains(filter.as_str()))
+    });
+    cases.sort();
+
+    println!("\nrunning {} cases", cases.len());
+    let mut failures = Vec::new();
+    let mut skipped = 0;
+    let mut runs = 0;
+    for case in cases.iter() {
+        let name = case.strip_prefix(root).unwrap_or(case);
+        let source = match fs::read_to_string(root.join(name)) {
+            Ok(source) => source,
+            Err(error) => {
+                let problem = format!("Could not read the case: {}", error);
+                failures.push((name.display().to_string(), vec![problem]));
+                runs += 1;
+                continue;
+            }
+        };
+        let expected = parse_expectations(&source);
+        for backend in backends(&expected) {
+            runs += 1;
+            let label = match backend {
+                Backend::Interpreter => name.display().to_string(),
+                Backend::Vm => format!("{} (vm)", name.display()),
+            };
+            let Some(problems) = run_case(root, name, &source, &expected, backend) else {
+                println!("case {} ... skipped", label);
+                skipped += 1;
+                continue;
+            };
+            println!(
+                "case {} ... {}",
+                label,
+                if problems.is_empty() { "ok" } else { "FAILED" }
+            );
+            if !problems.is_empty() {
+                failures.push((label, problems));
+            }
+        }
+    }
+
+    for (name, problems) in failures.iter() {
+        println!("\n---- {} ----", name);
+        for problem in problems {
+            println!("{}", problem);
+        }
+    }
+    println!(
+        "\ntest result: {}. {} passed; {} failed; {} skipped\n",
+        if failures.is_empty() { "ok" } else { "FAILED" },
+        runs - failures.len() - skipped,
+        failures.len(),
+        skipped
+    );
+    if !failures.is_empty() {
+        process::exit(1);
+    }
+}
+
+fn collect_cases(directory: &Path, cases: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(directory) else {
+        return;
+    };
+    for path in entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+    {
+        if path.is_dir() {
+            if !path.ends_with("lost_modules") {
+                collect_cases(&path, cases);
+            }
+        } else if path
+            .extension()
+            .is_some_and(|extension| extension == "lost")
+        {
+            cases.push(path);
+        }
+    }
+}
+
+// The runtimes the case runs on
+fn backends(expected: &Expectations) -> Vec<Backend> {
+    if let Some(backend) = expected.backend {
+        return vec![backend];
+    }
+    if expected.flags.iter().any(|flag| flag == "--vm") {
+        return vec![Backend::Vm];
+    }
+    let runs_nothing = expected
+        .flags
+        .iter()
+        .any(|flag| INTERPRETER_MODES.contains(&flag.as_str()));
+    if expected.repl || runs_nothing {
+        return vec![Backend::Interpreter];
+    }
+    vec![Backend::Interpreter, Backend::Vm]
+}
+
+// What's wrong with the run of a case, nothing when it passes
+// The problems found, None when the case needs a feature lost is built without
+fn run_case(
+    root: &Path,
+    name: &Path,
+    source: &str,
+    expected: &Expectations,
+    backend: Backend,
+) -> Option<Vec<String>> {
+    if !expected.features.iter().all(|feature| enabled(feature)) {
+        return None;
+    }
+    let output = match run(root, name, source, expected, backend) {
+        Ok(output) => output,
+        Err(error) => return Some(vec![format!("Could not run lost: {}", error)]),
+    };
+
+    let mut problems = Vec::new();
+    let stdout: Vec<&str> = output.stdout.lines().map(str::trim_end).collect();
+    if stdout != expected.stdout {
+        problems.push(format!(
+            "stdout was:\n{}\nexpected:\n{}",
+            indent(&stdout),
+            indent(&expected.stdout)
+        ));
+    }
+
+    let mut diagnostics = parse_diagnostics(&output.stderr);
+    if expected.repl {
+        diagnostics
+            .iter_mut()
+            .for_each(|diagnostic| diagnostic.line = None);
+    }
+    let mut missing = expected.diagnostics.clone();
+    for diagnostic in diagnostics {
+        match missing.iter().position(|expected| *expected == diagnostic) {
+            Some(index) => {
+                missing.remove(index);
+            }
+            None => problems.push(format!("unexpected {}", describe(&diagnostic))),
+        }
+    }
+    for diagnostic in missing {
+        problems.push(format!("missing {}", describe(&diagnostic)));
+    }
+
+    let mut stderr = output.stderr.lines().map(str::trim_end);
+    for line in expected.stderr.iter() {
+        if !stderr.any(|stderr_line| stderr_line == line) {
+            problems.push(format!(
+                "stderr is missing, in this order, the line:\n    {}\nstderr was:\n{}",
+                line,
+                indent(&output.stderr.lines().collect::<Vec<_>>())
+            ));
+            break;
+        }
+    }
+
+    let expects = |kind: Kind| {
+        expected
+            .diagnostics
+            .iter()
+            .any(|diagnostic| diagnostic.kind == kind)
+    };
+    // The REPL goes on after errors
+    let exit = expected.exit.unwrap_or(if expected.repl {
+        0
+    } else if expects(Kind::Error) {
+        65
+    } else if expects(Kind::RuntimeError) {
+        70
+    } else {
+        0
+    });
+    match output.status {
+        Some(status) if status == exit => {}
+        Some(status) => problems.push(format!("exited with {}, expected {}", status, exit)),
+        None => problems.push(format!("killed after {} seconds", TIMEOUT.as_secs())),
+    }
+    Some(problems)
+}
+
+// Whether lost is built with the cargo feature, tests are built with the same ones
+fn enabled(feature: &str) -> bool {
+    let features = [
+        ("sockets", cfg!(feature = "sockets")),
+        ("sync", cfg!(feature = "sync")),
+        ("serde", cfg!(feature = "serde")),
+    ];
+    features
+        .iter()
+        .any(|&(name, built)| name == feature && built)
+}
+
+fn parse_expectations(source: &str) -> Expectations {
+    let mut expected = Expectations::default();
+    for (index, line) in source.lines().enumerate() {
+        let line_number = Some(index + 1);
+        let comments: Vec<&str> = line.split("// ").skip(1).collect();
+        for (index, comment) in comments.iter().enumerate() {
+            // A line of stderr runs to the end of the line, it may show a comment itself
+            if comment.starts_with("expect stderr: ") {
+                let text = comments[index..].join("// ");
+                expected
+                    .stderr
+                    .push(text["expect stderr: ".len()..].trim_end().to_string());
+                break;
+            }
+            parse_expectation(&mut expected, comment.trim_end(), line_number);
+        }
+    }
+    if expected.repl {
+        expected
+            .diagnostics
+            .iter_mut()
+            .for_each(|diagnostic| diagnostic.line = None);
+    }
+    expected
+}
+
+// A comment can hold several, `print ""; // expect: // expect warning: ...`
+fn parse_expectation(expected: &mut Expectations, comment: &str, line: Option<usize>) {
+    let diagnostic = |kind: Kind, message: &str| Diagnostic {
+        kind,
+        message: message.to_string(),
+        line,
+    };
+
+    if let Some(text) = comment.strip_prefix("expect:") {
+        expected
+            .stdout
+            .push(text.strip_prefix(' ').unwrap_or(text).to_string());
+    } else if let Some(message) = comment.strip_prefix("expect error: ") {
+        expected.diagnostics.push(diagnostic(Kind::Error, message));
+    } else if let Some(message) = comment.strip_prefix("expect runtime error: ") {
+        expected
+            .diagnostics
+            .push(diagnostic(Kind::RuntimeError, message));
+    } else if let Some(message) = comment.strip_prefix("expect warning: ") {
+        expected
+            .diagnostics
+            .push(diagnostic(Kind::Warning, message));
+    } else if let Some(status) = comment.strip_prefix("expect exit: ") {
+        expected.exit = status.trim().parse().ok();
+    } else if let Some(flags) = comment.strip_prefix("flags: ") {
+        expected.flags = flags.split_whitespace().map(String::from).collect();
+    } else if let Some(arguments) = comment.strip_prefix("args: ") {
+        expected.arguments = arguments.split_whitespace().map(String::from).collect();
+    } else if let Some((name, value)) = comment
+        .strip_prefix("env: ")
+        .and_then(|variable| variable.split_once('='))
+    {
+        expected
+            .environment
+            .push((name.to_string(), value.to_string()));
+    } else if let Some(feature) = comment.strip_prefix("requires: ") {
+        expected.features.push(feature.trim().to_string());
+    } else if comment == "repl" {
+        expected.repl = true;
+    } else if let Some(backend) = comment.strip_prefix("backend: ") {
+        expected.backend = match backend.trim() {
+            "interpreter" => Some(Backend::Interpreter),
+            "vm" => Some(Backend::Vm),
+            _ => None,
+        };
+    }
+}
+
+// Reports start with a header, `ParseError[E0002]: message` or `Warning[W0006]: message`,
+// then ` --> file:line:column`. With --diagnostics=json they are a JSON object a line
+fn parse_diagnostics(stderr: &str) -> Vec<Diagnostic> {
+    let lines: Vec<&str> = stderr.lines().collect();
+    let mut diagnostics = Vec::new();
+    for (index, line) in lines.iter().enumerate() {
+        if let Ok(json) = serde_json::from_str::<serde_json::Value>(line) {
+            diagnostics.push(Diagnostic {
+                kind: kind(json["kind"].as_str().unwrap_or_default()),
+                message: json["message"].as_str().unwrap_or_default().to_string(),
+                line: json["line"].as_u64().map(|line| line as usize),
+            });
+            continue;
+        }
+
+        let Some((header, message)) = line.split_once(": ") else {
+            continue;
+        };
+        let Some((name, code)) = header.split_once('[') else {
+            continue;
+        };
+        if !code.ends_with(']') || name.contains(' ') {
+            continue;
+        }
+        let line = lines
+            .get(index + 1)
+            .and_then(|location| location.trim_start().strip_prefix("--> "))
+            .and_then(|location| location.rsplit(':').nth(1))
+            .and_then(|line| line.parse().ok());
+        diagnostics.push(Diagnostic {
+            kind: kind(name),
+            message: message.to_string(),
+            line,
+        });
+    }
+    diagnostics
+}
+
+fn kind(name: &str) -> Kind {
+    match name {
+        "Warning" => Kind::Warning,
+        "RuntimeError" => Kind::RuntimeError,
+        _ => Kind::Error,
+    }
+}
+
+fn run(
+    root: &Path,
+    name: &Path,
+    source: &str,
+    expected: &Expectations,
+    backend: Backend,
+) -> std::io::Result<Output> {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_lost"));
+    command.current_dir(root);
+    if backend == Backend::Vm && !expected.flags.iter().any(|flag| flag == "--vm") {
+        command.arg("--vm");
+    }
+    command
+        .args(&expected.flags)
+        .env("NO_COLOR", "1")
+        .env_remove("HOME") // The REPL would keep its history there
+        .env_remove("LOST_PATH")
+        .envs(expected.environment.iter().cloned())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    if !expected.repl {
+        command.arg(name).args(&expected.arguments);
+    }
+
+    let mut child = command.spawn()?;
+    let mut stdin = child.stdin.take();
+    if expected.repl {
+        if let Some(stdin) = stdin.as_mut() {
+            stdin.write_all(source.as_bytes())?;
+        }
+    }
+    drop(stdin);
+
+    // Read as it comes, a full pipe would block the child
+    let read = |mut pipe: Box<dyn Read + Send>| {
+        thread::spawn(move || {
+            let mut text = String::new();
+            let _ = pipe.read_to_string(&mut text);
+            text
+        })
+    };
+    let stdout = read(Box::new(child.stdout.take().expect("stdout is piped")));
+    let stderr = read(Box::new(child.stderr.take().expect("stderr is piped")));
+
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status.code();
+        }
+        if start.elapsed() > TIMEOUT {
+            let _ = child.kill();
+            let _ = child.wait();
+            break None;
+        }
+        thread::sleep(Duration::from_millis(5));
+    };
+
+    Ok(Output {
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+        status,
+    })
+}
+
+fn describe(diagnostic: &Diagnostic) -> String {
+    let kind = match diagnostic.kind {
+        Kind::Error => "error",
+        Kind::RuntimeError => "runtime error",
+        Kind::Warning => "warning",
+    };
+    match diagnostic.line {
+        Some(line) => format!("{} on line {}: {}", kind, line, diagnostic.message),
+        None => format!("{}: {}", kind, diagnostic.message),
+    }
+}
+
+fn indent(lines: &[impl AsRef<str>]) -> String {
+    lines
+        .iter()
+        .map(|line| format!("    {}", line.as_ref()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}